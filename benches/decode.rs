@@ -0,0 +1,85 @@
+// Decode throughput of a big script, read the usual way and, when built with the mmap feature,
+// mapped into memory:
+//
+// cargo bench --bench decode
+// cargo bench --bench decode --features mmap
+//
+// The script is written to the temp directory first: 200,000 strings of 12 glyphs each, about
+// 6 MB, which is the size of the biggest scripts of the games.
+
+use byteorder::{LittleEndian, WriteBytesExt};
+use sc3tools::{
+    format::{self, DecodeOptions, MagesScript},
+    gamedef::{self, GameDef},
+};
+use std::{
+    fs::{self, File},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+const STRINGS: u32 = 200_000;
+const PASSES: u32 = 5;
+
+fn script() -> Vec<u8> {
+    let string = |i: u32| -> Vec<u8> {
+        let mut s = Vec::new();
+        for j in 0..12 {
+            s.extend_from_slice(&(0x8000 + ((i + j) % 0x200) as u16).to_be_bytes());
+        }
+        s.push(0xFF);
+        s
+    };
+    let index_end = 12 + 4 * STRINGS;
+    let mut buf = b"SC3\0".to_vec();
+    buf.write_u32::<LittleEndian>(12).unwrap();
+    buf.write_u32::<LittleEndian>(index_end).unwrap();
+    let mut offset = index_end;
+    for i in 0..STRINGS {
+        buf.write_u32::<LittleEndian>(offset).unwrap();
+        offset += string(i).len() as u32;
+    }
+    for i in 0..STRINGS {
+        buf.extend_from_slice(&string(i));
+    }
+    buf
+}
+
+fn bench(name: &str, path: &Path, gamedef: &GameDef, open: impl Fn(&Path) -> Box<dyn MagesScript>) {
+    let mut total = Duration::ZERO;
+    for _ in 0..PASSES {
+        let start = Instant::now();
+        let script = open(path);
+        let decoded = script
+            .strings(gamedef, DecodeOptions::default())
+            .map(Result::unwrap)
+            .count();
+        total += start.elapsed();
+        assert_eq!(decoded, STRINGS as usize);
+    }
+    let per_pass = total.as_secs_f64() / PASSES as f64;
+    let size = fs::metadata(path).unwrap().len();
+    println!(
+        "{:>6}: {:.1} ms per pass, {:.2} MB/s",
+        name,
+        per_pass * 1000.0,
+        size as f64 / per_pass / 1_000_000.0
+    );
+}
+
+fn main() {
+    let defs = gamedef::builtin_gamedefs();
+    let gamedef = gamedef::get_by_alias(&defs, "sg0").unwrap();
+    let path = std::env::temp_dir().join(format!("sc3tools-bench-{}.scx", std::process::id()));
+    fs::write(&path, script()).unwrap();
+
+    bench("read", &path, gamedef, |path| {
+        format::open_with(File::open(path).unwrap(), gamedef).unwrap()
+    });
+    #[cfg(feature = "mmap")]
+    bench("mapped", &path, gamedef, |path| {
+        format::open_mapped(&File::open(path).unwrap(), gamedef).unwrap()
+    });
+
+    fs::remove_file(&path).unwrap();
+}
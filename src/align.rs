@@ -0,0 +1,239 @@
+// Matching the lines of two versions of a script, so that a translation made for the old one
+// can be carried over to the new one.
+//
+// Lines that occur exactly once in both versions are matched first, keeping the longest run of
+// them that's in the same order in both. They serve as anchors: the lines in between two
+// anchors are matched in order, to an identical line if there's one, or else to the most
+// similar one by edit distance.
+
+use std::{collections::HashMap, ops::Range};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Match {
+    /// The line is identical to this line of the old version.
+    Exact(usize),
+    /// The line is closest to this line of the old version, with the given similarity
+    /// (between 0 and 1).
+    Similar(usize, f64),
+    /// The line is equally close to several lines of the old version.
+    Ambiguous(Vec<usize>),
+    /// No line of the old version is close enough.
+    None,
+}
+
+pub fn align(old: &[String], new: &[String], min_similarity: f64) -> Vec<Match> {
+    let mut matches = vec![Match::None; new.len()];
+    let mut prev = (0, 0);
+    for (i, j) in anchors(old, new) {
+        match_range(old, new, prev.0..i, prev.1..j, min_similarity, &mut matches);
+        matches[j] = Match::Exact(i);
+        prev = (i + 1, j + 1);
+    }
+    match_range(
+        old,
+        new,
+        prev.0..old.len(),
+        prev.1..new.len(),
+        min_similarity,
+        &mut matches,
+    );
+    matches
+}
+
+// Pairs of lines that occur exactly once in both versions, keeping the longest sequence of
+// them that's ordered the same way in both.
+fn anchors(old: &[String], new: &[String]) -> Vec<(usize, usize)> {
+    // For each line of the old version: how many times it occurs in the old and in the new
+    // version, and where it is in the old one.
+    let mut counts: HashMap<&str, (usize, usize, usize)> = HashMap::new();
+    for (i, line) in old.iter().enumerate() {
+        counts.entry(line).or_insert((0, 0, i)).0 += 1;
+    }
+    for line in new {
+        if let Some(count) = counts.get_mut(line.as_str()) {
+            count.1 += 1;
+        }
+    }
+    let pairs = new
+        .iter()
+        .enumerate()
+        .filter_map(|(j, line)| match counts.get(line.as_str()) {
+            Some(&(1, 1, i)) => Some((i, j)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    longest_increasing(&pairs)
+}
+
+// The longest subsequence of `pairs` (which is sorted by its second element) whose first
+// elements are increasing as well.
+fn longest_increasing(pairs: &[(usize, usize)]) -> Vec<(usize, usize)> {
+    // tails[k] is the index of the smallest tail of an increasing run of length k + 1.
+    let mut tails: Vec<usize> = Vec::new();
+    let mut prev = vec![None; pairs.len()];
+    for (k, &(i, _)) in pairs.iter().enumerate() {
+        let len = tails.partition_point(|&t| pairs[t].0 < i);
+        if len > 0 {
+            prev[k] = Some(tails[len - 1]);
+        }
+        if len == tails.len() {
+            tails.push(k);
+        } else {
+            tails[len] = k;
+        }
+    }
+    let mut run = Vec::with_capacity(tails.len());
+    let mut k = tails.last().copied();
+    while let Some(x) = k {
+        run.push(pairs[x]);
+        k = prev[x];
+    }
+    run.reverse();
+    run
+}
+
+fn match_range(
+    old: &[String],
+    new: &[String],
+    old_range: Range<usize>,
+    new_range: Range<usize>,
+    min_similarity: f64,
+    matches: &mut [Match],
+) {
+    let mut next = old_range.start;
+    for j in new_range {
+        let candidates = next..old_range.end;
+        if let Some(i) = candidates.clone().find(|&i| old[i] == new[j]) {
+            matches[j] = Match::Exact(i);
+            next = i + 1;
+            continue;
+        }
+
+        let mut best = min_similarity;
+        let mut closest: Vec<usize> = Vec::new();
+        for i in candidates {
+            let score = match similarity(&old[i], &new[j], best) {
+                Some(score) => score,
+                None => continue,
+            };
+            if score > best || closest.is_empty() {
+                best = score;
+                closest = vec![i];
+            } else if score == best && closest.iter().all(|&k| old[k] != old[i]) {
+                closest.push(i);
+            }
+        }
+        matches[j] = match closest.len() {
+            0 => Match::None,
+            1 => {
+                next = closest[0] + 1;
+                Match::Similar(closest[0], best)
+            }
+            _ => Match::Ambiguous(closest),
+        };
+    }
+}
+
+// How similar two lines are, from 0 to 1, or `None` if it's below `min`.
+fn similarity(a: &str, b: &str, min: f64) -> Option<f64> {
+    let a = a.chars().collect::<Vec<_>>();
+    let b = b.chars().collect::<Vec<_>>();
+    let len = a.len().max(b.len());
+    if len == 0 {
+        return Some(1.0);
+    }
+    let score = |distance: usize| 1.0 - distance as f64 / len as f64;
+    // The length difference is a lower bound of the distance.
+    if score(a.len().abs_diff(b.len())) < min {
+        return None;
+    }
+    let score = score(edit_distance(&a, &b));
+    if score >= min {
+        Some(score)
+    } else {
+        None
+    }
+}
+
+pub fn edit_distance(a: &[char], b: &[char]) -> usize {
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &[&str]) -> Vec<String> {
+        text.iter().map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    fn inserted_and_removed_lines() {
+        let old = lines(&["A", "B", "C", "D"]);
+        let new = lines(&["A", "X", "B", "D"]);
+        assert_eq!(
+            align(&old, &new, 0.6),
+            vec![
+                Match::Exact(0),
+                Match::None,
+                Match::Exact(1),
+                Match::Exact(3)
+            ]
+        );
+    }
+
+    #[test]
+    fn edited_lines() {
+        let old = lines(&["Hello, world.", "Second line!", "Goodbye."]);
+        let new = lines(&["Hello, world.", "Second line?", "Something else entirely"]);
+        let matches = align(&old, &new, 0.6);
+        assert_eq!(matches[0], Match::Exact(0));
+        assert!(matches!(matches[1], Match::Similar(1, x) if x > 0.9));
+        assert_eq!(matches[2], Match::None);
+    }
+
+    #[test]
+    fn repeated_lines() {
+        let old = lines(&["A", "...", "B", "...", "C"]);
+        let new = lines(&["A", "...", "C", "..."]);
+        assert_eq!(
+            align(&old, &new, 0.6),
+            vec![
+                Match::Exact(0),
+                Match::Exact(1),
+                Match::Exact(4),
+                Match::None
+            ]
+        );
+    }
+
+    #[test]
+    fn ambiguous_lines() {
+        let old = lines(&["start", "abcd", "abce", "end"]);
+        let new = lines(&["start", "abcf", "end"]);
+        assert_eq!(align(&old, &new, 0.6)[1], Match::Ambiguous(vec![1, 2]));
+    }
+
+    #[test]
+    fn edit_distances() {
+        let distance = |a: &str, b: &str| {
+            edit_distance(
+                &a.chars().collect::<Vec<_>>(),
+                &b.chars().collect::<Vec<_>>(),
+            )
+        };
+        assert_eq!(distance("", "abc"), 3);
+        assert_eq!(distance("kitten", "sitting"), 3);
+        assert_eq!(distance("岡部", "岡部倫太郎"), 3);
+    }
+}
@@ -0,0 +1,162 @@
+// A bundle holds the text of several scripts in one file. Each script starts with a
+// `## <file name>` header followed by its lines, numbered from 0:
+//
+// ## op01.scx
+// 0: [name]Okabe[line]Hm.
+// 1: ...
+
+use crate::sparse;
+use std::{error, fmt, io};
+
+const HEADER_PREFIX: &str = "## ";
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Section {
+    pub name: String,
+    pub lines: Vec<String>,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum Error {
+    MissingHeader(usize),
+    InvalidName(usize, String),
+    DuplicateName(usize, String),
+    MalformedLine(usize),
+    UnexpectedLineNumber {
+        line: usize,
+        expected: usize,
+        found: usize,
+    },
+}
+
+impl error::Error for Error {}
+
+pub fn write_section(out: &mut impl io::Write, name: &str, lines: &[String]) -> io::Result<()> {
+    writeln!(out, "{}{}", HEADER_PREFIX, name)?;
+    for (i, line) in lines.iter().enumerate() {
+        sparse::write_line(out, i, line)?;
+    }
+    Ok(())
+}
+
+pub fn parse(bundle: &str) -> Result<Vec<Section>, Error> {
+    let mut sections: Vec<Section> = Vec::new();
+    for (i, line) in bundle.lines().enumerate() {
+        if let Some(name) = line.strip_prefix(HEADER_PREFIX) {
+            let name = name.trim();
+            if name.is_empty() || name.contains(['/', '\\']) || name == "." || name == ".." {
+                return Err(Error::InvalidName(i, name.to_owned()));
+            }
+            if sections.iter().any(|x| x.name == name) {
+                return Err(Error::DuplicateName(i, name.to_owned()));
+            }
+            sections.push(Section {
+                name: name.to_owned(),
+                lines: Vec::new(),
+            });
+        } else if line.trim().is_empty() {
+            continue;
+        } else {
+            let section = sections.last_mut().ok_or(Error::MissingHeader(i))?;
+            let (number, text) = sparse::parse_line(line).ok_or(Error::MalformedLine(i))?;
+            if number != section.lines.len() {
+                return Err(Error::UnexpectedLineNumber {
+                    line: i,
+                    expected: section.lines.len(),
+                    found: number,
+                });
+            }
+            section.lines.push(text.to_owned());
+        }
+    }
+    Ok(sections)
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::MissingHeader(line) => {
+                write!(f, "line {}: expected a '## <file name>' header", line + 1)
+            }
+            Error::InvalidName(line, name) => {
+                write!(f, "line {}: invalid file name '{}'", line + 1, name)
+            }
+            Error::DuplicateName(line, name) => {
+                write!(f, "line {}: '{}' appears more than once", line + 1, name)
+            }
+            Error::MalformedLine(line) => {
+                write!(f, "line {}: expected '<number>: <text>'", line + 1)
+            }
+            Error::UnexpectedLineNumber {
+                line,
+                expected,
+                found,
+            } => write!(
+                f,
+                "line {}: expected line number {}, found {}",
+                line + 1,
+                expected,
+                found
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn section(name: &str, lines: &[&str]) -> Section {
+        Section {
+            name: name.to_owned(),
+            lines: lines.iter().map(|x| x.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn roundtrip() {
+        let sections = vec![
+            section("a.scx", &["[name]Okabe[line]Hm.", "", "1: not a number"]),
+            section("b.scx", &[]),
+            section("c.msb", &["## not a header"]),
+        ];
+        let mut buf = Vec::new();
+        for section in &sections {
+            write_section(&mut buf, &section.name, &section.lines).unwrap();
+            buf.push(b'\n');
+        }
+        let bundle = String::from_utf8(buf).unwrap();
+        assert_eq!(parse(&bundle).unwrap(), sections);
+    }
+
+    #[test]
+    fn lenient_parsing() {
+        let bundle = "\r\n## a.scx\r\n0:\r\n1: text\r\n\r\n";
+        assert_eq!(
+            parse(bundle).unwrap(),
+            vec![section("a.scx", &["", "text"])]
+        );
+    }
+
+    #[test]
+    fn invalid_bundles() {
+        assert_eq!(parse("0: text"), Err(Error::MissingHeader(0)));
+        assert_eq!(
+            parse("## ../a.scx"),
+            Err(Error::InvalidName(0, "../a.scx".to_owned()))
+        );
+        assert_eq!(
+            parse("## a.scx\n## a.scx"),
+            Err(Error::DuplicateName(1, "a.scx".to_owned()))
+        );
+        assert_eq!(parse("## a.scx\ntext"), Err(Error::MalformedLine(1)));
+        assert_eq!(
+            parse("## a.scx\n0: a\n2: b"),
+            Err(Error::UnexpectedLineNumber {
+                line: 2,
+                expected: 1,
+                found: 2
+            })
+        );
+    }
+}
@@ -0,0 +1,295 @@
+// A table of every glyph in a game's charset, so that translators can check what the tool
+// thinks the game supports before writing text that relies on it, and how two games' charsets
+// differ, to tell whether a new port can reuse the definition of a similar game. The glyphs
+// the scripts of a game use can be counted too, for deciding which ones a font can do without,
+// and so can the characters they stand for, for packing a font atlas.
+
+use crate::gamedef::GameDef;
+use crate::text::{self, Char};
+use itertools::Itertools;
+use serde::Serialize;
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+#[derive(Debug, Eq, PartialEq, Serialize)]
+pub struct Glyph {
+    pub code: u16,
+    pub char: char,
+    /// The text the glyph stands for if it's a compound character.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compound: Option<String>,
+    /// The code the encoder writes for the glyph's text instead of `code`, if any. This
+    /// happens when a character appears more than once in the charset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encoded_as: Option<u16>,
+}
+
+/// How the charset of a second game differs from that of a first one.
+#[derive(Debug, Default, Eq, PartialEq, Serialize)]
+pub struct CharsetDiff {
+    /// The characters only the first charset has, in its order.
+    pub only_in_first: Vec<char>,
+    pub only_in_second: Vec<char>,
+    /// The compound characters that stand for different text, or that only one game has.
+    pub compounds: Vec<CompoundDiff>,
+}
+
+#[derive(Debug, Eq, PartialEq, Serialize)]
+pub struct CompoundDiff {
+    pub char: char,
+    pub first: Option<String>,
+    pub second: Option<String>,
+}
+
+/// Which glyphs of a charset some text uses.
+#[derive(Debug, Default, Eq, PartialEq, Serialize)]
+pub struct Coverage {
+    /// The glyphs the text uses, the most used first.
+    pub used: Vec<GlyphCount>,
+    /// The glyphs it doesn't use, in the order of the charset.
+    pub unused: Vec<Glyph>,
+}
+
+#[derive(Debug, Eq, PartialEq, Serialize)]
+pub struct GlyphCount {
+    #[serde(flatten)]
+    pub glyph: Glyph,
+    pub count: usize,
+}
+
+#[derive(Debug, Eq, PartialEq, Serialize)]
+pub struct CharCount {
+    pub char: char,
+    pub count: usize,
+}
+
+pub fn glyphs(gamedef: &GameDef) -> Vec<Glyph> {
+    let maps = &gamedef.encoding_maps;
+    gamedef
+        .charset()
+        .iter()
+        .enumerate()
+        .map(|(i, ch)| {
+            let code = 0x8000 | i as u16;
+            let compound = gamedef.compound_chars.get(ch).cloned();
+            let encoded = match &compound {
+                Some(s) => maps.compound_code_of(s),
+                None => maps.code_of(*ch),
+            };
+            Glyph {
+                code,
+                char: *ch,
+                compound,
+                encoded_as: encoded.filter(|x| *x != code),
+            }
+        })
+        .collect()
+}
+
+pub fn diff(first: &GameDef, second: &GameDef) -> CharsetDiff {
+    let only_in = |a: &GameDef, b: &GameDef| {
+        let b = b.charset().iter().collect::<HashSet<_>>();
+        a.charset()
+            .iter()
+            .filter(|x| !b.contains(x))
+            .unique()
+            .copied()
+            .collect()
+    };
+    let mut compounds = first
+        .compound_chars
+        .keys()
+        .chain(second.compound_chars.keys())
+        .unique()
+        .filter_map(|ch| {
+            let a = first.compound_chars.get(ch);
+            let b = second.compound_chars.get(ch);
+            (a != b).then(|| CompoundDiff {
+                char: *ch,
+                first: a.cloned(),
+                second: b.cloned(),
+            })
+        })
+        .collect::<Vec<_>>();
+    compounds.sort_by_key(|x| x.char);
+    CharsetDiff {
+        only_in_first: only_in(first, second),
+        only_in_second: only_in(second, first),
+        compounds,
+    }
+}
+
+/// Splits the glyphs of the charset by whether `counts`, how many times each character code
+/// comes up in the text, has them. Codes past the end of the charset are left out.
+pub fn coverage(gamedef: &GameDef, counts: &HashMap<u16, usize>) -> Coverage {
+    let mut coverage = Coverage::default();
+    for glyph in glyphs(gamedef) {
+        match counts.get(&glyph.code) {
+            Some(&count) => coverage.used.push(GlyphCount { glyph, count }),
+            None => coverage.unused.push(glyph),
+        }
+    }
+    // The sort is stable, so glyphs that are used as often stay in the order of the charset.
+    coverage.used.sort_by_key(|x| Reverse(x.count));
+    coverage
+}
+
+/// How many times each character comes up in the text whose character codes were counted in
+/// `counts`, the most frequent first. A compound character counts as each of the characters
+/// it stands for. Codes past the end of the charset are left out.
+pub fn char_frequencies(gamedef: &GameDef, counts: &HashMap<u16, usize>) -> Vec<CharCount> {
+    let mut chars = HashMap::new();
+    for (code, count) in counts {
+        match text::decode_char(*code, gamedef.charset(), &gamedef.compound_chars) {
+            Ok(Char::Regular(ch)) => *chars.entry(ch).or_insert(0) += count,
+            Ok(Char::Compound(s)) => {
+                for ch in s.chars() {
+                    *chars.entry(ch).or_insert(0) += count;
+                }
+            }
+            Err(_) => {}
+        }
+    }
+    let mut frequencies = chars
+        .into_iter()
+        .map(|(char, count)| CharCount { char, count })
+        .collect::<Vec<_>>();
+    frequencies.sort_by_key(|x| (Reverse(x.count), x.char));
+    frequencies
+}
+
+impl CharsetDiff {
+    pub fn is_empty(&self) -> bool {
+        self.only_in_first.is_empty() && self.only_in_second.is_empty() && self.compounds.is_empty()
+    }
+}
+
+impl fmt::Display for Glyph {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ch = if self.char.is_control() {
+            ' '
+        } else {
+            self.char
+        };
+        write!(f, "{:04X}\t{}\tU+{:04X}", self.code, ch, self.char as u32)?;
+        if let Some(s) = &self.compound {
+            write!(f, "\t[{}]", s)?;
+        }
+        if let Some(code) = self.encoded_as {
+            write!(f, "\t(encoded as {:04X})", code)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gamedef;
+
+    #[test]
+    fn sg0_glyphs() {
+        let defs = gamedef::builtin_gamedefs();
+        let sg0 = gamedef::get_by_alias(&defs, "sg0").unwrap();
+        let glyphs = glyphs(sg0);
+        assert_eq!(glyphs.len(), sg0.charset().len());
+        assert!(glyphs
+            .iter()
+            .enumerate()
+            .all(|(i, x)| x.code == 0x8000 | i as u16));
+
+        let compound = glyphs
+            .iter()
+            .find(|x| x.compound.is_some() && x.encoded_as.is_none())
+            .unwrap();
+        assert_eq!(
+            compound.to_string(),
+            format!(
+                "{:04X}\t{}\tU+{:04X}\t[{}]",
+                compound.code,
+                compound.char,
+                compound.char as u32,
+                compound.compound.as_ref().unwrap()
+            )
+        );
+    }
+
+    #[test]
+    fn glyph_coverage() {
+        let defs = gamedef::builtin_gamedefs();
+        let sg0 = gamedef::get_by_alias(&defs, "sg0").unwrap();
+        let counts = [(0x8001, 2), (0x8003, 5), (0x8000, 2), (0xFFFF, 1)]
+            .iter()
+            .copied()
+            .collect();
+        let coverage = coverage(sg0, &counts);
+        let used = coverage
+            .used
+            .iter()
+            .map(|x| (x.glyph.code, x.count))
+            .collect::<Vec<_>>();
+        assert_eq!(used, vec![(0x8003, 5), (0x8000, 2), (0x8001, 2)]);
+        assert_eq!(coverage.unused.len(), sg0.charset().len() - 3);
+        let unused = coverage.unused.iter().take(2).map(|x| x.code);
+        assert_eq!(unused.collect::<Vec<_>>(), vec![0x8002, 0x8004]);
+    }
+
+    #[test]
+    fn char_frequency() {
+        let defs = gamedef::builtin_gamedefs();
+        let sg0 = gamedef::get_by_alias(&defs, "sg0").unwrap();
+        let glyphs = glyphs(sg0);
+        let code_of = |ch| glyphs.iter().find(|x| x.char == ch).unwrap().code;
+        let compound = glyphs
+            .iter()
+            .find(|x| x.compound.as_ref().is_some_and(|s| s.chars().count() > 1))
+            .unwrap();
+        let compound_text = compound.compound.as_ref().unwrap();
+        let first = compound_text.chars().next().unwrap();
+
+        let counts = [
+            (code_of('Ａ'), 1),
+            (code_of('Ｂ'), 3),
+            (compound.code, 2),
+            (0xFFFF, 7),
+        ]
+        .iter()
+        .copied()
+        .collect();
+        let frequencies = char_frequencies(sg0, &counts)
+            .into_iter()
+            .map(|x| (x.char, x.count))
+            .collect::<Vec<_>>();
+        assert_eq!(frequencies[0], ('Ｂ', 3));
+        assert_eq!(frequencies.last(), Some(&('Ａ', 1)));
+        assert!(frequencies.contains(&(first, 2)));
+        let expected = 2 + compound_text.chars().unique().count();
+        assert_eq!(frequencies.len(), expected);
+    }
+
+    #[test]
+    fn charset_diffs() {
+        let defs = gamedef::builtin_gamedefs();
+        let sg0 = gamedef::get_by_alias(&defs, "sg0").unwrap();
+        let chlcc = gamedef::get_by_alias(&defs, "chlcc").unwrap();
+        assert!(diff(sg0, sg0).is_empty());
+
+        let forward = diff(sg0, chlcc);
+        let backward = diff(chlcc, sg0);
+        assert!(!forward.is_empty());
+        assert_eq!(forward.only_in_first, backward.only_in_second);
+        assert_eq!(forward.only_in_second, backward.only_in_first);
+        assert!(forward
+            .only_in_first
+            .iter()
+            .all(|x| sg0.charset().contains(x) && !chlcc.charset().contains(x)));
+        assert_eq!(forward.compounds.len(), backward.compounds.len());
+        for (a, b) in forward.compounds.iter().zip(&backward.compounds) {
+            assert_eq!((a.char, &a.first, &a.second), (b.char, &b.second, &b.first));
+            assert_eq!(a.first.as_ref(), sg0.compound_chars.get(&a.char));
+        }
+    }
+}
@@ -5,16 +5,16 @@ use crate::text;
 use gamedef::GameDef;
 use nom::{
     branch::alt,
-    bytes::complete::{is_not, tag, take, take_while, take_while1, take_while_m_n},
+    bytes::complete::{is_not, tag, take, take_while1, take_while_m_n},
     character::complete::{char, space1},
-    combinator::{map, map_res, opt, peek, recognize, rest, verify},
+    combinator::{map, map_opt, map_res, opt, peek, recognize, rest, verify},
     multi::{many1, many_till},
     sequence::{delimited, preceded, tuple},
     IResult,
 };
 use sc3::{Sc3String, StringToken};
 use std::fmt::Write;
-use std::{borrow::Cow, error, fmt, io::Cursor};
+use std::{borrow::Cow, convert::TryFrom, error, fmt, io::Cursor};
 
 #[derive(Debug)]
 pub enum Error {
@@ -30,7 +30,16 @@ pub enum ParseError {
     IllegalAttributeValue((String, String)),
 }
 
-impl error::Error for Error {}
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Parsing(err) => Some(err),
+            Error::TextEncoding(err) => Some(err),
+            Error::Serialization(err) => Some(err),
+        }
+    }
+}
+
 impl error::Error for ParseError {}
 
 #[derive(Debug, Eq, PartialEq)]
@@ -50,10 +59,31 @@ type Attr<'a> = (&'a str, Cow<'a, str>);
 
 #[derive(Eq, PartialEq, Debug, Clone)]
 pub struct Tag<'a> {
-    name: &'a str,
+    name: Cow<'a, str>,
     attr: Option<Attr<'a>>,
 }
 
+// A byte that has no tag of its own is written as `[byte:XX]`, in hex.
+const BYTE_TAG_PREFIX: &str = "byte:";
+
+/// How the numbers of `[margin]`, `[font]` and `[hardcoded-value]` are written. Either is read
+/// back, as a `0x` prefix marks the hex ones.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum TagRadix {
+    #[default]
+    Dec,
+    Hex,
+}
+
+impl TagRadix {
+    fn format(self, val: u16) -> String {
+        match self {
+            TagRadix::Dec => val.to_string(),
+            TagRadix::Hex => format!("0x{:X}", val),
+        }
+    }
+}
+
 impl fmt::Display for StringSegment<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -75,15 +105,62 @@ impl fmt::Display for CozString<'_> {
     }
 }
 
-impl<'a> Sc3String<'_> {
+impl Sc3String<'_> {
     pub fn serialize(
         &self,
         gamedef: &GameDef,
         keep_fullwidth_chars: bool,
-    ) -> Result<CozString, Error> {
+    ) -> Result<CozString<'_>, Error> {
+        Self::serialize_tokens(
+            self.iter(gamedef.dialect()),
+            gamedef,
+            keep_fullwidth_chars,
+            TagRadix::Dec,
+        )
+    }
+
+    /// Serializes bytes that can't be decoded as `[byte:XX]` tags instead of failing.
+    pub fn serialize_lenient(
+        &self,
+        gamedef: &GameDef,
+        keep_fullwidth_chars: bool,
+    ) -> Result<CozString<'_>, Error> {
+        Self::serialize_tokens(
+            self.iter_lenient(gamedef.dialect()),
+            gamedef,
+            keep_fullwidth_chars,
+            TagRadix::Dec,
+        )
+    }
+
+    /// Like `serialize`, or `serialize_lenient` if `lenient` is set, with the numbers of tags
+    /// written in `radix`.
+    pub fn serialize_with_radix(
+        &self,
+        gamedef: &GameDef,
+        keep_fullwidth_chars: bool,
+        lenient: bool,
+        radix: TagRadix,
+    ) -> Result<CozString<'_>, Error> {
+        let tokens = if lenient {
+            self.iter_lenient(gamedef.dialect())
+        } else {
+            self.iter(gamedef.dialect())
+        };
+        Self::serialize_tokens(tokens, gamedef, keep_fullwidth_chars, radix)
+    }
+
+    /// Serializes the tokens of a string, as `iter` or `iter_lenient` decode them, with the
+    /// numbers of tags written in `radix`.
+    pub fn serialize_tokens(
+        tokens: sc3::Sc3StringIter,
+        gamedef: &GameDef,
+        keep_fullwidth_chars: bool,
+        radix: TagRadix,
+    ) -> Result<CozString<'static>, Error> {
         let mut buf = String::new();
-        for tk in self.iter() {
-            let seg = tk?.serialize(&gamedef, keep_fullwidth_chars)?;
+        for tk in tokens {
+            let seg = tk?.serialize_with_radix(gamedef, keep_fullwidth_chars, radix)?;
             write!(buf, "{}", seg).unwrap();
         }
 
@@ -91,23 +168,50 @@ impl<'a> Sc3String<'_> {
     }
 
     pub fn deserialize(
-        s: &'a CozString,
+        s: &CozString,
         gamedef: &GameDef,
         convert_to_fullwidth: bool,
-    ) -> Result<Sc3String<'a>, Error> {
+    ) -> Result<Sc3String<'static>, Error> {
+        Self::deserialize_with_policy(
+            s,
+            gamedef,
+            convert_to_fullwidth,
+            text::MissingCharPolicy::Fail,
+        )
+    }
+
+    /// Like `deserialize`, with the characters that aren't in the charset dealt with by
+    /// `policy`. `text::missing_chars` lists the ones that are skipped or replaced.
+    pub fn deserialize_with_policy(
+        s: &CozString,
+        gamedef: &GameDef,
+        convert_to_fullwidth: bool,
+        policy: text::MissingCharPolicy,
+    ) -> Result<Sc3String<'static>, Error> {
         let mut buf = Cursor::new(Vec::new());
         for seg in s.iter() {
-            let tk = StringToken::deserialize(&seg, &gamedef, convert_to_fullwidth)?;
-            tk.encode(&mut buf).unwrap();
+            let tk = match &seg {
+                StringSegment::Text(s) => {
+                    let encoded =
+                        text::encode_str_with_policy(s, gamedef, convert_to_fullwidth, policy)?;
+                    StringToken::Text(encoded.into())
+                }
+                seg => StringToken::deserialize(seg, gamedef, convert_to_fullwidth)?,
+            };
+            tk.encode(&mut buf, gamedef.code_unit).unwrap();
         }
 
-        StringToken::Terminator.encode(&mut buf).unwrap();
+        if gamedef.terminated {
+            StringToken::Terminator
+                .encode(&mut buf, gamedef.code_unit)
+                .unwrap();
+        }
         Ok(Sc3String(buf.into_inner().into()))
     }
 }
 
 impl<'a> CozString<'a> {
-    pub fn iter(&self) -> CozStringIter {
+    pub fn iter(&self) -> CozStringIter<'_> {
         CozStringIter { remaining: &self.0 }
     }
 }
@@ -119,7 +223,7 @@ impl<'a> Iterator for CozStringIter<'a> {
         if self.remaining.is_empty() {
             None
         } else {
-            let (rem, seg) = StringSegment::parse(&self.remaining);
+            let (rem, seg) = StringSegment::parse(self.remaining);
             self.remaining = rem;
             Some(seg)
         }
@@ -127,22 +231,33 @@ impl<'a> Iterator for CozStringIter<'a> {
 }
 
 impl<'a> Tag<'a> {
-    pub fn new(name: &'a str, attr: Option<Attr<'a>>) -> Self {
-        Self { name, attr }
+    pub fn new(name: impl Into<Cow<'a, str>>, attr: Option<Attr<'a>>) -> Self {
+        Self {
+            name: name.into(),
+            attr,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn attribute(&self) -> Option<(&str, &str)> {
+        self.attr.as_ref().map(|(k, v)| (*k, v.as_ref()))
     }
 
     pub fn is_known_tag(s: &'a str) -> bool {
-        Self::parse(&s)
+        Self::parse(s)
             .ok()
             .map(|(_, tag)| tag.is_known())
             .unwrap_or_default()
     }
 
     pub fn is_known(&self) -> bool {
-        matches!(StringToken::from_tag(&self), Ok(Some(_)) | Err(_))
+        matches!(StringToken::from_tag(self), Ok(Some(_)) | Err(_))
     }
 
-    pub fn parse(input: &'a str) -> IResult<&str, Self> {
+    pub fn parse(input: &'a str) -> IResult<&'a str, Self> {
         delimited(
             char('['),
             map(
@@ -166,9 +281,9 @@ impl<'a> Tag<'a> {
 }
 
 impl<'a> StringSegment<'a> {
-    pub fn parse(input: &'a str) -> (&str, Self) {
+    pub fn parse(input: &'a str) -> (&'a str, Self) {
         alt((
-            map(Self::tag, |tag| StringSegment::Tag(tag)),
+            map(Self::tag, StringSegment::Tag),
             map(Self::text, |s| {
                 StringSegment::Text(text::Text(Cow::from(s)))
             }),
@@ -176,13 +291,14 @@ impl<'a> StringSegment<'a> {
         .expect("StringSegment::parse() should never fail.")
     }
 
-    fn tag(i: &str) -> IResult<&str, Tag> {
+    fn tag(i: &str) -> IResult<&str, Tag<'_>> {
         verify(Tag::parse, Tag::is_known)(i)
     }
 
+    // An escaped `\[` is part of the text, not the start of a tag.
     fn text(i: &str) -> IResult<&str, &str> {
         recognize(many_till(
-            take(1usize),
+            alt((recognize(text::escaped_char), take(1usize))),
             verify(peek(rest), |s: &str| Tag::is_known_tag(s) || s.is_empty()),
         ))(i)
     }
@@ -193,10 +309,26 @@ impl<'a> StringToken<'a> {
         self,
         gamedef: &GameDef,
         keep_fullwidth_chars: bool,
-    ) -> Result<StringSegment, text::EncodingError> {
-        if let StringToken::Text(encoded_text) = self {
-            let s = text::decode_str(&encoded_text, &gamedef, keep_fullwidth_chars)?;
-            return Ok(StringSegment::Text(s.into()));
+    ) -> Result<StringSegment<'_>, text::EncodingError> {
+        self.serialize_with_radix(gamedef, keep_fullwidth_chars, TagRadix::Dec)
+    }
+
+    pub fn serialize_with_radix(
+        self,
+        gamedef: &GameDef,
+        keep_fullwidth_chars: bool,
+        radix: TagRadix,
+    ) -> Result<StringSegment<'_>, text::EncodingError> {
+        match self {
+            StringToken::Text(encoded_text) => {
+                let s = text::decode_str(&encoded_text, gamedef, keep_fullwidth_chars)?;
+                return Ok(StringSegment::Text(s));
+            }
+            StringToken::Unknown(b) => {
+                let name = format!("{}{}", BYTE_TAG_PREFIX, hex::encode_upper([b]));
+                return Ok(StringSegment::Tag(Tag::new(name, None)));
+            }
+            _ => {}
         }
 
         let (name, attr) = match self {
@@ -205,32 +337,41 @@ impl<'a> StringToken<'a> {
             StringToken::LineStart => ("line", None),
             StringToken::Present(action) => (
                 match action {
-                    sc3::PresentAction::None => "%p",
-                    sc3::PresentAction::ResetAlignment => "%e",
-                    sc3::PresentAction::Unknown_0x05 => "%05",
-                    sc3::PresentAction::Unknown_0x18 => "%18",
+                    sc3::PresentAction::None => "present",
+                    sc3::PresentAction::ResetAlignment => "present-reset",
+                    sc3::PresentAction::Unknown_0x05 => "present-05",
+                    sc3::PresentAction::Unknown_0x18 => "present-18",
                 },
                 None,
             ),
+            StringToken::PresentVoice(expr) => {
+                ("present", Some(("voice", hex::encode_upper(&expr.0))))
+            }
             StringToken::RubyBaseStart => ("ruby-base", None),
             StringToken::RubyTextStart => ("ruby-text-start", None),
             StringToken::RubyTextEnd => ("ruby-text-end", None),
             StringToken::RubyCenterPerChar => ("ruby-center-per-char", None),
             StringToken::Parallel => ("parallel", None),
             StringToken::Center => ("center", None),
-            StringToken::MarginLeft(val) => ("margin", Some(("left", val.to_string()))),
-            StringToken::MarginTop(val) => ("margin", Some(("top", val.to_string()))),
+            StringToken::MarginLeft(val) => ("margin", Some(("left", radix.format(val)))),
+            StringToken::MarginTop(val) => ("margin", Some(("top", radix.format(val)))),
             StringToken::Terminator => ("", None),
-            StringToken::Color(expr) => ("color", Some(("index", hex::encode_upper(&expr.0)))),
-            StringToken::FontSize(val) => ("font", Some(("size", val.to_string()))),
+            StringToken::Color(expr) => match expr.as_const().and_then(|v| gamedef.color_name(v)) {
+                Some(name) => ("color", Some(("name", name.to_string()))),
+                None => ("color", Some(("index", hex::encode_upper(&expr.0)))),
+            },
+            StringToken::FontSize(val) => ("font", Some(("size", radix.format(val)))),
             StringToken::HardcodedValue(val) => {
-                ("hardcoded-value", Some(("index", val.to_string())))
+                ("hardcoded-value", Some(("index", radix.format(val))))
             }
-            StringToken::Eval(expr) => ("evaluate", Some(("expr", hex::encode_upper(&expr.0)))),
+            StringToken::Eval(expr) => match expr.as_const() {
+                Some(value) => ("evaluate", Some(("value", value.to_string()))),
+                None => ("evaluate", Some(("expr", hex::encode_upper(&expr.0)))),
+            },
             StringToken::AutoForward => ("auto-forward", None),
             StringToken::AutoForward_1A => ("auto-forward-1a", None),
             StringToken::AltLineBreak => ("alt-linebreak", None),
-            StringToken::Text(_) => unreachable!(),
+            StringToken::Text(_) | StringToken::Unknown(_) => unreachable!(),
         };
         Ok(StringSegment::Tag(Tag::new(
             name,
@@ -247,20 +388,49 @@ impl<'a> StringToken<'a> {
             StringSegment::Text(s) => text::encode_str(s, gamedef, convert_to_fullwidth)
                 .map(|x| StringToken::Text(x.into()))
                 .map_err(Into::into),
-            StringSegment::Tag(tag) => Self::from_tag(&tag).map(Option::unwrap).map_err(Into::into),
+            StringSegment::Tag(Tag {
+                name: tag_name,
+                attr: Some(("name", name)),
+            }) if tag_name == "color" => gamedef
+                .color_by_name(name)
+                .and_then(sc3::Expr::from_const)
+                .map(StringToken::Color)
+                .ok_or_else(|| {
+                    ParseError::IllegalAttributeValue(("name".to_string(), name.to_string()))
+                        .into()
+                }),
+            StringSegment::Tag(tag) => {
+                let token = Self::from_tag(tag)?.unwrap();
+                // The game reads an expression after every `[present]` or after none of them.
+                match token {
+                    StringToken::Present(sc3::PresentAction::None) if gamedef.present_voice => {
+                        Err(ParseError::MissingAttribute("voice".to_string()).into())
+                    }
+                    StringToken::PresentVoice(_) if !gamedef.present_voice => {
+                        Err(ParseError::UnexpectedAttribute("voice".to_string()).into())
+                    }
+                    token => Ok(token),
+                }
+            }
         }
     }
 
     pub fn from_tag<'t: 'a>(tag: &Tag<'t>) -> Result<Option<Self>, ParseError> {
-        let res = match tag.name {
+        let res = match tag.name.as_ref() {
             "linebreak" => Ok(StringToken::LineBreak),
             "alt-linebreak" => Ok(StringToken::AltLineBreak),
             "name" => Ok(StringToken::NameStart),
             "line" => Ok(StringToken::LineStart),
-            "%p" => Ok(StringToken::Present(sc3::PresentAction::None)),
-            "%e" => Ok(StringToken::Present(sc3::PresentAction::ResetAlignment)),
-            "%05" => Ok(StringToken::Present(sc3::PresentAction::Unknown_0x05)),
-            "%18" => Ok(StringToken::Present(sc3::PresentAction::Unknown_0x18)),
+            "present" | "%p" => match tag.attr.as_ref().map(|x| x.0) {
+                Some("voice") => {
+                    Self::expr_attr(tag.attr.as_ref(), "voice").map(StringToken::PresentVoice)
+                }
+                Some(name) => Err(ParseError::UnexpectedAttribute(name.to_string())),
+                None => Ok(StringToken::Present(sc3::PresentAction::None)),
+            },
+            "present-reset" | "%e" => Ok(StringToken::Present(sc3::PresentAction::ResetAlignment)),
+            "present-05" | "%05" => Ok(StringToken::Present(sc3::PresentAction::Unknown_0x05)),
+            "present-18" | "%18" => Ok(StringToken::Present(sc3::PresentAction::Unknown_0x18)),
             "color" => Self::expr_attr(tag.attr.as_ref(), "index").map(StringToken::Color),
             "ruby-base" | "rubybase" => Ok(StringToken::RubyBaseStart),
             "ruby-text-start" | "rubytextstart" => Ok(StringToken::RubyTextStart),
@@ -286,12 +456,27 @@ impl<'a> StringToken<'a> {
             "hardcoded-value" | "hardcodedvalue" => {
                 Self::u16_attr(tag.attr.as_ref(), "index").map(StringToken::HardcodedValue)
             }
-            "auto-forward" | "autoforward" => Ok(StringToken::AutoForward),
-            "auto-forward-1a" => Ok(StringToken::AutoForward_1A),
-            "evaluate" => Self::expr_attr(tag.attr.as_ref(), "expr").map(StringToken::Eval),
-            _ => return Ok(None),
+            "auto-forward" | "autoforward" | "auto" => Ok(StringToken::AutoForward),
+            "auto-forward-1a" | "auto2" => Ok(StringToken::AutoForward_1A),
+            "evaluate" => match tag.attr.as_ref().map(|x| x.0) {
+                Some("value") => {
+                    Self::const_attr(tag.attr.as_ref(), "value").map(StringToken::Eval)
+                }
+                _ => Self::expr_attr(tag.attr.as_ref(), "expr").map(StringToken::Eval),
+            },
+            name => match name.strip_prefix(BYTE_TAG_PREFIX) {
+                Some(_) if tag.attr.is_some() => Err(ParseError::UnexpectedAttribute(
+                    tag.attr.as_ref().unwrap().0.to_string(),
+                )),
+                Some(hex) => Self::hex_byte(hex)
+                    .map(|(_, b)| StringToken::Unknown(b))
+                    .map_err(|_| {
+                        ParseError::IllegalAttributeValue(("byte".to_string(), hex.to_string()))
+                    }),
+                None => return Ok(None),
+            },
         };
-        res.map(|x| Some(x))
+        res.map(Some)
     }
 
     fn get_attr<'val, T: 'val>(
@@ -310,16 +495,51 @@ impl<'a> StringToken<'a> {
         Ok(e)
     }
 
+    // A decimal or `0x`-prefixed hexadecimal integer.
     fn u16_attr(attr: Option<&Attr<'a>>, name: &'a str) -> Result<u16, ParseError> {
         fn u16_literal(value: &str) -> IResult<&str, u16> {
-            map_res(take_while(|c: char| c.is_digit(10)), |s| {
-                u16::from_str_radix(s, 10)
+            map_opt(rest, |s: &str| match s.strip_prefix("0x") {
+                Some(hex) => u16::from_str_radix(hex, 16).ok(),
+                None => s.parse::<u16>().ok(),
             })(value)
         }
 
         Self::get_attr(attr, name, u16_literal)
     }
 
+    // Exactly two hex digits.
+    fn hex_byte(value: &str) -> IResult<&str, u8> {
+        map_res(
+            verify(rest, |s: &str| {
+                s.len() == 2 && s.chars().all(|c| c.is_ascii_hexdigit())
+            }),
+            |hex| u8::from_str_radix(hex, 16),
+        )(value)
+    }
+
+    // A decimal or `0x`-prefixed hexadecimal integer, stored as a constant expression.
+    fn const_attr<'expr>(
+        attr: Option<&Attr<'a>>,
+        name: &'a str,
+    ) -> Result<sc3::Expr<'expr>, ParseError> {
+        fn constant<'expr>(value: &str) -> IResult<&str, sc3::Expr<'expr>> {
+            map_opt(rest, |s: &str| {
+                let (negative, digits) = match s.strip_prefix('-') {
+                    Some(digits) => (true, digits),
+                    None => (false, s),
+                };
+                let magnitude = match digits.strip_prefix("0x") {
+                    Some(hex) => i64::from_str_radix(hex, 16).ok()?,
+                    None => digits.parse::<i64>().ok()?,
+                };
+                let value = if negative { -magnitude } else { magnitude };
+                sc3::Expr::from_const(i32::try_from(value).ok()?)
+            })(value)
+        }
+
+        Self::get_attr(attr, name, constant)
+    }
+
     fn expr_attr<'expr>(
         attr: Option<&Attr<'a>>,
         name: &'a str,
@@ -330,7 +550,7 @@ impl<'a> StringToken<'a> {
 
         fn hex_string(value: &str) -> IResult<&str, Vec<u8>> {
             many1(map_res(
-                take_while_m_n(2, 2, |c: char| c.is_digit(16)),
+                take_while_m_n(2, 2, |c: char| c.is_ascii_hexdigit()),
                 |hex| u8::from_str_radix(hex, 16),
             ))(value)
         }
@@ -382,21 +602,7 @@ impl fmt::Display for Error {
 #[cfg(test)]
 mod tests {
     use super::*;
-
-    static SG0_DEF_JSON: &str = r#"
-    [{
-        "name": "Steins;Gate 0",
-        "resource_dir": "sg0",
-        "aliases": ["sg0", "steinsgate0"],
-        "reserved_codepoints": {
-        "start": "\uE12F",
-        "end": "\uE2AF"
-        },
-        "fullwidth_blocklist": ["'", "-", "[", "]", "(", ")"]
-    }]"#;
-
-    static DEFS: std::sync::LazyLock<Vec<gamedef::GameDef>> = std::sync::LazyLock::new(|| gamedef::build_gamedefs_from_json(SG0_DEF_JSON));
-
+    use crate::gamedef::test_defs::{DEFS, SG0_DEF_JSON};
 
     #[test]
     fn token_serialization_roundtrip() {
@@ -404,30 +610,302 @@ mod tests {
         let gamedef: &GameDef = gamedef::get_by_alias(&DEFS, "sg0").unwrap();
         let text = StringSegment::parse("sample text").1;
         assert_eq!(
-            StringToken::deserialize(&text, &gamedef, false)
-                .map(|tk| tk.serialize(&gamedef, false).unwrap())
+            StringToken::deserialize(&text, gamedef, false)
+                .map(|tk| tk.serialize(gamedef, false).unwrap())
                 .unwrap(),
             text
         );
 
+        // Unknown tags are plain text, so their bracket comes back escaped.
         let text = StringSegment::parse("[meow]").1;
         assert_eq!(
-            StringToken::deserialize(&text, &gamedef, false)
-                .map(|tk| tk.serialize(&gamedef, false).unwrap())
+            StringToken::deserialize(&text, gamedef, false)
+                .map(|tk| tk.serialize(gamedef, false).unwrap())
                 .unwrap(),
-            text
+            StringSegment::parse("\\[meow]").1
+        );
+    }
+
+    #[test]
+    fn escaped_brackets_roundtrip() -> Result<(), Box<dyn error::Error>> {
+        let gamedef = gamedef::get_by_alias(&DEFS, "sg0").unwrap();
+        let src = CozString(Cow::from(
+            "[name]LuLu[line]Press \\[line] or \\\\[linebreak]Hi\\[name]",
+        ));
+        let segments = src.iter().collect::<Vec<_>>();
+        assert_eq!(segments.len(), 6);
+        assert_eq!(
+            segments[3],
+            StringSegment::Text(text::Text(Cow::from("Press \\[line] or \\\\")))
         );
+        let sc3 = Sc3String::deserialize(&src, gamedef, false)?;
+        assert_eq!(src, sc3.serialize(gamedef, false)?);
+        Ok(())
     }
 
     #[test]
     fn serialization_roundtrip() -> Result<(), Box<dyn error::Error>> {
         let gamedef = gamedef::get_by_alias(&DEFS, "sg0").unwrap();
         let src = CozString(Cow::from("[name]LuLu[line]Hi I am LuLu"));
-        let sc3 = Sc3String::deserialize(&src, &gamedef, false)?;
-        assert_eq!(src, sc3.serialize(&gamedef, false)?);
+        let sc3 = Sc3String::deserialize(&src, gamedef, false)?;
+        assert_eq!(src, sc3.serialize(gamedef, false)?);
+        Ok(())
+    }
+
+    #[test]
+    fn unterminated_strings() -> Result<(), Box<dyn error::Error>> {
+        let defs = gamedef::build_gamedefs_from_json(
+            &SG0_DEF_JSON.replace("\"aliases\"", "\"terminated\": false, \"aliases\""),
+        );
+        let gamedef = gamedef::get_by_alias(&defs, "sg0").unwrap();
+        assert!(!gamedef.terminated);
+        let src = CozString(Cow::from("[name]LuLu[line]Hi"));
+        let sc3 = Sc3String::deserialize(&src, gamedef, false)?;
+        assert_ne!(sc3.0.last(), Some(&0xFF));
+        assert_eq!(src, sc3.serialize(gamedef, false)?);
         Ok(())
     }
 
+    #[test]
+    fn present_roundtrip() {
+        let gamedef = gamedef::get_by_alias(&DEFS, "sg0").unwrap();
+        let actions = [
+            (sc3::PresentAction::None, "[present]", "[%p]"),
+            (
+                sc3::PresentAction::ResetAlignment,
+                "[present-reset]",
+                "[%e]",
+            ),
+            (sc3::PresentAction::Unknown_0x05, "[present-05]", "[%05]"),
+            (sc3::PresentAction::Unknown_0x18, "[present-18]", "[%18]"),
+        ];
+        for (action, tag, legacy_tag) in actions.iter() {
+            let token = StringToken::Present(action.clone());
+            let seg = token.clone().serialize(gamedef, false).unwrap();
+            assert_eq!(seg.to_string(), *tag);
+            assert_eq!(
+                StringToken::deserialize(&seg, gamedef, false).unwrap(),
+                token
+            );
+
+            let legacy = StringSegment::parse(legacy_tag).1;
+            assert_eq!(
+                StringToken::deserialize(&legacy, gamedef, false).unwrap(),
+                token
+            );
+        }
+
+        let reset = StringSegment::parse("[present-reset]").1;
+        assert_ne!(
+            StringToken::deserialize(&reset, gamedef, false).unwrap(),
+            StringToken::Present(sc3::PresentAction::None)
+        );
+    }
+
+    #[test]
+    fn line_break_kinds_roundtrip() {
+        let gamedef = gamedef::get_by_alias(&DEFS, "sg0").unwrap();
+        let src = CozString(Cow::from("One[linebreak]Two[alt-linebreak]Three"));
+        let sc3 = Sc3String::deserialize(&src, gamedef, false).unwrap();
+        let tokens = sc3
+            .iter(gamedef.dialect())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(tokens[1], StringToken::LineBreak);
+        assert_eq!(tokens[3], StringToken::AltLineBreak);
+        assert_eq!(sc3.serialize(gamedef, false).unwrap(), src);
+
+        // Swapping them changes the bytes.
+        let swapped = CozString(Cow::from("One[alt-linebreak]Two[linebreak]Three"));
+        assert_ne!(
+            Sc3String::deserialize(&swapped, gamedef, false).unwrap(),
+            sc3
+        );
+    }
+
+    #[test]
+    fn auto_forward_kinds_roundtrip() {
+        let gamedef = gamedef::get_by_alias(&DEFS, "sg0").unwrap();
+        let src = CozString(Cow::from("One[auto-forward]Two[auto-forward-1a]"));
+        let sc3 = Sc3String::deserialize(&src, gamedef, false).unwrap();
+        assert_eq!(sc3.0[6], 0x19);
+        assert_eq!(sc3.0[13], 0x1A);
+        assert_eq!(sc3.serialize(gamedef, false).unwrap(), src);
+
+        // The short tags are read as the same tokens, and written back as the long ones.
+        let short = CozString(Cow::from("One[auto]Two[auto2]"));
+        assert_eq!(Sc3String::deserialize(&short, gamedef, false).unwrap(), sc3);
+
+        let swapped = CozString(Cow::from("One[auto-forward-1a]Two[auto-forward]"));
+        assert_ne!(
+            Sc3String::deserialize(&swapped, gamedef, false).unwrap(),
+            sc3
+        );
+    }
+
+    #[test]
+    fn present_voice_roundtrip() {
+        let mut gamedef = gamedef::build_gamedefs_from_json(SG0_DEF_JSON).remove(0);
+        gamedef.present_voice = true;
+        let voice = sc3::Expr::from_const(42).unwrap();
+        let mut bytes = Vec::new();
+        StringToken::Text(Cow::from(&[0x8010u16][..]))
+            .encode(&mut bytes, gamedef.code_unit)
+            .unwrap();
+        StringToken::PresentVoice(voice.clone())
+            .encode(&mut bytes, gamedef.code_unit)
+            .unwrap();
+        bytes.extend_from_slice(&[0x80, 0x11, 0xFF]);
+        let original = Sc3String(bytes.into());
+
+        let tokens = original
+            .iter(gamedef.dialect())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(tokens[1], StringToken::PresentVoice(voice));
+        assert!(matches!(tokens[2], StringToken::Text(_)));
+        assert_eq!(tokens.len(), 3);
+
+        let serialized = original.serialize(&gamedef, false).unwrap();
+        assert!(serialized.0.contains("[present voice=\"A02A0000\"]"));
+        assert_eq!(
+            Sc3String::deserialize(&serialized, &gamedef, false).unwrap(),
+            original
+        );
+
+        // The voice can't be left out, or added in games that don't read it.
+        let bare = CozString(Cow::from("[present]"));
+        assert!(Sc3String::deserialize(&bare, &gamedef, false).is_err());
+        let sg0 = gamedef::get_by_alias(&DEFS, "sg0").unwrap();
+        assert!(Sc3String::deserialize(&serialized, sg0, false).is_err());
+    }
+
+    #[test]
+    fn unknown_token_roundtrip() {
+        let gamedef = gamedef::get_by_alias(&DEFS, "sg0").unwrap();
+        let src = CozString(Cow::from("[name]LuLu[byte:06][line]Hi"));
+        let sc3 = Sc3String::deserialize(&src, gamedef, false).unwrap();
+        assert!(sc3.serialize(gamedef, false).is_err());
+        assert_eq!(sc3.serialize_lenient(gamedef, false).unwrap(), src);
+
+        // `[unknown]` isn't a tag, so it's read as a compound character the game doesn't have.
+        let old = CozString(Cow::from("[name]LuLu[unknown op=\"06\"][line]Hi"));
+        assert!(Sc3String::deserialize(&old, gamedef, false).is_err());
+        let lower = CozString(Cow::from("[name]LuLu[byte:1b][line]Hi"));
+        let sc3 = Sc3String::deserialize(&lower, gamedef, false).unwrap();
+        let upper = sc3.serialize_lenient(gamedef, false).unwrap();
+        assert_eq!(upper.0, "[name]LuLu[byte:1B][line]Hi");
+
+        for s in ["[byte:6]", "[byte:]", "[byte:XY]", "[byte:06 op=\"06\"]"] {
+            test_error(s, |res| matches!(res, Err(Error::Parsing(_))));
+        }
+    }
+
+    #[test]
+    fn eval_const_roundtrip() {
+        let gamedef = gamedef::get_by_alias(&DEFS, "sg0").unwrap();
+        for (value, tag) in [
+            (0x12345, "[evaluate value=\"74565\"]"),
+            (-3, "[evaluate value=\"-3\"]"),
+        ] {
+            let eval = StringToken::Eval(sc3::Expr::from_const(value).unwrap());
+            let seg = eval.clone().serialize(gamedef, false).unwrap();
+            assert_eq!(seg.to_string(), tag);
+            assert_eq!(
+                StringToken::deserialize(&seg, gamedef, false).unwrap(),
+                eval
+            );
+        }
+
+        let hex = StringSegment::parse("[evaluate value=\"0x12345\"]").1;
+        assert_eq!(
+            StringToken::deserialize(&hex, gamedef, false).unwrap(),
+            StringToken::Eval(sc3::Expr::from_const(0x12345).unwrap())
+        );
+
+        // Anything but a single constant keeps the raw form.
+        let expr = StringToken::Eval(sc3::Expr(vec![0x29, 0x0A, 0x80, 0x00, 0x00].into()));
+        let seg = expr.clone().serialize(gamedef, false).unwrap();
+        assert_eq!(seg.to_string(), "[evaluate expr=\"290A800000\"]");
+        assert_eq!(
+            StringToken::deserialize(&seg, gamedef, false).unwrap(),
+            expr
+        );
+
+        test_error("[evaluate value=\"0x10000000\"]", |res| {
+            matches!(
+                res,
+                Err(Error::Parsing(ParseError::IllegalAttributeValue(_)))
+            )
+        });
+    }
+
+    #[test]
+    fn tag_radix_roundtrip() {
+        let gamedef = gamedef::get_by_alias(&DEFS, "sg0").unwrap();
+        let dec = StringSegment::parse("[font size=\"24\"]").1;
+        let hex = StringSegment::parse("[font size=\"0x18\"]").1;
+        let size = StringToken::FontSize(24);
+        assert_eq!(
+            StringToken::deserialize(&dec, gamedef, false).unwrap(),
+            size
+        );
+        assert_eq!(
+            StringToken::deserialize(&hex, gamedef, false).unwrap(),
+            size
+        );
+
+        assert_eq!(size.clone().serialize(gamedef, false).unwrap(), dec);
+        assert_eq!(
+            size.serialize_with_radix(gamedef, false, TagRadix::Hex)
+                .unwrap(),
+            hex
+        );
+        let index = StringToken::HardcodedValue(0xBEEF)
+            .serialize_with_radix(gamedef, false, TagRadix::Hex)
+            .unwrap();
+        assert_eq!(index.to_string(), "[hardcoded-value index=\"0xBEEF\"]");
+
+        test_error("[font size=\"0x10000\"]", |res| {
+            matches!(
+                res,
+                Err(Error::Parsing(ParseError::IllegalAttributeValue(_)))
+            )
+        });
+    }
+
+    #[test]
+    fn color_name_roundtrip() {
+        let gamedef = gamedef::get_by_alias(&DEFS, "sg0").unwrap();
+        let red = StringToken::Color(sc3::Expr::from_const(3).unwrap());
+        let seg = red.clone().serialize(gamedef, false).unwrap();
+        assert_eq!(seg.to_string(), "[color name=\"red\"]");
+        assert_eq!(StringToken::deserialize(&seg, gamedef, false).unwrap(), red);
+
+        let by_index = StringSegment::parse("[color index=\"8C0000\"]").1;
+        let blue = StringSegment::parse("[color name=\"blue\"]").1;
+        assert_eq!(
+            StringToken::deserialize(&by_index, gamedef, false).unwrap(),
+            StringToken::deserialize(&blue, gamedef, false).unwrap()
+        );
+    }
+
+    #[test]
+    fn unnamed_color_serializes_as_index() {
+        let gamedef = gamedef::get_by_alias(&DEFS, "sg0").unwrap();
+        let seg = StringToken::Color(sc3::Expr::from_const(7).unwrap())
+            .serialize(gamedef, false)
+            .unwrap();
+        assert_eq!(seg.to_string(), "[color index=\"870000\"]");
+
+        test_error("[color name=\"teal\"]", |res| {
+            matches!(
+                res,
+                Err(Error::Parsing(ParseError::IllegalAttributeValue(_)))
+            )
+        });
+    }
+
     #[test]
     fn test_parse_errors() {
         test_error("[evaluate expr=\"meow\"]", |res| {
@@ -439,14 +917,14 @@ mod tests {
         });
         test_error("[color]", |res| {
             if let Err(Error::Parsing(ParseError::MissingAttribute(name))) = res {
-                name == "index".to_string()
+                name == "index"
             } else {
                 false
             }
         });
         test_error("[margin cat=\"LuLu\"]", |res| {
             if let Err(Error::Parsing(ParseError::UnexpectedAttribute(name))) = res {
-                name == "cat".to_string()
+                name == "cat"
             } else {
                 false
             }
@@ -455,8 +933,8 @@ mod tests {
 
     fn test_error(text: &str, f: impl FnOnce(Result<StringToken, Error>) -> bool) {
         let gamedef = gamedef::get_by_alias(&DEFS, "sg0").unwrap();
-        let seg = StringSegment::parse(&text).1;
-        let res = StringToken::deserialize(&seg, &gamedef, false);
+        let seg = StringSegment::parse(text).1;
+        let res = StringToken::deserialize(&seg, gamedef, false);
         assert!(f(res));
     }
 
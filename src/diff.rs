@@ -0,0 +1,102 @@
+// A character diff of two versions of a line, for txt-diff. The parts only in the old version
+// are marked as `{-...-}` and the parts only in the new one as `{+...+}`, the way `git diff
+// --word-diff` does, so that both versions can be printed on a single line.
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum Edit {
+    Same(String),
+    Removed(String),
+    Added(String),
+}
+
+/// The edits that turn `old` into `new`, with as many characters as possible left unchanged.
+pub fn diff_chars(old: &str, new: &str) -> Vec<Edit> {
+    let a = old.chars().collect::<Vec<_>>();
+    let b = new.chars().collect::<Vec<_>>();
+    // lcs[i][j] is the length of the longest common subsequence of a[i..] and b[j..].
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut edits = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            push(&mut edits, Edit::Same(a[i].to_string()));
+            i += 1;
+            j += 1;
+        } else if j == b.len() || (i < a.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            push(&mut edits, Edit::Removed(a[i].to_string()));
+            i += 1;
+        } else {
+            push(&mut edits, Edit::Added(b[j].to_string()));
+            j += 1;
+        }
+    }
+    edits
+}
+
+// Adds to the last edit if it's of the same kind.
+fn push(edits: &mut Vec<Edit>, edit: Edit) {
+    let merged = match (edits.last_mut(), &edit) {
+        (Some(Edit::Same(s)), Edit::Same(c))
+        | (Some(Edit::Removed(s)), Edit::Removed(c))
+        | (Some(Edit::Added(s)), Edit::Added(c)) => {
+            s.push_str(c);
+            true
+        }
+        _ => false,
+    };
+    if !merged {
+        edits.push(edit);
+    }
+}
+
+/// The old and the new version of a line, with the parts that changed marked.
+pub fn mark(old: &str, new: &str) -> (String, String) {
+    let (mut marked_old, mut marked_new) = (String::new(), String::new());
+    for edit in diff_chars(old, new) {
+        match edit {
+            Edit::Same(s) => {
+                marked_old.push_str(&s);
+                marked_new.push_str(&s);
+            }
+            Edit::Removed(s) => marked_old.push_str(&format!("{{-{}-}}", s)),
+            Edit::Added(s) => marked_new.push_str(&format!("{{+{}+}}", s)),
+        }
+    }
+    (marked_old, marked_new)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changed_chars() {
+        assert_eq!(
+            diff_chars("Second line!", "Second line?"),
+            vec![
+                Edit::Same("Second line".to_owned()),
+                Edit::Removed("!".to_owned()),
+                Edit::Added("?".to_owned())
+            ]
+        );
+        assert_eq!(
+            mark("[name]Okabe[line]Hm.", "[name]Okabe[line]Hmm..."),
+            (
+                "[name]Okabe[line]Hm.".to_owned(),
+                "[name]Okabe[line]Hm{+m+}.{+..+}".to_owned()
+            )
+        );
+        assert_eq!(mark("「あ」", ""), ("{-「あ」-}".to_owned(), String::new()));
+        assert!(diff_chars("", "").is_empty());
+    }
+}
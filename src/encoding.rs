@@ -0,0 +1,91 @@
+// The encoding of text files. They're written as UTF-8 by default, and UTF-16 for tools that
+// expect it, in which case they start with a byte order mark. Reading goes by the byte order
+// mark, and a file without one is taken as UTF-8.
+
+use std::{borrow::Cow, fs, io, path::Path};
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum TextEncoding {
+    #[default]
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+}
+
+const UTF8_BOM: &[u8] = &[0xEF, 0xBB, 0xBF];
+const UTF16LE_BOM: &[u8] = &[0xFF, 0xFE];
+const UTF16BE_BOM: &[u8] = &[0xFE, 0xFF];
+
+impl TextEncoding {
+    /// What a file in this encoding starts with. UTF-8 files are written without one.
+    pub fn bom(self) -> &'static [u8] {
+        match self {
+            TextEncoding::Utf8 => &[],
+            TextEncoding::Utf16Le => UTF16LE_BOM,
+            TextEncoding::Utf16Be => UTF16BE_BOM,
+        }
+    }
+
+    pub fn encode(self, s: &str) -> Cow<'_, [u8]> {
+        let units = s.encode_utf16();
+        match self {
+            TextEncoding::Utf8 => Cow::from(s.as_bytes()),
+            TextEncoding::Utf16Le => units.flat_map(u16::to_le_bytes).collect(),
+            TextEncoding::Utf16Be => units.flat_map(u16::to_be_bytes).collect(),
+        }
+    }
+}
+
+/// Decodes a text file by its byte order mark.
+pub fn decode(bytes: &[u8]) -> io::Result<String> {
+    let invalid = |encoding| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("the file isn't valid {}", encoding),
+        )
+    };
+    let utf16 = |bytes: &[u8], from_bytes: fn([u8; 2]) -> u16| {
+        let chunks = bytes.chunks_exact(2);
+        if !chunks.remainder().is_empty() {
+            return Err(invalid("UTF-16"));
+        }
+        let units = chunks.map(|x| from_bytes([x[0], x[1]])).collect::<Vec<_>>();
+        String::from_utf16(&units).map_err(|_| invalid("UTF-16"))
+    };
+    if let Some(rest) = bytes.strip_prefix(UTF16LE_BOM) {
+        utf16(rest, u16::from_le_bytes)
+    } else if let Some(rest) = bytes.strip_prefix(UTF16BE_BOM) {
+        utf16(rest, u16::from_be_bytes)
+    } else {
+        let rest = bytes.strip_prefix(UTF8_BOM).unwrap_or(bytes);
+        String::from_utf8(rest.to_vec()).map_err(|_| invalid("UTF-8"))
+    }
+}
+
+pub fn read_to_string(path: impl AsRef<Path>) -> io::Result<String> {
+    decode(&fs::read(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let s = "[name]岡部[line]Hm.\r\n";
+        for encoding in [
+            TextEncoding::Utf8,
+            TextEncoding::Utf16Le,
+            TextEncoding::Utf16Be,
+        ] {
+            let mut bytes = encoding.bom().to_vec();
+            bytes.extend_from_slice(&encoding.encode(s));
+            assert_eq!(decode(&bytes).unwrap(), s);
+        }
+        assert_eq!(TextEncoding::Utf16Le.encode("A"), &[0x41, 0x00][..]);
+        assert_eq!(TextEncoding::Utf16Be.encode("A"), &[0x00, 0x41][..]);
+        assert_eq!(decode(b"\xEF\xBB\xBFHm.").unwrap(), "Hm.");
+        assert!(decode(b"\xFF\xFEA").is_err());
+        assert!(decode(b"\xC3").is_err());
+    }
+}
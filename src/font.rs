@@ -0,0 +1,197 @@
+// Charsets read from the description of a font atlas, for build-charset. The glyph at
+// position `i` of a charset is the `i`th cell of the font's grid, read left to right and top
+// to bottom, so a description that says where each character is drawn has all a charset
+// needs. The format read is the text format of AngelCode's BMFont, which most atlas packers
+// can write:
+//
+// common lineHeight=48 base=40 scaleW=2048 scaleH=2048 pages=1
+// char id=48 x=48 y=0 width=24 height=48 xoffset=0 yoffset=0 xadvance=24 page=0
+//
+// Cells are `lineHeight` pixels wide and high unless told otherwise, and a character belongs to the
+// cell its top left corner is in. Each page of the atlas carries on the grid of the one before
+// it. Cells that no character is drawn in are written as U+FFFD, since the charset has to keep
+// the glyphs after them where they are.
+
+use std::{collections::BTreeMap, error, fmt};
+
+/// The character written for a cell that has none.
+pub const EMPTY_CELL: char = '\u{FFFD}';
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum Error {
+    /// A field of this `common` or `char` line isn't `key=<number>`, or one it needs is missing.
+    MalformedLine(usize),
+    /// There's no `common` line before the first `char` line to give the size of the atlas.
+    MissingCommon,
+    /// The cells are this many pixels wide and high, which doesn't fit the atlas.
+    InvalidCell(u32, u32),
+    /// The id of this line isn't a Unicode character.
+    InvalidChar(usize, u32),
+    /// The character of this line is drawn in the same cell as this earlier one.
+    SameCell(usize, char, char),
+}
+
+impl error::Error for Error {}
+
+/// A charset in the order of the glyph grid.
+#[derive(Debug, Eq, PartialEq)]
+pub struct Charset {
+    pub chars: String,
+    /// How many cells had no character.
+    pub empty_cells: usize,
+}
+
+/// Reads a charset from a BMFont text file whose cells are `cell` pixels wide and high. A
+/// dimension that's `None` is `lineHeight` pixels.
+pub fn charset_from_bmfont(s: &str, cell: (Option<u32>, Option<u32>)) -> Result<Charset, Error> {
+    let mut grid = None;
+    let mut cells = BTreeMap::new();
+    for (i, line) in s.lines().enumerate() {
+        let mut words = line.split_whitespace();
+        let kind = words.next();
+        if kind != Some("common") && kind != Some("char") {
+            continue;
+        }
+        let mut fields = BTreeMap::new();
+        for word in words {
+            let (key, value) = word.split_once('=').ok_or(Error::MalformedLine(i))?;
+            fields.insert(key, value);
+        }
+        let field = |key| -> Result<u32, Error> {
+            let value = fields.get(key).ok_or(Error::MalformedLine(i))?;
+            value.parse().map_err(|_| Error::MalformedLine(i))
+        };
+        if kind == Some("common") {
+            let line_height = field("lineHeight")?;
+            let (width, height) = (cell.0.unwrap_or(line_height), cell.1.unwrap_or(line_height));
+            let (columns, rows) = match (width, height) {
+                (0, _) | (_, 0) => (0, 0),
+                _ => (field("scaleW")? / width, field("scaleH")? / height),
+            };
+            if columns == 0 || rows == 0 {
+                return Err(Error::InvalidCell(width, height));
+            }
+            grid = Some((width, height, columns, columns * rows));
+            continue;
+        }
+        let (width, height, columns, per_page) = grid.ok_or(Error::MissingCommon)?;
+        let id = field("id")?;
+        let c = char::from_u32(id).ok_or(Error::InvalidChar(i, id))?;
+        let (x, y) = (field("x")? / width, field("y")? / height);
+        let page = fields.get("page").map_or(Ok(0), |_| field("page"))?;
+        let index = page as usize * per_page as usize + (y * columns + x) as usize;
+        if let Some(other) = cells.insert(index, c) {
+            return Err(Error::SameCell(i, c, other));
+        }
+    }
+
+    let len = cells.keys().next_back().map_or(0, |x| x + 1);
+    let chars = (0..len)
+        .map(|i| cells.get(&i).copied().unwrap_or(EMPTY_CELL))
+        .collect();
+    Ok(Charset {
+        chars,
+        empty_cells: len - cells.len(),
+    })
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::MalformedLine(i) => write!(
+                f,
+                "line {}: expected key=<number> fields, with every one the line needs",
+                i + 1
+            ),
+            Error::MissingCommon => write!(
+                f,
+                "expected a common line with the size of the atlas before the first char line"
+            ),
+            Error::InvalidCell(width, height) => {
+                write!(f, "{}x{} cells don't fit in the atlas", width, height)
+            }
+            Error::InvalidChar(i, id) => {
+                write!(f, "line {}: {} isn't a Unicode character", i + 1, id)
+            }
+            Error::SameCell(i, c, other) => write!(
+                f,
+                "line {}: '{}' is drawn in the same cell as '{}'",
+                i + 1,
+                c,
+                other
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = "\
+info face=\"Sample\" size=32 bold=0 italic=0 charset=\"\" unicode=1 padding=0,0,0,0
+common lineHeight=32 base=26 scaleW=96 scaleH=64 pages=2 packed=0
+page id=0 file=\"sample_0.png\"
+page id=1 file=\"sample_1.png\"
+chars count=5
+char id=65 x=0 y=0 width=20 height=32 xoffset=0 yoffset=0 xadvance=20 page=0 chnl=15
+char id=66 x=33 y=0 width=20 height=32 xoffset=-1 yoffset=0 xadvance=20 page=0 chnl=15
+char id=12354 x=0 y=32 width=32 height=32 xoffset=0 yoffset=0 xadvance=32 page=0 chnl=15
+char id=63 x=64 y=32 width=16 height=32 xoffset=0 yoffset=0 xadvance=16 page=0 chnl=15
+char id=33 x=32 y=0 width=8 height=32 xoffset=0 yoffset=0 xadvance=8 page=1 chnl=15
+";
+
+    #[test]
+    fn grid_order() {
+        // Three columns and two rows a page.
+        let charset = charset_from_bmfont(SAMPLE, (None, None)).unwrap();
+        assert_eq!(charset.chars, "AB\u{FFFD}あ\u{FFFD}?\u{FFFD}!");
+        assert_eq!(charset.empty_cells, 3);
+
+        // With cells half as wide, 'B' is in the cell after the one 'A' starts in.
+        let charset = charset_from_bmfont(SAMPLE, (Some(16), None)).unwrap();
+        assert_eq!(
+            charset.chars.chars().take(4).collect::<String>(),
+            "A\u{FFFD}B\u{FFFD}"
+        );
+        assert_eq!(
+            charset,
+            charset_from_bmfont(SAMPLE, (Some(16), Some(32))).unwrap()
+        );
+
+        // With cells half as high, 'あ' is two rows down rather than one.
+        let charset = charset_from_bmfont(SAMPLE, (None, Some(16))).unwrap();
+        assert_eq!(charset.chars.chars().nth(6), Some('あ'));
+    }
+
+    #[test]
+    fn errors() {
+        fn err(s: &str) -> Error {
+            charset_from_bmfont(s, (None, None)).unwrap_err()
+        }
+        assert_eq!(
+            err("char id=65 x=0 y=0 width=20 height=32 page=0"),
+            Error::MissingCommon
+        );
+        let common = "common lineHeight=32 scaleW=96 scaleH=64\n";
+        assert_eq!(
+            err(&format!(
+                "{}char id=65 x=0 y=0\nchar id=66 x=8 y=8\n",
+                common
+            )),
+            Error::SameCell(2, 'B', 'A')
+        );
+        assert_eq!(
+            err(&format!("{}char id=65 x=zero y=0\n", common)),
+            Error::MalformedLine(1)
+        );
+        assert_eq!(
+            err(&format!("{}char id=55296 x=0 y=0\n", common)),
+            Error::InvalidChar(1, 0xD800)
+        );
+        assert_eq!(
+            err("common lineHeight=128 scaleW=96 scaleH=64\n"),
+            Error::InvalidCell(128, 128)
+        );
+    }
+}
@@ -1,66 +1,481 @@
 use std::{
-    cell::RefCell, collections::HashMap, error, fmt, fs::File, io, io::BufReader, io::BufWriter,
-    marker::PhantomData, ops::Range,
+    cell::RefCell, collections::HashMap, collections::HashSet, error, fmt, fs::File, io,
+    io::BufReader, io::BufWriter, marker::PhantomData, ops::Range, rc::Rc,
 };
 
-use crate::sc3;
+use crate::{coz::TagRadix, gamedef::GameDef, sc3, Sc3ToolsError};
 use byteorder::{LittleEndian, WriteBytesExt};
 use io::{Read, Seek, SeekFrom, Write};
+use itertools::Itertools;
 use nom::{
     bytes::complete::tag, combinator::map, multi::many0, number::complete::le_u32,
     sequence::preceded, sequence::tuple, IResult,
 };
-use sc3::Sc3String;
+use sc3::{Dialect, Sc3String};
+use serde::Deserialize;
 
 #[derive(Debug)]
 pub enum Error {
     UnrecognizedFormat,
-    CorruptedFile,
+    /// The file is this many bytes long, less than any header, and doesn't start like a script.
+    TooSmall(u64),
+    CorruptedFile(Corruption),
     Io(io::Error),
 }
 
+/// What's wrong with a script that has a recognized header.
+#[derive(Debug, Eq, PartialEq)]
+pub enum Corruption {
+    /// The file ends before the end of the header.
+    TruncatedHeader,
+    /// The header puts the string index, at this location, past the end of the file.
+    IndexOutOfRange(Range<u32>),
+    /// The string index can't be read.
+    MalformedIndex,
+    /// The string at this position of the index starts past the end of the file.
+    OffsetOutOfRange(usize, u32),
+}
+
 impl error::Error for Error {}
 
-pub fn open(mut file: File) -> Result<Box<dyn MagesScript>, Error> {
-    let mut magic = [0; 4];
-    file.read_exact(&mut magic)?;
-    file.seek(SeekFrom::Start(0))?;
-    let magic = std::str::from_utf8(&magic).map_err(|_| Error::UnrecognizedFormat)?;
+impl Error {
+    /// Whether the file can't be read as a script at all, as opposed to failing to be read.
+    pub fn is_corrupt(&self) -> bool {
+        matches!(
+            self,
+            Error::UnrecognizedFormat | Error::TooSmall(_) | Error::CorruptedFile(_)
+        )
+    }
+}
+
+pub fn open(file: File) -> Result<Box<dyn MagesScript>, Error> {
+    open_with(file, ReadOptions::default())
+}
+
+/// Opens a script whose string offsets follow `offset_base`, or the convention that its
+/// index appears to use if it's `None`.
+pub fn open_with_offset_base(
+    file: File,
+    offset_base: Option<OffsetBase>,
+) -> Result<Box<dyn MagesScript>, Error> {
+    open_with(file, offset_base)
+}
+
+/// Opens a script the way `options` say, which can be those of a game's definition.
+pub fn open_with(
+    mut file: File,
+    options: impl Into<ReadOptions>,
+) -> Result<Box<dyn MagesScript>, Error> {
+    let options = options.into();
+    let magic = read_magic(&mut file)?;
+    if magic == Scx::magic() {
+        Ok(Box::new(Script::<Scx>::open(file, options)?))
+    } else if magic == Msb::magic() {
+        Ok(Box::new(Script::<Msb>::open(file, options)?))
+    } else {
+        Err(Error::UnrecognizedFormat)
+    }
+}
+
+/// How a script is read.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ReadOptions {
+    /// The convention that the string offsets follow, or `None` to guess it from the index.
+    pub offset_base: Option<OffsetBase>,
+    /// How the strings are decoded, if they end with a terminator. Padding after the last
+    /// string is only looked for when it's known, as the terminator of the string can't be
+    /// told apart from a character whose last byte is `0xFF` otherwise.
+    pub dialect: Option<Dialect>,
+}
+
+impl From<Option<OffsetBase>> for ReadOptions {
+    fn from(offset_base: Option<OffsetBase>) -> Self {
+        ReadOptions {
+            offset_base,
+            dialect: None,
+        }
+    }
+}
+
+impl From<&GameDef> for ReadOptions {
+    fn from(gamedef: &GameDef) -> Self {
+        ReadOptions {
+            offset_base: gamedef.offset_base,
+            dialect: Some(gamedef.dialect()).filter(|_| gamedef.terminated),
+        }
+    }
+}
 
+/// Like `open_with`, but for a script that's already in memory. The script can't
+/// be changed in place, so `replace_strings` fails, but `write_replaced` works.
+pub fn open_bytes(
+    bytes: &[u8],
+    options: impl Into<ReadOptions>,
+) -> Result<Box<dyn MagesScript>, Error> {
+    open_shared(Rc::new(bytes.to_vec()), options.into())
+}
+
+/// Like `open_with`, but maps the file into memory instead of reading it, so that
+/// `string_bytes` borrows the strings from the mapping rather than copying them. Like a script
+/// opened with `open_bytes`, it can't be changed in place.
+///
+/// The file mustn't be truncated by another program while it's mapped.
+#[cfg(feature = "mmap")]
+pub fn open_mapped(
+    file: &File,
+    options: impl Into<ReadOptions>,
+) -> Result<Box<dyn MagesScript>, Error> {
+    // Safe as long as the file stays the way it is, which is already assumed of the files
+    // that are read the usual way, as the index is only read once.
+    let map = unsafe { memmap2::Mmap::map(file)? };
+    open_shared(Rc::new(map), options.into())
+}
+
+fn open_shared(
+    bytes: Rc<dyn AsRef<[u8]>>,
+    options: ReadOptions,
+) -> Result<Box<dyn MagesScript>, Error> {
+    let mut cursor = io::Cursor::new(SharedBytes(bytes.clone()));
+    let magic = read_magic(&mut cursor)?;
+    let reader = Box::new(cursor);
+    let bytes = Some(bytes);
     if magic == Scx::magic() {
-        Ok(Box::new(Script::<Scx>::open(file)?))
+        Ok(Box::new(Script::<Scx>::read(reader, None, bytes, options)?))
     } else if magic == Msb::magic() {
-        Ok(Box::new(Script::<Msb>::open(file)?))
+        Ok(Box::new(Script::<Msb>::read(reader, None, bytes, options)?))
     } else {
         Err(Error::UnrecognizedFormat)
     }
 }
 
+// The bytes of a script in memory, shared by its reader and the script itself.
+struct SharedBytes(Rc<dyn AsRef<[u8]>>);
+
+impl AsRef<[u8]> for SharedBytes {
+    fn as_ref(&self) -> &[u8] {
+        (*self.0).as_ref()
+    }
+}
+
+/// Applies `changes` to the script in `original` and returns the new script, laid out the
+/// same way `replace_strings` would lay it out on disk.
+pub fn replace_in_memory(
+    original: &[u8],
+    changes: &HashMap<usize, Sc3String>,
+    gamedef: &GameDef,
+    options: WriteOptions,
+) -> Result<Vec<u8>, Error> {
+    let script = open_bytes(original, gamedef)?;
+    let mut out = io::Cursor::new(Vec::with_capacity(original.len()));
+    script.write_replaced(changes, options, &mut out)?;
+    Ok(out.into_inner())
+}
+
+// The header of an SCX script, the shortest one.
+const MIN_HEADER_LEN: u64 = 12;
+
+// A file that's too small to be a script is told apart from one of an unknown format, but
+// one that starts with a known magic is read as a script with a truncated header.
+fn read_magic(reader: &mut impl ReadSeek) -> Result<&'static str, Error> {
+    let len = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
+    let mut magic = [0; 4];
+    if len >= magic.len() as u64 {
+        reader.read_exact(&mut magic)?;
+        reader.seek(SeekFrom::Start(0))?;
+    }
+    match [Scx::magic(), Msb::magic()]
+        .iter()
+        .find(|x| x.as_bytes() == magic)
+    {
+        Some(magic) => Ok(magic),
+        None if len < MIN_HEADER_LEN => Err(Error::TooSmall(len)),
+        None => Err(Error::UnrecognizedFormat),
+    }
+}
+
 pub trait MagesScript {
+    fn header_magic(&self) -> &'static str;
+    /// The bytes of the header as they are in the file, along with the ones sc3tools doesn't
+    /// use, such as the two fields of an MSB header before the end of its index. Writing the
+    /// script keeps them as they are.
+    fn header(&self) -> &[u8];
+    fn string_index_offset(&self) -> u32;
+    fn string_index_bounds(&self) -> Range<u32>;
+    fn eof(&self) -> u32;
+    fn offset_base(&self) -> OffsetBase;
     fn string_index(&self) -> &StringIndex;
     fn read_string<'a>(&self, handle: StringHandle) -> io::Result<Sc3String<'a>>;
-    fn replace_strings<'a>(&mut self, changes: &HashMap<usize, Sc3String<'a>>) -> io::Result<()>;
+    /// Like `read_string`, but borrows the bytes of the string instead of copying them when
+    /// the script is in memory.
+    fn string_bytes(&self, handle: StringHandle) -> io::Result<Sc3String<'_>> {
+        self.read_string(handle)
+    }
+    /// Rewrites the heap with `changes` applied and updates the offsets of every string.
+    ///
+    /// A string runs up to the next distinct offset, so one that takes no bytes at all can't
+    /// be told apart from one that shares the offset of the string after it. An empty string
+    /// has to be written as a lone terminator instead, which is what the games that have
+    /// terminators do; a change that takes no bytes is an error unless nothing but other empty
+    /// strings come after it on the heap.
+    fn replace_strings<'a>(
+        &mut self,
+        changes: &HashMap<usize, Sc3String<'a>>,
+        options: WriteOptions,
+    ) -> io::Result<()>;
+    /// Writes a copy of the script with `changes` applied to `out`, one string at a time.
+    ///
+    /// Unlike `replace_strings`, which loads every string into memory so that it can rewrite
+    /// the file in place, this only keeps the string offsets in memory. The price is that the
+    /// output has to be a separate file.
+    fn write_replaced<'a>(
+        &self,
+        changes: &HashMap<usize, Sc3String<'a>>,
+        options: WriteOptions,
+        out: &mut dyn WriteSeek,
+    ) -> io::Result<()>;
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct WriteOptions {
+    /// Give every changed string its own copy on the heap instead of also changing the
+    /// strings that share its offset.
+    pub split_shared: bool,
+    /// Keep the file its original size, for loaders that expect whatever follows the strings
+    /// to stay where it is: a heap that shrinks is padded with `0xFF` up to where it used to
+    /// end, and one that grows is an error. Only for strings that end with a terminator, as the
+    /// padding would be read as part of the last string otherwise.
+    pub fixed_size: bool,
+}
+
+/// How `strings` decodes the strings of a script.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DecodeOptions {
+    pub keep_fullwidth_chars: bool,
+    /// Decode bytes that can't be decoded as `[byte:XX]` tags instead of failing.
+    pub lenient: bool,
+    pub tag_radix: TagRadix,
+}
+
+impl<'s> dyn MagesScript + 's {
+    /// Iterates over the strings of the script in index order, each with its handle and its
+    /// text the way extract-text writes it, before notes are escaped.
+    ///
+    /// A string is only read and decoded once the iterator gets to it. When that fails, the
+    /// item is the error and the iterator moves on to the next string, so collecting into a
+    /// `Result` stops at the first string that can't be decoded, while filtering the errors
+    /// out skips them.
+    pub fn strings<'a>(&'a self, gamedef: &'a GameDef, options: DecodeOptions) -> Strings<'a> {
+        Strings {
+            script: self,
+            handles: self.string_index().iter(),
+            gamedef,
+            options,
+        }
+    }
+}
+
+pub struct Strings<'a> {
+    script: &'a dyn MagesScript,
+    handles: StringIndexIter<'a>,
+    gamedef: &'a GameDef,
+    options: DecodeOptions,
+}
+
+impl Iterator for Strings<'_> {
+    type Item = Result<(StringHandle, String), Sc3ToolsError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let handle = self.handles.next()?;
+        let range = handle.0.clone();
+        let decode = || -> Self::Item {
+            let s = self.script.string_bytes(handle)?;
+            let dialect = self.gamedef.dialect();
+            // Only a string that runs up to the end of the file can have been cut short.
+            let tokens = if self.options.lenient {
+                s.iter_lenient(dialect)
+            } else {
+                s.iter(dialect)
+            }
+            .cut_short(range.end == self.script.eof());
+            let serialized = Sc3String::serialize_tokens(
+                tokens,
+                self.gamedef,
+                self.options.keep_fullwidth_chars,
+                self.options.tag_radix,
+            )?;
+            Ok((StringHandle(range), serialized.to_string()))
+        };
+        Some(decode())
+    }
 }
 
+pub trait WriteSeek: Write + Seek {}
+
+impl<T: Write + Seek> WriteSeek for T {}
+
+pub trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek> ReadSeek for T {}
+
 pub struct Script<F: Format> {
-    reader: RefCell<BufReader<File>>,
-    writer: BufWriter<File>,
+    reader: RefCell<Box<dyn ReadSeek>>,
+    // The file the changes are written back to, unless the script was read from memory.
+    writer: Option<BufWriter<File>>,
+    // The whole file, if it's in memory.
+    bytes: Option<Rc<dyn AsRef<[u8]>>>,
+    header: Vec<u8>,
     pub string_index: StringIndex,
     pub string_index_location: Range<u32>,
+    pub offset_base: OffsetBase,
+    eof: u32,
+    layout: Layout,
+    padding: Option<Padding>,
     phantom: PhantomData<F>,
 }
 
 pub trait Format {
     fn magic() -> &'static str;
+    fn header_len() -> usize;
     fn str_index_location(header: &[u8]) -> IResult<&[u8], Range<u32>>;
     fn str_index(i: &[u8]) -> IResult<&[u8], Vec<StringIndexEntry>>;
-    fn str_seek_origin() -> StrSeekOrigin;
+    fn default_offset_base() -> OffsetBase;
     fn write_offset(offset: u32, writer: &mut (impl Seek + Write)) -> io::Result<()>;
+    /// Points the header at a string index that has moved.
+    fn write_index_location(
+        location: Range<u32>,
+        writer: &mut (impl Seek + Write),
+    ) -> io::Result<()>;
+}
+
+/// What the offsets in the string index are counted from.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OffsetBase {
+    /// The start of the file.
+    Absolute,
+    /// The first byte after the string index.
+    RelativeToHeap,
+}
+
+impl OffsetBase {
+    // Absolute offsets can't point into the header or the index, and relative ones can't
+    // point past the end of the file once the index is added to them. Absolute offsets below
+    // the index belong to strings stored before it, which can't start at the very beginning
+    // of the file, whereas relative ones start at 0.
+    fn detect(entries: &[StringIndexEntry], index: &Range<u32>, eof: u32, default: Self) -> Self {
+        let (min, max) = match entries.iter().map(|x| x.offset).minmax().into_option() {
+            Some(bounds) => bounds,
+            None => return default,
+        };
+        if min > 0 && max < index.start {
+            OffsetBase::Absolute
+        } else if min < index.end {
+            OffsetBase::RelativeToHeap
+        } else if max as u64 + index.end as u64 >= eof as u64 {
+            OffsetBase::Absolute
+        } else {
+            default
+        }
+    }
+}
+
+// Where the string index is relative to the strings it points at.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Layout {
+    /// The index comes first, and the strings run up to the end of the file.
+    IndexFirst,
+    /// The strings come first and end where the index starts. Whatever follows the index
+    /// stays right after it when the strings change size, and the header is updated to point
+    /// at where the index ends up.
+    HeapFirst,
+}
+
+impl Layout {
+    fn detect(entries: &[StringIndexEntry], offset_base: OffsetBase, index: &Range<u32>) -> Self {
+        let first = entries.iter().map(|x| x.offset).min();
+        match (offset_base, first) {
+            (OffsetBase::Absolute, Some(first)) if first < index.start => Layout::HeapFirst,
+            _ => Layout::IndexFirst,
+        }
+    }
+}
+
+// The largest alignment that trailing padding is assumed to be for.
+const MAX_ALIGNMENT: u32 = 0x1000;
+
+// Bytes after the last string that pad the file to a multiple of `alignment`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+struct Padding {
+    byte: u8,
+    alignment: u32,
+}
+
+impl Padding {
+    // Finds where the last string really ends, given its bytes up to the end of the file.
+    // Padding is a run of a single byte, 0x00 or 0xFF, that follows the terminator of the
+    // string and is shorter than the alignment that the end of the file is a multiple of.
+    fn detect(last_string: &[u8], eof: u32, dialect: Dialect) -> Option<(usize, Padding)> {
+        let s = Sc3String(last_string.into());
+        let mut tokens = s.iter_lenient(dialect);
+        tokens.by_ref().for_each(drop);
+        if !tokens.terminated() {
+            return None;
+        }
+        let rest = tokens.remaining();
+        let end = last_string.len() - rest.len();
+        let byte = *rest.first()?;
+        if byte != 0x00 && byte != 0xFF {
+            return None;
+        }
+        let alignment = 1 << eof.trailing_zeros().min(MAX_ALIGNMENT.trailing_zeros());
+        if rest.iter().all(|&b| b == byte) && rest.len() < alignment as usize {
+            Some((end, Padding { byte, alignment }))
+        } else {
+            None
+        }
+    }
+}
+
+// Pads a heap that ends at `heap_end` the same way as the original file and returns where
+// the file ends.
+fn write_padding<W: Write + ?Sized>(
+    padding: Option<Padding>,
+    out: &mut W,
+    heap_end: u64,
+) -> io::Result<u64> {
+    let padding = match padding {
+        Some(padding) => padding,
+        None => return Ok(heap_end),
+    };
+    let alignment = padding.alignment as u64;
+    let len = (alignment - heap_end % alignment) % alignment;
+    out.write_all(&vec![padding.byte; len as usize])?;
+    Ok(heap_end + len)
+}
+
+// A misdetected format reads a string index full of bogus offsets, which would decode as
+// gibberish. Offsets have to stay within the file. They don't have to be in the order of the
+// heap, as some scripts list their strings in another order than they're stored.
+fn check_offsets(entries: &[StringIndexEntry], seek_from: u32, eof: u32) -> Result<(), Corruption> {
+    for (i, entry) in entries.iter().enumerate() {
+        let offset = entry.offset;
+        if offset as u64 + seek_from as u64 > eof as u64 {
+            return Err(Corruption::OffsetOutOfRange(i, offset));
+        }
+    }
+    Ok(())
 }
 
-pub enum StrSeekOrigin {
-    FileStart,
-    HeapStart,
+// The file a script is changed in, which a script read from memory doesn't have.
+fn in_place(writer: &mut Option<BufWriter<File>>) -> io::Result<&mut BufWriter<File>> {
+    match writer {
+        Some(writer) => Ok(writer),
+        None => {
+            let msg = "a script read from memory can't be changed in place";
+            Err(io::Error::new(io::ErrorKind::Unsupported, msg))
+        }
+    }
 }
 
 pub struct StringHandle(pub Range<u32>);
@@ -72,12 +487,14 @@ impl StringHandle {
 }
 pub struct StringIndex {
     entries: Vec<StringIndexEntry>,
+    sorted_offsets: Vec<u32>,
     seek_from: u32,
-    eof: u32,
+    heap_end: u32,
 }
 
 #[derive(Debug, Copy, Clone)]
 pub struct StringIndexEntry {
+    #[allow(dead_code)]
     pub id: u32,
     pub offset: u32,
 }
@@ -93,42 +510,245 @@ pub struct StringIndexIter<'a> {
     pos: usize,
 }
 
+// A run of bytes on the heap together with every index entry that points at it.
+struct HeapSlot<'c, 'a> {
+    indices: Vec<usize>,
+    range: Range<u32>,
+    change: Option<&'c Sc3String<'a>>,
+}
+
+impl HeapSlot<'_, '_> {
+    fn size(&self) -> u32 {
+        match self.change {
+            Some(s) => s.0.len() as u32,
+            None => self.range.len() as u32,
+        }
+    }
+}
+
 impl<F: Format> Script<F> {
-    pub fn open(file: File) -> Result<Self, Error> {
-        let mut reader = BufReader::new(file.try_clone()?);
-        let mut header = [0; 16];
-        reader.read_exact(&mut header)?;
+    pub fn open(file: File, options: ReadOptions) -> Result<Self, Error> {
+        let reader = Box::new(BufReader::new(file.try_clone()?));
+        let writer = BufWriter::new(file.try_clone()?);
+        Self::read(reader, Some(writer), None, options)
+    }
+
+    fn read(
+        mut reader: Box<dyn ReadSeek>,
+        writer: Option<BufWriter<File>>,
+        bytes: Option<Rc<dyn AsRef<[u8]>>>,
+        options: ReadOptions,
+    ) -> Result<Self, Error> {
+        let mut header = vec![0; F::header_len()];
+        reader
+            .read_exact(&mut header)
+            .map_err(|err| match err.kind() {
+                io::ErrorKind::UnexpectedEof => Error::CorruptedFile(Corruption::TruncatedHeader),
+                _ => Error::Io(err),
+            })?;
         let (_, str_index_loc) =
             F::str_index_location(&header).map_err(|_| Error::UnrecognizedFormat)?;
+        let eof = reader.seek(SeekFrom::End(0))? as u32;
+        if str_index_loc.start > str_index_loc.end || str_index_loc.end > eof {
+            let corruption = Corruption::IndexOutOfRange(str_index_loc);
+            return Err(Error::CorruptedFile(corruption));
+        }
 
         reader.seek(SeekFrom::Start(str_index_loc.start as u64))?;
         let mut buf = vec![0u8; str_index_loc.len()];
         reader.read_exact(&mut buf)?;
-        let (_, str_index_entries) = F::str_index(&buf).map_err(|_| Error::CorruptedFile)?;
-        let seek_from = match F::str_seek_origin() {
-            StrSeekOrigin::FileStart => 0,
-            StrSeekOrigin::HeapStart => str_index_loc.end,
+        let (_, str_index_entries) =
+            F::str_index(&buf).map_err(|_| Error::CorruptedFile(Corruption::MalformedIndex))?;
+        let offset_base = options.offset_base.unwrap_or_else(|| {
+            let default = F::default_offset_base();
+            OffsetBase::detect(&str_index_entries, &str_index_loc, eof, default)
+        });
+        let layout = Layout::detect(&str_index_entries, offset_base, &str_index_loc);
+        let seek_from = match offset_base {
+            OffsetBase::Absolute => 0,
+            OffsetBase::RelativeToHeap => str_index_loc.end,
         };
-        let eof = reader.seek(SeekFrom::End(0))?;
+        check_offsets(&str_index_entries, seek_from, eof).map_err(Error::CorruptedFile)?;
 
-        let writer = BufWriter::new(file.try_clone()?);
+        let last_offset = str_index_entries.iter().map(|x| x.offset).max();
+        let (heap_end, padding) = match last_offset {
+            _ if layout == Layout::HeapFirst => (str_index_loc.start, None),
+            Some(last) if last as u64 + (seek_from as u64) < eof as u64 => {
+                let last = last + seek_from;
+                reader.seek(SeekFrom::Start(last as u64))?;
+                let mut buf = vec![0u8; (eof - last) as usize];
+                reader.read_exact(&mut buf)?;
+                let padding = options
+                    .dialect
+                    .and_then(|dialect| Padding::detect(&buf, eof, dialect));
+                match padding {
+                    Some((len, padding)) => (last + len as u32, Some(padding)),
+                    None => (eof, None),
+                }
+            }
+            _ => (eof, None),
+        };
 
         Ok(Self {
             reader: RefCell::new(reader),
             writer,
+            bytes,
+            header,
             string_index_location: str_index_loc,
-            string_index: StringIndex::new(str_index_entries, seek_from, eof as u32),
+            string_index: StringIndex::new(str_index_entries, seek_from, heap_end),
+            offset_base,
+            eof,
+            layout,
+            padding,
             phantom: PhantomData,
         })
     }
+
+    // The number of bytes to pad a heap that ends at `heap_end` with so that it ends where it
+    // used to, which it can't be past when the file has to keep its size.
+    fn fixed_size_fill(&self, heap_end: u64, options: WriteOptions) -> io::Result<usize> {
+        if !options.fixed_size {
+            return Ok(0);
+        }
+        let original_end = match self.layout {
+            Layout::IndexFirst => self.eof,
+            Layout::HeapFirst => self.string_index_location.start,
+        } as u64;
+        if heap_end > original_end {
+            let msg = format!(
+                "the strings take {} bytes more than there's room for at the original size",
+                heap_end - original_end
+            );
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+        }
+        Ok((original_end - heap_end) as usize)
+    }
+
+    // The first string on the heap isn't necessarily the first one of the index.
+    fn heap_start(&self) -> u32 {
+        match self.offset_base {
+            OffsetBase::Absolute => self.string_index.sorted_offsets[0],
+            OffsetBase::RelativeToHeap => self.string_index.seek_from,
+        }
+    }
+
+    // The offset written for the first string on the heap.
+    fn base_offset(&self) -> u32 {
+        match self.offset_base {
+            OffsetBase::Absolute => self.heap_start(),
+            OffsetBase::RelativeToHeap => 0,
+        }
+    }
+
+    fn heap_layout<'c, 'a>(
+        &self,
+        changes: &'c HashMap<usize, Sc3String<'a>>,
+        options: WriteOptions,
+    ) -> io::Result<Vec<HeapSlot<'c, 'a>>> {
+        let mut slots: Vec<HeapSlot> = Vec::new();
+        let mut slot_by_offset = HashMap::new();
+        // The strings stay in the order they're stored, whatever the order of the index. A
+        // string split from the ones it was shared with goes right after them.
+        let entries = self
+            .string_index
+            .entries
+            .iter()
+            .enumerate()
+            .sorted_by_key(|(i, x)| (x.offset, *i));
+        for (i, entry) in entries {
+            let change = changes.get(&i);
+            let shared = !(options.split_shared && change.is_some());
+            let existing = slot_by_offset
+                .get(&entry.offset)
+                .cloned()
+                .filter(|_| shared);
+            if let Some(slot) = existing {
+                let slot: &mut HeapSlot = &mut slots[slot];
+                // Only one of the texts could be written, the others would be lost.
+                if slot.change.is_some() && change.is_some() && slot.change != change {
+                    let other = slot.indices.iter().find(|x| changes.contains_key(x));
+                    let msg = format!(
+                        "strings {} and {} share their bytes but were given different text",
+                        other.unwrap(),
+                        i
+                    );
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+                }
+                slot.indices.push(i);
+                slot.change = slot.change.or(change);
+            } else {
+                if shared {
+                    slot_by_offset.insert(entry.offset, slots.len());
+                }
+                slots.push(HeapSlot {
+                    indices: vec![i],
+                    range: self.string_index.get(i).unwrap().0,
+                    change,
+                });
+            }
+        }
+        // An empty string at the end of the heap reads as empty, anywhere else it would read
+        // as the string after it.
+        let last_nonempty = slots.iter().rposition(|x| x.size() > 0);
+        let swallowed = slots[..last_nonempty.unwrap_or(0)]
+            .iter()
+            .find(|x| x.change.is_some() && x.size() == 0);
+        if let Some(slot) = swallowed {
+            let msg = format!(
+                "string {} would take no bytes and be read as the string after it",
+                slot.indices[0]
+            );
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+        }
+        Ok(slots)
+    }
+
+    fn slot_offsets(&self, slots: &[HeapSlot]) -> Vec<u32> {
+        let mut offsets = vec![0; self.string_index.count()];
+        let mut offset = self.base_offset();
+        for slot in slots {
+            for i in &slot.indices {
+                offsets[*i] = offset;
+            }
+            offset += slot.size();
+        }
+        offsets
+    }
 }
 
 impl<F: Format> MagesScript for Script<F> {
+    fn header_magic(&self) -> &'static str {
+        F::magic()
+    }
+
+    fn header(&self) -> &[u8] {
+        &self.header
+    }
+
+    fn string_index_offset(&self) -> u32 {
+        self.string_index_location.start
+    }
+
+    fn string_index_bounds(&self) -> Range<u32> {
+        self.string_index_location.clone()
+    }
+
+    fn eof(&self) -> u32 {
+        self.eof
+    }
+
+    fn offset_base(&self) -> OffsetBase {
+        self.offset_base
+    }
+
     fn string_index(&self) -> &StringIndex {
         &self.string_index
     }
 
     fn read_string<'a>(&self, handle: StringHandle) -> io::Result<Sc3String<'a>> {
+        if self.bytes.is_some() {
+            return Ok(Sc3String(self.string_bytes(handle)?.0.into_owned().into()));
+        }
         let mut reader = self.reader.borrow_mut();
         reader.seek(SeekFrom::Start(handle.0.start.into()))?;
         let mut buf = vec![0u8; handle.size()];
@@ -136,59 +756,155 @@ impl<F: Format> MagesScript for Script<F> {
         Ok(Sc3String(buf.into()))
     }
 
-    fn replace_strings<'a>(&mut self, changes: &HashMap<usize, Sc3String<'a>>) -> io::Result<()> {
+    fn string_bytes(&self, handle: StringHandle) -> io::Result<Sc3String<'_>> {
+        let bytes = match &self.bytes {
+            Some(bytes) => (**bytes).as_ref(),
+            None => return self.read_string(handle),
+        };
+        let range = handle.0.start as usize..handle.0.end as usize;
+        match bytes.get(range) {
+            Some(s) => Ok(Sc3String(s.into())),
+            None => Err(io::ErrorKind::UnexpectedEof.into()),
+        }
+    }
+
+    fn replace_strings<'a>(
+        &mut self,
+        changes: &HashMap<usize, Sc3String<'a>>,
+        options: WriteOptions,
+    ) -> io::Result<()> {
         if self.string_index.entries.is_empty() {
             return Ok(());
         }
-        let lines = self
-            .string_index
+        let slots = self.heap_layout(changes, options)?;
+        // Strings that keep their length are overwritten where they are, which leaves the
+        // offsets and every other byte of the file as they are. A string split from the ones
+        // it shares its offset with needs room of its own.
+        let same_layout = slots.iter().all(|x| x.size() == x.range.len() as u32)
+            && slots
+                .windows(2)
+                .all(|x| x[0].range.start != x[1].range.start);
+        if same_layout {
+            let writer = in_place(&mut self.writer)?;
+            for slot in &slots {
+                if let Some(s) = slot.change {
+                    writer.seek(SeekFrom::Start(slot.range.start as u64))?;
+                    writer.write_all(&s.0)?;
+                }
+            }
+            return writer.flush();
+        }
+        let lines = slots
             .iter()
-            .enumerate()
-            .map(|(i, handle)| {
-                changes
-                    .get(&i)
-                    .map(|s| Ok(s.clone()))
-                    .unwrap_or_else(|| self.read_string(handle))
+            .map(|slot| match slot.change {
+                Some(s) => Ok(s.clone()),
+                None => self.read_string(StringHandle(slot.range.clone())),
             })
             .collect::<Result<Vec<_>, _>>()?;
 
-        let mut heap_start = self.string_index.entries[0].offset;
-        if F::magic() == Msb::magic() {
-            heap_start = self.string_index.seek_from;
+        let heap_start = self.heap_start();
+        let offsets = self.slot_offsets(&slots);
+        let heap_end = heap_start as u64 + lines.iter().map(|s| s.0.len() as u64).sum::<u64>();
+        let fill = self.fixed_size_fill(heap_end, options)?;
+        let heap_end = heap_end + fill as u64;
+        // The index and whatever follows it get overwritten when the heap grows.
+        let mut trailer = Vec::new();
+        if self.layout == Layout::HeapFirst {
+            let mut reader = self.reader.borrow_mut();
+            reader.seek(SeekFrom::Start(self.string_index_location.start as u64))?;
+            reader.read_to_end(&mut trailer)?;
         }
 
-        let base_offset = match F::str_seek_origin() {
-            StrSeekOrigin::FileStart => heap_start,
-            StrSeekOrigin::HeapStart => 0,
-        };
-
-        let offsets = lines.iter().scan(base_offset, |acc, x| {
-            let offset = Some(*acc);
-            *acc += x.0.len() as u32;
-            offset
-        });
-
-        let mut writer = &mut self.writer;
+        let mut writer = in_place(&mut self.writer)?;
         writer.seek(SeekFrom::Start(heap_start as u64))?;
         for s in &lines {
-            writer.write(&s.0)?;
+            writer.write_all(&s.0)?;
         }
+        writer.write_all(&vec![0xFF; fill])?;
+        let (index_start, end) = match self.layout {
+            Layout::IndexFirst => (
+                self.string_index_location.start as u64,
+                write_padding(self.padding, writer, heap_end)?,
+            ),
+            Layout::HeapFirst => {
+                writer.write_all(&trailer)?;
+                let len = self.string_index_location.len() as u32;
+                F::write_index_location(heap_end as u32..heap_end as u32 + len, writer)?;
+                (heap_end, heap_end + trailer.len() as u64)
+            }
+        };
 
-        writer.seek(SeekFrom::Start(self.string_index_location.start as u64))?;
+        writer.seek(SeekFrom::Start(index_start))?;
         for offset in offsets {
             F::write_offset(offset, &mut writer)?;
         }
 
-        Ok(())
+        // The old heap may have been longer.
+        writer.flush()?;
+        writer.get_ref().set_len(end)
+    }
+
+    fn write_replaced<'a>(
+        &self,
+        changes: &HashMap<usize, Sc3String<'a>>,
+        options: WriteOptions,
+        mut out: &mut dyn WriteSeek,
+    ) -> io::Result<()> {
+        let mut reader = self.reader.borrow_mut();
+        reader.seek(SeekFrom::Start(0))?;
+        if self.string_index.entries.is_empty() {
+            io::copy(&mut *reader, &mut out)?;
+            return out.flush();
+        }
+
+        let slots = self.heap_layout(changes, options)?;
+        io::copy(&mut (&mut *reader).take(self.heap_start() as u64), &mut out)?;
+
+        let offsets = self.slot_offsets(&slots);
+        let heap_end =
+            self.heap_start() as u64 + slots.iter().map(|x| x.size() as u64).sum::<u64>();
+        let fill = self.fixed_size_fill(heap_end, options)?;
+        let heap_end = heap_end + fill as u64;
+        for slot in &slots {
+            if let Some(s) = slot.change {
+                out.write_all(&s.0)?;
+            } else {
+                reader.seek(SeekFrom::Start(slot.range.start as u64))?;
+                io::copy(&mut (&mut *reader).take(slot.range.len() as u64), &mut out)?;
+            }
+        }
+        out.write_all(&vec![0xFF; fill])?;
+        let index_start = match self.layout {
+            Layout::IndexFirst => {
+                write_padding(self.padding, out, heap_end)?;
+                self.string_index_location.start as u64
+            }
+            Layout::HeapFirst => {
+                reader.seek(SeekFrom::Start(self.string_index_location.start as u64))?;
+                io::copy(&mut *reader, &mut out)?;
+                let len = self.string_index_location.len() as u32;
+                F::write_index_location(heap_end as u32..heap_end as u32 + len, &mut out)?;
+                heap_end
+            }
+        };
+
+        out.seek(SeekFrom::Start(index_start))?;
+        for offset in offsets {
+            F::write_offset(offset, &mut out)?;
+        }
+
+        out.flush()
     }
 }
 
 impl StringIndex {
-    pub fn new(entries: Vec<StringIndexEntry>, seek_from: u32, eof: u32) -> Self {
+    pub fn new(entries: Vec<StringIndexEntry>, seek_from: u32, heap_end: u32) -> Self {
+        let sorted_offsets = entries.iter().map(|x| x.offset).sorted().dedup().collect();
         Self {
             entries,
+            sorted_offsets,
             seek_from,
-            eof,
+            heap_end,
         }
     }
 
@@ -196,25 +912,63 @@ impl StringIndex {
         self.entries.len()
     }
 
-    pub fn iter(&self) -> StringIndexIter {
+    pub fn iter(&self) -> StringIndexIter<'_> {
         StringIndexIter {
-            index: &self,
+            index: self,
             pos: 0,
         }
     }
 
+    // A string runs up to the next distinct offset, so entries that share an offset all see
+    // the whole string.
     pub fn get(&self, index: usize) -> Option<StringHandle> {
-        if index < self.entries.len() {
-            let range = if index < self.entries.len() - 1 {
-                self.entries[index].offset + self.seek_from
-                    ..self.entries[index + 1].offset + self.seek_from
-            } else {
-                self.entries[index].offset + self.seek_from..self.eof
-            };
-            Some(StringHandle(range))
-        } else {
-            None
+        let start = self.entries.get(index)?.offset;
+        let next = self.sorted_offsets.partition_point(|x| *x <= start);
+        let end = match self.sorted_offsets.get(next) {
+            Some(end) => end + self.seek_from,
+            None => self.heap_end,
+        };
+        Some(StringHandle(start + self.seek_from..end))
+    }
+
+    /// The first string of the index that's stored before one listed ahead of it, without
+    /// sharing its offset, if the index doesn't list the strings in the order of the heap.
+    pub fn first_unsorted(&self) -> Option<usize> {
+        let mut seen = HashSet::new();
+        let mut max = None;
+        for (i, entry) in self.entries.iter().enumerate() {
+            if max.is_some_and(|max| entry.offset < max) && !seen.contains(&entry.offset) {
+                return Some(i);
+            }
+            seen.insert(entry.offset);
+            max = max.max(Some(entry.offset));
         }
+        None
+    }
+
+    pub fn aliases(&self, index: usize) -> Vec<usize> {
+        let offset = match self.entries.get(index) {
+            Some(entry) => entry.offset,
+            None => return Vec::new(),
+        };
+        self.entries
+            .iter()
+            .enumerate()
+            .filter(|(i, x)| *i != index && x.offset == offset)
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    pub fn shared_groups(&self) -> Vec<Vec<usize>> {
+        self.entries
+            .iter()
+            .enumerate()
+            .into_group_map_by(|(_, x)| x.offset)
+            .into_values()
+            .filter(|group| group.len() > 1)
+            .map(|group| group.into_iter().map(|(i, _)| i).collect())
+            .sorted()
+            .collect()
     }
 }
 
@@ -237,6 +991,10 @@ impl Format for Scx {
         "SC3\0"
     }
 
+    fn header_len() -> usize {
+        12
+    }
+
     fn str_index_location(header: &[u8]) -> IResult<&[u8], Range<u32>> {
         map(
             preceded(tag("SC3\0"), tuple((le_u32, le_u32))),
@@ -248,13 +1006,22 @@ impl Format for Scx {
         many0(map(le_u32, |offset| StringIndexEntry::new(0, offset)))(i)
     }
 
-    fn str_seek_origin() -> StrSeekOrigin {
-        StrSeekOrigin::FileStart
+    fn default_offset_base() -> OffsetBase {
+        OffsetBase::Absolute
     }
 
     fn write_offset(offset: u32, writer: &mut (impl Seek + Write)) -> io::Result<()> {
         writer.write_u32::<LittleEndian>(offset)
     }
+
+    fn write_index_location(
+        location: Range<u32>,
+        writer: &mut (impl Seek + Write),
+    ) -> io::Result<()> {
+        writer.seek(SeekFrom::Start(4))?;
+        writer.write_u32::<LittleEndian>(location.start)?;
+        writer.write_u32::<LittleEndian>(location.end)
+    }
 }
 
 pub struct Msb {}
@@ -264,6 +1031,10 @@ impl Format for Msb {
         "MES\0"
     }
 
+    fn header_len() -> usize {
+        16
+    }
+
     fn str_index_location(header: &[u8]) -> IResult<&[u8], Range<u32>> {
         map(
             preceded(tag("MES\0"), tuple((le_u32, le_u32, le_u32))),
@@ -277,14 +1048,23 @@ impl Format for Msb {
         }))(i)
     }
 
-    fn str_seek_origin() -> StrSeekOrigin {
-        StrSeekOrigin::HeapStart
+    fn default_offset_base() -> OffsetBase {
+        OffsetBase::RelativeToHeap
     }
 
     fn write_offset(offset: u32, writer: &mut (impl Seek + Write)) -> io::Result<()> {
         writer.seek(SeekFrom::Current(4))?;
         writer.write_u32::<LittleEndian>(offset)
     }
+
+    // The index always starts right after the header, so only its end can move.
+    fn write_index_location(
+        location: Range<u32>,
+        writer: &mut (impl Seek + Write),
+    ) -> io::Result<()> {
+        writer.seek(SeekFrom::Start(12))?;
+        writer.write_u32::<LittleEndian>(location.end)
+    }
 }
 
 impl From<io::Error> for Error {
@@ -298,7 +1078,731 @@ impl fmt::Display for Error {
         match self {
             Error::Io(err) => fmt::Display::fmt(&err, f),
             Error::UnrecognizedFormat => write!(f, "unrecognized format"),
-            Error::CorruptedFile => write!(f, "corrupted file"),
+            Error::TooSmall(len) => write!(f, "file too small to be an SC3 script ({} bytes)", len),
+            Error::CorruptedFile(corruption) => write!(f, "corrupted file: {}", corruption),
+        }
+    }
+}
+
+impl fmt::Display for Corruption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Corruption::TruncatedHeader => write!(f, "the file ends within the header"),
+            Corruption::IndexOutOfRange(location) => write!(
+                f,
+                "the string index ({:#X}..{:#X}) lies past the end of the file",
+                location.start, location.end
+            ),
+            Corruption::MalformedIndex => write!(f, "the string index can't be read"),
+            Corruption::OffsetOutOfRange(i, offset) => write!(
+                f,
+                "the offset of string {} ({:#X}) points past the end of the file",
+                i, offset
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, path::PathBuf};
+
+    fn scx(strings: &[&[u8]]) -> Vec<u8> {
+        let index_start = 12u32;
+        let index_end = index_start + 4 * strings.len() as u32;
+        let mut buf = b"SC3\0".to_vec();
+        buf.write_u32::<LittleEndian>(index_start).unwrap();
+        buf.write_u32::<LittleEndian>(index_end).unwrap();
+        let mut offset = index_end;
+        for s in strings {
+            buf.write_u32::<LittleEndian>(offset).unwrap();
+            offset += s.len() as u32;
+        }
+        for s in strings {
+            buf.extend_from_slice(s);
+        }
+        buf
+    }
+
+    fn scx_with_offsets(offsets: &[u32], heap: &[u8]) -> Vec<u8> {
+        let index_end = 12 + 4 * offsets.len() as u32;
+        let mut buf = b"SC3\0".to_vec();
+        buf.write_u32::<LittleEndian>(12).unwrap();
+        buf.write_u32::<LittleEndian>(index_end).unwrap();
+        for offset in offsets {
+            buf.write_u32::<LittleEndian>(index_end + offset).unwrap();
+        }
+        buf.extend_from_slice(heap);
+        buf
+    }
+
+    fn scx_relative(strings: &[&[u8]]) -> Vec<u8> {
+        let index_end = 12 + 4 * strings.len() as u32;
+        let mut buf = b"SC3\0".to_vec();
+        buf.write_u32::<LittleEndian>(12).unwrap();
+        buf.write_u32::<LittleEndian>(index_end).unwrap();
+        let mut offset = 0;
+        for s in strings {
+            buf.write_u32::<LittleEndian>(offset).unwrap();
+            offset += s.len() as u32;
+        }
+        for s in strings {
+            buf.extend_from_slice(s);
         }
+        buf
+    }
+
+    fn read_all(script: &dyn MagesScript) -> Vec<Vec<u8>> {
+        script
+            .string_index()
+            .iter()
+            .map(|handle| script.read_string(handle).unwrap().0.into_owned())
+            .collect()
+    }
+
+    fn temp_file(name: &str, contents: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "sc3tools-format-{}-{}",
+            std::process::id(),
+            name
+        ));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn open_rw(path: &PathBuf) -> Box<dyn MagesScript> {
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .unwrap();
+        open(file).unwrap()
+    }
+
+    #[test]
+    fn layout_accessors() {
+        let path = temp_file("layout.scx", &scx(&[&[0x80, 0x01, 0xFF], &[0xFF]]));
+        let script = open_rw(&path);
+        assert_eq!(script.header_magic(), "SC3\0");
+        assert_eq!(script.string_index_offset(), 12);
+        assert_eq!(script.string_index_bounds(), 12..20);
+        assert_eq!(script.eof(), 24);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn same_length_change_in_place() {
+        let original = scx(&[
+            &[0x80, 0x01, 0xFF],
+            &[0x80, 0x02, 0xFF],
+            &[0x80, 0x03, 0xFF],
+        ]);
+        let path = temp_file("in-place.scx", &original);
+        let mut script = open_rw(&path);
+        // Everything but the string that changes is zeroed behind the back of the script, so
+        // any byte it writes besides that string's shows.
+        let mut zeroed = vec![0; original.len()];
+        zeroed[27..30].copy_from_slice(&original[27..30]);
+        fs::write(&path, &zeroed).unwrap();
+
+        let mut changes = HashMap::new();
+        changes.insert(1, Sc3String(vec![0x80, 0x09, 0xFF].into()));
+        script
+            .replace_strings(&changes, WriteOptions::default())
+            .unwrap();
+        drop(script);
+        zeroed[27..30].copy_from_slice(&[0x80, 0x09, 0xFF]);
+        assert_eq!(fs::read(&path).unwrap(), zeroed);
+
+        // A change of length moves the strings after it, so the heap is written anew.
+        fs::write(&path, &original).unwrap();
+        changes.insert(1, Sc3String(vec![0x80, 0x09, 0x80, 0x0A, 0xFF].into()));
+        open_rw(&path)
+            .replace_strings(&changes, WriteOptions::default())
+            .unwrap();
+        assert_eq!(
+            read_all(&*open_rw(&path)),
+            vec![
+                vec![0x80, 0x01, 0xFF],
+                vec![0x80, 0x09, 0x80, 0x0A, 0xFF],
+                vec![0x80, 0x03, 0xFF]
+            ]
+        );
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn empty_strings() {
+        let original = scx(&[
+            &[0x80, 0x01, 0xFF],
+            &[0x80, 0x02, 0xFF],
+            &[0x80, 0x03, 0xFF],
+        ]);
+        let path = temp_file("empty.scx", &original);
+        let changes_to = |indices: &[usize], s: &[u8]| {
+            indices
+                .iter()
+                .map(|i| (*i, Sc3String(s.to_vec().into())))
+                .collect::<HashMap<_, _>>()
+        };
+
+        // A lone terminator keeps the strings after it where they belong.
+        open_rw(&path)
+            .replace_strings(&changes_to(&[1], &[0xFF]), WriteOptions::default())
+            .unwrap();
+        assert_eq!(
+            read_all(&*open_rw(&path)),
+            vec![vec![0x80, 0x01, 0xFF], vec![0xFF], vec![0x80, 0x03, 0xFF]]
+        );
+
+        // No bytes at all would run into the next string.
+        let replaced = fs::read(&path).unwrap();
+        let changes = changes_to(&[0], &[]);
+        let err = open_rw(&path)
+            .replace_strings(&changes, WriteOptions::default())
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let mut out = io::Cursor::new(Vec::new());
+        let err = open_rw(&path)
+            .write_replaced(&changes, WriteOptions::default(), &mut out)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(out.into_inner().is_empty());
+        assert_eq!(fs::read(&path).unwrap(), replaced);
+
+        // At the end of the heap, there's nothing to run into.
+        open_rw(&path)
+            .replace_strings(&changes_to(&[1, 2], &[]), WriteOptions::default())
+            .unwrap();
+        assert_eq!(
+            read_all(&*open_rw(&path)),
+            vec![vec![0x80, 0x01, 0xFF], vec![], vec![]]
+        );
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn absolute_offsets() {
+        let path = temp_file("absolute.scx", &scx(&[&[0x80, 0x01, 0xFF], &[0xFF]]));
+        assert_eq!(open_rw(&path).offset_base(), OffsetBase::Absolute);
+
+        let mut changes = HashMap::new();
+        changes.insert(0, Sc3String(vec![0x80, 0x02, 0x80, 0x03, 0xFF].into()));
+        open_rw(&path)
+            .replace_strings(&changes, WriteOptions::default())
+            .unwrap();
+        let script = open_rw(&path);
+        assert_eq!(script.offset_base(), OffsetBase::Absolute);
+        assert_eq!(
+            read_all(&*script),
+            vec![vec![0x80, 0x02, 0x80, 0x03, 0xFF], vec![0xFF]]
+        );
+        assert_eq!(fs::read(&path).unwrap()[12..20], [20, 0, 0, 0, 25, 0, 0, 0]);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn relative_offsets() {
+        let path = temp_file(
+            "relative.scx",
+            &scx_relative(&[&[0x80, 0x01, 0xFF], &[0xFF]]),
+        );
+        let script = open_rw(&path);
+        assert_eq!(script.offset_base(), OffsetBase::RelativeToHeap);
+        assert_eq!(read_all(&*script), vec![vec![0x80, 0x01, 0xFF], vec![0xFF]]);
+
+        let mut changes = HashMap::new();
+        changes.insert(0, Sc3String(vec![0x80, 0x02, 0x80, 0x03, 0xFF].into()));
+        open_rw(&path)
+            .replace_strings(&changes, WriteOptions::default())
+            .unwrap();
+        let script = open_rw(&path);
+        assert_eq!(script.offset_base(), OffsetBase::RelativeToHeap);
+        assert_eq!(
+            read_all(&*script),
+            vec![vec![0x80, 0x02, 0x80, 0x03, 0xFF], vec![0xFF]]
+        );
+        assert_eq!(fs::read(&path).unwrap()[12..20], [0, 0, 0, 0, 5, 0, 0, 0]);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn detect_offset_base() {
+        let entries = |offsets: &[u32]| -> Vec<StringIndexEntry> {
+            offsets
+                .iter()
+                .map(|x| StringIndexEntry::new(0, *x))
+                .collect()
+        };
+        let detect = |offsets: &[u32], default| {
+            OffsetBase::detect(&entries(offsets), &(12..20), 100, default)
+        };
+        assert_eq!(
+            detect(&[0, 10], OffsetBase::Absolute),
+            OffsetBase::RelativeToHeap
+        );
+        assert_eq!(
+            detect(&[20, 90], OffsetBase::RelativeToHeap),
+            OffsetBase::Absolute
+        );
+        assert_eq!(
+            detect(&[20, 30], OffsetBase::Absolute),
+            OffsetBase::Absolute
+        );
+        assert_eq!(
+            detect(&[20, 30], OffsetBase::RelativeToHeap),
+            OffsetBase::RelativeToHeap
+        );
+        assert_eq!(detect(&[], OffsetBase::Absolute), OffsetBase::Absolute);
+        assert_eq!(
+            OffsetBase::detect(
+                &entries(&[12, 15]),
+                &(18..26),
+                30,
+                OffsetBase::RelativeToHeap
+            ),
+            OffsetBase::Absolute
+        );
+    }
+
+    #[test]
+    fn index_after_heap() {
+        // The strings come right after the header, followed by the index and 4 other bytes.
+        let mut original = b"SC3\0".to_vec();
+        original.write_u32::<LittleEndian>(18).unwrap();
+        original.write_u32::<LittleEndian>(26).unwrap();
+        original.extend_from_slice(&[0x80, 0x01, 0xFF, 0x80, 0x02, 0xFF]);
+        original.write_u32::<LittleEndian>(12).unwrap();
+        original.write_u32::<LittleEndian>(15).unwrap();
+        original.extend_from_slice(&[0xAA; 4]);
+        let path = temp_file("index-after-heap.scx", &original);
+        let script = open_rw(&path);
+        assert_eq!(script.offset_base(), OffsetBase::Absolute);
+        assert_eq!(
+            read_all(&*script),
+            vec![vec![0x80, 0x01, 0xFF], vec![0x80, 0x02, 0xFF]]
+        );
+
+        let mut changes = HashMap::new();
+        changes.insert(0, Sc3String(vec![0x80, 0x03, 0x80, 0x04, 0xFF].into()));
+        let mut out = io::Cursor::new(Vec::new());
+        script
+            .write_replaced(&changes, WriteOptions::default(), &mut out)
+            .unwrap();
+        open_rw(&path)
+            .replace_strings(&changes, WriteOptions::default())
+            .unwrap();
+        let replaced = fs::read(&path).unwrap();
+        assert_eq!(out.into_inner(), replaced);
+        assert_eq!(replaced[4..12], [20, 0, 0, 0, 28, 0, 0, 0]);
+        assert_eq!(
+            replaced[20..],
+            [12, 0, 0, 0, 17, 0, 0, 0, 0xAA, 0xAA, 0xAA, 0xAA]
+        );
+
+        let script = open_rw(&path);
+        assert_eq!(script.string_index_bounds(), 20..28);
+        assert_eq!(
+            read_all(&*script),
+            vec![vec![0x80, 0x03, 0x80, 0x04, 0xFF], vec![0x80, 0x02, 0xFF]]
+        );
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn shared_offsets() {
+        // Lines 0 and 2 point at the same string.
+        let heap = [0x80, 0x01, 0xFF, 0x80, 0x02, 0xFF];
+        let path = temp_file("shared.scx", &scx_with_offsets(&[0, 3, 0], &heap));
+        let script = open_rw(&path);
+        let index = script.string_index();
+        assert_eq!(index.aliases(0), vec![2]);
+        assert_eq!(index.aliases(1), Vec::<usize>::new());
+        assert_eq!(index.shared_groups(), vec![vec![0, 2]]);
+
+        let strings = read_all(&*script);
+        assert_eq!(strings[0], vec![0x80, 0x01, 0xFF]);
+        assert_eq!(strings[2], strings[0]);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn corrupted_index() {
+        let heap = [0x80, 0x01, 0xFF, 0x80, 0x02, 0xFF];
+        let open_err = |name, offsets: &[u32]| {
+            let path = temp_file(name, &scx_with_offsets(offsets, &heap));
+            let err = open(fs::File::open(&path).unwrap()).err().unwrap();
+            fs::remove_file(path).unwrap();
+            match err {
+                Error::CorruptedFile(corruption) => corruption,
+                err => panic!("unexpected error: {}", err),
+            }
+        };
+        assert_eq!(
+            open_err("out-of-range.scx", &[0, 50]),
+            Corruption::OffsetOutOfRange(1, 70)
+        );
+    }
+
+    #[test]
+    fn unsorted_index() {
+        // The first string of the index is stored second, and the second one first.
+        let heap = [0x80, 0x01, 0xFF, 0x80, 0x02, 0xFF, 0x80, 0x03, 0xFF];
+        let original = scx_with_offsets(&[3, 0, 6], &heap);
+        let script = open_bytes(&original, None).unwrap();
+        assert_eq!(script.string_index().first_unsorted(), Some(1));
+        assert_eq!(
+            read_all(&*script),
+            vec![
+                vec![0x80, 0x02, 0xFF],
+                vec![0x80, 0x01, 0xFF],
+                vec![0x80, 0x03, 0xFF]
+            ]
+        );
+
+        let mut changes = HashMap::new();
+        changes.insert(0, Sc3String(vec![0x80, 0x04, 0x80, 0x05, 0xFF].into()));
+        let path = temp_file("unsorted.scx", &original);
+        open_rw(&path)
+            .replace_strings(&changes, WriteOptions::default())
+            .unwrap();
+        let replaced = fs::read(&path).unwrap();
+        fs::remove_file(path).unwrap();
+        let script = open_bytes(&replaced, None).unwrap();
+        // The heap keeps its order, and only the strings after the changed one move.
+        let index = script.string_index();
+        let starts = index.iter().map(|x| x.0.start).collect::<Vec<_>>();
+        assert_eq!(starts, vec![27, 24, 32]);
+        assert_eq!(
+            read_all(&*script),
+            vec![
+                vec![0x80, 0x04, 0x80, 0x05, 0xFF],
+                vec![0x80, 0x01, 0xFF],
+                vec![0x80, 0x03, 0xFF]
+            ]
+        );
+
+        let script = open_bytes(&original, None).unwrap();
+        let mut out = io::Cursor::new(Vec::new());
+        script
+            .write_replaced(&changes, WriteOptions::default(), &mut out)
+            .unwrap();
+        assert_eq!(out.into_inner(), replaced);
+
+        let sorted = open_bytes(&scx_with_offsets(&[0, 3, 0], &heap[..6]), None).unwrap();
+        assert_eq!(sorted.string_index().first_unsorted(), None);
+    }
+
+    #[test]
+    fn truncated_header() {
+        let open_err = |name, contents: &[u8]| {
+            let path = temp_file(name, contents);
+            let err = open(fs::File::open(&path).unwrap()).err().unwrap();
+            fs::remove_file(path).unwrap();
+            err
+        };
+        let script = scx(&[&[0x80, 0x01, 0xFF]]);
+        let err = open_err("truncated-header.scx", &script[..10]);
+        assert!(err.is_corrupt());
+        assert!(matches!(
+            err,
+            Error::CorruptedFile(Corruption::TruncatedHeader)
+        ));
+
+        let err = open_err("too-small.scx", b"abcde");
+        assert!(err.is_corrupt());
+        assert!(matches!(err, Error::TooSmall(5)));
+        assert_eq!(
+            err.to_string(),
+            "file too small to be an SC3 script (5 bytes)"
+        );
+        assert!(matches!(open_err("empty.scx", b""), Error::TooSmall(0)));
+
+        let mut script = scx(&[&[0x80, 0x01, 0xFF], &[0x80, 0x02, 0xFF]]);
+        script[8..12].copy_from_slice(&0x100u32.to_le_bytes());
+        let err = open_err("index-past-eof.scx", &script);
+        assert!(matches!(
+            err,
+            Error::CorruptedFile(Corruption::IndexOutOfRange(ref x)) if *x == (12..0x100)
+        ));
+    }
+
+    #[test]
+    fn replace_shared_string() {
+        let heap = [0x80, 0x01, 0xFF, 0x80, 0x02, 0xFF];
+        let original = scx_with_offsets(&[0, 3, 0], &heap);
+        let mut changes = HashMap::new();
+        changes.insert(2, Sc3String(vec![0x80, 0x03, 0x80, 0x04, 0xFF].into()));
+
+        let path = temp_file("shared-replace.scx", &original);
+        open_rw(&path)
+            .replace_strings(&changes, WriteOptions::default())
+            .unwrap();
+        let script = open_rw(&path);
+        assert_eq!(script.string_index().aliases(0), vec![2]);
+        let strings = read_all(&*script);
+        assert_eq!(strings[0], vec![0x80, 0x03, 0x80, 0x04, 0xFF]);
+        assert_eq!(strings[1], vec![0x80, 0x02, 0xFF]);
+        assert_eq!(strings[2], strings[0]);
+        fs::remove_file(path).unwrap();
+
+        let path = temp_file("shared-split.scx", &original);
+        let split = WriteOptions {
+            split_shared: true,
+            ..Default::default()
+        };
+        open_rw(&path).replace_strings(&changes, split).unwrap();
+        let script = open_rw(&path);
+        assert!(script.string_index().shared_groups().is_empty());
+        let strings = read_all(&*script);
+        assert_eq!(strings[0], vec![0x80, 0x01, 0xFF]);
+        assert_eq!(strings[1], vec![0x80, 0x02, 0xFF]);
+        assert_eq!(strings[2], vec![0x80, 0x03, 0x80, 0x04, 0xFF]);
+        fs::remove_file(path).unwrap();
+
+        let path = temp_file("shared-conflict.scx", &original);
+        changes.insert(0, Sc3String(vec![0x80, 0x05, 0xFF].into()));
+        let err = open_rw(&path)
+            .replace_strings(&changes, WriteOptions::default())
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(fs::read(&path).unwrap(), original);
+        open_rw(&path).replace_strings(&changes, split).unwrap();
+        let strings = read_all(&*open_rw(&path));
+        assert_eq!(strings[0], vec![0x80, 0x05, 0xFF]);
+        assert_eq!(strings[2], vec![0x80, 0x03, 0x80, 0x04, 0xFF]);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn write_replaced_matches_in_place_replacement() {
+        let original = scx(&[&[0x80, 0x01, 0xFF], &[0x80, 0x02, 0x80, 0x03, 0xFF], &[0xFF]]);
+        let mut changes = HashMap::new();
+        changes.insert(1, Sc3String(vec![0x80, 0x04, 0x80, 0x05, 0x80, 0x06, 0xFF].into()));
+
+        let in_place = temp_file("in-place.scx", &original);
+        open_rw(&in_place)
+            .replace_strings(&changes, WriteOptions::default())
+            .unwrap();
+
+        let streamed = temp_file("streamed.scx", &original);
+        let script = open_rw(&streamed);
+        let mut out = io::Cursor::new(Vec::new());
+        script
+            .write_replaced(&changes, WriteOptions::default(), &mut out)
+            .unwrap();
+
+        assert_eq!(out.into_inner(), fs::read(&in_place).unwrap());
+        fs::remove_file(in_place).unwrap();
+        fs::remove_file(streamed).unwrap();
+    }
+
+    #[test]
+    fn header_kept_verbatim() {
+        // Whatever the two fields before the end of the index are, they come out unchanged.
+        let mut original = b"MES\0".to_vec();
+        for x in [0x0102_0304, 0xDEAD_BEEF, 32, 7, 0, 9, 3].iter().copied() {
+            original.write_u32::<LittleEndian>(x).unwrap();
+        }
+        original.extend_from_slice(&[0x80, 0x01, 0xFF, 0x80, 0x02, 0xFF]);
+
+        let script = open_bytes(&original, None).unwrap();
+        assert_eq!(script.header(), &original[..16]);
+        let mut changes = HashMap::new();
+        changes.insert(0, Sc3String(vec![0x80, 0x03, 0x80, 0x04, 0xFF].into()));
+        let mut out = io::Cursor::new(Vec::new());
+        script
+            .write_replaced(&changes, WriteOptions::default(), &mut out)
+            .unwrap();
+        let replaced = out.into_inner();
+        assert_eq!(replaced[..16], original[..16]);
+        let script = open_bytes(&replaced, None).unwrap();
+        assert_eq!(script.header(), &original[..16]);
+        assert_eq!(read_all(&*script)[1], vec![0x80, 0x02, 0xFF]);
+
+        // An SCX header is only 12 bytes long, so an empty script is too.
+        let empty = scx(&[]);
+        assert_eq!(empty.len(), 12);
+        assert_eq!(open_bytes(&empty, None).unwrap().header(), &empty[..]);
+    }
+
+    #[test]
+    fn backends_agree() {
+        let original = scx(&[&[0x80, 0x01, 0xFF], &[], &[0x80, 0x02, 0x80, 0x03, 0xFF]]);
+        let path = temp_file("backends", &original);
+        let file = File::open(&path).unwrap();
+        let read = open(file.try_clone().unwrap()).unwrap();
+        let in_memory = vec![
+            open_bytes(&original, None).unwrap(),
+            #[cfg(feature = "mmap")]
+            open_mapped(&file, None).unwrap(),
+        ];
+
+        let strings = read_all(&*read);
+        assert_eq!(strings.len(), 3);
+        for script in &in_memory {
+            assert_eq!(read_all(&**script), strings);
+            for (handle, expected) in script.string_index().iter().zip(&strings) {
+                let s = script.string_bytes(handle).unwrap();
+                assert!(matches!(s.0, std::borrow::Cow::Borrowed(_)));
+                assert_eq!(s.0, &expected[..]);
+            }
+        }
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn padded_heap() {
+        let mut original = scx(&[&[0x80, 0x01, 0xFF], &[0x80, 0x02, 0xFF]]);
+        original.resize(32, 0x00);
+        let path = temp_file("padded.scx", &original);
+        // Padding is only looked for in scripts whose strings are known to have terminators.
+        assert_eq!(open_rw(&path).eof(), 32);
+        assert_eq!(read_all(&*open_rw(&path))[1].len(), 9);
+        let open_padded = || {
+            let file = fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&path)
+                .unwrap();
+            let options = ReadOptions {
+                offset_base: None,
+                dialect: Some(Dialect::default()),
+            };
+            open_with(file, options).unwrap()
+        };
+        let script = open_padded();
+        assert_eq!(script.eof(), 32);
+        assert_eq!(
+            read_all(&*script),
+            vec![vec![0x80, 0x01, 0xFF], vec![0x80, 0x02, 0xFF]]
+        );
+
+        let mut changes = HashMap::new();
+        changes.insert(1, Sc3String(vec![0xFF].into()));
+        open_padded()
+            .replace_strings(&changes, WriteOptions::default())
+            .unwrap();
+        let replaced = fs::read(&path).unwrap();
+        assert_eq!(replaced.len(), 32);
+        assert_eq!(
+            replaced[20..],
+            [0x80, 0x01, 0xFF, 0xFF, 0, 0, 0, 0, 0, 0, 0, 0]
+        );
+
+        changes.insert(
+            1,
+            Sc3String(vec![0x80, 0x02, 0x80, 0x03, 0x80, 0x04, 0xFF].into()),
+        );
+        let mut out = io::Cursor::new(Vec::new());
+        open_padded()
+            .write_replaced(&changes, WriteOptions::default(), &mut out)
+            .unwrap();
+        let streamed = out.into_inner();
+        assert_eq!(streamed.len(), 32);
+        assert_eq!(streamed[30..], [0x00, 0x00]);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn fixed_size() {
+        let fixed = WriteOptions {
+            fixed_size: true,
+            ..Default::default()
+        };
+        let original = scx(&[&[0x80, 0x01, 0x80, 0x02, 0xFF], &[0x80, 0x03, 0xFF]]);
+        let path = temp_file("fixed-size.scx", &original);
+        let mut changes = HashMap::new();
+        changes.insert(0, Sc3String(vec![0x80, 0x04, 0xFF].into()));
+        let mut out = io::Cursor::new(Vec::new());
+        open_rw(&path)
+            .write_replaced(&changes, fixed, &mut out)
+            .unwrap();
+        open_rw(&path).replace_strings(&changes, fixed).unwrap();
+        let replaced = fs::read(&path).unwrap();
+        assert_eq!(out.into_inner(), replaced);
+        assert_eq!(replaced.len(), original.len());
+        assert_eq!(
+            replaced[20..],
+            [0x80, 0x04, 0xFF, 0x80, 0x03, 0xFF, 0xFF, 0xFF]
+        );
+        let strings = read_all(&*open_rw(&path));
+        assert_eq!(strings[0], vec![0x80, 0x04, 0xFF]);
+        assert_eq!(strings[1][..3], [0x80, 0x03, 0xFF]);
+
+        changes.insert(
+            0,
+            Sc3String(vec![0x80, 0x04, 0x80, 0x05, 0x80, 0x06, 0xFF].into()),
+        );
+        let err = open_rw(&path).replace_strings(&changes, fixed).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(fs::read(&path).unwrap(), replaced);
+        fs::remove_file(path).unwrap();
+
+        // With the index after the heap, the index stays where it is.
+        let mut original = b"SC3\0".to_vec();
+        original.write_u32::<LittleEndian>(18).unwrap();
+        original.write_u32::<LittleEndian>(26).unwrap();
+        original.extend_from_slice(&[0x80, 0x01, 0xFF, 0x80, 0x02, 0xFF]);
+        original.write_u32::<LittleEndian>(12).unwrap();
+        original.write_u32::<LittleEndian>(15).unwrap();
+        let path = temp_file("fixed-size-heap-first.scx", &original);
+        let mut changes = HashMap::new();
+        changes.insert(0, Sc3String(vec![0xFF].into()));
+        open_rw(&path).replace_strings(&changes, fixed).unwrap();
+        let replaced = fs::read(&path).unwrap();
+        assert_eq!(replaced[4..12], original[4..12]);
+        assert_eq!(replaced[12..18], [0xFF, 0x80, 0x02, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(replaced[18..], [12, 0, 0, 0, 13, 0, 0, 0]);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn unterminated_strings() {
+        // Each string ends where the next one starts.
+        let path = temp_file(
+            "unterminated.scx",
+            &scx(&[&[0x80, 0x01], &[0x00, 0x80, 0x02]]),
+        );
+        let script = open_rw(&path);
+        let strings = read_all(&*script);
+        assert_eq!(strings, vec![vec![0x80, 0x01], vec![0x00, 0x80, 0x02]]);
+        let first = Sc3String(strings[0].as_slice().into());
+        let tokens = first
+            .iter(sc3::CodeUnit::U16Be)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(tokens, vec![sc3::StringToken::Text(vec![0x8001].into())]);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn detect_padding() {
+        let padding = |byte, alignment| Padding { byte, alignment };
+        let detect = |bytes: &[u8], eof| Padding::detect(bytes, eof, Dialect::default());
+        assert_eq!(
+            detect(&[0x80, 0x01, 0xFF, 0x00, 0x00], 16),
+            Some((3, padding(0x00, 16)))
+        );
+        assert_eq!(
+            detect(&[0x80, 0x01, 0xFF, 0xFF, 0xFF], 16),
+            Some((3, padding(0xFF, 16)))
+        );
+        assert_eq!(
+            detect(&[0x04, 0x00, 0xFF, 0x00], 16),
+            Some((3, padding(0x00, 16)))
+        );
+        // Nothing after the terminator, bytes other than the padding byte, or more padding
+        // than the alignment calls for.
+        assert_eq!(detect(&[0x80, 0x01, 0xFF], 16), None);
+        assert_eq!(detect(&[0x04, 0xFF, 0x00, 0xFF], 16), None);
+        assert_eq!(detect(&[0x80, 0x01, 0xFF, 0x00, 0x00], 2), None);
+        // The last character ends in 0xFF, and the terminator after it is no padding.
+        assert_eq!(detect(&[0x80, 0x01, 0x81, 0xFF, 0xFF], 16), None);
+        assert_eq!(
+            detect(&[0x81, 0xFF, 0xFF, 0xFF], 16),
+            Some((3, padding(0xFF, 16)))
+        );
     }
 }
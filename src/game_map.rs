@@ -0,0 +1,289 @@
+// Which game each script belongs to, for directories that mix the scripts of several games or
+// ports. The rules are read from a file, one per line, and the first one whose glob matches
+// the name of a script wins:
+//
+// # The PS4 scripts have their own charset.
+// *_ps4.scx = sg0ps4
+// *.scx = sg0
+//
+// Blank lines and lines starting with `#` are skipped.
+//
+// A map can also be built from a list of extensions and the game each one stands for, like
+// `scx=sg0,msb=rn`, for setups where the extension alone is enough to tell the games apart.
+
+use crate::gamedef;
+use std::{
+    error, fmt,
+    path::{Path, PathBuf},
+};
+
+pub struct GameMap {
+    rules: Vec<(glob::Pattern, String)>,
+    case_sensitive: bool,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// The line isn't of the `<glob> = <game>` form.
+    Malformed(usize),
+    Pattern(usize, glob::PatternError),
+    /// The game of a rule isn't supported, along with the alias it looks like a misspelling of.
+    /// Games of an extension list have no line.
+    UnknownGame(Option<usize>, String, Option<String>),
+    /// An entry of an extension list isn't of the `<extension>=<game>` form.
+    MalformedExtension(String),
+    /// The extension list maps an extension to two different games.
+    AmbiguousExtension(String, String, String),
+    /// No rule matches these files, and there's no default game.
+    Unmatched(Vec<PathBuf>),
+}
+
+impl error::Error for Error {}
+
+impl GameMap {
+    /// Reads the rules, whose games have to be among `aliases`. The games are case-insensitive
+    /// and come out the way they're written in `aliases`.
+    pub fn parse(s: &str, aliases: &[String]) -> Result<Self, Error> {
+        let mut rules = Vec::new();
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (pattern, game) = line
+                .split_once('=')
+                .map(|(pattern, game)| (pattern.trim(), game.trim()))
+                .filter(|(pattern, game)| !pattern.is_empty() && !game.is_empty())
+                .ok_or(Error::Malformed(i))?;
+            let pattern = glob::Pattern::new(pattern).map_err(|err| Error::Pattern(i, err))?;
+            let game = find_game(aliases, game).map_err(|err| match err {
+                Error::UnknownGame(_, game, closest) => Error::UnknownGame(Some(i), game, closest),
+                err => err,
+            })?;
+            rules.push((pattern, game.clone()));
+        }
+        Ok(GameMap {
+            rules,
+            case_sensitive: true,
+        })
+    }
+
+    /// Reads a comma-separated list of `<extension>=<game>` entries, whose games have to be
+    /// among `aliases`. The extensions are case-insensitive, and listing one with two different
+    /// games is an error rather than a first-match-wins rule, as nothing tells which one is meant.
+    pub fn by_extension(s: &str, aliases: &[String]) -> Result<Self, Error> {
+        let mut extensions: Vec<(String, String)> = Vec::new();
+        for entry in s.split(',').map(str::trim).filter(|x| !x.is_empty()) {
+            let (ext, game) = entry
+                .split_once('=')
+                .map(|(ext, game)| (ext.trim().trim_start_matches('.'), game.trim()))
+                .filter(|(ext, game)| !ext.is_empty() && !game.is_empty())
+                .ok_or_else(|| Error::MalformedExtension(entry.to_owned()))?;
+            let ext = ext.to_ascii_lowercase();
+            let game = find_game(aliases, game)?;
+            match extensions.iter().find(|(x, _)| *x == ext) {
+                Some((_, other)) if other != game => {
+                    return Err(Error::AmbiguousExtension(ext, other.clone(), game.clone()))
+                }
+                Some(_) => {}
+                None => extensions.push((ext, game.clone())),
+            }
+        }
+        let rules = extensions
+            .into_iter()
+            .map(|(ext, game)| {
+                // The extension is escaped, so the pattern can't be malformed.
+                let pattern = glob::Pattern::new(&format!("*.{}", glob::Pattern::escape(&ext)));
+                (pattern.unwrap(), game)
+            })
+            .collect();
+        Ok(GameMap {
+            rules,
+            case_sensitive: false,
+        })
+    }
+
+    /// The game of the first rule that matches the file name of `path`.
+    pub fn game_for(&self, path: &Path) -> Option<&str> {
+        let name = path.file_name()?.to_str()?;
+        let options = glob::MatchOptions {
+            case_sensitive: self.case_sensitive,
+            ..Default::default()
+        };
+        self.rules
+            .iter()
+            .find(|(pattern, _)| pattern.matches_with(name, options))
+            .map(|(_, game)| game.as_str())
+    }
+
+    /// Splits the files by their game, in the order each game first comes up. Files that no
+    /// rule matches go with `default`, and are an error if there isn't one.
+    pub fn group(
+        &self,
+        files: Vec<PathBuf>,
+        default: Option<&str>,
+    ) -> Result<Vec<(String, Vec<PathBuf>)>, Error> {
+        let mut groups: Vec<(String, Vec<PathBuf>)> = Vec::new();
+        let mut unmatched = Vec::new();
+        for file in files {
+            let game = match self.game_for(&file).or(default) {
+                Some(game) => game,
+                None => {
+                    unmatched.push(file);
+                    continue;
+                }
+            };
+            match groups.iter_mut().find(|(x, _)| x == game) {
+                Some((_, files)) => files.push(file),
+                None => groups.push((game.to_owned(), vec![file])),
+            }
+        }
+        if !unmatched.is_empty() {
+            return Err(Error::Unmatched(unmatched));
+        }
+        Ok(groups)
+    }
+}
+
+// The way `game` is written in `aliases`, ignoring case.
+fn find_game<'a>(aliases: &'a [String], game: &str) -> Result<&'a String, Error> {
+    aliases
+        .iter()
+        .find(|x| x.eq_ignore_ascii_case(game))
+        .ok_or_else(|| {
+            let closest = gamedef::closest_alias(aliases, game).map(str::to_owned);
+            Error::UnknownGame(None, game.to_owned(), closest)
+        })
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Malformed(i) => write!(f, "line {}: expected <glob> = <game>", i + 1),
+            Error::Pattern(i, err) => write!(f, "line {}: {}", i + 1, err),
+            Error::UnknownGame(i, game, closest) => {
+                if let Some(i) = i {
+                    write!(f, "line {}: ", i + 1)?;
+                }
+                write!(f, "unknown game '{}'", game)?;
+                match closest {
+                    Some(closest) => write!(f, ", did you mean '{}'?", closest),
+                    None => Ok(()),
+                }
+            }
+            Error::MalformedExtension(entry) => {
+                write!(f, "expected <extension>=<game>, got '{}'", entry)
+            }
+            Error::AmbiguousExtension(ext, a, b) => write!(
+                f,
+                ".{} is listed as both {} and {}, it can only stand for one game",
+                ext, a, b
+            ),
+            Error::Unmatched(files) => {
+                let names = files
+                    .iter()
+                    .map(|x| x.display().to_string())
+                    .collect::<Vec<_>>();
+                write!(
+                    f,
+                    "no rule of the game map matches {}, pass a game for them",
+                    names.join(", ")
+                )
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn aliases() -> Vec<String> {
+        vec!["sg0".to_owned(), "sg0ps4".to_owned(), "chlcc".to_owned()]
+    }
+
+    #[test]
+    fn rules() {
+        let map = GameMap::parse(
+            "# PS4 scripts\n*_ps4.scx = SG0PS4\n\n*.scx=sg0\n  chlcc_* = chlcc\n",
+            &aliases(),
+        )
+        .unwrap();
+        let game = |path| map.game_for(Path::new(path));
+        assert_eq!(game("scr/_startup_ps4.scx"), Some("sg0ps4"));
+        assert_eq!(game("scr/_startup.scx"), Some("sg0"));
+        // The first rule that matches wins.
+        assert_eq!(game("chlcc_01.scx"), Some("sg0"));
+        assert_eq!(game("chlcc_01.msb"), Some("chlcc"));
+        assert_eq!(game("readme.txt"), None);
+    }
+
+    #[test]
+    fn malformed_rules() {
+        let err = |s| GameMap::parse(s, &aliases()).err().unwrap();
+        assert!(matches!(err("*.scx sg0"), Error::Malformed(0)));
+        assert!(matches!(err("\n*.scx = "), Error::Malformed(1)));
+        assert!(matches!(err("[.scx = sg0"), Error::Pattern(0, _)));
+        assert_eq!(
+            err("*.scx = sg9").to_string(),
+            "line 1: unknown game 'sg9', did you mean 'sg0'?"
+        );
+    }
+
+    #[test]
+    fn groups() {
+        let map = GameMap::parse("*_ps4.scx = sg0ps4", &aliases()).unwrap();
+        let files = ["a_ps4.scx", "a.scx", "b_ps4.scx"]
+            .iter()
+            .map(PathBuf::from)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            map.group(files.clone(), Some("sg0")).unwrap(),
+            vec![
+                (
+                    "sg0ps4".to_owned(),
+                    vec![PathBuf::from("a_ps4.scx"), PathBuf::from("b_ps4.scx")]
+                ),
+                ("sg0".to_owned(), vec![PathBuf::from("a.scx")]),
+            ]
+        );
+        assert_eq!(
+            map.group(files, None).err().unwrap().to_string(),
+            "no rule of the game map matches a.scx, pass a game for them"
+        );
+    }
+
+    #[test]
+    fn extensions() {
+        let map = GameMap::by_extension(" scx=SG0, .msb = chlcc,scx=sg0,", &aliases()).unwrap();
+        let game = |path| map.game_for(Path::new(path));
+        assert_eq!(game("scr/_startup.scx"), Some("sg0"));
+        assert_eq!(game("CHLCC_01.MSB"), Some("chlcc"));
+        assert_eq!(game("readme.txt"), None);
+        assert_eq!(game("scx"), None);
+        assert!(GameMap::by_extension("", &aliases())
+            .unwrap()
+            .rules
+            .is_empty());
+    }
+
+    #[test]
+    fn malformed_extensions() {
+        let err = |s| {
+            GameMap::by_extension(s, &aliases())
+                .err()
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(err("scx"), "expected <extension>=<game>, got 'scx'");
+        assert_eq!(
+            err("scx=sg0,=chlcc"),
+            "expected <extension>=<game>, got '=chlcc'"
+        );
+        assert_eq!(err("scx=sg9"), "unknown game 'sg9', did you mean 'sg0'?");
+        assert_eq!(
+            err("scx=sg0,SCX=sg0ps4"),
+            ".scx is listed as both sg0 and sg0ps4, it can only stand for one game"
+        );
+    }
+}
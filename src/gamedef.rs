@@ -1,5 +1,9 @@
-use crate::text::EncodingMaps;
-use itertools::Itertools;
+use crate::{
+    align,
+    format::OffsetBase,
+    sc3::{self, CodeUnit, Dialect},
+    text::{EncodingMapConstructionError, EncodingMaps},
+};
 use nom::{
     bytes::complete::is_not,
     character::complete::{char, line_ending, not_line_ending},
@@ -9,9 +13,14 @@ use nom::{
     IResult,
 };
 use rust_embed::RustEmbed;
-use std::{borrow::Cow, collections::HashMap, ops::RangeInclusive};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    convert::TryFrom,
+    error, fmt,
+    ops::RangeInclusive,
+};
 use serde::Deserialize;
-use serde_json;
 
 #[derive(RustEmbed)]
 #[folder = "resources/"]
@@ -21,14 +30,66 @@ pub struct GameDef {
     #[allow(dead_code)]
     pub full_name: String,
     pub aliases: Vec<String>,
+    /// The folder under `resources/` that the charset and the compound characters come from.
+    pub resource_dir: String,
     #[allow(dead_code)]
     reserved_codepoints: Option<RangeInclusive<char>>,
     charset: Vec<char>,
     pub compound_chars: HashMap<char, String>,
     pub encoding_maps: EncodingMaps,
     pub fullwidth_blocklist: Vec<char>,
+    pub color_names: BTreeMap<String, i32>,
+    /// How the string offsets in the game's scripts are counted. Guessed per script if unset.
+    pub offset_base: Option<OffsetBase>,
+    /// How many columns a line of text can take up in the game's text box.
+    pub max_line_width: Option<u32>,
+    pub glyph_widths: GlyphWidths,
+    /// How the characters of text runs are stored in the game's scripts.
+    pub code_unit: CodeUnit,
+    /// Whether the game's strings end with a terminator. Strings without one only end where
+    /// the next one starts, according to the string index.
+    pub terminated: bool,
+    /// Whether fullwidth characters are preserved when neither --preserve-fullwidth nor
+    /// --no-preserve-fullwidth is passed.
+    pub default_preserve_fullwidth: bool,
+    /// Whether compound characters are encoded even when they're written without brackets.
+    pub bare_compound_chars: bool,
+    /// Whether `[present]` carries the expression of a voice to play in the game's scripts.
+    pub present_voice: bool,
+}
+
+/// How many columns halfwidth and fullwidth characters take up on screen.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct GlyphWidths {
+    pub halfwidth: u32,
+    pub fullwidth: u32,
+}
+
+impl Default for GlyphWidths {
+    fn default() -> Self {
+        Self {
+            halfwidth: 1,
+            fullwidth: 2,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    MissingResource(String),
+    InvalidUtf8(String),
+    EncodingMaps(EncodingMapConstructionError),
+    UnsupportedUnitWidth(u32),
+    CharsetTooLarge(usize),
+    /// The glyphs of a charset with little-endian text units whose index ends in the opcode
+    /// of an instruction, which the character can't be told apart from.
+    OpcodeGlyphs(Vec<usize>),
+    /// Codepoints that `compound_chars.map` maps to different text more than once.
+    ConflictingCompoundChars(Vec<char>),
 }
 
+impl error::Error for Error {}
+
 #[derive(Deserialize)]
 pub struct GameDefJson<'a> {
     pub name: String,
@@ -37,20 +98,115 @@ pub struct GameDefJson<'a> {
     #[allow(dead_code)]
     pub reserved_codepoints: Option<RangeInclusive<char>>,
     pub fullwidth_blocklist: Vec<char>,
+    #[serde(default)]
+    pub color_names: BTreeMap<String, i32>,
+    #[serde(default)]
+    pub offset_base: Option<OffsetBase>,
+    #[serde(default)]
+    pub max_line_width: Option<u32>,
+    #[serde(default)]
+    pub glyph_widths: GlyphWidths,
+    #[serde(default)]
+    pub text_units: TextUnitsJson,
+    #[serde(default = "terminated_by_default")]
+    pub terminated: bool,
+    #[serde(default)]
+    pub default_preserve_fullwidth: bool,
+    #[serde(default)]
+    pub bare_compound_chars: bool,
+    #[serde(default)]
+    pub present_voice: bool,
+}
+
+fn terminated_by_default() -> bool {
+    true
 }
 
-impl<'a> From<GameDefJson<'a>> for GameDef {
-    fn from(json: GameDefJson<'a>) -> Self {
-        Self::new(
+/// How the characters of text runs are stored: `{ "width": 8 }` or
+/// `{ "width": 16, "endianness": "little" }`.
+#[derive(Deserialize)]
+pub struct TextUnitsJson {
+    pub width: u32,
+    #[serde(default)]
+    pub endianness: Endianness,
+}
+
+impl Default for TextUnitsJson {
+    fn default() -> Self {
+        Self {
+            width: 16,
+            endianness: Endianness::Big,
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Endianness {
+    #[default]
+    Big,
+    Little,
+}
+
+impl TryFrom<TextUnitsJson> for CodeUnit {
+    type Error = Error;
+
+    fn try_from(json: TextUnitsJson) -> Result<Self, Error> {
+        match (json.width, json.endianness) {
+            (8, _) => Ok(CodeUnit::U8),
+            (16, Endianness::Big) => Ok(CodeUnit::U16Be),
+            (16, Endianness::Little) => Ok(CodeUnit::U16Le),
+            (width, _) => Err(Error::UnsupportedUnitWidth(width)),
+        }
+    }
+}
+
+impl<'a> TryFrom<GameDefJson<'a>> for GameDef {
+    type Error = Error;
+
+    fn try_from(json: GameDefJson<'a>) -> Result<Self, Error> {
+        let code_unit = CodeUnit::try_from(json.text_units)?;
+        let mut def = Self::new(
             json.name,
             json.resource_dir,
             json.aliases,
             json.reserved_codepoints,
             json.fullwidth_blocklist,
-        )
+        )?;
+        def.color_names = json.color_names;
+        def.offset_base = json.offset_base;
+        def.max_line_width = json.max_line_width;
+        def.glyph_widths = json.glyph_widths;
+        def.terminated = json.terminated;
+        def.default_preserve_fullwidth = json.default_preserve_fullwidth;
+        def.present_voice = json.present_voice;
+        if json.bare_compound_chars {
+            def.encoding_maps.match_bare_compounds();
+            def.bare_compound_chars = true;
+        }
+        // A byte leaves 7 bits for the glyph index, and 0xFF is the string terminator.
+        if code_unit == CodeUnit::U8 && def.charset.len() > 0x7F {
+            return Err(Error::CharsetTooLarge(def.charset.len()));
+        }
+        if code_unit == CodeUnit::U16Le {
+            let glyphs = opcode_glyphs(&def.charset);
+            if !glyphs.is_empty() {
+                return Err(Error::OpcodeGlyphs(glyphs));
+            }
+        }
+        def.code_unit = code_unit;
+        Ok(def)
     }
 }
 
+// The first byte of a little-endian character is the low byte of its glyph index, so the
+// glyphs whose index ends in an opcode can't be used. Their slots have to hold U+FFFD.
+fn opcode_glyphs(charset: &[char]) -> Vec<usize> {
+    (0..charset.len())
+        .filter(|i| sc3::OPCODES.contains(&(*i as u8)) && charset[*i] != '\u{FFFD}')
+        .collect()
+}
+
 impl GameDef {
     pub fn new(
         full_name: String,
@@ -58,43 +214,66 @@ impl GameDef {
         aliases: Vec<String>,
         reserved_codepoints: Option<RangeInclusive<char>>,
         fullwidth_blocklist: Vec<char>,
-    ) -> Self {
-        fn file_path(resource_dir: &str, name: &'static str) -> String {
-            format!("{}/{}", resource_dir, name)
+    ) -> Result<Self, Error> {
+        fn resource(resource_dir: &str, name: &'static str) -> Result<String, Error> {
+            let path = format!("{}/{}", resource_dir, name);
+            let bytes: Cow<[u8]> =
+                ResourceDir::get(&path).ok_or_else(|| Error::MissingResource(path.clone()))?;
+            String::from_utf8(bytes.into_owned()).map_err(|_| Error::InvalidUtf8(path))
         }
 
-        let charset: Cow<[u8]> =
-            ResourceDir::get(&file_path(resource_dir, "charset.utf8")).unwrap();
-        let charset: Vec<char> = std::str::from_utf8(charset.as_ref())
-            .unwrap()
-            .chars()
-            .collect();
-        let compound_chars: Cow<[u8]> =
-            ResourceDir::get(&file_path(resource_dir, "compound_chars.map")).unwrap();
-        let compound_chars = std::str::from_utf8(compound_chars.as_ref()).unwrap();
-        let compound_chars = parse_compound_ch_map(compound_chars);
-        let encoding_maps = EncodingMaps::new(&charset, &compound_chars);
-
-        if let Err(err) = encoding_maps {
-            panic!(
-                "Error while constructing encoding maps for {}. \
-                The following Private Use Area characters were not found in the charset: [{}]",
-                full_name,
-                err.missing_pua_chars
-                    .into_iter()
-                    .map(|ch| format!("'{}'", ch.escape_unicode()))
-                    .join(", ")
-            );
-        }
+        let charset: Vec<char> = resource(resource_dir, "charset.utf8")?.chars().collect();
+        let compound_chars = parse_compound_ch_map(&resource(resource_dir, "compound_chars.map")?)
+            .map_err(Error::ConflictingCompoundChars)?;
+        let encoding_maps =
+            EncodingMaps::new(&charset, &compound_chars, reserved_codepoints.as_ref())
+                .map_err(Error::EncodingMaps)?;
 
-        Self {
+        Ok(Self {
             full_name,
             aliases,
+            resource_dir: resource_dir.to_owned(),
             reserved_codepoints,
             charset,
             compound_chars,
-            encoding_maps: encoding_maps.unwrap(),
+            encoding_maps,
             fullwidth_blocklist,
+            color_names: BTreeMap::new(),
+            offset_base: None,
+            max_line_width: None,
+            glyph_widths: GlyphWidths::default(),
+            code_unit: CodeUnit::default(),
+            terminated: true,
+            default_preserve_fullwidth: false,
+            bare_compound_chars: false,
+            present_voice: false,
+        })
+    }
+
+    /// Rebuilds the encoding maps so that compound characters are checked against `range`
+    /// instead of the range from the game definition.
+    pub fn set_reserved_codepoints(
+        &mut self,
+        range: RangeInclusive<char>,
+    ) -> Result<(), EncodingMapConstructionError> {
+        self.encoding_maps = EncodingMaps::new(&self.charset, &self.compound_chars, Some(&range))?;
+        if self.bare_compound_chars {
+            self.encoding_maps.match_bare_compounds();
+        }
+        self.reserved_codepoints = Some(range);
+        Ok(())
+    }
+
+    /// Whether to preserve fullwidth characters, given what the command line asked for.
+    pub fn keep_fullwidth_chars(&self, flag: Option<bool>) -> bool {
+        flag.unwrap_or(self.default_preserve_fullwidth)
+    }
+
+    /// How the game's strings are decoded.
+    pub fn dialect(&self) -> Dialect {
+        Dialect {
+            unit: self.code_unit,
+            present_voice: self.present_voice,
         }
     }
 
@@ -102,15 +281,70 @@ impl GameDef {
         &self.charset
     }
 
+    pub fn color_name(&self, value: i32) -> Option<&str> {
+        self.color_names
+            .iter()
+            .find(|(_, v)| **v == value)
+            .map(|(name, _)| name.as_str())
+    }
+
+    pub fn color_by_name(&self, name: &str) -> Option<i32> {
+        self.color_names.get(name).cloned()
+    }
+
 }
 
+/// Finds a game by one of its aliases, ignoring case.
 pub fn get_by_alias<'a>(defs: &'a [GameDef], alias: &str) -> Option<&'a GameDef> {
-    defs.iter().find(|x| x.aliases.iter().any(|a| a == alias))
+    defs.iter()
+        .find(|x| x.aliases.iter().any(|a| a.eq_ignore_ascii_case(alias)))
+}
+
+/// The alias that `alias` looks like a misspelling of, if any.
+pub fn closest_alias<'a>(aliases: &'a [String], alias: &str) -> Option<&'a str> {
+    let chars = |s: &str| s.to_lowercase().chars().collect::<Vec<_>>();
+    let alias = chars(alias);
+    let max_distance = (alias.len() / 3).max(1);
+    aliases
+        .iter()
+        .map(|x| (x, align::edit_distance(&chars(x), &alias)))
+        .filter(|(_, distance)| *distance <= max_distance)
+        .min_by_key(|(_, distance)| *distance)
+        .map(|(x, _)| x.as_str())
+}
+
+pub fn builtin_gamedefs() -> Vec<GameDef> {
+    build_gamedefs_from_json(&builtin_json())
 }
 
 pub fn build_gamedefs_from_json(json: &str) -> Vec<GameDef> {
-    let defs: Vec<GameDefJson> = serde_json::from_str(json).unwrap();
-    defs.into_iter().map(GameDef::from).collect()
+    load_gamedefs_from_json(json)
+        .unwrap()
+        .into_iter()
+        .map(|(name, def)| def.unwrap_or_else(|err| panic!("{}: {}", name, err)))
+        .collect()
+}
+
+/// Game definitions paired with the names of their games, kept even if they fail to load.
+pub type LoadedGameDefs = Vec<(String, Result<GameDef, Error>)>;
+
+/// Like `builtin_gamedefs`, but a definition that fails to load doesn't take the others
+/// down with it.
+pub fn load_builtin_gamedefs() -> Result<LoadedGameDefs, serde_json::Error> {
+    load_gamedefs_from_json(&builtin_json())
+}
+
+pub fn load_gamedefs_from_json(json: &str) -> Result<LoadedGameDefs, serde_json::Error> {
+    let defs: Vec<GameDefJson> = serde_json::from_str(json)?;
+    Ok(defs
+        .into_iter()
+        .map(|json| (json.name.clone(), GameDef::try_from(json)))
+        .collect())
+}
+
+fn builtin_json() -> String {
+    let json = ResourceDir::get("gamedefs.json").unwrap();
+    String::from_utf8(json.into_owned()).unwrap()
 }
 
 #[derive(Eq, PartialEq, Debug)]
@@ -119,6 +353,44 @@ struct PuaMapping<'a> {
     ch: &'a str,
 }
 
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::MissingResource(path) => write!(f, "'{}' is missing", path),
+            Error::InvalidUtf8(path) => write!(f, "'{}' is not valid UTF-8", path),
+            Error::EncodingMaps(err) => {
+                write!(f, "error while constructing encoding maps. {}", err)
+            }
+            Error::UnsupportedUnitWidth(width) => {
+                write!(f, "text units can be 8 or 16 bits wide, not {}", width)
+            }
+            Error::CharsetTooLarge(len) => write!(
+                f,
+                "the charset has {} glyphs, but 8-bit text units can only address 127",
+                len
+            ),
+            Error::OpcodeGlyphs(glyphs) => write!(
+                f,
+                "with little-endian text units, glyphs {} can't be told apart from instructions; put U+FFFD in their place in the charset to leave them unused",
+                glyphs
+                    .iter()
+                    .map(|i| format!("{:04X}", 0x8000 + i))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Error::ConflictingCompoundChars(chars) => write!(
+                f,
+                "compound_chars.map maps these codepoints to different text more than once: [{}]",
+                chars
+                    .iter()
+                    .map(|ch| format!("{:04X}", *ch as u32))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+}
+
 impl<'a> PuaMapping<'a> {
     fn new(codepoint_range: RangeInclusive<char>, ch: &'a str) -> Self {
         Self {
@@ -127,7 +399,7 @@ impl<'a> PuaMapping<'a> {
         }
     }
 
-    pub fn parse(i: &str) -> IResult<&str, PuaMapping> {
+    pub fn parse(i: &str) -> IResult<&str, PuaMapping<'_>> {
         fn codepoint(i: &str) -> IResult<&str, char> {
             map_opt(
                 map_res(is_not("-]"), |s| u32::from_str_radix(s, 16)),
@@ -143,8 +415,8 @@ impl<'a> PuaMapping<'a> {
                     char(']'),
                 ),
                 |(a, b)| match (a, b) {
-                    (a, Some(b)) => (a..=b),
-                    _ => (a..=a),
+                    (a, Some(b)) => a..=b,
+                    _ => a..=a,
                 },
             )(i)
         }
@@ -155,16 +427,49 @@ impl<'a> PuaMapping<'a> {
     }
 }
 
-fn parse_compound_ch_map(i: &str) -> HashMap<char, String> {
+// Fails with the codepoints that overlapping ranges map to different text.
+fn parse_compound_ch_map(i: &str) -> Result<HashMap<char, String>, Vec<char>> {
     let mappings = separated_list0(line_ending, PuaMapping::parse)(i).unwrap().1;
-    mappings
-        .iter()
-        .flat_map(|m| {
-            m.codepoint_range
-                .clone()
-                .map(move |codepoint| (codepoint, m.ch.to_string()))
-        })
-        .collect()
+    let mut map = HashMap::new();
+    let mut conflicts = Vec::new();
+    for m in &mappings {
+        for codepoint in m.codepoint_range.clone() {
+            match map.insert(codepoint, m.ch.to_string()) {
+                Some(previous) if previous != m.ch => conflicts.push(codepoint),
+                _ => {}
+            }
+        }
+    }
+    conflicts.sort_unstable();
+    conflicts.dedup();
+    if conflicts.is_empty() {
+        Ok(map)
+    } else {
+        Err(conflicts)
+    }
+}
+
+// The definition of Steins;Gate 0 the unit tests of the other modules build their game from.
+#[cfg(test)]
+pub mod test_defs {
+    use super::GameDef;
+    use std::sync::LazyLock;
+
+    pub static SG0_DEF_JSON: &str = r#"
+    [{
+        "name": "Steins;Gate 0",
+        "resource_dir": "sg0",
+        "aliases": ["sg0", "steinsgate0"],
+        "reserved_codepoints": {
+        "start": "\uE12F",
+        "end": "\uE2AF"
+        },
+        "fullwidth_blocklist": ["'", "-", "[", "]", "(", ")"],
+        "color_names": { "red": 3, "blue": 12 }
+    }]"#;
+
+    pub static DEFS: LazyLock<Vec<GameDef>> =
+        LazyLock::new(|| super::build_gamedefs_from_json(SG0_DEF_JSON));
 }
 
 #[cfg(test)]
@@ -183,4 +488,105 @@ mod tests {
             PuaMapping::new('\u{E01C}'..='\u{E01F}', "¹⁸")
         );
     }
+
+    #[test]
+    fn overlapping_compound_chars() {
+        assert_eq!(
+            parse_compound_ch_map("[E000-E002]=¹\n[E002]=¹⁸"),
+            Err(vec!['\u{E002}'])
+        );
+        assert!(parse_compound_ch_map("[E000]=¹\n[E001]=¹⁸\n[E000]=¹").is_ok());
+
+        let defs = build_gamedefs_from_json(
+            r#"[{ "name": "Steins;Gate 0", "resource_dir": "sg0", "aliases": ["sg0"], "fullwidth_blocklist": [], "bare_compound_chars": true }]"#,
+        );
+        let gamedef = get_by_alias(&defs, "sg0").unwrap();
+        let text = crate::text::Text("x¹⁸".into());
+        let chars = text.iter(&gamedef.encoding_maps).collect::<Vec<_>>();
+        assert_eq!(chars[1], crate::text::Char::Compound("¹⁸"));
+    }
+
+    #[test]
+    fn aliases() {
+        let defs = build_gamedefs_from_json(
+            r#"[{ "name": "Steins;Gate 0", "resource_dir": "sg0", "aliases": ["sg0", "steinsgate0"], "fullwidth_blocklist": [] }]"#,
+        );
+        assert!(get_by_alias(&defs, "sg0").is_some());
+        assert!(get_by_alias(&defs, "SG0").is_some());
+        assert!(get_by_alias(&defs, "SteinsGate0").is_some());
+        assert!(get_by_alias(&defs, "sg").is_none());
+
+        let aliases = ["sghd", "sg0", "steinsgate0", "cc", "rn"].map(String::from);
+        assert_eq!(closest_alias(&aliases, "sgh"), Some("sghd"));
+        assert_eq!(closest_alias(&aliases, "SG-0"), Some("sg0"));
+        assert_eq!(closest_alias(&aliases, "steinsgate"), Some("steinsgate0"));
+        assert_eq!(closest_alias(&aliases, "chaoschild"), None);
+    }
+
+    #[test]
+    fn default_preserve_fullwidth() {
+        let defs = build_gamedefs_from_json(
+            r#"[
+                { "name": "JP", "resource_dir": "sg0", "aliases": ["jp"], "fullwidth_blocklist": [], "default_preserve_fullwidth": true },
+                { "name": "EN", "resource_dir": "sg0", "aliases": ["en"], "fullwidth_blocklist": [] }
+            ]"#,
+        );
+        let jp = get_by_alias(&defs, "jp").unwrap();
+        assert!(jp.keep_fullwidth_chars(None));
+        assert!(!jp.keep_fullwidth_chars(Some(false)));
+        let en = get_by_alias(&defs, "en").unwrap();
+        assert!(!en.keep_fullwidth_chars(None));
+        assert!(en.keep_fullwidth_chars(Some(true)));
+    }
+
+    #[test]
+    fn broken_def_does_not_stop_the_others() {
+        let json = r#"[
+            { "name": "Missing", "resource_dir": "missing", "aliases": ["x"], "fullwidth_blocklist": [] },
+            { "name": "Steins;Gate 0", "resource_dir": "sg0", "aliases": ["sg0"], "fullwidth_blocklist": [] }
+        ]"#;
+        let defs = load_gamedefs_from_json(json).unwrap();
+        assert_eq!(defs.len(), 2);
+        assert!(
+            matches!(&defs[0].1, Err(Error::MissingResource(path)) if path == "missing/charset.utf8")
+        );
+        assert!(defs[1].1.is_ok());
+    }
+
+    #[test]
+    fn text_units() {
+        let load = |text_units: &str| {
+            let json = format!(
+                r#"[{{ "name": "sg0", "resource_dir": "sg0", "aliases": [], "fullwidth_blocklist": [], "text_units": {} }}]"#,
+                text_units
+            );
+            load_gamedefs_from_json(&json).unwrap().remove(0).1
+        };
+        let unit = |text_units| load(text_units).map(|def| def.code_unit);
+        assert!(matches!(unit(r#"{ "width": 16 }"#), Ok(CodeUnit::U16Be)));
+        // Glyph 0, the space, would read as a line break with the low byte first.
+        let little = unit(r#"{ "width": 16, "endianness": "little" }"#);
+        assert!(matches!(little, Err(Error::OpcodeGlyphs(glyphs)) if glyphs[0] == 0));
+        assert!(matches!(
+            unit(r#"{ "width": 32 }"#),
+            Err(Error::UnsupportedUnitWidth(32))
+        ));
+        // The sg0 font has far more glyphs than a byte can address.
+        assert!(matches!(
+            unit(r#"{ "width": 8 }"#),
+            Err(Error::CharsetTooLarge(_))
+        ));
+    }
+
+    #[test]
+    fn little_endian_charset() {
+        let mut charset = vec!['a'; 0x120];
+        assert_eq!(opcode_glyphs(&charset)[..3], [0x00, 0x01, 0x02]);
+        for (i, ch) in charset.iter_mut().enumerate() {
+            if sc3::OPCODES.contains(&(i as u8)) {
+                *ch = '\u{FFFD}';
+            }
+        }
+        assert!(opcode_glyphs(&charset).is_empty());
+    }
 }
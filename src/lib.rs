@@ -1,5 +1,3 @@
-#[macro_use]
-extern crate lazy_static;
 extern crate byteorder;
 extern crate clap;
 extern crate glob;
@@ -7,59 +5,683 @@ extern crate nom;
 extern crate rust_embed;
 extern crate termcolor;
 
+mod align;
+mod bundle;
+mod charset;
 mod coz;
-mod format;
-mod gamedef;
-mod sc3;
+mod diff;
+pub mod encoding;
+mod font;
+pub mod format;
+mod game_map;
+pub mod gamedef;
+mod lint;
+mod names;
+mod naming;
+mod note;
+mod per_line;
+mod placeholder;
+mod profile;
+pub mod sc3;
+mod sparse;
+mod stamp;
 mod text;
+mod token_json;
+mod tsv;
+mod width;
 
-use clap::{Arg, ArgAction, Command, Subcommand};
+use clap::{Arg, ArgAction, Command};
 use core::fmt;
-use coz::CozString;
+use coz::{CozString, StringSegment};
+use encoding::TextEncoding;
+use game_map::GameMap;
 use gamedef::GameDef;
 use glob::Paths;
+use indicatif::{ProgressBar, ProgressStyle};
 use itertools::{EitherOrBoth, Itertools};
+use profile::Phase;
 use sc3::Sc3String;
+use serde::Serialize;
+use stamp::Stamp;
 use std::{
-    collections::HashMap,
+    borrow::Cow,
+    cell::Cell,
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
     error,
     fs::{self, OpenOptions},
     io,
-    io::{BufRead, BufReader, BufWriter, Write},
+    io::{BufWriter, IsTerminal, Write},
+    ops::RangeInclusive,
     path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 use std::{error::Error, fs::File, path::Path};
 use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
 
+pub use coz::TagRadix;
+pub use text::EncodingError;
+pub use text::MissingCharPolicy;
+pub use text::{can_encode, Substitution};
+pub use token_json::TextFormat;
+
+// Scripts bigger than this are streamed into a temporary file rather than rewritten in memory.
+const STREAMING_THRESHOLD: u64 = 16 * 1024 * 1024;
+
+// What extract-text --skip-errors writes in place of a string it can't decode. replace-text
+// leaves the strings of such lines untouched.
+const DECODE_ERROR: &str = "[decode-error]";
+
+// The default of --game-by-extension, so that a setup with one game per extension can leave out
+// <game> for good.
+const GAME_BY_EXTENSION_VAR: &str = "SC3TOOLS_GAME_BY_EXTENSION";
+
+// Cleared by --no-color.
+static COLOR: AtomicBool = AtomicBool::new(true);
+
+/// Why a public function of the crate failed, for callers that want to tell the kinds of
+/// failure apart rather than only print them.
+#[derive(Debug)]
+pub enum Sc3ToolsError {
+    Io(io::Error),
+    /// The script can't be read as one.
+    Format(format::Error),
+    /// A string of the script can't be decoded.
+    Decode(sc3::Error),
+    /// A line of the text file has characters the game's charset can't encode.
+    Encoding(text::EncodingError),
+    /// A line of the text file has a malformed tag.
+    Markup(coz::ParseError),
+    /// A script or a text file was read, but not everything in it could be processed.
+    Processing(ProcessingError),
+    /// A game definition, a glossary or a report can't be read or written as JSON.
+    Json(serde_json::Error),
+    /// An argument can't be used as given, such as an index past the end of the script, or
+    /// an option that needs another one.
+    InvalidArgument(String),
+    /// Some of the files or lines a command went through failed, each of which was reported
+    /// on its own. The message sums them up.
+    Failed(String),
+    /// The replacement of many scripts wasn't confirmed.
+    Aborted,
+}
 
 #[derive(Debug)]
-enum ProcessingError {
+pub enum ProcessingError {
     Script(PathBuf, usize, Box<dyn Error>),
     Text(PathBuf, usize, Box<dyn Error>),
     Io(io::Error),
     LineCountMismatch,
+    StampMismatch(PathBuf),
+    SharedString(PathBuf, usize, Vec<usize>),
+    /// These two lines share a string but were given different text.
+    ConflictingSharedString(PathBuf, usize, usize),
+    /// The text file has this many lines, far more than the script has strings.
+    TooManyLines(PathBuf, usize, usize),
+    /// A file can't be processed as a whole, such as a malformed TSV or placeholder sidecar,
+    /// or a script that fails to open partway through a batch.
+    File(PathBuf, Box<dyn Error>),
+    /// Gave up on the file after this many lines failed, which suggests it isn't a script of
+    /// the game of this name.
+    TooManyErrors(PathBuf, usize, String),
+}
+
+// How a command that goes through multiple files deals with the ones that fail, and which ones
+// it skips with --since.
+#[derive(Debug, Default, Clone, Copy)]
+struct BatchOptions<'a> {
+    fail_fast: bool,
+    context: bool,
+    quiet: bool,
+    since: Option<SystemTime>,
+    budget: Option<&'a ErrorBudget>,
+}
+
+// How many failed files a run reports before giving up, for --max-errors. A run can go
+// through several batches, such as one per game, which all draw from the same budget.
+#[derive(Debug)]
+struct ErrorBudget {
+    max: usize,
+    spent: Cell<usize>,
+}
+
+// A text file written by extract-text, as listed by --manifest.
+#[derive(Debug, Serialize)]
+struct ExtractedFile {
+    input_script: PathBuf,
+    output_txt: PathBuf,
+    line_count: usize,
+}
+
+// The scripts a read-only command skipped with --keep-going-on-corrupt, for its summary.
+#[derive(Debug, Default)]
+struct CorruptScripts {
+    keep_going: bool,
+    skipped: Vec<(PathBuf, String)>,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ExtractOptions {
+    pub keep_fullwidth_chars: bool,
+    /// Start the text file with a line identifying the script, which replace-text checks.
+    pub stamp: bool,
+    /// Extract bytes that can't be decoded as `[byte:XX]` tags instead of failing.
+    pub lenient: bool,
+    /// Only extract the lines that pass the filter. The text file is written in the sparse
+    /// format so that replace-text can tell which strings the lines belong to.
+    pub only: Option<LineFilter>,
+    /// Write fullwidth spaces as regular ones even when keeping other fullwidth characters.
+    pub halfwidth_spaces: bool,
+    /// End the lines with `\r\n` instead of `\n`.
+    pub crlf: bool,
+    /// Replace the tags with numbered placeholders and write them to a sidecar file.
+    pub placeholders: bool,
+    pub encoding: TextEncoding,
+    /// Write a `[decode-error]` line for each string that can't be decoded instead of failing.
+    pub skip_errors: bool,
+    /// Give up on a script after this many strings that can't be decoded, even with
+    /// `skip_errors`.
+    pub max_errors: Option<usize>,
+    /// Leave out the strings that have no text, only control tags. Like a filter, this writes
+    /// the text file in the sparse format.
+    pub skip_empty_text: bool,
+    /// Write each string to a file of its own in the output directory, named after its index.
+    /// There's no stamp or notes in this format.
+    pub per_line: bool,
+    /// How the numbers of tags like `[font size]` are written.
+    pub tag_radix: TagRadix,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum LineFilter {
+    /// Lines with a `[line]` tag.
+    Dialogue,
+    /// Lines with a `[name]` tag.
+    Names,
+    /// Lines whose text contains the pattern. Tags aren't text, so `[line]` doesn't match
+    /// `line`.
+    Matching(String),
+}
+
+impl LineFilter {
+    fn matches(&self, s: &Sc3String, serialized: &str, gamedef: &GameDef) -> bool {
+        let has_token = |token| {
+            s.iter_lenient(gamedef.dialect())
+                .any(|x| x.is_ok_and(|x| x == token))
+        };
+        match self {
+            LineFilter::Dialogue => has_token(sc3::StringToken::LineStart),
+            LineFilter::Names => has_token(sc3::StringToken::NameStart),
+            LineFilter::Matching(pattern) => {
+                let serialized = CozString(serialized.into());
+                let mut segments = serialized.iter();
+                segments.any(|seg| match seg {
+                    StringSegment::Text(text) => text.unescaped().contains(pattern.as_str()),
+                    StringSegment::Tag(_) => false,
+                })
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ReplaceOptions {
+    pub keep_fullwidth_chars: bool,
+    /// Treat warnings as errors.
+    pub strict: bool,
+    /// Give changed lines that share their string with other lines a copy of their own.
+    pub split_shared: bool,
+    /// Skip the lines that fail to encode instead of leaving the whole file untouched.
+    pub partial: bool,
+    /// Write the characters the encoder substituted in each changed line to a `.map` file
+    /// next to the text file.
+    pub map_report: bool,
+    /// Accept the `[byte:XX]` tags written by a lenient extraction.
+    pub lenient: bool,
+    /// Read the text files in the sparse format written by a filtered extraction and leave
+    /// the strings they don't mention untouched.
+    pub sparse: bool,
+    /// Consider regular and fullwidth spaces the same when looking for changed lines, so that
+    /// text extracted with halfwidth spaces leaves the original spaces alone.
+    pub halfwidth_spaces: bool,
+    /// Put back the tags from the sidecar file written by an extraction with placeholders.
+    pub placeholders: bool,
+    /// Write a JSON report of every script to this file.
+    pub report_json: Option<PathBuf>,
+    /// Write every substitution the encoder made in the scripts to this file, with how many
+    /// changed lines needed it.
+    pub subst_log: Option<PathBuf>,
+    /// Give changed lines that don't end with control tokens (`[present]`, `[auto-forward]`)
+    /// the ones their original string ends with.
+    pub preserve_tail: bool,
+    /// Strip ASCII whitespace from the start and the end of each line.
+    pub trim: bool,
+    /// Warn about changed lines with control characters such as tabs in their text, which
+    /// are more likely pasted by accident than meant. With `strict`, they're an error.
+    pub check_controls: bool,
+    /// Give up on text files with more than this many lines per string of the script, which
+    /// are more likely to be the wrong file than a translation.
+    pub max_line_factor: Option<usize>,
+    /// Go through every check without writing the script or the `.map` file.
+    pub dry_run: bool,
+    /// Keep the script its original size, padding the strings if they shrink and failing if
+    /// they grow.
+    pub fixed_size: bool,
+    /// Read the text from directories of one file per string, as written by an extraction
+    /// with `per_line`. The strings without a file are left untouched.
+    pub per_line: bool,
+    /// What to do with the characters of a changed line that aren't in the charset. Ignored
+    /// with `strict`, which always fails on them.
+    pub on_missing: MissingCharPolicy,
+    /// Give up on a script after this many lines that can't be encoded, even with `partial`.
+    pub max_errors: Option<usize>,
+    /// Read the text file as a TSV of lines and their translations, and replace each string
+    /// whose line is one of them wherever it is in the script. The other strings are left
+    /// untouched.
+    pub tsv: bool,
+}
+
+/// What replacing the text of a script did, as written by --report-json.
+#[derive(Debug, Default, Serialize)]
+pub struct ReplaceReport {
+    pub file: PathBuf,
+    /// How many lines the script has.
+    pub total: usize,
+    pub changed: usize,
+    pub changed_indices: Vec<usize>,
+    /// Why the script couldn't be replaced, or the lines that were skipped with --partial.
+    pub errors: Vec<String>,
+    /// The characters the encoder substituted in each changed line, by line, if asked for with
+    /// `map_report` or `subst_log`.
+    #[serde(skip)]
+    pub substitutions: Vec<(usize, Vec<Substitution>)>,
+}
+
+/// What renaming the speakers of a script after a glossary did.
+#[derive(Debug, Default)]
+pub struct RenamedSpeakers {
+    pub changed: usize,
+    /// The names the glossary has nothing for, with how many lines each one speaks.
+    pub unknown: HashMap<String, usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct AlignOptions {
+    pub keep_fullwidth_chars: bool,
+    /// How similar a changed line has to be to an old one to keep its translation, from 0
+    /// to 1.
+    pub min_similarity: f64,
+}
+
+impl Default for AlignOptions {
+    fn default() -> Self {
+        Self {
+            keep_fullwidth_chars: false,
+            min_similarity: 0.6,
+        }
+    }
+}
+
+impl error::Error for ProcessingError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ProcessingError::Script(_, _, err)
+            | ProcessingError::Text(_, _, err)
+            | ProcessingError::File(_, err) => Some(&**err),
+            ProcessingError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl error::Error for Sc3ToolsError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Sc3ToolsError::Io(err) => Some(err),
+            Sc3ToolsError::Format(err) => Some(err),
+            Sc3ToolsError::Decode(err) => Some(err),
+            Sc3ToolsError::Encoding(err) => Some(err),
+            Sc3ToolsError::Markup(err) => Some(err),
+            Sc3ToolsError::Processing(err) => Some(err),
+            Sc3ToolsError::Json(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Sc3ToolsError {
+    fn from(err: io::Error) -> Self {
+        Sc3ToolsError::Io(err)
+    }
+}
+
+impl From<format::Error> for Sc3ToolsError {
+    fn from(err: format::Error) -> Self {
+        Sc3ToolsError::Format(err)
+    }
+}
+
+impl From<sc3::Error> for Sc3ToolsError {
+    fn from(err: sc3::Error) -> Self {
+        Sc3ToolsError::Decode(err)
+    }
+}
+
+impl From<text::EncodingError> for Sc3ToolsError {
+    fn from(err: text::EncodingError) -> Self {
+        Sc3ToolsError::Encoding(err)
+    }
+}
+
+impl From<coz::Error> for Sc3ToolsError {
+    fn from(err: coz::Error) -> Self {
+        match err {
+            coz::Error::Parsing(err) => Sc3ToolsError::Markup(err),
+            coz::Error::TextEncoding(err) => Sc3ToolsError::Encoding(err),
+            coz::Error::Serialization(err) => Sc3ToolsError::Decode(err),
+        }
+    }
+}
+
+impl From<ProcessingError> for Sc3ToolsError {
+    fn from(err: ProcessingError) -> Self {
+        Sc3ToolsError::Processing(err)
+    }
+}
+
+impl From<serde_json::Error> for Sc3ToolsError {
+    fn from(err: serde_json::Error) -> Self {
+        Sc3ToolsError::Json(err)
+    }
+}
+
+// A file the pattern matched can't be read, such as a directory without permission.
+impl From<glob::GlobError> for Sc3ToolsError {
+    fn from(err: glob::GlobError) -> Self {
+        let path = err.path().to_owned();
+        ProcessingError::File(path, Box::new(io::Error::from(err))).into()
+    }
 }
 
-impl error::Error for ProcessingError {}
+pub fn run() -> Result<(), Sc3ToolsError> {
+    run_command()
+}
 
-pub fn run() -> Result<(), Box<dyn Error>> {
+fn run_command() -> Result<(), Sc3ToolsError> {
     
-    let game_defs_file = gamedef::ResourceDir::get("gamedefs.json").unwrap();
-    let game_defs_json = std::str::from_utf8(game_defs_file.as_ref()).unwrap();
-    let defs = gamedef::build_gamedefs_from_json(game_defs_json);
+    let mut defs = Vec::new();
+    let mut broken_defs = Vec::new();
+    for (name, def) in gamedef::load_builtin_gamedefs()? {
+        match def {
+            Ok(def) => defs.push(def),
+            Err(err) => broken_defs.push((name, err)),
+        }
+    }
     let supported_games: Vec<String> = defs.iter()
         .flat_map(|v| v.aliases.iter().cloned()) // Clone the strings to own them
         .collect();
 
-    fn parse_glob(arg: &str, pattern: &str) -> Result<Paths, String> {
-        glob::glob(pattern).map_err(|err| format!("Invalid value for '<{}>'\n{}", arg, err))
+    fn parse_glob(arg: &str, pattern: &str) -> Result<Paths, Sc3ToolsError> {
+        glob::glob(pattern).map_err(|err| {
+            Sc3ToolsError::InvalidArgument(format!("Invalid value for '<{}>'\n{}", arg, err))
+        })
     }
 
-    fn game_arg(idx: usize, supported_games: &Vec<String>) -> Arg {   
+    fn game_arg(idx: usize, supported_games: &[String]) -> Arg {   
+        let aliases = supported_games.to_vec();
         Arg::new("game")
             .index(idx)
             .required(true)
-            .value_parser(clap::builder::PossibleValuesParser::new(supported_games))
+            .value_parser(move |arg: &str| parse_game(arg, &aliases))
+    }
+
+    // Resolves the alias regardless of case, and suggests the closest one when there's no
+    // such game.
+    fn parse_game(arg: &str, aliases: &[String]) -> Result<String, String> {
+        if let Some(alias) = aliases.iter().find(|x| x.eq_ignore_ascii_case(arg)) {
+            return Ok(alias.clone());
+        }
+        let message = match gamedef::closest_alias(aliases, arg) {
+            Some(alias) => format!("did you mean '{}'?", alias),
+            None => "run sc3tools without arguments to see the supported ones".to_owned(),
+        };
+        Err(format!("unknown game, {}", message))
+    }
+
+    fn game_map_arg() -> Arg {
+        Arg::new("game-map")
+            .long("game-map")
+            .value_name("FILE")
+            .value_parser(clap::value_parser!(PathBuf))
+            .help("Pick the game of each script by the <glob> = <game> rules of FILE, falling back to <game>")
+    }
+
+    fn game_by_extension_arg() -> Arg {
+        Arg::new("game-by-extension")
+            .long("game-by-extension")
+            .value_name("EXT=GAME,...")
+            .conflicts_with("game-map")
+            .help(format!("Pick the game of each script by its extension when <game> is omitted, e.g. scx=sg0,msb=rn [default: ${}]", GAME_BY_EXTENSION_VAR))
+    }
+
+    fn keep_going_arg() -> Arg {
+        Arg::new("keep-going-on-corrupt")
+            .long("keep-going-on-corrupt")
+            .action(ArgAction::SetTrue)
+            .help("Skip the scripts that can't be opened because they're corrupt, and list them at the end")
+    }
+
+    // For the lists of char-freq and extract-names, which are tab-separated text without either.
+    fn list_format_args() -> [Arg; 2] {
+        [
+            Arg::new("json")
+                .long("json")
+                .action(ArgAction::SetTrue)
+                .help("Print the list as JSON"),
+            Arg::new("csv")
+                .long("csv")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("json")
+                .help("Print the list as CSV"),
+        ]
+    }
+
+    fn fail_fast_arg() -> Arg {
+        Arg::new("fail-fast")
+            .long("fail-fast")
+            .action(ArgAction::SetTrue)
+            .help("Stop at the first file that fails instead of moving on to the next one")
+    }
+
+    fn max_errors_arg() -> Arg {
+        Arg::new("max-errors")
+            .long("max-errors")
+            .value_name("N")
+            .value_parser(clap::builder::RangedU64ValueParser::<usize>::new().range(1..))
+            .help("Give up on a file after N lines fail, and on the whole run after N files do")
+    }
+
+    fn since_arg() -> Arg {
+        Arg::new("since")
+            .long("since")
+            .value_name("TIMESTAMP|FILE")
+            .value_parser(parse_since)
+            .help(
+                "Skip the files that weren't modified after this Unix timestamp, or after FILE was",
+            )
+    }
+
+    fn parse_since(arg: &str) -> Result<SystemTime, String> {
+        if let Ok(secs) = arg.parse::<u64>() {
+            return Ok(UNIX_EPOCH + Duration::from_secs(secs));
+        }
+        fs::metadata(arg)
+            .and_then(|x| x.modified())
+            .map_err(|err| format!("expected a Unix timestamp or a file, {}", err))
+    }
+
+    fn parse_codepoint_range(arg: &str) -> Result<RangeInclusive<char>, String> {
+        let codepoint = |s: &str| {
+            u32::from_str_radix(s.trim_start_matches("U+"), 16)
+                .ok()
+                .and_then(std::char::from_u32)
+                .ok_or(format!("'{}' is not a valid codepoint", s))
+        };
+        let (start, end) = arg
+            .split_once('-')
+            .ok_or("expected a range such as E12F-E2AF")?;
+        Ok(codepoint(start)?..=codepoint(end)?)
+    }
+
+    fn parse_line_filter(arg: &str) -> Result<LineFilter, String> {
+        match arg {
+            "dialogue" => Ok(LineFilter::Dialogue),
+            "names" => Ok(LineFilter::Names),
+            _ => match arg.strip_prefix("matching=") {
+                Some(pattern) if !pattern.is_empty() => {
+                    Ok(LineFilter::Matching(pattern.to_owned()))
+                }
+                _ => Err("expected 'dialogue', 'names' or 'matching=<pattern>'".to_owned()),
+            },
+        }
+    }
+
+    fn parse_missing_char_policy(arg: &str) -> Result<MissingCharPolicy, String> {
+        match arg {
+            "fail" => Ok(MissingCharPolicy::Fail),
+            "skip" => Ok(MissingCharPolicy::Skip),
+            _ => {
+                let mut chars = arg.strip_prefix("replace:").unwrap_or_default().chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => Ok(MissingCharPolicy::Replace(c)),
+                    _ => Err("expected 'fail', 'skip' or 'replace:<char>'".to_owned()),
+                }
+            }
+        }
+    }
+
+    fn lenient_arg() -> Arg {
+        Arg::new("lenient")
+            .long("lenient")
+            .action(ArgAction::SetTrue)
+            .help("Keep bytes that can't be decoded as [byte:XX] tags instead of failing")
+    }
+
+    // The options shared by replace-text and apply.
+    fn replace_args() -> Vec<Arg> {
+        let mut args = preserve_fullwidth_args("Preserve fullwidth characters").to_vec();
+        args.extend([
+            fail_fast_arg(),
+            max_errors_arg(),
+            Arg::new("strict")
+                .long("strict")
+                .action(ArgAction::SetTrue)
+                .help("Treat warnings as errors"),
+            Arg::new("split-shared")
+                .long("split-shared")
+                .action(ArgAction::SetTrue)
+                .help("Give changed lines that share a string with other lines their own copy"),
+            lenient_arg(),
+            Arg::new("text-ext")
+                .long("text-ext")
+                .value_name("SUFFIX")
+                .help("Only pair scripts with text files named <script stem>.<SUFFIX>"),
+            Arg::new("yes")
+                .long("yes")
+                .short('y')
+                .action(ArgAction::SetTrue)
+                .help("Don't ask for confirmation before modifying many files"),
+            Arg::new("confirm-threshold")
+                .long("confirm-threshold")
+                .value_name("N")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("50")
+                .help("Ask for confirmation before modifying more than N files"),
+            Arg::new("partial")
+                .long("partial")
+                .action(ArgAction::SetTrue)
+                .help("Replace the lines that can be encoded and report the ones that can't"),
+            Arg::new("map-report")
+                .long("map-report")
+                .action(ArgAction::SetTrue)
+                .help("List the characters substituted in each changed line in a .map file next to the text file"),
+            Arg::new("halfwidth-spaces")
+                .long("halfwidth-spaces")
+                .action(ArgAction::SetTrue)
+                .help("Don't count a line as changed if only the width of its spaces differs"),
+            Arg::new("sparse")
+                .long("sparse")
+                .action(ArgAction::SetTrue)
+                .help("Read text files written by extract-text --only, leaving the other lines untouched"),
+            Arg::new("placeholders")
+                .long("placeholders")
+                .action(ArgAction::SetTrue)
+                .help("Put back the tags from the .tags file written by extract-text --placeholders"),
+            Arg::new("report-json")
+                .long("report-json")
+                .value_name("PATH")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Write how many lines of each script were changed to a JSON file"),
+            Arg::new("subst-log")
+                .long("subst-log")
+                .value_name("PATH")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Write every character substituted in the changed lines of all the scripts to a file, with counts"),
+            Arg::new("preserve-tail")
+                .long("preserve-tail")
+                .action(ArgAction::SetTrue)
+                .help("Keep the [present] and [auto-forward] tags a string ends with when its new text has none"),
+            Arg::new("trim")
+                .long("trim")
+                .action(ArgAction::SetTrue)
+                .help("Strip ASCII whitespace from the start and the end of each line"),
+            Arg::new("check-controls")
+                .long("check-controls")
+                .action(ArgAction::SetTrue)
+                .help("Warn about control characters such as tabs in the text of changed lines, or fail with --strict"),
+            Arg::new("fixed-size")
+                .long("fixed-size")
+                .action(ArgAction::SetTrue)
+                .help("Keep each script its original size, padding it if the strings shrink and failing if they grow"),
+            Arg::new("max-line-factor")
+                .long("max-line-factor")
+                .value_name("N")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("10")
+                .help("Refuse text files with more than N lines per string of the script, or 0 to allow any number"),
+            Arg::new("on-missing")
+                .long("on-missing")
+                .value_name("POLICY")
+                .value_parser(parse_missing_char_policy)
+                .default_value("fail")
+                .help("What to do with characters that aren't in the charset: fail, skip them, or replace:<char> them with a fallback"),
+        ]);
+        args
+    }
+
+    // --preserve-fullwidth and a flag to turn it off for games that preserve fullwidth
+    // characters by default. The last one passed wins.
+    fn preserve_fullwidth_args(help: &'static str) -> [Arg; 2] {
+        [
+            Arg::new("preserve-fullwidth")
+                .long("preserve-fullwidth")
+                .action(ArgAction::SetTrue)
+                .overrides_with("no-preserve-fullwidth")
+                .help(help),
+            Arg::new("no-preserve-fullwidth")
+                .long("no-preserve-fullwidth")
+                .action(ArgAction::SetTrue)
+                .overrides_with("preserve-fullwidth")
+                .help("Don't preserve fullwidth characters, even if the game does by default"),
+        ]
+    }
+
+    fn text_format_arg(id: &'static str) -> Arg {
+        Arg::new(id)
+            .long(id)
+            .required(true)
+            .value_parser(["txt", "json"])
     }
 
     let after_help: String = {
@@ -77,6 +699,51 @@ pub fn run() -> Result<(), Box<dyn Error>> {
         .author("Committee of Zero")
         .version("2.1")
         .after_help(&after_help)
+        .arg(
+            Arg::new("context")
+                .long("context")
+                .global(true)
+                .action(ArgAction::SetTrue)
+                .help("Show the lines around the one an error occurred at"),
+        )
+        .arg(
+            Arg::new("quiet")
+                .long("quiet")
+                .short('q')
+                .global(true)
+                .action(ArgAction::SetTrue)
+                .help("Don't show a progress bar while going through multiple files"),
+        )
+        .arg(
+            Arg::new("verbose")
+                .long("verbose")
+                .short('v')
+                .global(true)
+                .action(ArgAction::SetTrue)
+                .help("Show which game definition is used"),
+        )
+        .arg(
+            Arg::new("no-color")
+                .long("no-color")
+                .global(true)
+                .action(ArgAction::SetTrue)
+                .help("Don't color the messages"),
+        )
+        .arg(
+            Arg::new("profile")
+                .long("profile")
+                .global(true)
+                .action(ArgAction::SetTrue)
+                .help("Print how long opening, decoding, encoding and writing took in all, once done"),
+        )
+        .arg(
+            Arg::new("reserved")
+                .long("reserved")
+                .global(true)
+                .value_name("START-END")
+                .value_parser(parse_codepoint_range)
+                .help("Override the range of codepoints reserved for new compound characters, e.g. E12F-E2AF"),
+        )
         .subcommand(
             Command::new("extract-text")
                 .about("Extracts text from one or multiple script files")
@@ -87,13 +754,75 @@ pub fn run() -> Result<(), Box<dyn Error>> {
                         .help("Path to the input file or a glob pattern")
                         .index(1)
                         .required(true),
-                    game_arg(2, &supported_games),
-                    Arg::new("preserve-fullwidth")
-                        .long("preserve-fullwidth")
+                    game_arg(2, &supported_games).required(false),
+                    game_map_arg(),
+                    game_by_extension_arg(),
+                    fail_fast_arg(),
+                    max_errors_arg(),
+                    keep_going_arg(),
+                    since_arg(),
+                    lenient_arg(),
+                    Arg::new("skip-errors")
+                        .long("skip-errors")
                         .action(ArgAction::SetTrue)
-                        .help("Preserve fullwidth characters")
-                        .required(false)
-                ]),
+                        .help("Write a [decode-error] line for each string that can't be decoded instead of failing"),
+                    Arg::new("out-ext")
+                        .long("out-ext")
+                        .value_name("SUFFIX")
+                        .help("Name the text files <script stem>.<SUFFIX> instead of <script name>.txt"),
+                    Arg::new("stamp")
+                        .long("stamp")
+                        .action(ArgAction::SetTrue)
+                        .help("Start each text file with a line that ties it to the script it was extracted from"),
+                    Arg::new("halfwidth-spaces")
+                        .long("halfwidth-spaces")
+                        .action(ArgAction::SetTrue)
+                        .help("Write fullwidth spaces as regular spaces, even with --preserve-fullwidth"),
+                    Arg::new("only")
+                        .long("only")
+                        .value_name("FILTER")
+                        .value_parser(parse_line_filter)
+                        .help("Only extract dialogue, names or matching=<pattern> lines, in the format expected by replace-text --sparse"),
+                    Arg::new("skip-empty-text")
+                        .long("skip-empty-text")
+                        .action(ArgAction::SetTrue)
+                        .help("Leave out the strings with only control tags, in the format expected by replace-text --sparse"),
+                    Arg::new("placeholders")
+                        .long("placeholders")
+                        .action(ArgAction::SetTrue)
+                        .help("Replace tags with numbered placeholders for machine translation, saving them to a .tags file"),
+                    Arg::new("manifest")
+                        .long("manifest")
+                        .value_name("PATH")
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .help("Write the script, the text file and the line count of every extraction to a JSON file"),
+                    Arg::new("per-line")
+                        .long("per-line")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with_all(["stamp", "out-ext"])
+                        .help("Write each string to a file of its own, <script stem>/NNNN.txt, in the format expected by replace-text --per-line"),
+                    Arg::new("group-by-dir")
+                        .long("group-by-dir")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with_all(["per-line", "stamp", "out-ext", "only", "skip-empty-text", "placeholders", "manifest", "since", "line-ending", "encoding"])
+                        .help("Write the text of all the scripts of a directory to txt/<directory>.bundle.txt, in the format expected by unbundle"),
+                    Arg::new("line-ending")
+                        .long("line-ending")
+                        .value_parser(["lf", "crlf"])
+                        .default_value("lf")
+                        .help("Line ending of the text files"),
+                    Arg::new("encoding")
+                        .long("encoding")
+                        .value_parser(["utf8", "utf16le", "utf16be"])
+                        .default_value("utf8")
+                        .help("Encoding of the text files, with a byte order mark for UTF-16"),
+                    Arg::new("tag-radix")
+                        .long("tag-radix")
+                        .value_parser(["dec", "hex"])
+                        .default_value("dec")
+                        .help("Base the numbers of tags like [font size] are written in, either is read back"),
+                ])
+                .args(preserve_fullwidth_args("Preserve fullwidth characters")),
         )
         .subcommand(
             Command::new("replace-text")
@@ -109,142 +838,2856 @@ pub fn run() -> Result<(), Box<dyn Error>> {
                         .help("Path to the input text file or a glob pattern")
                         .index(2)
                         .required(true),
-                        game_arg(3, &supported_games),
-                    Arg::new("preserve-fullwidth")
-                            .long("preserve-fullwidth")
+                        game_arg(3, &supported_games).required(false),
+                        game_map_arg(),
+                        game_by_extension_arg(),
+                        since_arg(),
+                        Arg::new("per-line")
+                            .long("per-line")
+                            .action(ArgAction::SetTrue)
+                            .conflicts_with("text-ext")
+                            .help("Read the directories of one file per string written by extract-text --per-line"),
+                        Arg::new("tsv")
+                            .long("tsv")
                             .action(ArgAction::SetTrue)
-                            .help("Preserve fullwidth characters")
-                            .required(false),
+                            .conflicts_with_all(["per-line", "text-ext", "sparse", "placeholders"])
+                            .help("Read a single TSV of lines and their translations, and replace the strings with those lines wherever they are"),
+                ])
+                .args(replace_args()),
+        )
+        .subcommand(
+            Command::new("apply")
+                .about("Applies a directory of text files to the scripts of another directory")
+                .display_order(10)
+                .disable_version_flag(true)
+                .args(&[
+                    Arg::new("script-dir")
+                        .help("Directory with the .scx or .msb scripts")
+                        .index(1)
+                        .required(true),
+                    Arg::new("text-dir")
+                        .help("Directory with the text files")
+                        .index(2)
+                        .required(true),
+                    game_arg(3, &supported_games),
+                ])
+                .args(replace_args()),
+        )
+        .subcommand(
+            Command::new("verify-apply")
+                .about("Checks that a directory of text files would apply to the scripts of another directory, without modifying them")
+                .display_order(10)
+                .disable_version_flag(true)
+                .args(&[
+                    Arg::new("script-dir")
+                        .help("Directory with the .scx or .msb scripts")
+                        .index(1)
+                        .required(true),
+                    Arg::new("text-dir")
+                        .help("Directory with the text files")
+                        .index(2)
+                        .required(true),
+                    game_arg(3, &supported_games),
+                ])
+                .args(replace_args()),
+        )
+        .subcommand(
+            Command::new("normalize-width")
+                .about("Converts the text of one or multiple script files to fullwidth or halfwidth characters")
+                .display_order(3)
+                .disable_version_flag(true)
+                .args(&[
+                    Arg::new("input")
+                        .help("Path to the input file or a glob pattern")
+                        .index(1)
+                        .required(true),
+                    game_arg(2, &supported_games),
+                    Arg::new("to")
+                        .long("to")
+                        .value_parser(["fullwidth", "halfwidth"])
+                        .default_value("halfwidth")
+                        .help("Target character width"),
+                    max_errors_arg(),
                 ]),
         )
-        .get_matches();
-    
-    match matches.subcommand() {
-        Some(("extract-text", sub_m)) => {
-            let input = sub_m.get_one::<String>("input").unwrap();
-            let game = sub_m.get_one::<String>("game").unwrap();
-            let gamedef = gamedef::get_by_alias(&defs, game).unwrap();
-            let keep_fullwidth_chars = sub_m.get_flag("preserve-fullwidth");
-            run_extract_text(parse_glob("input", input)?, gamedef, keep_fullwidth_chars)
-        }
-        Some(("replace-text", sub_m)) => {
-            let scripts = sub_m.get_one::<String>("scripts").unwrap();
-            let txts = sub_m.get_one::<String>("text-files").unwrap();
-            let game = sub_m.get_one::<String>("game").unwrap();
-            let gamedef = gamedef::get_by_alias(&defs, game).unwrap();
-            let keep_fullwidth_chars = sub_m.get_flag("preserve-fullwidth");
-
-            run_replace_text(
-                parse_glob("scripts", scripts)?,
-                parse_glob("text-files", txts)?,
-                &gamedef, keep_fullwidth_chars
-            )
-        }
-        _ => Ok(()),
-    }
-}
-
-fn run_extract_text(
-    paths: Paths,
-    gamedef: &GameDef,
-    keep_fullwidth_chars: bool,
-) -> Result<(), Box<dyn Error>> {
-    Ok(for entry in paths {
-        let path = entry?;
-        let out_dir = if let Some(script_dir) = path.parent() {
-            let out_dir = script_dir.join("txt");
-            fs::create_dir_all(&out_dir)?;
-            out_dir
-        } else {
-            continue;
-        };
-
-        let stem = if let Some(stem) = path.file_stem().and_then(|s| s.to_str()) {
-            stem.to_owned()
-        } else {
-            continue;
-        };
-
-        println!("Processing {:?}...", path);
-        let ext = ".".to_owned() + &path.extension().unwrap_or_default().to_str().unwrap() + ".txt";
-        let output = out_dir.join(stem + &ext);
-        if let Err(err) = extract_text(&path, &output, gamedef, keep_fullwidth_chars) {
-            report_err(err)
-        }
+        .subcommand(
+            Command::new("bundle")
+                .about("Extracts the text of multiple script files into a single file")
+                .display_order(4)
+                .disable_version_flag(true)
+                .args(&[
+                    Arg::new("input")
+                        .help("Path to the input file or a glob pattern")
+                        .index(1)
+                        .required(true),
+                    game_arg(2, &supported_games),
+                    Arg::new("output")
+                        .help("Path to the bundle file")
+                        .index(3)
+                        .required(true),
+                    fail_fast_arg(),
+                    max_errors_arg(),
+                ])
+                .args(preserve_fullwidth_args("Preserve fullwidth characters")),
+        )
+        .subcommand(
+            Command::new("unbundle")
+                .about("Splits a bundle back into text files that can be used with replace-text")
+                .display_order(5)
+                .disable_version_flag(true)
+                .args(&[
+                    Arg::new("input")
+                        .help("Path to the bundle file")
+                        .index(1)
+                        .required(true),
+                    Arg::new("out-dir")
+                        .help("Directory to write the text files to")
+                        .index(2)
+                        .required(true),
+                ]),
+        )
+        .subcommand(
+            Command::new("align")
+                .about("Carries a translation over to a new version of its script")
+                .display_order(8)
+                .disable_version_flag(true)
+                .args(&[
+                    Arg::new("old-script")
+                        .help("Path to the script the translation was made for")
+                        .index(1)
+                        .required(true),
+                    Arg::new("new-script")
+                        .help("Path to the new version of the script")
+                        .index(2)
+                        .required(true),
+                    Arg::new("old-text")
+                        .help("Path to the translated text file of the old script")
+                        .index(3)
+                        .required(true),
+                    Arg::new("new-text")
+                        .help("Path to write the text file of the new script to")
+                        .index(4)
+                        .required(true),
+                    game_arg(5, &supported_games),
+                    Arg::new("min-similarity")
+                        .long("min-similarity")
+                        .value_name("PERCENT")
+                        .value_parser(clap::value_parser!(u8).range(0..=100))
+                        .default_value("60")
+                        .help("How similar a changed line has to be to an old one to keep its translation"),
+                ])
+                .args(preserve_fullwidth_args(
+                    "Preserve fullwidth characters in the lines left untranslated",
+                )),
+        )
+        .subcommand(
+            Command::new("check-width")
+                .about("Looks for lines of text files that are too wide to fit in the text box")
+                .display_order(9)
+                .disable_version_flag(true)
+                .args(&[
+                    Arg::new("input")
+                        .help("Path to the text file or a glob pattern")
+                        .index(1)
+                        .required(true),
+                    game_arg(2, &supported_games),
+                    Arg::new("max")
+                        .long("max")
+                        .value_name("N")
+                        .value_parser(clap::value_parser!(u32))
+                        .help("How many columns a line can take up, if not the game's own limit"),
+                    Arg::new("sparse")
+                        .long("sparse")
+                        .action(ArgAction::SetTrue)
+                        .help("Read text files written by extract-text --only"),
+                ]),
+        )
+        .subcommand(
+            Command::new("lint-text")
+                .about("Checks that the tags of text files are well-formed, without the scripts")
+                .display_order(12)
+                .disable_version_flag(true)
+                .args(&[
+                    Arg::new("input")
+                        .help("Path to the text file or a glob pattern")
+                        .index(1)
+                        .required(true),
+                    game_arg(2, &supported_games),
+                    Arg::new("sparse")
+                        .long("sparse")
+                        .action(ArgAction::SetTrue)
+                        .help("Read text files written by extract-text --only"),
+                ]),
+        )
+        .subcommand(
+            Command::new("bench-decode")
+                .about("Decodes every string of one or multiple script files and reports how long it took")
+                .display_order(11)
+                .disable_version_flag(true)
+                .args(&[
+                    Arg::new("input")
+                        .help("Path to the input file or a glob pattern")
+                        .index(1)
+                        .required(true),
+                    game_arg(2, &supported_games),
+                    keep_going_arg(),
+                    lenient_arg(),
+                    Arg::new("repeat")
+                        .long("repeat")
+                        .value_name("N")
+                        .value_parser(clap::value_parser!(u32).range(1..))
+                        .default_value("1")
+                        .help("Decode the scripts N times, once the files are in the cache"),
+                    Arg::new("mmap")
+                        .long("mmap")
+                        .action(ArgAction::SetTrue)
+                        .help("Map the scripts into memory instead of reading them, if built with the mmap feature"),
+                ]),
+        )
+        .subcommand(
+            Command::new("roundtrip")
+                .about("Checks that every string of one or multiple script files is encoded back unchanged once extracted")
+                .display_order(13)
+                .disable_version_flag(true)
+                .args(&[
+                    Arg::new("input")
+                        .help("Path to the input file or a glob pattern")
+                        .index(1)
+                        .required(true),
+                    game_arg(2, &supported_games),
+                    keep_going_arg(),
+                    lenient_arg(),
+                ])
+                .args(preserve_fullwidth_args("Preserve fullwidth characters")),
+        )
+        .subcommand(
+            Command::new("txt-diff")
+                .about("Lists the lines that differ between two text files, with the characters that changed")
+                .display_order(14)
+                .disable_version_flag(true)
+                .args(&[
+                    Arg::new("old")
+                        .help("Path to the original text file")
+                        .index(1)
+                        .required(true),
+                    Arg::new("new")
+                        .help("Path to the changed text file")
+                        .index(2)
+                        .required(true),
+                    game_arg(3, &supported_games).required(false),
+                    Arg::new("ignore-width")
+                        .long("ignore-width")
+                        .action(ArgAction::SetTrue)
+                        .requires("game")
+                        .help("Treat fullwidth characters as their halfwidth counterparts, using the game's charset"),
+                ]),
+        )
+        .subcommand(
+            Command::new("dump-raw")
+                .about("Writes the bytes of each string of a script to a file of its own")
+                .display_order(15)
+                .disable_version_flag(true)
+                .args(&[
+                    Arg::new("script")
+                        .help("Path to the script file")
+                        .index(1)
+                        .required(true),
+                    Arg::new("out-dir")
+                        .help("Directory to write the NNNN.bin files to")
+                        .index(2)
+                        .required(true),
+                    game_arg(3, &supported_games)
+                        .required(false)
+                        .help("Game whose string offset convention to use, guessed if not given"),
+                    Arg::new("manifest")
+                        .long("manifest")
+                        .action(ArgAction::SetTrue)
+                        .help("Also write manifest.json with the offset and the size of each string"),
+                ]),
+        )
+        .subcommand(
+            Command::new("import-raw")
+                .about("Replaces the strings of a script with the bytes of NNNN.bin files, as written by dump-raw")
+                .display_order(16)
+                .disable_version_flag(true)
+                .args(&[
+                    Arg::new("script")
+                        .help("Path to the script file")
+                        .index(1)
+                        .required(true),
+                    Arg::new("in-dir")
+                        .help("Directory with the NNNN.bin files")
+                        .index(2)
+                        .required(true),
+                    game_arg(3, &supported_games)
+                        .required(false)
+                        .help("Game whose string offset convention and terminator to use, guessed if not given"),
+                ]),
+        )
+        .subcommand(
+            Command::new("list-empty")
+                .about("Prints the index of each string of a script that has no text, only control tags")
+                .display_order(17)
+                .disable_version_flag(true)
+                .args(&[
+                    Arg::new("script")
+                        .help("Path to the script file")
+                        .index(1)
+                        .required(true),
+                    game_arg(2, &supported_games),
+                ]),
+        )
+        .subcommand(
+            Command::new("self-test")
+                .about("Checks that every built-in game definition loads")
+                .display_order(7)
+                .disable_version_flag(true),
+        )
+        .subcommand(
+            Command::new("dump-charset")
+                .about("Prints the characters a game's font supports and the codes they're encoded as")
+                .display_order(6)
+                .disable_version_flag(true)
+                .args(&[
+                    game_arg(1, &supported_games),
+                    Arg::new("json")
+                        .long("json")
+                        .action(ArgAction::SetTrue)
+                        .help("Print the table as JSON"),
+                ]),
+        )
+        .subcommand(
+            Command::new("charset-diff")
+                .about("Prints the characters and compound characters of two games' charsets that differ")
+                .display_order(18)
+                .disable_version_flag(true)
+                .args(&[
+                    game_arg(1, &supported_games).id("game-a").value_name("GAME-A"),
+                    game_arg(2, &supported_games).id("game-b").value_name("GAME-B"),
+                    Arg::new("json")
+                        .long("json")
+                        .action(ArgAction::SetTrue)
+                        .help("Print the differences as JSON"),
+                ]),
+        )
+        .subcommand(
+            Command::new("charset-coverage")
+                .about("Counts how often the scripts of a game use each glyph of its charset, and lists the ones they never use")
+                .display_order(19)
+                .disable_version_flag(true)
+                .args(&[
+                    Arg::new("input")
+                        .help("Path to the input file or a glob pattern")
+                        .index(1)
+                        .required(true),
+                    game_arg(2, &supported_games),
+                    keep_going_arg(),
+                    Arg::new("json")
+                        .long("json")
+                        .action(ArgAction::SetTrue)
+                        .help("Print the used and unused glyphs as JSON"),
+                ]),
+        )
+        .subcommand(
+            Command::new("char-freq")
+                .about("Lists the characters the scripts of a game use, the most frequent first, such as for packing a font atlas")
+                .display_order(21)
+                .disable_version_flag(true)
+                .args(&[
+                    Arg::new("input")
+                        .help("Path to the input file or a glob pattern")
+                        .index(1)
+                        .required(true),
+                    game_arg(2, &supported_games),
+                    keep_going_arg(),
+                ])
+                .args(list_format_args()),
+        )
+        .subcommand(
+            Command::new("extract-names")
+                .about("Lists the speaker names of the scripts of a game with how many lines each one speaks, for a glossary")
+                .display_order(23)
+                .disable_version_flag(true)
+                .args(&[
+                    Arg::new("input")
+                        .help("Path to the input file or a glob pattern")
+                        .index(1)
+                        .required(true),
+                    game_arg(2, &supported_games),
+                    keep_going_arg(),
+                ])
+                .args(list_format_args())
+                .args(preserve_fullwidth_args("Preserve fullwidth characters")),
+        )
+        .subcommand(
+            Command::new("apply-names")
+                .about("Renames the speakers of the scripts of a game after a glossary of names, leaving the lines themselves as they are")
+                .display_order(24)
+                .disable_version_flag(true)
+                .args(&[
+                    Arg::new("input")
+                        .help("Path to the input file or a glob pattern")
+                        .index(1)
+                        .required(true),
+                    game_arg(2, &supported_games),
+                    Arg::new("glossary")
+                        .help("TSV of names and what to rename them to, one per line")
+                        .index(3)
+                        .required(true),
+                    keep_going_arg(),
+                ])
+                .args(preserve_fullwidth_args("Preserve fullwidth characters")),
+        )
+        .subcommand(
+            Command::new("build-charset")
+                .about("Writes the charset.utf8 of a font from the BMFont description of its atlas")
+                .display_order(25)
+                .disable_version_flag(true)
+                .args(&[
+                    Arg::new("font")
+                        .help("BMFont text file that says where each character is drawn in the atlas")
+                        .index(1)
+                        .required(true),
+                    Arg::new("output")
+                        .help("Path to the charset.utf8 to write")
+                        .index(2)
+                        .required(true),
+                    Arg::new("cell-width")
+                        .long("cell-width")
+                        .value_name("PIXELS")
+                        .value_parser(clap::value_parser!(u32))
+                        .help("Width of a cell of the glyph grid, if it's not the line height of the font"),
+                    Arg::new("cell-height")
+                        .long("cell-height")
+                        .value_name("PIXELS")
+                        .value_parser(clap::value_parser!(u32))
+                        .help("Height of a cell of the glyph grid, if it's not the line height of the font"),
+                ]),
+        )
+        .subcommand(
+            Command::new("convert-text")
+                .about("Converts a text file to the JSON token format or back")
+                .display_order(22)
+                .disable_version_flag(true)
+                .args(&[
+                    game_arg(1, &supported_games),
+                    Arg::new("input")
+                        .help("Path to the file to convert")
+                        .index(2)
+                        .required(true),
+                    Arg::new("output")
+                        .help("Path to write the converted file to")
+                        .index(3)
+                        .required(true),
+                    text_format_arg("from").help("Format of the input file"),
+                    text_format_arg("to").help("Format to convert to"),
+                ])
+                .args(preserve_fullwidth_args("Preserve fullwidth characters")),
+        )
+        .subcommand(
+            Command::new("inspect")
+                .about("Prints the tokens, the text and the bytes of a single string of a script")
+                .display_order(20)
+                .disable_version_flag(true)
+                .args(&[
+                    Arg::new("script")
+                        .help("Path to the script file")
+                        .index(1)
+                        .required(true),
+                    game_arg(2, &supported_games),
+                    Arg::new("index")
+                        .help("Index of the string, as in the line numbers of extract-text minus one")
+                        .index(3)
+                        .required(true)
+                        .value_parser(clap::value_parser!(usize)),
+                ]),
+        )
+        .get_matches();
+    
+    if matches.get_flag("no-color") {
+        COLOR.store(false, Ordering::Relaxed);
+    }
+    if matches.get_flag("profile") {
+        profile::enable();
+    }
+
+    if !matches!(matches.subcommand(), Some(("self-test", _))) {
+        for (name, err) in &broken_defs {
+            report_warning(&format!(
+                "{} is unavailable ({}). Run self-test for details",
+                name, err
+            ));
+        }
+    }
+
+    if let Some(range) = matches.get_one::<RangeInclusive<char>>("reserved") {
+        let game = matches
+            .subcommand()
+            .and_then(|(_, sub_m)| sub_m.try_get_one::<String>("game").ok().flatten());
+        let game = game.ok_or_else(|| {
+            Sc3ToolsError::InvalidArgument("--reserved needs a <game> to apply to".to_owned())
+        })?;
+        let def = defs
+            .iter_mut()
+            .find(|x| x.aliases.iter().any(|a| a.eq_ignore_ascii_case(game)));
+        if let Some(def) = def {
+            def.set_reserved_codepoints(range.clone())
+                .map_err(|err| Sc3ToolsError::InvalidArgument(err.to_string()))?;
+        }
+    }
+
+    if matches.get_flag("verbose") {
+        let game = matches
+            .subcommand()
+            .and_then(|(_, sub_m)| sub_m.try_get_one::<String>("game").ok().flatten());
+        if let Some(game) = game {
+            let def = gamedef::get_by_alias(&defs, game).unwrap();
+            eprintln!(
+                "Game: {} (alias \"{}\", resources/{}, {} glyphs)\n",
+                def.full_name,
+                game,
+                def.resource_dir,
+                def.charset().len()
+            );
+        }
+    }
+
+    let context = matches.get_flag("context");
+    let quiet = matches.get_flag("quiet");
+    // Shared by all the batches of the run.
+    let budget = matches
+        .subcommand()
+        .and_then(|(_, sub_m)| sub_m.try_get_one::<usize>("max-errors").ok().flatten())
+        .map(|max| ErrorBudget::new(*max));
+    // Not every command has --fail-fast.
+    let batch = |sub_m: &clap::ArgMatches| BatchOptions {
+        fail_fast: sub_m
+            .try_get_one::<bool>("fail-fast")
+            .ok()
+            .flatten()
+            .copied()
+            .unwrap_or(false),
+        context,
+        quiet,
+        since: None,
+        budget: budget.as_ref(),
+    };
+    // For the commands that have --since.
+    let since_batch = |sub_m: &clap::ArgMatches| BatchOptions {
+        since: sub_m.get_one::<SystemTime>("since").copied(),
+        ..batch(sub_m)
+    };
+    // The format of a list, from --json and --csv.
+    let list_format = |sub_m: &clap::ArgMatches| {
+        if sub_m.get_flag("json") {
+            "json"
+        } else if sub_m.get_flag("csv") {
+            "csv"
+        } else {
+            "text"
+        }
+    };
+    // The flags take precedence over the game's default.
+    let keep_fullwidth_chars = |sub_m: &clap::ArgMatches, gamedef: &GameDef| {
+        let flag = if sub_m.get_flag("preserve-fullwidth") {
+            Some(true)
+        } else if sub_m.get_flag("no-preserve-fullwidth") {
+            Some(false)
+        } else {
+            None
+        };
+        gamedef.keep_fullwidth_chars(flag)
+    };
+    let replace_options = |sub_m: &clap::ArgMatches, gamedef: &GameDef| ReplaceOptions {
+        keep_fullwidth_chars: keep_fullwidth_chars(sub_m, gamedef),
+        strict: sub_m.get_flag("strict"),
+        split_shared: sub_m.get_flag("split-shared"),
+        map_report: sub_m.get_flag("map-report"),
+        partial: sub_m.get_flag("partial"),
+        lenient: sub_m.get_flag("lenient"),
+        sparse: sub_m.get_flag("sparse"),
+        halfwidth_spaces: sub_m.get_flag("halfwidth-spaces"),
+        placeholders: sub_m.get_flag("placeholders"),
+        report_json: sub_m.get_one::<PathBuf>("report-json").cloned(),
+        subst_log: sub_m.get_one::<PathBuf>("subst-log").cloned(),
+        preserve_tail: sub_m.get_flag("preserve-tail"),
+        trim: sub_m.get_flag("trim"),
+        check_controls: sub_m.get_flag("check-controls"),
+        max_line_factor: Some(*sub_m.get_one::<usize>("max-line-factor").unwrap())
+            .filter(|x| *x > 0),
+        dry_run: false,
+        fixed_size: sub_m.get_flag("fixed-size"),
+        per_line: false,
+        tsv: false,
+        on_missing: *sub_m.get_one::<MissingCharPolicy>("on-missing").unwrap(),
+        max_errors: sub_m.get_one::<usize>("max-errors").copied(),
+    };
+    let confirm_threshold = |sub_m: &clap::ArgMatches| {
+        if sub_m.get_flag("yes") {
+            None
+        } else {
+            sub_m.get_one::<usize>("confirm-threshold").cloned()
+        }
+    };
+    // The scripts of each game, as told by --game-map or else by <game>, and by the extension
+    // table only when <game> is omitted.
+    let games_of = |sub_m: &clap::ArgMatches, paths: Paths| {
+        let paths = paths.collect::<Result<Vec<_>, _>>()?;
+        let game = sub_m.get_one::<String>("game").map(String::as_str);
+        let by_extension = sub_m
+            .get_one::<String>("game-by-extension")
+            .cloned()
+            .or_else(|| std::env::var(GAME_BY_EXTENSION_VAR).ok());
+        let groups = match (sub_m.get_one::<PathBuf>("game-map"), game, by_extension) {
+            (Some(path), _, _) => {
+                let in_map = |err| ProcessingError::File(path.clone(), Box::new(err));
+                let map =
+                    GameMap::parse(&fs::read_to_string(path)?, &supported_games).map_err(in_map)?;
+                map.group(paths, game).map_err(in_map)?
+            }
+            (None, Some(game), _) => vec![(game.to_owned(), paths)],
+            (None, None, Some(table)) => {
+                let map = GameMap::by_extension(&table, &supported_games).map_err(|err| {
+                    Sc3ToolsError::InvalidArgument(format!("--game-by-extension: {}", err))
+                })?;
+                map.group(paths, None).map_err(|err| {
+                    Sc3ToolsError::InvalidArgument(match err {
+                        game_map::Error::Unmatched(files) => format!(
+                            "no game is listed for the extension of {}, pass a game for them",
+                            files
+                                .iter()
+                                .map(|x| x.display().to_string())
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        ),
+                        err => err.to_string(),
+                    })
+                })?
+            }
+            (None, None, None) => {
+                return Err(Sc3ToolsError::InvalidArgument(format!(
+                    "<game> is required unless --game-map, --game-by-extension or ${} is given",
+                    GAME_BY_EXTENSION_VAR
+                )))
+            }
+        };
+        let groups = groups
+            .into_iter()
+            .map(|(game, paths)| (gamedef::get_by_alias(&defs, &game).unwrap(), paths))
+            .collect::<Vec<_>>();
+        Ok::<_, Sc3ToolsError>(groups)
+    };
+    let result = match matches.subcommand() {
+        Some(("extract-text", sub_m)) => {
+            let input = sub_m.get_one::<String>("input").unwrap();
+            let out_ext = sub_m.get_one::<String>("out-ext");
+            let mut corrupt = CorruptScripts::new(sub_m.get_flag("keep-going-on-corrupt"));
+            let mut manifest = Vec::new();
+            let group_by_dir = sub_m.get_flag("group-by-dir");
+            let mut groups = Vec::new();
+            for (gamedef, paths) in games_of(sub_m, parse_glob("input", input)?)? {
+                let options = ExtractOptions {
+                    keep_fullwidth_chars: keep_fullwidth_chars(sub_m, gamedef),
+                    stamp: sub_m.get_flag("stamp"),
+                    lenient: sub_m.get_flag("lenient"),
+                    only: sub_m.get_one::<LineFilter>("only").cloned(),
+                    halfwidth_spaces: sub_m.get_flag("halfwidth-spaces"),
+                    crlf: sub_m.get_one::<String>("line-ending").unwrap() == "crlf",
+                    placeholders: sub_m.get_flag("placeholders"),
+                    encoding: match sub_m.get_one::<String>("encoding").unwrap().as_str() {
+                        "utf16le" => TextEncoding::Utf16Le,
+                        "utf16be" => TextEncoding::Utf16Be,
+                        _ => TextEncoding::Utf8,
+                    },
+                    skip_errors: sub_m.get_flag("skip-errors"),
+                    max_errors: sub_m.get_one::<usize>("max-errors").copied(),
+                    skip_empty_text: sub_m.get_flag("skip-empty-text"),
+                    per_line: sub_m.get_flag("per-line"),
+                    tag_radix: match sub_m.get_one::<String>("tag-radix").unwrap().as_str() {
+                        "hex" => TagRadix::Hex,
+                        _ => TagRadix::Dec,
+                    },
+                };
+                if group_by_dir {
+                    extract_grouped(
+                        paths,
+                        gamedef,
+                        &options,
+                        batch(sub_m),
+                        &mut corrupt,
+                        &mut groups,
+                    )?;
+                    continue;
+                }
+                run_extract_text(
+                    paths,
+                    gamedef,
+                    &options,
+                    out_ext.map(String::as_str),
+                    since_batch(sub_m),
+                    &mut corrupt,
+                    &mut manifest,
+                )?;
+            }
+            write_groups(&groups)?;
+            corrupt.report();
+            if let Some(path) = sub_m.get_one::<PathBuf>("manifest") {
+                let mut writer = BufWriter::new(File::create(path)?);
+                serde_json::to_writer_pretty(&mut writer, &manifest)?;
+                writeln!(writer)?;
+                writer.flush()?;
+            }
+            Ok(())
+        }
+        Some(("replace-text", sub_m)) => {
+            let scripts = sub_m.get_one::<String>("scripts").unwrap();
+            let txts = sub_m.get_one::<String>("text-files").unwrap();
+            let per_line = sub_m.get_flag("per-line");
+            // With --per-line, the text of a script is a directory, which a text file of the
+            // same name mustn't be mistaken for.
+            let text_files = parse_glob("text-files", txts)?
+                .map(|x| x.unwrap())
+                .filter(|x| !per_line || x.is_dir())
+                .collect::<Vec<_>>();
+            let text_ext = sub_m.get_one::<String>("text-ext");
+            let groups = games_of(sub_m, parse_glob("scripts", scripts)?)?
+                .into_iter()
+                .map(|(gamedef, scripts)| {
+                    let options = ReplaceOptions {
+                        per_line,
+                        tsv: sub_m.get_flag("tsv"),
+                        ..replace_options(sub_m, gamedef)
+                    };
+                    (gamedef, options, scripts)
+                })
+                .collect();
+            run_replace_text(
+                groups,
+                &text_files,
+                text_ext.map(String::as_str),
+                confirm_threshold(sub_m),
+                since_batch(sub_m),
+            )
+        }
+        Some((name @ ("apply" | "verify-apply"), sub_m)) => {
+            let script_dir = sub_m.get_one::<String>("script-dir").unwrap();
+            let text_dir = sub_m.get_one::<String>("text-dir").unwrap();
+            let game = sub_m.get_one::<String>("game").unwrap();
+            let gamedef = gamedef::get_by_alias(&defs, game).unwrap();
+            let text_ext = sub_m.get_one::<String>("text-ext");
+            let dry_run = name == "verify-apply";
+            let options = ReplaceOptions {
+                dry_run,
+                ..replace_options(sub_m, gamedef)
+            };
+            run_apply(
+                Path::new(script_dir),
+                Path::new(text_dir),
+                gamedef,
+                &options,
+                text_ext.map(String::as_str),
+                confirm_threshold(sub_m).filter(|_| !dry_run),
+                batch(sub_m),
+            )
+        }
+        Some(("normalize-width", sub_m)) => {
+            let input = sub_m.get_one::<String>("input").unwrap();
+            let game = sub_m.get_one::<String>("game").unwrap();
+            let gamedef = gamedef::get_by_alias(&defs, game).unwrap();
+            let to_fullwidth = sub_m.get_one::<String>("to").unwrap() == "fullwidth";
+            run_normalize_width(
+                parse_glob("input", input)?,
+                gamedef,
+                to_fullwidth,
+                batch(sub_m),
+            )
+        }
+        Some(("bundle", sub_m)) => {
+            let input = sub_m.get_one::<String>("input").unwrap();
+            let game = sub_m.get_one::<String>("game").unwrap();
+            let gamedef = gamedef::get_by_alias(&defs, game).unwrap();
+            let output = sub_m.get_one::<String>("output").unwrap();
+            run_bundle(
+                parse_glob("input", input)?,
+                gamedef,
+                output,
+                keep_fullwidth_chars(sub_m, gamedef),
+                batch(sub_m),
+            )
+        }
+        Some(("unbundle", sub_m)) => {
+            let input = sub_m.get_one::<String>("input").unwrap();
+            let out_dir = sub_m.get_one::<String>("out-dir").unwrap();
+            unbundle(input, out_dir)
+        }
+        Some(("align", sub_m)) => {
+            let game = sub_m.get_one::<String>("game").unwrap();
+            let gamedef = gamedef::get_by_alias(&defs, game).unwrap();
+            let min_similarity = *sub_m.get_one::<u8>("min-similarity").unwrap();
+            let options = AlignOptions {
+                keep_fullwidth_chars: keep_fullwidth_chars(sub_m, gamedef),
+                min_similarity: f64::from(min_similarity) / 100.0,
+            };
+            align_text(
+                sub_m.get_one::<String>("old-script").unwrap(),
+                sub_m.get_one::<String>("new-script").unwrap(),
+                sub_m.get_one::<String>("old-text").unwrap(),
+                sub_m.get_one::<String>("new-text").unwrap(),
+                gamedef,
+                &options,
+            )
+        }
+        Some(("check-width", sub_m)) => {
+            let input = sub_m.get_one::<String>("input").unwrap();
+            let game = sub_m.get_one::<String>("game").unwrap();
+            let gamedef = gamedef::get_by_alias(&defs, game).unwrap();
+            let max = sub_m
+                .get_one::<u32>("max")
+                .cloned()
+                .or(gamedef.max_line_width)
+                .ok_or_else(|| {
+                    let msg = format!("{} has no line width limit, pass --max", game);
+                    Sc3ToolsError::InvalidArgument(msg)
+                })?;
+            check_width(
+                parse_glob("input", input)?,
+                gamedef,
+                max,
+                sub_m.get_flag("sparse"),
+            )
+        }
+        Some(("lint-text", sub_m)) => {
+            let input = sub_m.get_one::<String>("input").unwrap();
+            let game = sub_m.get_one::<String>("game").unwrap();
+            let gamedef = gamedef::get_by_alias(&defs, game).unwrap();
+            lint_text(
+                parse_glob("input", input)?,
+                gamedef,
+                sub_m.get_flag("sparse"),
+            )
+        }
+        Some(("bench-decode", sub_m)) => {
+            let input = sub_m.get_one::<String>("input").unwrap();
+            let game = sub_m.get_one::<String>("game").unwrap();
+            let gamedef = gamedef::get_by_alias(&defs, game).unwrap();
+            let options = ExtractOptions {
+                lenient: sub_m.get_flag("lenient"),
+                ..Default::default()
+            };
+            if sub_m.get_flag("mmap") && !cfg!(feature = "mmap") {
+                let msg = "--mmap needs sc3tools to be built with the mmap feature";
+                return Err(Sc3ToolsError::InvalidArgument(msg.to_owned()));
+            }
+            bench_decode(
+                parse_glob("input", input)?,
+                gamedef,
+                &options,
+                *sub_m.get_one::<u32>("repeat").unwrap(),
+                sub_m.get_flag("mmap"),
+                &mut CorruptScripts::new(sub_m.get_flag("keep-going-on-corrupt")),
+            )
+        }
+        Some(("roundtrip", sub_m)) => {
+            let input = sub_m.get_one::<String>("input").unwrap();
+            let game = sub_m.get_one::<String>("game").unwrap();
+            let gamedef = gamedef::get_by_alias(&defs, game).unwrap();
+            let options = ExtractOptions {
+                keep_fullwidth_chars: keep_fullwidth_chars(sub_m, gamedef),
+                lenient: sub_m.get_flag("lenient"),
+                ..Default::default()
+            };
+            let mut corrupt = CorruptScripts::new(sub_m.get_flag("keep-going-on-corrupt"));
+            roundtrip(parse_glob("input", input)?, gamedef, &options, &mut corrupt)
+        }
+        Some(("txt-diff", sub_m)) => {
+            let gamedef = sub_m
+                .get_one::<String>("game")
+                .filter(|_| sub_m.get_flag("ignore-width"))
+                .map(|game| gamedef::get_by_alias(&defs, game).unwrap());
+            txt_diff(
+                sub_m.get_one::<String>("old").unwrap(),
+                sub_m.get_one::<String>("new").unwrap(),
+                gamedef,
+            )
+        }
+        Some(("dump-raw", sub_m)) => {
+            let offset_base = sub_m
+                .get_one::<String>("game")
+                .and_then(|game| gamedef::get_by_alias(&defs, game).unwrap().offset_base);
+            dump_raw(
+                sub_m.get_one::<String>("script").unwrap(),
+                sub_m.get_one::<String>("out-dir").unwrap(),
+                offset_base,
+                sub_m.get_flag("manifest"),
+            )
+        }
+        Some(("import-raw", sub_m)) => {
+            let gamedef = sub_m
+                .get_one::<String>("game")
+                .map(|game| gamedef::get_by_alias(&defs, game).unwrap());
+            import_raw(
+                sub_m.get_one::<String>("script").unwrap(),
+                sub_m.get_one::<String>("in-dir").unwrap(),
+                gamedef.and_then(|x| x.offset_base),
+                gamedef.map_or_else(Default::default, GameDef::dialect),
+                gamedef.is_none_or(|x| x.terminated),
+            )
+        }
+        Some(("list-empty", sub_m)) => {
+            let game = sub_m.get_one::<String>("game").unwrap();
+            let gamedef = gamedef::get_by_alias(&defs, game).unwrap();
+            let empty = list_empty(sub_m.get_one::<String>("script").unwrap(), gamedef)?;
+            for i in &empty {
+                println!("{}", i);
+            }
+            report_ok(&format!("{} strings have no text.", empty.len()));
+            Ok(())
+        }
+        Some(("self-test", _)) => self_test(),
+        Some(("dump-charset", sub_m)) => {
+            let game = sub_m.get_one::<String>("game").unwrap();
+            let gamedef = gamedef::get_by_alias(&defs, game).unwrap();
+            dump_charset(gamedef, sub_m.get_flag("json"))
+        }
+        Some(("charset-diff", sub_m)) => {
+            let games = [sub_m.get_one::<String>("game-a"), sub_m.get_one("game-b")]
+                .map(|x| x.unwrap().as_str());
+            let gamedefs = games.map(|x| gamedef::get_by_alias(&defs, x).unwrap());
+            print_charset_diff(games, gamedefs, sub_m.get_flag("json"))
+        }
+        Some(("charset-coverage", sub_m)) => {
+            let input = sub_m.get_one::<String>("input").unwrap();
+            let game = sub_m.get_one::<String>("game").unwrap();
+            let gamedef = gamedef::get_by_alias(&defs, game).unwrap();
+            let mut corrupt = CorruptScripts::new(sub_m.get_flag("keep-going-on-corrupt"));
+            charset_coverage(
+                parse_glob("input", input)?,
+                gamedef,
+                sub_m.get_flag("json"),
+                &mut corrupt,
+            )
+        }
+        Some(("char-freq", sub_m)) => {
+            let input = sub_m.get_one::<String>("input").unwrap();
+            let game = sub_m.get_one::<String>("game").unwrap();
+            let gamedef = gamedef::get_by_alias(&defs, game).unwrap();
+            let mut corrupt = CorruptScripts::new(sub_m.get_flag("keep-going-on-corrupt"));
+            char_freq(
+                parse_glob("input", input)?,
+                gamedef,
+                list_format(sub_m),
+                &mut corrupt,
+            )
+        }
+        Some(("extract-names", sub_m)) => {
+            let input = sub_m.get_one::<String>("input").unwrap();
+            let game = sub_m.get_one::<String>("game").unwrap();
+            let gamedef = gamedef::get_by_alias(&defs, game).unwrap();
+            let options = ExtractOptions {
+                keep_fullwidth_chars: keep_fullwidth_chars(sub_m, gamedef),
+                ..Default::default()
+            };
+            let mut corrupt = CorruptScripts::new(sub_m.get_flag("keep-going-on-corrupt"));
+            extract_names(
+                parse_glob("input", input)?,
+                gamedef,
+                &options,
+                list_format(sub_m),
+                &mut corrupt,
+            )
+        }
+        Some(("apply-names", sub_m)) => {
+            let input = sub_m.get_one::<String>("input").unwrap();
+            let game = sub_m.get_one::<String>("game").unwrap();
+            let gamedef = gamedef::get_by_alias(&defs, game).unwrap();
+            let glossary = sub_m.get_one::<String>("glossary").unwrap();
+            let glossary = tsv::parse(&encoding::read_to_string(glossary)?)
+                .map_err(|err| ProcessingError::File(glossary.into(), Box::new(err)))?;
+            let mut corrupt = CorruptScripts::new(sub_m.get_flag("keep-going-on-corrupt"));
+            apply_names(
+                parse_glob("input", input)?,
+                gamedef,
+                &glossary,
+                keep_fullwidth_chars(sub_m, gamedef),
+                &mut corrupt,
+            )
+        }
+        Some(("build-charset", sub_m)) => {
+            let cell_width = sub_m.get_one::<u32>("cell-width").copied();
+            let cell_height = sub_m.get_one::<u32>("cell-height").copied();
+            build_charset(
+                sub_m.get_one::<String>("font").unwrap(),
+                sub_m.get_one::<String>("output").unwrap(),
+                (cell_width, cell_height),
+            )
+        }
+        Some(("convert-text", sub_m)) => {
+            let game = sub_m.get_one::<String>("game").unwrap();
+            let gamedef = gamedef::get_by_alias(&defs, game).unwrap();
+            let format = |id| match sub_m.get_one::<String>(id).unwrap().as_str() {
+                "json" => TextFormat::Json,
+                _ => TextFormat::Txt,
+            };
+            let warnings = convert_text(
+                sub_m.get_one::<String>("input").unwrap(),
+                sub_m.get_one::<String>("output").unwrap(),
+                gamedef,
+                format("from"),
+                format("to"),
+                keep_fullwidth_chars(sub_m, gamedef),
+            )?;
+            for warning in &warnings {
+                report_warning(warning);
+            }
+            Ok(())
+        }
+        Some(("inspect", sub_m)) => {
+            let game = sub_m.get_one::<String>("game").unwrap();
+            let gamedef = gamedef::get_by_alias(&defs, game).unwrap();
+            let string = inspect(
+                sub_m.get_one::<String>("script").unwrap(),
+                gamedef,
+                *sub_m.get_one::<usize>("index").unwrap(),
+            )?;
+            print_inspected(&string).map_err(Into::into)
+        }
+        _ => Ok(()),
+    };
+    if matches.get_flag("profile") {
+        eprint!("{}", profile::summary());
+    }
+    result
+}
+
+fn run_extract_text(
+    paths: Vec<PathBuf>,
+    gamedef: &GameDef,
+    options: &ExtractOptions,
+    out_ext: Option<&str>,
+    batch: BatchOptions<'_>,
+    corrupt: &mut CorruptScripts,
+    manifest: &mut Vec<ExtractedFile>,
+) -> Result<(), Sc3ToolsError> {
+    let paths = skip_unmodified(paths, |x| vec![x.as_path()], batch.since)?;
+    batch.for_each_file(
+        paths,
+        |x| x.as_path(),
+        |path| {
+            let out_dir = if let Some(script_dir) = path.parent() {
+                let out_dir = script_dir.join("txt");
+                fs::create_dir_all(&out_dir)?;
+                out_dir
+            } else {
+                return Ok(());
+            };
+
+            let name = if options.per_line {
+                path.file_stem().map(ToOwned::to_owned)
+            } else {
+                naming::text_file_name(&path, out_ext)
+            };
+            let name = if let Some(name) = name {
+                name
+            } else {
+                return Ok(());
+            };
+
+            println!("Processing {:?}...", path);
+            let output = out_dir.join(name);
+            match extract_text(&path, &output, gamedef, options) {
+                Ok(line_count) => manifest.push(ExtractedFile {
+                    input_script: path,
+                    output_txt: output,
+                    line_count,
+                }),
+                Err(err) if corrupt.skip(&path, &err) => {}
+                Err(err) => batch.handle_err(err, gamedef)?,
+            }
+            Ok(())
+        },
+    )
+}
+
+// Extracts the text of each script into a section of the bundle of its directory, for
+// --group-by-dir. The bundles are written once the scripts of every game are extracted, since
+// a directory can hold the scripts of several games.
+fn extract_grouped(
+    paths: Vec<PathBuf>,
+    gamedef: &GameDef,
+    options: &ExtractOptions,
+    batch: BatchOptions<'_>,
+    corrupt: &mut CorruptScripts,
+    groups: &mut Vec<(PathBuf, Vec<bundle::Section>)>,
+) -> Result<(), Sc3ToolsError> {
+    batch.for_each_file(
+        paths,
+        |x| x.as_path(),
+        |path| {
+            let (dir, name) = match (path.parent(), path.file_name().and_then(|x| x.to_str())) {
+                (Some(dir), Some(name)) => (dir.to_owned(), name.to_owned()),
+                _ => return Ok(()),
+            };
+
+            println!("Processing {:?}...", path);
+            let lines = open_for_reading(File::open(&path)?, gamedef)
+                .map_err(Into::into)
+                .and_then(|script| extract_lines(&*script, &path, gamedef, options));
+            match lines {
+                Ok(lines) => {
+                    report_ok(&format!("Sucessfully extracted {} lines.", lines.len()));
+                    let section = bundle::Section { name, lines };
+                    match groups.iter_mut().find(|(x, _)| *x == dir) {
+                        Some((_, sections)) => sections.push(section),
+                        None => groups.push((dir, vec![section])),
+                    }
+                }
+                Err(err) if corrupt.skip(&path, &err) => {}
+                Err(err) => batch.handle_err(err, gamedef)?,
+            }
+            Ok(())
+        },
+    )
+}
+
+fn write_groups(groups: &[(PathBuf, Vec<bundle::Section>)]) -> io::Result<()> {
+    for (dir, sections) in groups {
+        let out_dir = dir.join("txt");
+        fs::create_dir_all(&out_dir)?;
+        // The scripts can be in the current directory, whose path is empty.
+        let dir_name = fs::canonicalize(dir.join("."))?
+            .file_name()
+            .map_or_else(|| "scripts".into(), |x| x.to_string_lossy().into_owned());
+        let path = out_dir.join(format!("{}.bundle.txt", dir_name));
+        let mut writer = BufWriter::new(File::create(&path)?);
+        for (i, section) in sections.iter().enumerate() {
+            if i > 0 {
+                writeln!(writer)?;
+            }
+            bundle::write_section(&mut writer, &section.name, &section.lines)?;
+        }
+        writer.flush()?;
+        println!(
+            "Wrote the text of {} scripts to {:?}.",
+            sections.len(),
+            path
+        );
+    }
+    Ok(())
+}
+
+// Replaces the text of the scripts of each game with the options of that game. Every group
+// is paired with its text files before any script is modified, so that a group that can't be
+// replaced stops the run before the others are, and the confirmation and the reports cover
+// the scripts of all the groups.
+fn run_replace_text(
+    groups: Vec<(&GameDef, ReplaceOptions, Vec<PathBuf>)>,
+    text_files: &[PathBuf],
+    text_ext: Option<&str>,
+    confirm_threshold: Option<usize>,
+    batch: BatchOptions<'_>,
+) -> Result<(), Sc3ToolsError> {
+    let mut pairs = Vec::new();
+    let mut broken_parts = Vec::new();
+    for (game, options, scripts) in &groups {
+        let pairing = if options.tsv {
+            // The translations of a TSV go with every script.
+            let tsv = match text_files {
+                [tsv] => tsv.as_path(),
+                _ => {
+                    let msg = format!("--tsv takes a single file, got {}", text_files.len());
+                    return Err(Sc3ToolsError::InvalidArgument(msg));
+                }
+            };
+            naming::Pairing {
+                scripts: scripts.iter().map(|x| (x.as_path(), vec![tsv])).collect(),
+                broken_parts: Vec::new(),
+                lone_text_files: Vec::new(),
+            }
+        } else {
+            naming::pair(scripts, text_files, text_ext)
+        };
+        broken_parts.extend(pairing.broken_parts);
+        let group = pairing.scripts.into_iter();
+        pairs.extend(group.map(|(script, txts)| (script, txts, *game, options)));
+    }
+    check_parts(&broken_parts)?;
+    // Only the text files count, as replacing a script makes it newer than the previous run
+    // every time.
+    let pairs = skip_unmodified(pairs, |(_, txts, ..)| txts.clone(), batch.since)?;
+    // The reports go to the same files whatever the game.
+    let reports = groups.first().map(|(_, options, _)| options);
+    replace_pairs(
+        pairs,
+        reports.and_then(|x| x.report_json.as_deref()),
+        reports.and_then(|x| x.subst_log.as_deref()),
+        confirm_threshold,
+        batch,
+    )?;
+    Ok(())
+}
+
+// Leaves out the items none of whose files were modified after `since`, and says how many
+// there were.
+fn skip_unmodified<T>(
+    items: Vec<T>,
+    files: impl Fn(&T) -> Vec<&Path>,
+    since: Option<SystemTime>,
+) -> io::Result<Vec<T>> {
+    let since = match since {
+        Some(since) => since,
+        None => return Ok(items),
+    };
+    let mut kept = Vec::with_capacity(items.len());
+    let mut skipped = 0;
+    for item in items {
+        let mut modified = false;
+        for file in files(&item) {
+            if fs::metadata(file)?.modified()? > since {
+                modified = true;
+                break;
+            }
+        }
+        if modified {
+            kept.push(item);
+        } else {
+            skipped += 1;
+        }
+    }
+    if skipped > 0 {
+        report_ok(&format!(
+            "Skipped {} scripts that weren't modified since then.",
+            skipped
+        ));
+    }
+    Ok(kept)
+}
+
+// Fails before any script is modified if the parts of a text file can't be put together.
+fn check_parts(broken_parts: &[(&Path, naming::PartsError)]) -> Result<(), Sc3ToolsError> {
+    for (script, err) in broken_parts {
+        report(&format!("{}: {}", script.display(), err));
+    }
+    if !broken_parts.is_empty() {
+        return Err(Sc3ToolsError::Failed(format!(
+            "the text of {} scripts is split into parts that don't fit together",
+            broken_parts.len()
+        )));
+    }
+    Ok(())
+}
+
+fn run_apply(
+    script_dir: &Path,
+    text_dir: &Path,
+    game: &GameDef,
+    options: &ReplaceOptions,
+    text_ext: Option<&str>,
+    confirm_threshold: Option<usize>,
+    batch: BatchOptions<'_>,
+) -> Result<(), Sc3ToolsError> {
+    let mut scripts = files_in(script_dir)?;
+    scripts.retain(|x| naming::is_script(x));
+    let mut text_files = files_in(text_dir)?;
+    text_files.retain(|x| naming::has_text_suffix(x, text_ext));
+    let pairing = naming::pair(&scripts, &text_files, text_ext);
+    for text_file in &pairing.lone_text_files {
+        report_warning(&format!(
+            "{} doesn't belong to any script",
+            text_file.display()
+        ));
+    }
+    check_parts(&pairing.broken_parts)?;
+
+    let untranslated = pairing.scripts.iter().filter(|(_, x)| x.is_empty()).count();
+    let lone_text_files = pairing.lone_text_files.len();
+    let pairs = pairing.scripts.into_iter();
+    let (replaced, failed) = replace_pairs(
+        pairs.map(|(x, txts)| (x, txts, game, options)).collect(),
+        options.report_json.as_deref(),
+        options.subst_log.as_deref(),
+        confirm_threshold,
+        batch,
+    )?;
+    let total = replaced + failed;
+    let applied = if options.dry_run {
+        format!("{} of {} text files would apply.", replaced, total)
+    } else {
+        format!("Applied {} of {} text files.", replaced, total)
+    };
+    let summary = format!(
+        "{} {} scripts had no text file, {} text files had no script.",
+        applied, untranslated, lone_text_files
+    );
+    if failed > 0 {
+        println!("{}\n", summary);
+        let fail = if options.dry_run {
+            "would fail"
+        } else {
+            "failed"
+        };
+        return Err(Sc3ToolsError::Failed(format!(
+            "{} text files {} to apply",
+            failed, fail
+        )));
+    }
+    report_ok(&summary);
+    Ok(())
+}
+
+fn files_in(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+// Replaces the text of each script that has a text file, with the game and the options it
+// comes with, and writes the reports of all of them. Returns how many of them were replaced
+// and how many failed.
+fn replace_pairs(
+    pairs: Vec<(&Path, Vec<&Path>, &GameDef, &ReplaceOptions)>,
+    report_json: Option<&Path>,
+    subst_log: Option<&Path>,
+    confirm_threshold: Option<usize>,
+    batch: BatchOptions<'_>,
+) -> Result<(usize, usize), Sc3ToolsError> {
+    let to_modify = pairs
+        .iter()
+        .filter(|(_, txt, ..)| !txt.is_empty())
+        .map(|(script, ..)| *script)
+        .collect::<Vec<_>>();
+    if let Some(threshold) = confirm_threshold {
+        if to_modify.len() > threshold && !confirm_replacement(&to_modify)? {
+            return Err(Sc3ToolsError::Aborted);
+        }
+    }
+
+    let mut replaced = 0;
+    let mut failed = 0;
+    let mut reports = Vec::new();
+    let res = batch.for_each_file(
+        pairs,
+        |(script, ..)| script,
+        |(script_path, txt_paths, game, options)| {
+            println!("Processing {:?}", script_path);
+            if !txt_paths.is_empty() {
+                match replace_text_parts(script_path, &txt_paths, game, options) {
+                    Ok(report) => {
+                        replaced += 1;
+                        reports.push(report);
+                    }
+                    Err(err) => {
+                        failed += 1;
+                        reports.push(ReplaceReport {
+                            file: script_path.to_owned(),
+                            errors: vec![err.to_string()],
+                            ..Default::default()
+                        });
+                        batch.handle_err(err, game)?;
+                    }
+                }
+            }
+            Ok(())
+        },
+    );
+    // The report covers the scripts done so far even if --fail-fast stopped the batch.
+    if let Some(path) = report_json {
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut writer, &reports)?;
+        writer.flush()?;
+    }
+    if let Some(path) = subst_log {
+        write_subst_log(path, &reports)?;
+    }
+    res?;
+    Ok((replaced, failed))
+}
+
+// Writes each substitution the scripts of `reports` needed as `<count>\t<substitution>`, the
+// most frequent first, counting each changed line once.
+fn write_subst_log(path: &Path, reports: &[ReplaceReport]) -> io::Result<()> {
+    let mut counts: Vec<(&Substitution, usize)> = Vec::new();
+    let line_subs = reports.iter().flat_map(|x| &x.substitutions);
+    for sub in line_subs.flat_map(|(_, subs)| subs) {
+        match counts.iter_mut().find(|(x, _)| *x == sub) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((sub, 1)),
+        }
+    }
+    counts.sort_by_key(|(sub, count)| (Reverse(*count), sub.to_string()));
+    let mut writer = BufWriter::new(File::create(path)?);
+    for (sub, count) in &counts {
+        writeln!(writer, "{}\t{}", count, sub)?;
+    }
+    writer.flush()
+}
+
+// Asks whether to go on with modifying a large number of scripts. Without a terminal to ask
+// on, as in a script or in CI, there's no one to answer and the run goes on.
+fn confirm_replacement(scripts: &[&Path]) -> Result<bool, Sc3ToolsError> {
+    const SAMPLE_SIZE: usize = 5;
+    if !io::stdin().is_terminal() {
+        return Ok(true);
+    }
+
+    println!("About to modify {} files in place:", scripts.len());
+    for script in scripts.iter().take(SAMPLE_SIZE) {
+        println!("    {}", script.display());
+    }
+    if scripts.len() > SAMPLE_SIZE {
+        println!("    ... and {} more", scripts.len() - SAMPLE_SIZE);
+    }
+    print!("Continue? [y/N] ");
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+fn run_normalize_width(
+    paths: Paths,
+    gamedef: &GameDef,
+    to_fullwidth: bool,
+    batch: BatchOptions<'_>,
+) -> Result<(), Sc3ToolsError> {
+    for entry in paths {
+        let path = entry?;
+        println!("Processing {:?}...", path);
+        if let Err(err) = normalize_width(&path, gamedef, to_fullwidth) {
+            batch.handle_err(err, gamedef)?;
+        }
+    }
+    Ok(())
+}
+
+fn run_bundle(
+    paths: Paths,
+    gamedef: &GameDef,
+    output: impl AsRef<Path>,
+    keep_fullwidth_chars: bool,
+    batch: BatchOptions<'_>,
+) -> Result<(), Sc3ToolsError> {
+    let paths = paths.collect::<Result<Vec<_>, _>>()?;
+    // A section is named after the file alone, so unbundle couldn't tell two scripts of the
+    // same name apart.
+    let mut names = HashMap::new();
+    for path in &paths {
+        if let Some(other) = path.file_name().and_then(|name| names.insert(name, path)) {
+            return Err(Sc3ToolsError::InvalidArgument(format!(
+                "{} and {} would both be bundled as {}",
+                other.display(),
+                path.display(),
+                path.file_name().unwrap().to_string_lossy()
+            )));
+        }
+    }
+
+    let options = ExtractOptions {
+        keep_fullwidth_chars,
+        ..Default::default()
+    };
+    let mut writer = BufWriter::new(File::create(output)?);
+    let mut first = true;
+    for path in paths {
+        let name = if let Some(name) = path.file_name().and_then(|s| s.to_str()) {
+            name.to_owned()
+        } else {
+            continue;
+        };
+
+        println!("Processing {:?}...", path);
+        let lines = open_for_reading(File::open(&path)?, gamedef)
+            .map_err(Into::into)
+            .and_then(|script| extract_lines(&*script, &path, gamedef, &options));
+        match lines {
+            Ok(lines) => {
+                if !first {
+                    writeln!(writer)?;
+                }
+                first = false;
+                bundle::write_section(&mut writer, &name, &lines)?;
+                report_ok(&format!("Sucessfully extracted {} lines.", lines.len()));
+            }
+            Err(err) => batch.handle_err(err, gamedef)?,
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn unbundle(input: impl AsRef<Path>, out_dir: impl AsRef<Path>) -> Result<(), Sc3ToolsError> {
+    let bundle = fs::read_to_string(&input)?;
+    let sections = bundle::parse(&bundle)
+        .map_err(|err| ProcessingError::File(input.as_ref().to_owned(), Box::new(err)))?;
+    fs::create_dir_all(&out_dir)?;
+    for section in &sections {
+        let path = out_dir.as_ref().join(section.name.clone() + ".txt");
+        let mut writer = BufWriter::new(File::create(&path)?);
+        for line in &section.lines {
+            writeln!(writer, "{}", line)?;
+        }
+        writer.flush()?;
+    }
+    report_ok(&format!("Sucessfully unbundled {} files.", sections.len()));
+    Ok(())
+}
+
+/// Converts a text file between the plain-text and the JSON token format, as described in
+/// `token_json`, and writes it to `output`. Returns what the conversion had to drop, such as
+/// the notes of a text file going to JSON.
+pub fn convert_text(
+    input: impl AsRef<Path>,
+    output: impl AsRef<Path>,
+    gamedef: &GameDef,
+    from: TextFormat,
+    to: TextFormat,
+    keep_fullwidth_chars: bool,
+) -> Result<Vec<String>, Sc3ToolsError> {
+    let s = encoding::read_to_string(&input)?;
+    let mut warnings = Vec::new();
+    let converted = token_json::convert(&s, from, to, gamedef, keep_fullwidth_chars, &mut warnings)
+        .map_err(|err| ProcessingError::File(input.as_ref().to_owned(), Box::new(err)))?;
+    fs::write(output, converted)?;
+    Ok(warnings)
+}
+
+fn check_width(
+    paths: Paths,
+    gamedef: &GameDef,
+    max: u32,
+    sparse: bool,
+) -> Result<(), Sc3ToolsError> {
+    let mut too_wide = 0;
+    for entry in paths {
+        let path = entry?;
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let text = encoding::read_to_string(&path)?;
+        let mut lines = text.lines().peekable();
+        if lines.peek().and_then(|x| Stamp::parse(x)).is_some() {
+            lines.next();
+        }
+        for (i, line) in lines.enumerate() {
+            let (index, line) = if sparse {
+                match sparse::parse_line(line) {
+                    Some(x) => x,
+                    None => continue,
+                }
+            } else {
+                (i, line)
+            };
+            let width = width::max_width(&note::split(line).0, &gamedef.glyph_widths);
+            if width > max {
+                too_wide += 1;
+                println!("{}, line {}: {} columns", name, index + 1, width);
+            }
+        }
+    }
+    if too_wide > 0 {
+        println!();
+        return Err(Sc3ToolsError::Failed(format!(
+            "{} lines are wider than {} columns",
+            too_wide, max
+        )));
+    }
+    report_ok(&format!("All lines fit in {} columns.", max));
+    Ok(())
+}
+
+fn lint_text(paths: Paths, gamedef: &GameDef, sparse: bool) -> Result<(), Sc3ToolsError> {
+    let mut with_errors = 0;
+    for entry in paths {
+        let path = entry?;
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let text = encoding::read_to_string(&path)?;
+        let mut lines = text.lines().peekable();
+        if lines.peek().and_then(|x| Stamp::parse(x)).is_some() {
+            lines.next();
+        }
+        for (i, line) in lines.enumerate() {
+            let (index, line) = if sparse {
+                match sparse::parse_line(line) {
+                    Some(x) => x,
+                    None => continue,
+                }
+            } else {
+                (i, line)
+            };
+            let issues = lint::lint_line(&note::split(line).0, gamedef);
+            for issue in &issues {
+                println!(
+                    "{}, line {}, column {}: {}",
+                    name,
+                    index + 1,
+                    issue.column,
+                    issue.message
+                );
+            }
+            if !issues.is_empty() {
+                with_errors += 1;
+            }
+        }
+    }
+    if with_errors > 0 {
+        println!();
+        return Err(Sc3ToolsError::Failed(format!(
+            "{} lines have malformed tags",
+            with_errors
+        )));
+    }
+    report_ok("All tags are well-formed.");
+    Ok(())
+}
+
+// Decodes the scripts the way extract-text does, without writing anything, `repeat` times.
+fn bench_decode(
+    paths: Paths,
+    gamedef: &GameDef,
+    options: &ExtractOptions,
+    repeat: u32,
+    mmap: bool,
+    corrupt: &mut CorruptScripts,
+) -> Result<(), Sc3ToolsError> {
+    let paths = paths.collect::<Result<Vec<_>, _>>()?;
+    // The corrupt scripts are left out of every pass.
+    let mut usable = Vec::with_capacity(paths.len());
+    for path in paths {
+        match format::open_with(File::open(&path)?, gamedef).map_err(Sc3ToolsError::from) {
+            Err(err) if corrupt.skip(&path, &err) => {}
+            _ => usable.push(path),
+        }
+    }
+    let paths = usable;
+    let (mut strings, mut bytes) = (0, 0);
+    let mut total = Duration::ZERO;
+    for pass in 1..=repeat {
+        strings = 0;
+        bytes = 0;
+        let start = Instant::now();
+        for path in &paths {
+            let script = if mmap {
+                open_for_reading(File::open(path)?, gamedef)?
+            } else {
+                format::open_with(File::open(path)?, gamedef)?
+            };
+            strings += extract_lines(&*script, path, gamedef, options)?.len();
+            bytes += script
+                .string_index()
+                .iter()
+                .map(|x| x.size() as u64)
+                .sum::<u64>();
+        }
+        let elapsed = start.elapsed();
+        if repeat > 1 {
+            println!("Pass {}: {:.1} ms", pass, elapsed.as_secs_f64() * 1000.0);
+        }
+        total += elapsed;
+    }
+    let per_pass = total.as_secs_f64() / repeat as f64;
+    if repeat > 1 {
+        println!();
+    }
+    report_ok(&format!(
+        "Decoded {} strings ({} bytes) from {} files in {:.1} ms{}, {:.2} MB/s.",
+        strings,
+        bytes,
+        paths.len(),
+        per_pass * 1000.0,
+        if repeat > 1 { " per pass" } else { "" },
+        bytes as f64 / per_pass.max(f64::EPSILON) / 1_000_000.0
+    ));
+    corrupt.report();
+    Ok(())
+}
+
+// Opens a script that's only read, mapped into memory when sc3tools is built with the mmap
+// feature. The commands that write scripts open them with `format::open_with` instead.
+fn open_for_reading(
+    file: File,
+    gamedef: &GameDef,
+) -> Result<Box<dyn format::MagesScript>, format::Error> {
+    #[cfg(feature = "mmap")]
+    {
+        format::open_mapped(&file, gamedef)
+    }
+    #[cfg(not(feature = "mmap"))]
+    {
+        format::open_with(file, gamedef)
+    }
+}
+
+fn roundtrip(
+    paths: Paths,
+    gamedef: &GameDef,
+    options: &ExtractOptions,
+    corrupt: &mut CorruptScripts,
+) -> Result<(), Sc3ToolsError> {
+    let (mut total, mut failed) = (0, 0);
+    for entry in paths {
+        let path = entry?;
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        match check_roundtrip(&path, gamedef, options) {
+            Ok(()) => {
+                total += 1;
+                println!("{}: OK", name);
+            }
+            Err(err) if corrupt.skip(&path, &err) => {}
+            Err(err) => {
+                total += 1;
+                failed += 1;
+                // The name of the script is already there, so only its line is added.
+                let reason = match err {
+                    Sc3ToolsError::Processing(ProcessingError::Script(_, i, err)) => {
+                        format!("line {}: {}", i + 1, err)
+                    }
+                    err => err.to_string(),
+                };
+                report(&format!("{}: FAILED ({})", name, reason));
+            }
+        }
+    }
+    corrupt.report();
+    if failed > 0 {
+        return Err(Sc3ToolsError::Failed(format!(
+            "{} of {} scripts don't round-trip",
+            failed, total
+        )));
+    }
+    report_ok(&format!("\nAll {} scripts round-trip.", total));
+    Ok(())
+}
+
+/// Extracts each string of a script the way extract-text does and encodes it back the way
+/// replace-text does for a changed line, failing at the first one that doesn't come out the
+/// same.
+pub fn check_roundtrip(
+    script_path: impl AsRef<Path>,
+    gamedef: &GameDef,
+    options: &ExtractOptions,
+) -> Result<(), Sc3ToolsError> {
+    let script_path = script_path.as_ref();
+    let script = open_for_reading(File::open(script_path)?, gamedef)?;
+    for (i, handle) in script.string_index().iter().enumerate() {
+        let at_line = |err: Box<dyn Error>| ProcessingError::Script(script_path.to_owned(), i, err);
+        let original = script.read_string(handle)?;
+        let serialized = original.serialize_with_radix(
+            gamedef,
+            options.keep_fullwidth_chars,
+            options.lenient,
+            options.tag_radix,
+        );
+        let line = serialized.map_err(|err| at_line(Box::new(err)))?;
+        let line = note::escape(&line.to_string()).into_owned();
+        let line = CozString(note::split(&line).0.into_owned().into());
+        let fullwidth = uses_fullwidth(&original, gamedef, options.lenient)
+            .map_err(|err| at_line(Box::new(err)))?;
+        let encoded = Sc3String::deserialize(&line, gamedef, fullwidth)
+            .map_err(|err| at_line(Box::new(err)))?;
+        if encoded.0 != original.0 {
+            let (original, encoded) = (&original.0[..], &encoded.0[..]);
+            let at = original
+                .iter()
+                .zip(encoded)
+                .position(|(a, b)| a != b)
+                .unwrap_or_else(|| original.len().min(encoded.len()));
+            let bytes = |x: &[u8]| match &x[at..x.len().min(at + 8)] {
+                [] => "nothing".to_owned(),
+                x => hex::encode_upper(x),
+            };
+            return Err(at_line(
+                format!(
+                    "byte {} was {} but is encoded back as {}, from \"{}\"",
+                    at,
+                    bytes(original),
+                    bytes(encoded),
+                    line
+                )
+                .into(),
+            )
+            .into());
+        }
+    }
+    Ok(())
+}
+
+// Prints each line that differs as `<line>: - <old> / + <new>`, or only one side for the lines
+// past the end of the other file. Notes are left out. With a game, fullwidth characters are
+// compared as halfwidth ones.
+fn txt_diff(
+    old: impl AsRef<Path>,
+    new: impl AsRef<Path>,
+    ignore_width: Option<&GameDef>,
+) -> Result<(), Sc3ToolsError> {
+    let normalize = |line: &String| {
+        let line = text::Text(note::split(line).0.into_owned().into());
+        match ignore_width {
+            Some(gamedef) => text::to_halfwidth(&line, &gamedef.encoding_maps)
+                .0
+                .into_owned(),
+            None => line.0.into_owned(),
+        }
+    };
+    let old = text_lines(old.as_ref())?;
+    let new = text_lines(new.as_ref())?;
+    let mut differ = 0;
+    for (i, pair) in old.iter().zip_longest(&new).enumerate() {
+        match pair {
+            EitherOrBoth::Both(a, b) => {
+                let (a, b) = (normalize(a), normalize(b));
+                if a == b {
+                    continue;
+                }
+                let (a, b) = diff::mark(&a, &b);
+                println!("{}: - {} / + {}", i + 1, a, b);
+            }
+            EitherOrBoth::Left(a) => println!("{}: - {}", i + 1, normalize(a)),
+            EitherOrBoth::Right(b) => println!("{}: + {}", i + 1, normalize(b)),
+        }
+        differ += 1;
+    }
+    if differ > 0 {
+        println!();
+        report_ok(&format!(
+            "{} of {} lines differ.",
+            differ,
+            old.len().max(new.len())
+        ));
+    } else {
+        report_ok("The text files match.");
+    }
+    Ok(())
+}
+
+fn self_test() -> Result<(), Sc3ToolsError> {
+    let defs = gamedef::load_builtin_gamedefs()?;
+    let mut failed = 0;
+    for (name, def) in &defs {
+        match def {
+            Ok(_) => println!("{}: OK", name),
+            Err(err) => {
+                failed += 1;
+                report(&format!("{}: FAILED ({})", name, err));
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(Sc3ToolsError::Failed(format!(
+            "{} of {} game definitions failed to load",
+            failed,
+            defs.len()
+        )));
+    }
+    report_ok(&format!("\nAll {} game definitions loaded.", defs.len()));
+    Ok(())
+}
+
+fn dump_charset(gamedef: &GameDef, json: bool) -> Result<(), Sc3ToolsError> {
+    let glyphs = charset::glyphs(gamedef);
+    let stdout = io::stdout();
+    let mut writer = BufWriter::new(stdout.lock());
+    if json {
+        serde_json::to_writer_pretty(&mut writer, &glyphs)?;
+        writeln!(writer)?;
+    } else {
+        for glyph in &glyphs {
+            writeln!(writer, "{}", glyph)?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+// How many times each character code comes up in the text of the scripts, and how many scripts
+// were read.
+fn count_codes(
+    paths: Paths,
+    gamedef: &GameDef,
+    corrupt: &mut CorruptScripts,
+) -> Result<(HashMap<u16, usize>, usize), Sc3ToolsError> {
+    let mut counts = HashMap::new();
+    let mut scripts = 0;
+    for entry in paths {
+        let path = entry?;
+        let opened = open_for_reading(File::open(&path)?, gamedef).map_err(Sc3ToolsError::from);
+        let script = match opened {
+            Ok(script) => script,
+            Err(err) if corrupt.skip(&path, &err) => continue,
+            Err(err) => return Err(ProcessingError::File(path, Box::new(err)).into()),
+        };
+        // Bytes that aren't tokens can't hide text, so they're skipped rather than failing.
+        for handle in script.string_index().iter() {
+            for tk in script.read_string(handle)?.iter_lenient(gamedef.dialect()) {
+                if let Ok(sc3::StringToken::Text(text)) = tk {
+                    for code in text.iter() {
+                        *counts.entry(*code).or_insert(0) += 1;
+                    }
+                }
+            }
+        }
+        scripts += 1;
+    }
+    corrupt.report();
+    Ok((counts, scripts))
+}
+
+fn charset_coverage(
+    paths: Paths,
+    gamedef: &GameDef,
+    json: bool,
+    corrupt: &mut CorruptScripts,
+) -> Result<(), Sc3ToolsError> {
+    let (counts, scripts) = count_codes(paths, gamedef, corrupt)?;
+    let coverage = charset::coverage(gamedef, &counts);
+    let stdout = io::stdout();
+    let mut writer = BufWriter::new(stdout.lock());
+    if json {
+        serde_json::to_writer_pretty(&mut writer, &coverage)?;
+        writeln!(writer)?;
+        writer.flush()?;
+        return Ok(());
+    }
+    writeln!(writer, "Used glyphs ({}):", coverage.used.len())?;
+    for used in &coverage.used {
+        writeln!(writer, "    {:>8}\t{}", used.count, used.glyph)?;
+    }
+    writeln!(writer, "Unused glyphs ({}):", coverage.unused.len())?;
+    for glyph in &coverage.unused {
+        writeln!(writer, "    {}", glyph)?;
+    }
+    writer.flush()?;
+    report_ok(&format!(
+        "{} scripts use {} of the {} glyphs.",
+        scripts,
+        coverage.used.len(),
+        coverage.used.len() + coverage.unused.len()
+    ));
+    Ok(())
+}
+
+// Quotes a field of a CSV file if it has to be.
+fn csv_field(s: &str) -> Cow<'_, str> {
+    if s.contains(['"', ',', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", s.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(s)
+    }
+}
+
+// Prints the speaker names of the scripts, the ones with the most lines first. The names are
+// written the way extract-text would write them.
+fn extract_names(
+    paths: Paths,
+    gamedef: &GameDef,
+    options: &ExtractOptions,
+    format: &str,
+    corrupt: &mut CorruptScripts,
+) -> Result<(), Sc3ToolsError> {
+    let mut lines = Vec::new();
+    let mut scripts = 0;
+    for entry in paths {
+        let path = entry?;
+        let opened = open_for_reading(File::open(&path)?, gamedef).map_err(Sc3ToolsError::from);
+        let script = match opened {
+            Ok(script) => script,
+            Err(err) if corrupt.skip(&path, &err) => continue,
+            Err(err) => return Err(ProcessingError::File(path, Box::new(err)).into()),
+        };
+        lines.extend(extract_lines(&*script, &path, gamedef, options)?);
+        scripts += 1;
+    }
+    corrupt.report();
+    let names = names::count(lines.iter().map(String::as_str));
+
+    let stdout = io::stdout();
+    let mut writer = BufWriter::new(stdout.lock());
+    match format {
+        "json" => {
+            serde_json::to_writer_pretty(&mut writer, &names)?;
+            writeln!(writer)?;
+        }
+        "csv" => {
+            writeln!(writer, "name,count")?;
+            for x in &names {
+                writeln!(writer, "{},{}", csv_field(&x.name), x.count)?;
+            }
+        }
+        _ => {
+            for x in &names {
+                writeln!(writer, "{}\t{}", x.name, x.count)?;
+            }
+        }
+    }
+    writer.flush()?;
+    if format == "text" {
+        report_ok(&format!(
+            "{} scripts have {} different speakers.",
+            scripts,
+            names.len()
+        ));
+    }
+    Ok(())
+}
+
+fn build_charset(
+    font: &str,
+    out: &str,
+    cell: (Option<u32>, Option<u32>),
+) -> Result<(), Sc3ToolsError> {
+    let charset = font::charset_from_bmfont(&fs::read_to_string(font)?, cell)
+        .map_err(|err| ProcessingError::File(font.into(), Box::new(err)))?;
+    fs::write(out, &charset.chars)?;
+    if charset.empty_cells > 0 {
+        report_warning(&format!(
+            "{} cells have no character and were written as U+{:04X}",
+            charset.empty_cells,
+            font::EMPTY_CELL as u32
+        ));
+    }
+    report_ok(&format!(
+        "Wrote the {} glyphs of {} to {}.",
+        charset.chars.chars().count(),
+        font,
+        out
+    ));
+    Ok(())
+}
+
+fn apply_names(
+    paths: Paths,
+    gamedef: &GameDef,
+    glossary: &HashMap<String, String>,
+    keep_fullwidth_chars: bool,
+    corrupt: &mut CorruptScripts,
+) -> Result<(), Sc3ToolsError> {
+    let (mut changed, mut scripts) = (0, 0);
+    let mut unknown = HashMap::new();
+    for entry in paths {
+        let path = entry?;
+        match rename_speakers(&path, gamedef, glossary, keep_fullwidth_chars) {
+            Ok(renamed) => {
+                changed += renamed.changed;
+                scripts += 1;
+                for (name, count) in renamed.unknown {
+                    *unknown.entry(name).or_insert(0) += count;
+                }
+            }
+            Err(err) if corrupt.skip(&path, &err) => {}
+            Err(err) => return Err(ProcessingError::File(path, Box::new(err)).into()),
+        }
+    }
+    corrupt.report();
+    if !unknown.is_empty() {
+        let list = names::sorted(unknown)
+            .iter()
+            .map(|x| format!("\n    {} ({})", x.name, x.count))
+            .join("");
+        report_warning(&format!(
+            "Names not in the glossary, with how many lines they speak:{}",
+            list
+        ));
+    }
+    report_ok(&format!(
+        "Renamed the speakers of {} lines in {} scripts.",
+        changed, scripts
+    ));
+    Ok(())
+}
+
+/// Renames the speakers of a script after `glossary`, which has the names written the way
+/// extract-names lists them. Only the bytes of the names change; the rest of each line is
+/// kept as it is in the script.
+pub fn rename_speakers(
+    script_file: impl AsRef<Path>,
+    gamedef: &GameDef,
+    glossary: &HashMap<String, String>,
+    keep_fullwidth_chars: bool,
+) -> Result<RenamedSpeakers, Sc3ToolsError> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&script_file)?;
+    let mut script = format::open_with(file, gamedef)?;
+    let decode_options = format::DecodeOptions {
+        keep_fullwidth_chars,
+        ..Default::default()
+    };
+    let scr_err = |err, i| ProcessingError::Script(script_file.as_ref().to_owned(), i, err);
+
+    let mut changes = HashMap::new();
+    let mut unknown = HashMap::new();
+    for (i, string) in script.strings(gamedef, decode_options).enumerate() {
+        let (handle, line) = string.map_err(|err| scr_err(Box::new(err), i))?;
+        let name = match names::speaker(&line) {
+            Some(x) => x,
+            None => continue,
+        };
+        let new_name = match glossary.get(&name) {
+            Some(x) if *x != name => x,
+            Some(_) => continue,
+            None => {
+                *unknown.entry(name).or_insert(0) += 1;
+                continue;
+            }
+        };
+        let orig = script.read_string(handle)?;
+        let span = names::name_span(&orig, gamedef.dialect())
+            .map_err(|err| scr_err(Box::new(err), i))?
+            .unwrap();
+        let fullwidth =
+            uses_fullwidth(&orig, gamedef, false).map_err(|err| scr_err(Box::new(err), i))?;
+        let mut renamed = orig.0[..span.start].to_vec();
+        for seg in CozString(new_name.as_str().into()).iter() {
+            sc3::StringToken::deserialize(&seg, gamedef, fullwidth)
+                .map_err(|err| {
+                    scr_err(format!("'{}' in the glossary: {}", new_name, err).into(), i)
+                })?
+                .encode(&mut renamed, gamedef.code_unit)?;
+        }
+        renamed.extend_from_slice(&orig.0[span.end..]);
+        changes.insert(i, Sc3String(renamed.into()));
+    }
+    script.replace_strings(&changes, format::WriteOptions::default())?;
+    Ok(RenamedSpeakers {
+        changed: changes.len(),
+        unknown,
+    })
+}
+
+fn char_freq(
+    paths: Paths,
+    gamedef: &GameDef,
+    format: &str,
+    corrupt: &mut CorruptScripts,
+) -> Result<(), Sc3ToolsError> {
+    let (counts, scripts) = count_codes(paths, gamedef, corrupt)?;
+    let frequencies = charset::char_frequencies(gamedef, &counts);
+    let stdout = io::stdout();
+    let mut writer = BufWriter::new(stdout.lock());
+    match format {
+        "json" => {
+            serde_json::to_writer_pretty(&mut writer, &frequencies)?;
+            writeln!(writer)?;
+        }
+        "csv" => {
+            writeln!(writer, "char,count")?;
+            for x in &frequencies {
+                writeln!(writer, "{},{}", csv_field(&x.char.to_string()), x.count)?;
+            }
+        }
+        _ => {
+            for x in &frequencies {
+                writeln!(writer, "{}\t{}", x.char, x.count)?;
+            }
+        }
+    }
+    writer.flush()?;
+    if format == "text" {
+        report_ok(&format!(
+            "{} scripts use {} different characters.",
+            scripts,
+            frequencies.len()
+        ));
+    }
+    Ok(())
+}
+
+fn print_charset_diff(
+    games: [&str; 2],
+    gamedefs: [&GameDef; 2],
+    json: bool,
+) -> Result<(), Sc3ToolsError> {
+    let diff = charset::diff(gamedefs[0], gamedefs[1]);
+    let stdout = io::stdout();
+    let mut writer = BufWriter::new(stdout.lock());
+    if json {
+        serde_json::to_writer_pretty(&mut writer, &diff)?;
+        writeln!(writer)?;
+        writer.flush()?;
+        return Ok(());
+    }
+    if diff.is_empty() {
+        report_ok(&format!(
+            "The charsets of {} and {} are the same.",
+            games[0], games[1]
+        ));
+        return Ok(());
+    }
+    let only_in = [&diff.only_in_first, &diff.only_in_second];
+    for (game, chars) in games.iter().zip(only_in) {
+        writeln!(writer, "Only in {} ({}):", game, chars.len())?;
+        for ch in chars {
+            writeln!(writer, "    U+{:04X}\t{}", *ch as u32, ch)?;
+        }
+    }
+    writeln!(
+        writer,
+        "Compound characters that differ ({}):",
+        diff.compounds.len()
+    )?;
+    let text = |x: &Option<String>| match x {
+        Some(x) => format!("[{}]", x),
+        None => "(none)".to_owned(),
+    };
+    for compound in &diff.compounds {
+        writeln!(
+            writer,
+            "    U+{:04X}\t{}: {}\t{}: {}",
+            compound.char as u32,
+            games[0],
+            text(&compound.first),
+            games[1],
+            text(&compound.second)
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn print_inspected(string: &InspectedString) -> io::Result<()> {
+    let hex = |bytes: &[u8]| bytes.iter().map(|b| format!("{:02X}", b)).join(" ");
+    let mut writer = BufWriter::new(io::stdout().lock());
+    writeln!(
+        writer,
+        "String {} of {}, at 0x{:X}, {} bytes",
+        string.index,
+        string.count,
+        string.offset,
+        string.bytes.len()
+    )?;
+    writeln!(writer, "Header: {}", hex(&string.header))?;
+    match &string.text {
+        Ok(text) => writeln!(writer, "Text:  {}", text)?,
+        Err(err) => writeln!(writer, "Text:  can't be written as a line ({})", err)?,
+    }
+    writeln!(writer, "Bytes: {}", hex(&string.bytes))?;
+    writeln!(writer, "Tokens:")?;
+    for tk in &string.tokens {
+        writeln!(
+            writer,
+            "  {:>5}\t{}\t{}",
+            tk.offset,
+            tk.text,
+            hex(&tk.bytes)
+        )?;
+    }
+    writer.flush()
+}
+
+// Where a string dumped by dump-raw came from, as listed in its manifest.
+#[derive(Serialize)]
+struct RawString {
+    index: usize,
+    file: String,
+    // The offset of the string in the script file.
+    offset: u32,
+    size: usize,
+}
+
+/// Writes the bytes of each string of the script to `<index>.bin` in `out_dir`, and lists
+/// them in `manifest.json` if `manifest` is set.
+pub fn dump_raw(
+    script_path: impl AsRef<Path>,
+    out_dir: impl AsRef<Path>,
+    offset_base: Option<format::OffsetBase>,
+    manifest: bool,
+) -> Result<(), Sc3ToolsError> {
+    let script = format::open_with_offset_base(File::open(&script_path)?, offset_base)?;
+    let out_dir = out_dir.as_ref();
+    fs::create_dir_all(out_dir)?;
+    let index = script.string_index();
+    let width = index.count().to_string().len().max(4);
+    let mut strings = Vec::with_capacity(index.count());
+    for (i, handle) in index.iter().enumerate() {
+        let (offset, size) = (handle.0.start, handle.size());
+        let file = format!("{:0width$}.bin", i, width = width);
+        fs::write(out_dir.join(&file), &script.read_string(handle)?.0)?;
+        strings.push(RawString {
+            index: i,
+            file,
+            offset,
+            size,
+        });
+    }
+    if manifest {
+        let mut writer = BufWriter::new(File::create(out_dir.join("manifest.json"))?);
+        serde_json::to_writer_pretty(&mut writer, &strings).map_err(io::Error::from)?;
+        writer.flush()?;
+    }
+    report_ok(&format!(
+        "Wrote {} strings to {}.",
+        strings.len(),
+        out_dir.display()
+    ));
+    Ok(())
+}
+
+/// Replaces the strings of the script with the bytes of the `<index>.bin` files in `in_dir`,
+/// without decoding or encoding them. A terminator is added to the ones that lack it if
+/// `terminated` is set; the bytes are split into the tokens of `dialect` to tell, as the last
+/// character of a string can end in the same byte as the terminator. Other files are ignored.
+pub fn import_raw(
+    script_path: impl AsRef<Path>,
+    in_dir: impl AsRef<Path>,
+    offset_base: Option<format::OffsetBase>,
+    dialect: sc3::Dialect,
+    terminated: bool,
+) -> Result<(), Sc3ToolsError> {
+    const TERMINATOR: u8 = 0xFF;
+
+    let ends_with_terminator = |bytes: &[u8]| {
+        let mut rest = bytes;
+        let mut terminated = false;
+        while !rest.is_empty() {
+            let s = Sc3String(rest.into());
+            let mut tokens = s.iter_lenient(dialect);
+            tokens.by_ref().for_each(drop);
+            terminated = tokens.terminated();
+            rest = &rest[rest.len() - tokens.remaining().len()..];
+        }
+        terminated
+    };
+
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&script_path)?;
+    let mut script = format::open_with_offset_base(file, offset_base)?;
+    let count = script.string_index().count();
+    let mut changes = HashMap::new();
+    let mut seen = HashSet::new();
+    for path in files_in(in_dir.as_ref())? {
+        let index = match path.file_stem().and_then(|x| x.to_str()) {
+            Some(stem)
+                if path.extension().is_some_and(|x| x == "bin")
+                    && !stem.is_empty()
+                    && stem.chars().all(|c| c.is_ascii_digit()) =>
+            {
+                stem.parse::<usize>()
+                    .map_err(|err| ProcessingError::File(path.clone(), Box::new(err)))?
+            }
+            _ => continue,
+        };
+        let name = path.file_name().unwrap().to_string_lossy();
+        if index >= count {
+            let msg = format!("the script only has {} strings", count);
+            return Err(ProcessingError::File(path, msg.into()).into());
+        }
+        let mut bytes = fs::read(&path)?;
+        if terminated && !ends_with_terminator(&bytes) {
+            report_warning(&format!(
+                "{} doesn't end with a terminator, adding one",
+                name
+            ));
+            bytes.push(TERMINATOR);
+        }
+        if !seen.insert(index) {
+            let msg = format!("more than one file is string {}", index);
+            return Err(ProcessingError::File(path, msg.into()).into());
+        }
+        let original = script.read_string(script.string_index().get(index).unwrap())?;
+        if original.0 != bytes {
+            changes.insert(index, Sc3String(bytes.into()));
+        }
+    }
+
+    // Each file stands for one string, so a changed one gets its own copy if it's shared.
+    if !changes.is_empty() {
+        let options = format::WriteOptions {
+            split_shared: true,
+            ..Default::default()
+        };
+        script.replace_strings(&changes, options)?;
+    }
+    report_ok(&format!(
+        "Replaced {} of {} strings, {} were unchanged.",
+        changes.len(),
+        count,
+        seen.len() - changes.len()
+    ));
+    Ok(())
+}
+
+fn extract_lines(
+    script: &dyn format::MagesScript,
+    script_path: &impl AsRef<Path>,
+    gamedef: &GameDef,
+    options: &ExtractOptions,
+) -> Result<Vec<String>, Sc3ToolsError> {
+    let decode_options = format::DecodeOptions {
+        keep_fullwidth_chars: options.keep_fullwidth_chars,
+        lenient: options.lenient,
+        tag_radix: options.tag_radix,
+    };
+    let mut lines = Vec::with_capacity(script.string_index().count());
+    let mut errors = 0;
+    for (i, string) in script.strings(gamedef, decode_options).enumerate() {
+        let mut serialized = match string {
+            Ok((_, x)) => x,
+            Err(err @ Sc3ToolsError::Io(_)) => return Err(err),
+            Err(_) if options.skip_errors => {
+                errors += 1;
+                if options.max_errors == Some(errors) {
+                    let path = script_path.as_ref().to_owned();
+                    let game = gamedef.full_name.clone();
+                    return Err(ProcessingError::TooManyErrors(path, errors, game).into());
+                }
+                lines.push(DECODE_ERROR.to_owned());
+                continue;
+            }
+            Err(err) => {
+                let path = script_path.as_ref().to_owned();
+                return Err(ProcessingError::Script(path, i, Box::new(err)).into());
+            }
+        };
+        if options.halfwidth_spaces {
+            serialized = text::to_halfwidth_spaces(&serialized);
+        }
+        lines.push(note::escape(&serialized).into_owned());
+    }
+    // A dump that was cut short ends in the middle of its last string, which is then read up
+    // to the end of the file.
+    let last = script
+        .string_index()
+        .iter()
+        .find(|x| x.0.end == script.eof());
+    let unterminated = match last {
+        Some(last) if gamedef.terminated => {
+            let s = script.read_string(last)?;
+            let mut tokens = s.iter(gamedef.dialect()).cut_short(true);
+            tokens.by_ref().for_each(drop);
+            !tokens.terminated()
+        }
+        _ => false,
+    };
+    if unterminated {
+        report_warning(&format!(
+            "{}: the last string has no terminator and was read up to the end of the file",
+            script_path.as_ref().display()
+        ));
+    }
+    Ok(lines)
+}
+
+/// Extracts the text of a script to `out`, and returns the number of lines written.
+pub fn extract_text(
+    script_path: &impl AsRef<Path>,
+    out: &impl AsRef<Path>,
+    gamedef: &GameDef,
+    options: &ExtractOptions,
+) -> Result<usize, Sc3ToolsError> {
+    let open = profile::start(Phase::Open);
+    let script = open_for_reading(File::open(script_path)?, gamedef)?;
+    drop(open);
+    let decode = profile::start(Phase::Decode);
+    let lines = extract_lines(&*script, script_path, gamedef, options)?;
+    drop(decode);
+    let _write = profile::start(Phase::Write);
+    let sparse = options.only.is_some() || options.skip_empty_text;
+    let newline = if options.crlf { "\r\n" } else { "\n" };
+    let encoding = options.encoding;
+    let (notes, mut writer) = if options.per_line {
+        per_line::prepare_dir(out.as_ref())?;
+        (HashMap::new(), None)
+    } else {
+        let notes = existing_notes(out.as_ref(), sparse);
+        let mut writer = BufWriter::new(File::create(out)?);
+        writer.write_all(encoding.bom())?;
+        if options.stamp {
+            let stamp = format!("{}{}", Stamp::of(&*script)?, newline);
+            writer.write_all(&encoding.encode(&stamp))?;
+        }
+        (notes, Some(writer))
+    };
+    let mut count = 0;
+    let mut tags = Vec::new();
+    let mut empty = 0;
+    for (i, text) in lines.iter().enumerate() {
+        if sparse {
+            let string = script.read_string(script.string_index().get(i).unwrap())?;
+            if options.skip_empty_text && !string.has_text(gamedef.dialect()) {
+                empty += 1;
+                continue;
+            }
+            let filter = options.only.as_ref();
+            if filter.is_some_and(|x| !x.matches(&string, text, gamedef)) {
+                continue;
+            }
+        }
+        let masked;
+        let text = if options.placeholders {
+            let (text, line_tags) = placeholder::mask(text);
+            tags.push((i, line_tags));
+            masked = text;
+            &masked
+        } else {
+            text
+        };
+        let mut line = note::join(text, notes.get(&i).map(String::as_str)).into_owned();
+        if sparse && !options.per_line {
+            line = sparse::format_line(i, &line);
+        }
+        line.push_str(newline);
+        match &mut writer {
+            Some(writer) => writer.write_all(&encoding.encode(&line))?,
+            None => {
+                let path = out.as_ref().join(per_line::file_name(i, lines.len()));
+                fs::write(path, [encoding.bom(), &encoding.encode(&line)].concat())?;
+            }
+        }
+        count += 1;
+    }
+    if let Some(writer) = &mut writer {
+        writer.flush()?;
+    }
+    if options.placeholders {
+        placeholder::write_sidecar(&placeholder::sidecar_path(out.as_ref()), &tags)?;
+    }
+
+    if count > 0 {
+        report_ok(&format!("Sucessfully extracted {} lines.", count));
+    } else if !lines.is_empty() && empty < lines.len() {
+        report_ok("No lines matched the filter.");
+    } else if !lines.is_empty() {
+        report_ok("None of the strings have text.");
+    } else {
+        report_ok("No text data to be extracted.");
+    }
+    let skipped = lines
+        .iter()
+        .positions(|x| x == DECODE_ERROR)
+        .collect::<Vec<_>>();
+    if !skipped.is_empty() {
+        report_warning(&format!(
+            "{} strings could not be decoded and were extracted as {}: {}",
+            skipped.len(),
+            DECODE_ERROR,
+            skipped.iter().map(|x| x + 1).join(", ")
+        ));
+    }
+    Ok(count)
+}
+
+/// A string of a script broken down into its tokens, as printed by inspect.
+#[derive(Debug)]
+pub struct InspectedString {
+    pub index: usize,
+    /// How many strings the script has.
+    pub count: usize,
+    /// The header of the script, as it is in the file.
+    pub header: Vec<u8>,
+    /// The offset of the string in the script file.
+    pub offset: u32,
+    pub bytes: Vec<u8>,
+    /// The string as a line of a text file, or why it can't be written as one.
+    pub text: Result<String, String>,
+    pub tokens: Vec<InspectedToken>,
+}
+
+#[derive(Debug)]
+pub struct InspectedToken {
+    /// The offset of the token in the string.
+    pub offset: usize,
+    pub bytes: Vec<u8>,
+    /// The token as written in a text file, or as the decoder sees it if it can't be.
+    pub text: String,
+}
+
+/// Breaks the string at `index` of the script down into its tokens. Bytes that can't be
+/// decoded come out as `[byte:XX]` tokens, as the strings that need inspecting are often the
+/// broken ones.
+pub fn inspect(
+    script_path: impl AsRef<Path>,
+    gamedef: &GameDef,
+    index: usize,
+) -> Result<InspectedString, Sc3ToolsError> {
+    let script = open_for_reading(File::open(script_path)?, gamedef)?;
+    let string_index = script.string_index();
+    let count = string_index.count();
+    let handle = string_index.get(index).ok_or_else(|| {
+        Sc3ToolsError::InvalidArgument(format!(
+            "there's no string {}, the script only has {} strings",
+            index, count
+        ))
+    })?;
+    let offset = handle.0.start;
+    let string = script.read_string(handle)?;
+    let keep_fullwidth_chars = gamedef.keep_fullwidth_chars(None);
+
+    let mut tokens = Vec::new();
+    let mut iter = string.iter_lenient(gamedef.dialect());
+    loop {
+        let start = string.0.len() - iter.remaining().len();
+        let tk = match iter.next() {
+            Some(tk) => tk?,
+            None => break,
+        };
+        let end = string.0.len() - iter.remaining().len();
+        let debug = format!("{:?}", tk);
+        let text = tk
+            .serialize(gamedef, keep_fullwidth_chars)
+            .map_or_else(|err| format!("{} ({})", debug, err), |x| x.to_string());
+        tokens.push(InspectedToken {
+            offset: start,
+            bytes: string.0[start..end].to_vec(),
+            text,
+        });
+    }
+    let text = string
+        .serialize_lenient(gamedef, keep_fullwidth_chars)
+        .map(|x| x.0.into_owned())
+        .map_err(|err| err.to_string());
+    Ok(InspectedString {
+        index,
+        count,
+        header: script.header().to_vec(),
+        offset,
+        bytes: string.0.to_vec(),
+        text,
+        tokens,
     })
 }
 
-fn run_replace_text(
-    scripts: Paths,
-    text_files: Paths,
-    game: &GameDef,
-    keep_fullwidth_chars: bool,
-) -> Result<(), Box<dyn Error>> {
-    let text_files: Vec<_> = text_files.map(|x| x.unwrap()).collect();
-    Ok(for res in scripts {
-        let script_path = res?;
-        println!("Processing {:?}", script_path);
-        let script_fname = script_path.file_name();
-        let script_stem = script_path.file_stem();
-        let txt_path = text_files.iter().find(|p| {
-            let stem = p.file_stem();
-            stem == script_stem || stem == script_fname
-        });
-        if let Some(txt_path) = txt_path {
-            if let Err(err) = replace_text(script_path, txt_path, &game, keep_fullwidth_chars) {
-                report_err(err)
-            }
+/// The indices of the strings of a script that have no text, only control tags. These are
+/// the strings extract-text --skip-empty-text leaves out.
+pub fn list_empty(
+    script_path: impl AsRef<Path>,
+    gamedef: &GameDef,
+) -> Result<Vec<usize>, Sc3ToolsError> {
+    let script = open_for_reading(File::open(script_path)?, gamedef)?;
+    let mut empty = Vec::new();
+    for (i, handle) in script.string_index().iter().enumerate() {
+        if !script.read_string(handle)?.has_text(gamedef.dialect()) {
+            empty.push(i);
         }
-    })
+    }
+    Ok(empty)
 }
 
-fn extract_text(
+// The sparse text file that gives each string of the script whose line is in `pairs` its
+// translation.
+fn tsv_text(
+    script: &dyn format::MagesScript,
     script_path: &impl AsRef<Path>,
-    out: &impl AsRef<Path>,
     gamedef: &GameDef,
-    keep_fullwidth_chars: bool,
-) -> Result<(), Box<dyn Error>> {
-    let script = format::open(File::open(script_path)?)?;
-    let txt = File::create(out)?;
-    let mut writer = BufWriter::new(txt);
-
-    let table = &script.string_index();
-    for (i, handle) in table.iter().enumerate() {
-        let line = script.read_string(handle)?;
-        let serialized = line
-            .serialize(&gamedef, keep_fullwidth_chars)
-            .map_err(|err| {
-                ProcessingError::Script(script_path.as_ref().to_owned(), i, Box::new(err))
-            })?;
-        writeln!(writer, "{}", serialized)?;
+    options: &ReplaceOptions,
+    pairs: &HashMap<String, String>,
+) -> Result<String, Sc3ToolsError> {
+    let decode_options = format::DecodeOptions {
+        keep_fullwidth_chars: options.keep_fullwidth_chars,
+        lenient: options.lenient,
+        ..Default::default()
+    };
+    let mut text = String::new();
+    for (i, string) in script.strings(gamedef, decode_options).enumerate() {
+        let line = match string {
+            Ok((_, x)) => x,
+            Err(err @ Sc3ToolsError::Io(_)) => return Err(err),
+            Err(err) => {
+                let path = script_path.as_ref().to_owned();
+                return Err(ProcessingError::Script(path, i, Box::new(err)).into());
+            }
+        };
+        if let Some(translation) = pairs.get(note::escape(&line).as_ref()) {
+            text.push_str(&sparse::format_line(i, translation));
+            text.push('\n');
+        }
     }
+    Ok(text)
+}
 
-    if table.count() > 0 {
-        report_ok(&format!("Sucessfully extracted {} lines.", table.count()));
-    } else {
-        report_ok("No text data to be extracted.");
+// The notes of a text file that's about to be overwritten, by the index of their line.
+fn existing_notes(path: &Path, sparse: bool) -> HashMap<usize, String> {
+    let mut notes = HashMap::new();
+    let text = match encoding::read_to_string(path) {
+        Ok(text) => text,
+        Err(_) => return notes,
+    };
+    let mut lines = text.lines().peekable();
+    if lines.peek().and_then(|x| Stamp::parse(x)).is_some() {
+        lines.next();
     }
-    Ok(())
+    for (i, line) in lines.enumerate() {
+        let (index, line) = if sparse {
+            match sparse::parse_line(line) {
+                Some(x) => x,
+                None => continue,
+            }
+        } else {
+            (i, line)
+        };
+        if let (_, Some(note)) = note::split(line) {
+            notes.insert(index, note.to_owned());
+        }
+    }
+    notes
 }
 
-fn replace_text(
+pub fn replace_text(
     script_file: impl AsRef<Path>,
     text_file: impl AsRef<Path>,
     gamedef: &GameDef,
-    keep_fullwidth_chars: bool
-) -> Result<(), Box<dyn Error>> {
+    options: &ReplaceOptions,
+) -> Result<ReplaceReport, Sc3ToolsError> {
+    replace_text_parts(script_file, &[text_file], gamedef, options)
+}
+
+/// Replaces the text of a script with that of several text files read one after the other,
+/// such as the numbered parts of a translation. Each part may start with its own stamp. The
+/// placeholder sidecar and the substitution map belong to the first part.
+pub fn replace_text_parts(
+    script_file: impl AsRef<Path>,
+    text_files: &[impl AsRef<Path>],
+    gamedef: &GameDef,
+    options: &ReplaceOptions,
+) -> Result<ReplaceReport, Sc3ToolsError> {
+    let text_file = text_files
+        .first()
+        .ok_or_else(|| Sc3ToolsError::InvalidArgument("no text file to replace with".to_owned()))?;
+    // Without a terminator, the padding would be read as the end of the last string.
+    if options.fixed_size && !gamedef.terminated {
+        let msg = "--fixed-size needs strings that end with a terminator";
+        return Err(Sc3ToolsError::InvalidArgument(msg.to_owned()));
+    }
     let file = OpenOptions::new()
         .read(true)
-        .write(true)
+        .write(!options.dry_run)
         .open(&script_file)?;
-    let mut script = format::open(file)?;
-    let txt = BufReader::new(File::open(&text_file)?);
+    let open = profile::start(Phase::Open);
+    let mut script = format::open_with(file, gamedef)?;
+    drop(open);
+    if let Some(i) = script.string_index().first_unsorted() {
+        report_warning(&format!(
+            "{}: string {} is stored before strings that come earlier in the index, they're written back in the order they're stored",
+            script_file.as_ref().display(),
+            i
+        ));
+    }
+    let mut txt_lines = Vec::new();
+    // The line of the combined text each part starts at.
+    let mut line_starts = Vec::new();
+    for path in text_files {
+        let text = if options.per_line {
+            // Read as the sparse text file it amounts to.
+            per_line::read(path.as_ref())?
+                .iter()
+                .map(|(i, line)| sparse::format_line(*i, line) + "\n")
+                .collect()
+        } else if options.tsv {
+            let pairs = tsv::parse(&encoding::read_to_string(path)?)
+                .map_err(|err| ProcessingError::File(path.as_ref().to_owned(), Box::new(err)))?;
+            tsv_text(&*script, &script_file, gamedef, options, &pairs)?
+        } else {
+            encoding::read_to_string(path)?
+        };
+        let mut lines = text.lines().map(str::to_owned).peekable();
+        let stamp = lines.peek().and_then(|x| Stamp::parse(x));
+        if let Some(stamp) = stamp {
+            if stamp != Stamp::of(&*script)? {
+                return Err(ProcessingError::StampMismatch(path.as_ref().to_owned()).into());
+            }
+            lines.next();
+        }
+        line_starts.push(txt_lines.len());
+        txt_lines.extend(lines);
+        let count = script.string_index().count();
+        if let Some(factor) = options.max_line_factor {
+            if txt_lines.len() > count.max(1).saturating_mul(factor) {
+                let path = path.as_ref().to_owned();
+                return Err(ProcessingError::TooManyLines(path, txt_lines.len(), count).into());
+            }
+        }
+    }
+
+    // The string index each part starts at, for sparse text files.
+    let index_starts = line_starts
+        .iter()
+        .enumerate()
+        .map(|(k, start)| {
+            let end = line_starts.get(k + 1).copied().unwrap_or(txt_lines.len());
+            txt_lines[*start..end]
+                .iter()
+                .find_map(|x| sparse::parse_line(x))
+                .map_or(0, |(index, _)| index)
+        })
+        .collect::<Vec<_>>();
+    // The part a line of the combined text comes from, and the line within it. Lines of sparse
+    // text files are known by their string index once they're expanded.
+    let locate = |i: usize, by_index: bool| {
+        let starts = if by_index {
+            &index_starts
+        } else {
+            &line_starts
+        };
+        let k = starts.partition_point(|x| *x <= i).saturating_sub(1);
+        let line = if by_index { i } else { i - starts[k] };
+        (text_files[k].as_ref().to_owned(), line)
+    };
 
+    let sparse = options.sparse || options.per_line || options.tsv;
+    let txt_lines: Vec<Option<String>> = if sparse {
+        let count = script.string_index().count();
+        sparse::expand(txt_lines.iter().cloned(), count).map_err(|(i, err)| {
+            let (path, line) = locate(i, false);
+            ProcessingError::Text(path, line, Box::new(err))
+        })?
+    } else {
+        txt_lines.iter().cloned().map(Some).collect()
+    };
+    let tags = if options.placeholders {
+        let mut tags = HashMap::new();
+        for (path, start) in text_files.iter().zip(&line_starts) {
+            let path = placeholder::sidecar_path(path.as_ref());
+            let part_tags = placeholder::read_sidecar(&path)
+                .map_err(|err| ProcessingError::File(path.clone(), Box::new(err)))?;
+            let start = if sparse { 0 } else { *start };
+            tags.extend(part_tags.into_iter().map(|(i, x)| (i + start, x)));
+        }
+        Some(tags)
+    } else {
+        None
+    };
+    let txt_lines = txt_lines
+        .into_iter()
+        .enumerate()
+        .map(|(i, line)| {
+            let mut line = match line {
+                Some(x) => note::split(&x).0.into_owned(),
+                None => return Ok(None),
+            };
+            if line == DECODE_ERROR {
+                return Ok(None);
+            }
+            if options.trim {
+                line = line
+                    .trim_matches(|c: char| c.is_ascii_whitespace())
+                    .to_owned();
+            }
+            let line_tags = match &tags {
+                Some(tags) => tags.get(&i).map(Vec::as_slice).unwrap_or_default(),
+                None => return Ok(Some(line)),
+            };
+            placeholder::unmask(&line, line_tags)
+                .map(Some)
+                .map_err(|err| {
+                    let (path, line) = locate(i, sparse);
+                    ProcessingError::Text(path, line, err.into())
+                })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    // The lines the text file gives text for, changed or not.
+    let given = (0..txt_lines.len())
+        .filter(|i| txt_lines[*i].is_some())
+        .collect::<HashSet<_>>();
     let lines = script
         .string_index()
         .iter()
         .map(|x| script.read_string(x))
-        .zip_longest(txt.lines().map(|res| res.map(|s| CozString(s.into()))));
+        .zip_longest(txt_lines);
 
     let mut changes = Vec::new();
 
@@ -252,85 +3695,478 @@ fn replace_text(
         ProcessingError::Script(script_file.as_ref().to_owned(), line, err)
     };
 
-    let txt_err =
-        |err: Box<dyn Error>, line| ProcessingError::Text(text_file.as_ref().to_owned(), line, err);
+    let txt_err = |err: Box<dyn Error>, i| {
+        let (path, line) = locate(i, sparse);
+        ProcessingError::Text(path, line, err)
+    };
 
+    let decode = profile::start(Phase::Decode);
     for (i, line_pair) in lines.enumerate() {
         if let EitherOrBoth::Both(scr_line, txt_line) = line_pair {
+            let txt_line = match txt_line {
+                Some(s) => CozString(s.into()),
+                None => continue,
+            };
             let scr_line = scr_line?;
-            let txt_line = txt_line?;
-            for pair in scr_line.iter().zip_longest(txt_line.iter()) {
-                match pair {
-                    EitherOrBoth::Both(sc3, coz) => {
-                        let eq = equivalent(&sc3?, &coz, &gamedef, keep_fullwidth_chars)
-                            .map_err(|err| scr_err(Box::new(err), i))?;
-                        if !eq {
-                            changes.push((i, txt_line));
-                            break;
-                        }
-                    }
-                    EitherOrBoth::Left(sc3) => {
-                        let _ = sc3?;
-                        changes.push((i, txt_line));
-                        break;
-                    }
-                    EitherOrBoth::Right(coz) => {
-                        let _ = sc3::StringToken::deserialize(&coz, &gamedef, false)
-                            .map_err(|err| txt_err(Box::new(err), i))?;
-                        changes.push((i, txt_line));
-                        break;
-                    }
+            let scr_tokens = if options.lenient {
+                scr_line.iter_lenient(gamedef.dialect())
+            } else {
+                scr_line.iter(gamedef.dialect())
+            };
+            for pair in scr_tokens.zip_longest(txt_line.iter()) {
+                let eq: Result<bool, Sc3ToolsError> = match pair {
+                    EitherOrBoth::Both(sc3, coz) => sc3.map_err(Into::into).and_then(|sc3| {
+                        equivalent(&sc3, &coz, gamedef, options)
+                            .map_err(|err| scr_err(Box::new(err), i).into())
+                    }),
+                    EitherOrBoth::Left(sc3) => sc3.map(|_| false).map_err(Into::into),
+                    EitherOrBoth::Right(coz) => sc3::StringToken::deserialize(&coz, gamedef, false)
+                        .map(|_| false)
+                        .map_err(|err| txt_err(Box::new(err), i).into()),
                 };
+                match eq {
+                    Ok(true) => continue,
+                    Ok(false) => {}
+                    // The line is treated as changed and gets skipped when it fails to encode.
+                    Err(_) if options.partial => {}
+                    Err(err) => return Err(err),
+                }
+                changes.push((i, txt_line));
+                break;
             }
         } else {
-            return Err(Box::new(ProcessingError::LineCountMismatch));
+            return Err(ProcessingError::LineCountMismatch.into());
         }
     }
+    drop(decode);
 
-    let process_change = |i, s| {
+    let uses_fullwidth = |i| -> Result<bool, ProcessingError> {
         let index = &script.string_index();
         let orig = script.read_string(index.get(i).unwrap())?;
-        let mut fullwidth = false;
-        for tk in orig.iter() {
-            let tk = tk.map_err(|err| scr_err(Box::new(err), i))?;
-            if let sc3::StringToken::Text(text) = tk {
-                let decoded = text::decode_str(&text, gamedef, true)
-                    .map_err(|err| txt_err(Box::new(err), i))?;
-
-                fullwidth = decoded.iter(&gamedef.encoding_maps).any(|ch| {
-                    if let text::Char::Regular(c) = ch {
-                        c != text::FULLWIDTH_SPACE
-                            && text::is_fullwidth_ch(c)
-                            && text::replace_fullwidth(c).is_ascii_alphanumeric()
-                    } else {
-                        false
-                    }
+        uses_fullwidth(&orig, gamedef, options.lenient).map_err(|err| scr_err(Box::new(err), i))
+    };
+
+    // The control tokens the original string ends with, as tags.
+    let original_tail = |i| -> Result<String, ProcessingError> {
+        let index = &script.string_index();
+        let orig = script.read_string(index.get(i).unwrap())?;
+        let tokens = if options.lenient {
+            orig.iter_lenient(gamedef.dialect())
+        } else {
+            orig.iter(gamedef.dialect())
+        };
+        let tokens = tokens
+            .filter(|tk| !matches!(tk, Ok(sc3::StringToken::Terminator)))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| scr_err(Box::new(err), i))?;
+        let start = tokens
+            .iter()
+            .rposition(|tk| !tk.is_control())
+            .map_or(0, |x| x + 1);
+        let mut tail = String::new();
+        for tk in tokens.into_iter().skip(start) {
+            let seg = tk
+                .serialize(gamedef, false)
+                .map_err(|err| scr_err(Box::new(err), i))?;
+            tail.push_str(&seg.to_string());
+        }
+        Ok(tail)
+    };
+
+    let ends_with_control = |s: &CozString| match s.iter().last() {
+        Some(StringSegment::Tag(tag)) => {
+            matches!(sc3::StringToken::from_tag(&tag), Ok(Some(tk)) if tk.is_control())
+        }
+        _ => false,
+    };
+
+    let policy = if options.strict {
+        MissingCharPolicy::Fail
+    } else {
+        options.on_missing
+    };
+    let process_change = |i, s: &CozString| {
+        if options.check_controls {
+            if let Some(err) = control_char(s) {
+                if options.strict {
+                    return Err(txt_err(err.into(), i));
+                }
+                report_warning(&txt_err(err.into(), i).to_string());
+            }
+        }
+        let with_tail;
+        let s = if options.preserve_tail && !ends_with_control(s) {
+            with_tail = CozString(format!("{}{}", s, original_tail(i)?).into());
+            &with_tail
+        } else {
+            s
+        };
+        let fullwidth = uses_fullwidth(i)?;
+        let substitutions = if options.map_report || options.subst_log.is_some() {
+            line_substitutions(s, gamedef, fullwidth).map_err(|err| txt_err(Box::new(err), i))?
+        } else {
+            Vec::new()
+        };
+        let encode = profile::start(Phase::Encode);
+        let sc3 = Sc3String::deserialize_with_policy(s, gamedef, fullwidth, policy)
+            .map_err(|err| txt_err(Box::new(err), i))?;
+        drop(encode);
+        let missing = text::missing_chars(s, gamedef, fullwidth);
+        if !missing.is_empty() {
+            let what = match policy {
+                MissingCharPolicy::Replace(c) => format!("replaced with '{}'", c),
+                _ => "skipped".to_owned(),
+            };
+            let (path, line) = locate(i, sparse);
+            report_warning(&format!(
+                "{}, line {}: {} not in the charset, {}",
+                path.file_name().unwrap().to_string_lossy(),
+                line + 1,
+                missing.iter().join(" "),
+                what
+            ));
+        }
+        Ok::<_, ProcessingError>((sc3, substitutions))
+    };
+
+    let mut substitutions = Vec::new();
+    let mut skipped = Vec::new();
+    let mut errors = Vec::new();
+    let mut processed = HashMap::new();
+    for (i, s) in &changes {
+        match process_change(*i, s) {
+            Ok((sc3, line_subs)) => {
+                if !line_subs.is_empty() {
+                    substitutions.push((*i, line_subs));
+                }
+                processed.insert(*i, sc3);
+            }
+            Err(err) if options.partial => {
+                report_warning(&format!("{}; the line was skipped", err));
+                skipped.push(*i);
+                errors.push(err.to_string());
+                if options.max_errors == Some(errors.len()) {
+                    let path = script_file.as_ref().to_owned();
+                    let game = gamedef.full_name.clone();
+                    return Err(ProcessingError::TooManyErrors(path, errors.len(), game).into());
+                }
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+    let changes = processed;
+
+    if options.map_report && !options.dry_run {
+        let report_path = text_file.as_ref().with_extension("map");
+        let mut writer = BufWriter::new(File::create(report_path)?);
+        for (i, line_subs) in &substitutions {
+            writeln!(writer, "line {}: {}", i + 1, line_subs.iter().join(", "))?;
+        }
+        writer.flush()?;
+    }
+
+    if !options.split_shared {
+        let index = script.string_index();
+        for i in changes.keys().sorted() {
+            // A line the text file keeps as it was is given different text as much as one it
+            // changes otherwise.
+            let conflicting = index
+                .aliases(*i)
+                .into_iter()
+                .find(|x| match changes.get(x) {
+                    Some(s) => Some(s) != changes.get(i),
+                    None => given.contains(x) && !skipped.contains(x),
                 });
+            if let Some(other) = conflicting {
+                let path = script_file.as_ref().to_owned();
+                return Err(ProcessingError::ConflictingSharedString(path, *i, other).into());
+            }
+            // Lines that point at the same string can only be changed together, which the
+            // lines the text file leaves out are.
+            let aliases = index
+                .aliases(*i)
+                .into_iter()
+                .filter(|x| !changes.contains_key(x))
+                .collect::<Vec<_>>();
+            if !aliases.is_empty() {
+                let err =
+                    ProcessingError::SharedString(script_file.as_ref().to_owned(), *i, aliases);
+                if options.strict {
+                    return Err(err.into());
+                }
+                report_warning(&err.to_string());
+            }
+        }
+    }
+
+    let write_options = format::WriteOptions {
+        split_shared: options.split_shared,
+        fixed_size: options.fixed_size,
+    };
+    let line_count = script.string_index().count();
+    // Changes that keep the length of their strings are written in place whatever the size
+    // of the script, without rewriting the rest of it.
+    let same_length = !options.split_shared
+        && changes
+            .iter()
+            .all(|(i, s)| script.string_index().get(*i).unwrap().size() == s.0.len());
+    let write = profile::start(Phase::Write);
+    if options.dry_run {
+        // Writing to memory still catches what only fails once the strings are laid out.
+        let mut out = io::Cursor::new(Vec::new());
+        script.write_replaced(&changes, write_options, &mut out)?;
+    } else if !same_length && fs::metadata(&script_file)?.len() > STREAMING_THRESHOLD {
+        let mut tmp_path = script_file.as_ref().as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        let written = (|| -> io::Result<()> {
+            let mut out = BufWriter::new(File::create(&tmp_path)?);
+            script.write_replaced(&changes, write_options, &mut out)?;
+            out.flush()
+        })();
+        drop(script);
+        // The script is left as it was, without a half-written copy next to it.
+        if let Err(err) = written.and_then(|()| fs::rename(&tmp_path, &script_file)) {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(err.into());
+        }
+    } else {
+        script.replace_strings(&changes, write_options)?;
+    }
+    drop(write);
+
+    if !changes.is_empty() && options.dry_run {
+        report_ok(&format!(
+            "{} out of {} lines would be replaced.",
+            changes.len(),
+            line_count
+        ));
+    } else if !changes.is_empty() {
+        report_ok(&format!(
+            "Successfully replaced {} out of {} lines.",
+            changes.len(),
+            line_count
+        ));
+    } else {
+        report_ok("No changes found.");
+    }
+    if !skipped.is_empty() {
+        report_warning(&format!(
+            "Lines left unchanged because they could not be encoded: {}",
+            skipped.iter().map(|x| x + 1).join(", ")
+        ));
+    }
+    Ok(ReplaceReport {
+        file: script_file.as_ref().to_owned(),
+        total: line_count,
+        changed: changes.len(),
+        changed_indices: changes.keys().copied().sorted().collect(),
+        errors,
+        substitutions,
+    })
+}
+
+// Where the first control character in the text of a line is, if it has one. Tags are
+// skipped, as they're the only place where a tab or a line break could be meant.
+fn control_char(s: &CozString) -> Option<String> {
+    let mut column = 1;
+    for seg in s.iter() {
+        let written = seg.to_string();
+        if let StringSegment::Text(_) = seg {
+            if let Some((k, c)) = written.chars().enumerate().find(|(_, c)| c.is_control()) {
+                return Some(format!(
+                    "control character U+{:04X} at column {}",
+                    c as u32,
+                    column + k
+                ));
+            }
+        }
+        column += written.chars().count();
+    }
+    None
+}
 
-                if fullwidth {
-                    break;
+// Whether the text of a string has fullwidth letters or digits, in which case a changed
+// version of it is written with fullwidth characters as well.
+fn uses_fullwidth(s: &Sc3String, gamedef: &GameDef, lenient: bool) -> Result<bool, Sc3ToolsError> {
+    let tokens = if lenient {
+        s.iter_lenient(gamedef.dialect())
+    } else {
+        s.iter(gamedef.dialect())
+    };
+    for tk in tokens {
+        if let sc3::StringToken::Text(text) = tk? {
+            let decoded = text::decode_str(&text, gamedef, true)?;
+            let fullwidth = decoded.iter(&gamedef.encoding_maps).any(|ch| {
+                if let text::Char::Regular(c) = ch {
+                    c != text::FULLWIDTH_SPACE
+                        && text::is_fullwidth_ch(c)
+                        && text::replace_fullwidth(c).is_ascii_alphanumeric()
+                } else {
+                    false
                 }
+            });
+            if fullwidth {
+                return Ok(true);
             }
         }
+    }
+    Ok(false)
+}
 
-        Sc3String::deserialize(s, &gamedef, fullwidth).map_err(|err| txt_err(Box::new(err), i))
+pub fn align_text(
+    old_script_path: impl AsRef<Path>,
+    new_script_path: impl AsRef<Path>,
+    old_text: impl AsRef<Path>,
+    new_text: impl AsRef<Path>,
+    gamedef: &GameDef,
+    options: &AlignOptions,
+) -> Result<(), Sc3ToolsError> {
+    let extract_options = ExtractOptions {
+        keep_fullwidth_chars: options.keep_fullwidth_chars,
+        ..Default::default()
     };
+    let old_script = open_for_reading(File::open(&old_script_path)?, gamedef)?;
+    let new_script = open_for_reading(File::open(&new_script_path)?, gamedef)?;
+    let old_lines = extract_lines(&*old_script, &old_script_path, gamedef, &extract_options)?;
+    let new_lines = extract_lines(&*new_script, &new_script_path, gamedef, &extract_options)?;
 
-    let changes = changes
-        .iter()
-        .map(|(i, s)| Ok((*i, process_change(*i, s)?)))
-        .collect::<Result<HashMap<_, _>, ProcessingError>>()?;
+    let text = encoding::read_to_string(&old_text)?;
+    let mut translation = text.lines().peekable();
+    let stamp = translation.peek().and_then(|x| Stamp::parse(x));
+    if let Some(stamp) = stamp {
+        if stamp != Stamp::of(&*old_script)? {
+            return Err(ProcessingError::StampMismatch(old_text.as_ref().to_owned()).into());
+        }
+        translation.next();
+    }
+    let translation = translation.collect::<Vec<_>>();
+    if translation.len() != old_lines.len() {
+        return Err(ProcessingError::LineCountMismatch.into());
+    }
+
+    let mut writer = BufWriter::new(File::create(&new_text)?);
+    if stamp.is_some() {
+        writeln!(writer, "{}", Stamp::of(&*new_script)?)?;
+    }
+    let mut kept = 0;
+    let mut review = Vec::new();
+    for (j, m) in align::align(&old_lines, &new_lines, options.min_similarity)
+        .into_iter()
+        .enumerate()
+    {
+        let line = match m {
+            align::Match::Exact(i) => {
+                kept += 1;
+                translation[i]
+            }
+            align::Match::Similar(i, similarity) => {
+                kept += 1;
+                review.push(format!(
+                    "line {}: changed, kept the translation of old line {} ({:.0}% similar)",
+                    j + 1,
+                    i + 1,
+                    similarity * 100.0
+                ));
+                translation[i]
+            }
+            align::Match::Ambiguous(candidates) => {
+                review.push(format!(
+                    "line {}: left untranslated, as close to old lines {}",
+                    j + 1,
+                    candidates.iter().map(|x| x + 1).join(", ")
+                ));
+                &new_lines[j]
+            }
+            align::Match::None => {
+                review.push(format!("line {}: new, left untranslated", j + 1));
+                &new_lines[j]
+            }
+        };
+        writeln!(writer, "{}", line)?;
+    }
+    writer.flush()?;
+
+    for line in &review {
+        println!("{}", line);
+    }
+    if !review.is_empty() {
+        println!();
+    }
+    report_ok(&format!(
+        "Aligned {} of {} lines, {} to review.",
+        kept,
+        new_lines.len(),
+        review.len()
+    ));
+    Ok(())
+}
+
+fn line_substitutions(
+    s: &CozString,
+    gamedef: &GameDef,
+    fullwidth: bool,
+) -> Result<Vec<text::Substitution>, text::EncodingError> {
+    let mut substitutions = Vec::new();
+    for seg in s.iter() {
+        if let coz::StringSegment::Text(text) = seg {
+            for sub in text::substitutions(&text, gamedef, fullwidth)? {
+                if !substitutions.contains(&sub) {
+                    substitutions.push(sub);
+                }
+            }
+        }
+    }
+    Ok(substitutions)
+}
 
-    script.replace_strings(&changes)?;
+fn normalize_width(
+    script_path: impl AsRef<Path>,
+    gamedef: &GameDef,
+    to_fullwidth: bool,
+) -> Result<(), Sc3ToolsError> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&script_path)?;
+    let mut script = format::open_with(file, gamedef)?;
+    let scr_err = |err: Box<dyn Error>, line| {
+        ProcessingError::Script(script_path.as_ref().to_owned(), line, err)
+    };
+
+    let mut changes = HashMap::new();
+    for (i, handle) in script.string_index().iter().enumerate() {
+        let line = script.read_string(handle)?;
+        let mut buf = Vec::new();
+        for tk in line.iter(gamedef.dialect()) {
+            let tk = tk.map_err(|err| scr_err(Box::new(err), i))?;
+            let tk = if let sc3::StringToken::Text(chars) = tk {
+                let chars = text::normalize_width(&chars, gamedef, to_fullwidth)
+                    .map_err(|err| scr_err(Box::new(err), i))?;
+                sc3::StringToken::Text(chars.into())
+            } else {
+                tk
+            };
+            tk.encode(&mut buf, gamedef.code_unit)?;
+        }
+        if gamedef.terminated {
+            sc3::StringToken::Terminator.encode(&mut buf, gamedef.code_unit)?;
+        }
+        if buf != *line.0 {
+            changes.insert(i, Sc3String(buf.into()));
+        }
+    }
+
+    // Every line is re-encoded on its own, so lines sharing a string always agree.
+    script.replace_strings(&changes, format::WriteOptions::default())?;
 
     if !changes.is_empty() {
         report_ok(&format!(
-            "Successfully replaced {} out of {} lines.",
+            "Successfully converted {} out of {} lines.",
             changes.len(),
             script.string_index().count()
         ));
     } else {
-        report_ok("No changes found.");
+        report_ok("No changes needed.");
     }
     Ok(())
 }
@@ -339,23 +4175,27 @@ fn equivalent(
     scr_tk: &sc3::StringToken,
     txt_seg: &coz::StringSegment,
     gamedef: &GameDef,
-    width_insensitive: bool,
+    options: &ReplaceOptions,
 ) -> Result<bool, text::EncodingError> {
     if let coz::StringSegment::Text(txt_str) = txt_seg {
         if let sc3::StringToken::Text(scr_str) = scr_tk {
-            if width_insensitive {
-                let scr_str = text::decode_str(&scr_str, &gamedef, false)?;
-                let txt_str = text::to_halfwidth(&txt_str, &gamedef.encoding_maps);
+            if options.keep_fullwidth_chars {
+                let scr_str = text::decode_str(scr_str, gamedef, false)?;
+                let txt_str = text::to_halfwidth(txt_str, &gamedef.encoding_maps);
                 return Ok(txt_str == scr_str);
+            } else if options.halfwidth_spaces {
+                let scr_str = text::decode_str(scr_str, gamedef, true)?;
+                return Ok(text::to_halfwidth_spaces(txt_str.as_str())
+                    == text::to_halfwidth_spaces(scr_str.as_str()));
             } else {
-                let scr_str = text::decode_str(&scr_str, &gamedef, true)?;
+                let scr_str = text::decode_str(scr_str, gamedef, true)?;
                 return Ok(*txt_str == scr_str);
             }
         }
     }
 
     Ok(
-        if let Ok(txt_tk) = sc3::StringToken::deserialize(txt_seg, &gamedef, false) {
+        if let Ok(txt_tk) = sc3::StringToken::deserialize(txt_seg, gamedef, false) {
             *scr_tk == txt_tk
         } else {
             false
@@ -363,8 +4203,18 @@ fn equivalent(
     )
 }
 
+// Messages are colored unless the stream isn't a terminal, NO_COLOR is set or --no-color
+// is passed.
+fn color_choice(stream: &impl IsTerminal) -> ColorChoice {
+    if COLOR.load(Ordering::Relaxed) && stream.is_terminal() {
+        ColorChoice::Auto
+    } else {
+        ColorChoice::Never
+    }
+}
+
 fn report(message: &str) {
-    let mut stderr = StandardStream::stderr(ColorChoice::Always);
+    let mut stderr = StandardStream::stderr(color_choice(&io::stderr()));
     stderr
         .set_color(ColorSpec::new().set_fg(Some(Color::Red)))
         .unwrap();
@@ -372,13 +4222,173 @@ fn report(message: &str) {
     stderr.set_color(&ColorSpec::default()).unwrap();
 }
 
-fn report_err(err: Box<dyn Error>) {
+fn report_err(err: &dyn Error) {
     let message = format!("Error: {}.", err);
     report(&message);
 }
 
+impl CorruptScripts {
+    fn new(keep_going: bool) -> Self {
+        CorruptScripts {
+            keep_going,
+            skipped: Vec::new(),
+        }
+    }
+
+    // Whether the error is that of a corrupt script to be skipped, in which case it's noted
+    // down for the summary.
+    fn skip(&mut self, path: &Path, err: &Sc3ToolsError) -> bool {
+        let corrupt = matches!(err, Sc3ToolsError::Format(err) if err.is_corrupt());
+        if self.keep_going && corrupt {
+            self.skipped.push((path.to_owned(), err.to_string()));
+        }
+        self.keep_going && corrupt
+    }
+
+    fn report(&self) {
+        if self.skipped.is_empty() {
+            return;
+        }
+        let list = self
+            .skipped
+            .iter()
+            .map(|(path, err)| format!("\n    {}: {}", path.display(), err))
+            .join("");
+        report_warning(&format!(
+            "Skipped {} corrupt scripts:{}",
+            self.skipped.len(),
+            list
+        ));
+    }
+}
+
+impl ErrorBudget {
+    fn new(max: usize) -> Self {
+        ErrorBudget {
+            max,
+            spent: Cell::new(0),
+        }
+    }
+}
+
+// Why a script or a whole run was given up on after `count` errors. So many errors usually
+// come from passing the wrong game.
+fn too_many_errors(count: usize, game: &str) -> String {
+    format!(
+        "gave up after {} errors, check that these are really scripts of {}",
+        count, game
+    )
+}
+
+impl BatchOptions<'_> {
+    // Calls `f` on each file while a progress bar on stderr counts the finished ones. The bar
+    // is hidden with --quiet or when stderr isn't a terminal.
+    fn for_each_file<T>(
+        &self,
+        files: Vec<T>,
+        path_of: impl Fn(&T) -> &Path,
+        mut f: impl FnMut(T) -> Result<(), Sc3ToolsError>,
+    ) -> Result<(), Sc3ToolsError> {
+        let bar = if self.quiet || !io::stderr().is_terminal() {
+            ProgressBar::hidden()
+        } else {
+            ProgressBar::new(files.len() as u64)
+        };
+        bar.set_style(ProgressStyle::with_template("[{pos}/{len}] {wide_bar} {msg}").unwrap());
+        for file in files {
+            let name = path_of(&file).file_name().unwrap_or_default();
+            bar.set_message(name.to_string_lossy().into_owned());
+            let res = bar.suspend(|| f(file));
+            if res.is_err() {
+                bar.finish_and_clear();
+                return res;
+            }
+            bar.inc(1);
+        }
+        bar.finish_and_clear();
+        Ok(())
+    }
+
+    // Reports an error that occurred while processing a single file, or returns it if the
+    // whole batch should stop.
+    fn handle_err(&self, err: Sc3ToolsError, gamedef: &GameDef) -> Result<(), Sc3ToolsError> {
+        if self.fail_fast {
+            if self.context {
+                report_context(&err, gamedef);
+            }
+            return Err(err);
+        }
+        report_err(&err);
+        if self.context {
+            report_context(&err, gamedef);
+        }
+        if let Some(budget) = self.budget {
+            budget.spent.set(budget.spent.get() + 1);
+            if budget.spent.get() >= budget.max {
+                let message = too_many_errors(budget.max, &gamedef.full_name);
+                return Err(Sc3ToolsError::Failed(message));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Prints the line an error occurred at along with the ones next to it.
+fn report_context(err: &Sc3ToolsError, gamedef: &GameDef) {
+    let (lines, line) = match err {
+        Sc3ToolsError::Processing(ProcessingError::Script(path, line, _)) => {
+            (script_lines(path, gamedef), *line)
+        }
+        Sc3ToolsError::Processing(ProcessingError::Text(path, line, _)) => {
+            (text_lines(path), *line)
+        }
+        _ => return,
+    };
+    if let Ok(lines) = lines {
+        for i in line.saturating_sub(1)..=line + 1 {
+            if let Some(text) = lines.get(i) {
+                let marker = if i == line { '>' } else { ' ' };
+                eprintln!("{} {:>5} | {}", marker, i + 1, text);
+            }
+        }
+        eprintln!();
+    }
+}
+
+fn script_lines(path: &Path, gamedef: &GameDef) -> Result<Vec<String>, Sc3ToolsError> {
+    let script = open_for_reading(File::open(path)?, gamedef)?;
+    let mut lines = Vec::new();
+    for handle in script.string_index().iter() {
+        let line = script.read_string(handle)?;
+        let serialized = match line.serialize_lenient(gamedef, true) {
+            Ok(s) => s.to_string(),
+            Err(_) => hex::encode_upper(&line.0),
+        };
+        lines.push(serialized);
+    }
+    Ok(lines)
+}
+
+fn text_lines(path: &Path) -> Result<Vec<String>, Sc3ToolsError> {
+    let text = encoding::read_to_string(path)?;
+    let mut lines = text.lines().peekable();
+    if lines.peek().and_then(|x| Stamp::parse(x)).is_some() {
+        lines.next();
+    }
+    Ok(lines.map(str::to_owned).collect())
+}
+
+fn report_warning(message: &str) {
+    let mut stderr = StandardStream::stderr(color_choice(&io::stderr()));
+    stderr
+        .set_color(ColorSpec::new().set_fg(Some(Color::Yellow)))
+        .unwrap();
+    writeln!(&mut stderr, "Warning: {}.\n", message).unwrap();
+    stderr.set_color(&ColorSpec::default()).unwrap();
+}
+
 fn report_ok(message: &str) {
-    let mut stdout = StandardStream::stdout(ColorChoice::Always);
+    let mut stdout = StandardStream::stdout(color_choice(&io::stdout()));
     stdout
         .set_color(ColorSpec::new().set_fg(Some(Color::Green)))
         .unwrap();
@@ -386,6 +4396,24 @@ fn report_ok(message: &str) {
     stdout.set_color(&ColorSpec::default()).unwrap();
 }
 
+impl fmt::Display for Sc3ToolsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Sc3ToolsError::Io(err) => fmt::Display::fmt(err, f),
+            Sc3ToolsError::Format(err) => fmt::Display::fmt(err, f),
+            Sc3ToolsError::Decode(err) => fmt::Display::fmt(err, f),
+            Sc3ToolsError::Encoding(err) => fmt::Display::fmt(err, f),
+            Sc3ToolsError::Markup(err) => fmt::Display::fmt(err, f),
+            Sc3ToolsError::Processing(err) => fmt::Display::fmt(err, f),
+            Sc3ToolsError::Json(err) => fmt::Display::fmt(err, f),
+            Sc3ToolsError::InvalidArgument(message) | Sc3ToolsError::Failed(message) => {
+                f.write_str(message)
+            }
+            Sc3ToolsError::Aborted => write!(f, "aborted, no files were modified"),
+        }
+    }
+}
+
 impl fmt::Display for ProcessingError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -408,6 +4436,39 @@ impl fmt::Display for ProcessingError {
                 f,
                 "The number of lines in the text file has to match that of the script file"
             ),
+            ProcessingError::StampMismatch(path) => write!(
+                f,
+                "{} was extracted from a different version of the script",
+                path.file_name().unwrap().to_string_lossy()
+            ),
+            ProcessingError::SharedString(path, line, aliases) => write!(
+                f,
+                "{}, line {}: the string is shared with line(s) {}, which will change as well \
+                (use --split-shared to give it a copy of its own)",
+                path.file_name().unwrap().to_string_lossy(),
+                line + 1,
+                aliases.iter().map(|x| x + 1).join(", ")
+            ),
+            ProcessingError::ConflictingSharedString(path, line, other) => write!(
+                f,
+                "{}, line {}: the string is shared with line {}, which was given different text \
+                (use --split-shared to give each line a copy of its own)",
+                path.file_name().unwrap().to_string_lossy(),
+                line + 1,
+                other + 1
+            ),
+            ProcessingError::TooManyLines(path, lines, count) => write!(
+                f,
+                "{} has {} lines for a script of {} strings, this doesn't look like the right \
+                text file (see --max-line-factor)",
+                path.file_name().unwrap().to_string_lossy(),
+                lines,
+                count
+            ),
+            ProcessingError::File(path, err) => write!(f, "{}: {}", path.display(), err),
+            ProcessingError::TooManyErrors(path, count, game) => {
+                write!(f, "{}: {}", path.display(), too_many_errors(*count, game))
+            }
         }
     }
 }
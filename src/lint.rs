@@ -0,0 +1,179 @@
+// Checking the tags of a line of a text file without the script it goes into, so that typos
+// are caught before running replace-text.
+//
+// Each segment of the line goes through the same conversion as `Sc3String::deserialize`, which
+// catches malformed attributes and characters missing from the charset. On top of that, text
+// that looks like a tag but isn't one (`[colr]`, `[color=]`, an unclosed `[ruby-base`) is
+// reported, since it would otherwise end up in the script as plain text, and so are ruby tags
+// that don't come in the `[ruby-base]`, `[ruby-text-start]`, `[ruby-text-end]` order.
+// A bracket escaped as `\[` is meant as text and isn't reported.
+
+use crate::{
+    coz::{self, StringSegment, Tag},
+    gamedef::GameDef,
+    sc3::StringToken,
+    text::{self, EncodingError},
+};
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct Issue {
+    /// The column of the segment the issue is about, counted in characters from 1.
+    pub column: usize,
+    pub message: String,
+}
+
+#[derive(Clone, Copy)]
+enum Ruby {
+    Outside,
+    Base(usize),
+    Text(usize),
+}
+
+pub fn lint_line(line: &str, gamedef: &GameDef) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    let mut issue = |offset: usize, message: String| {
+        issues.push(Issue {
+            column: line[..offset].chars().count() + 1,
+            message,
+        })
+    };
+    let mut ruby = Ruby::Outside;
+    let mut rest = line;
+    while !rest.is_empty() {
+        let offset = line.len() - rest.len();
+        let (remaining, segment) = StringSegment::parse(rest);
+        rest = remaining;
+        // The characters of a tag that couldn't be parsed aren't worth reporting on their own.
+        if let StringSegment::Text(text) = &segment {
+            let stray = bracket_positions(text.as_str())
+                .into_iter()
+                .filter_map(|position| Some((position, stray_tag(&text.as_str()[position..])?)))
+                .collect::<Vec<_>>();
+            if !stray.is_empty() {
+                for (position, message) in stray {
+                    issue(offset + position, message);
+                }
+                continue;
+            }
+        }
+        let token = match StringToken::deserialize(&segment, gamedef, false) {
+            Ok(token) => token,
+            Err(coz::Error::TextEncoding(EncodingError::CharNotInCharset(c))) => {
+                let position = segment.to_string().find(c.as_str()).unwrap_or_default();
+                issue(
+                    offset + position,
+                    format!("character '{}' isn't in the charset", c),
+                );
+                continue;
+            }
+            Err(err) => {
+                issue(offset, err.to_string());
+                continue;
+            }
+        };
+        match (token, ruby) {
+            (StringToken::RubyBaseStart, Ruby::Outside) => ruby = Ruby::Base(offset),
+            (StringToken::RubyBaseStart, _) => {
+                unclosed_ruby(&mut issue, ruby);
+                ruby = Ruby::Base(offset);
+            }
+            (StringToken::RubyTextStart, Ruby::Base(_)) => ruby = Ruby::Text(offset),
+            (StringToken::RubyTextStart, _) => {
+                issue(
+                    offset,
+                    "[ruby-text-start] doesn't follow a [ruby-base]".to_owned(),
+                );
+            }
+            (StringToken::RubyTextEnd, Ruby::Text(_)) => ruby = Ruby::Outside,
+            (StringToken::RubyTextEnd, _) => {
+                issue(
+                    offset,
+                    "[ruby-text-end] doesn't follow a [ruby-text-start]".to_owned(),
+                );
+            }
+            _ => {}
+        }
+    }
+    unclosed_ruby(&mut issue, ruby);
+    issues.sort_by_key(|x| x.column);
+    issues
+}
+
+fn unclosed_ruby(issue: &mut impl FnMut(usize, String), ruby: Ruby) {
+    match ruby {
+        Ruby::Outside => {}
+        Ruby::Base(offset) => issue(
+            offset,
+            "[ruby-base] isn't followed by a [ruby-text-start]".to_owned(),
+        ),
+        Ruby::Text(offset) => issue(
+            offset,
+            "[ruby-text-start] isn't closed with a [ruby-text-end]".to_owned(),
+        ),
+    }
+}
+
+// Where the brackets that aren't escaped as `\[` are.
+fn bracket_positions(s: &str) -> Vec<usize> {
+    let mut positions = Vec::new();
+    let mut rest = s;
+    while let Some(c) = rest.chars().next() {
+        if let Ok((after, _)) = text::escaped_char(rest) {
+            rest = after;
+            continue;
+        }
+        if c == '[' {
+            positions.push(s.len() - rest.len());
+        }
+        rest = &rest[c.len_utf8()..];
+    }
+    positions
+}
+
+// Text starting with `[` that was meant to be a tag: either one with an unknown name, or one
+// with a known name that couldn't be parsed.
+fn stray_tag(s: &str) -> Option<String> {
+    if let Ok((_, tag)) = Tag::parse(s) {
+        return Some(format!("unknown tag {}", StringSegment::Tag(tag)));
+    }
+    let name = s[1..].split([' ', ']']).next().unwrap_or_default();
+    if Tag::new(name, None).is_known() {
+        Some(format!("malformed [{}] tag", name))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gamedef::{self, test_defs::DEFS};
+
+    fn columns(line: &str) -> Vec<usize> {
+        let gamedef = gamedef::get_by_alias(&DEFS, "sg0").unwrap();
+        lint_line(line, gamedef).iter().map(|x| x.column).collect()
+    }
+
+    #[test]
+    fn well_formed() {
+        assert!(columns("[name]Okabe[line]Hello, world.").is_empty());
+        assert!(columns("[color name=\"red\"]It's red[font size=\"24\"]Big").is_empty());
+        assert!(columns("[ruby-base]あ[ruby-text-start]a[ruby-text-end]").is_empty());
+        assert!(columns("").is_empty());
+        assert!(columns("Press \\[colr] or \\[line]").is_empty());
+    }
+
+    #[test]
+    fn malformed() {
+        assert_eq!(columns("Hi[colr]there"), vec![3]);
+        assert_eq!(columns("Hi[color=]there"), vec![3]);
+        assert_eq!(columns("\\\\[colr]"), vec![3]);
+        assert_eq!(columns("「[color index=\"830000\"It's red"), vec![2]);
+        assert_eq!(columns("[color name=\"purple\"]x"), vec![1]);
+        assert_eq!(columns("[font]x"), vec![1]);
+        assert_eq!(columns("Hi 😀"), vec![4]);
+        assert_eq!(columns("[ruby-base]あ[ruby-text-start]a"), vec![13]);
+        assert_eq!(columns("[ruby-base]あ[ruby-text-end]"), vec![1, 13]);
+        assert_eq!(columns("x[ruby-text-start]a[ruby-text-end]"), vec![2, 20]);
+    }
+}
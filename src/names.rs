@@ -0,0 +1,144 @@
+// The speaker of a line is the text between its `[name]` and `[line]` tags:
+//
+// [name]Okabe[line]Hm.
+//
+// A line with `[name]` but no `[line]` is all name. Lines without `[name]` have no speaker.
+// apply-names renames speakers by swapping the bytes between the two tokens, so the rest of the
+// line stays exactly as it is in the script.
+
+use crate::{
+    coz::{CozString, StringSegment},
+    sc3::{self, Dialect, Sc3String, StringToken},
+};
+use serde::Serialize;
+use std::{cmp::Reverse, collections::HashMap, ops::Range};
+
+/// A speaker name and how many lines it's the name of.
+#[derive(Debug, Eq, PartialEq, Serialize)]
+pub struct NameCount {
+    pub name: String,
+    pub count: usize,
+}
+
+/// The name of a line of a text file, written the way it is in the line.
+pub fn speaker(line: &str) -> Option<String> {
+    let mut name: Option<String> = None;
+    for segment in CozString(line.into()).iter() {
+        let token = match &segment {
+            StringSegment::Tag(tag) => StringToken::from_tag(tag).ok().flatten(),
+            StringSegment::Text(_) => None,
+        };
+        match (token, &mut name) {
+            (Some(StringToken::NameStart), None) => name = Some(String::new()),
+            (Some(StringToken::LineStart), Some(_)) => break,
+            (_, Some(name)) => name.push_str(&segment.to_string()),
+            (_, None) => {}
+        }
+    }
+    name.filter(|x| !x.is_empty())
+}
+
+/// The bytes of a string of a script that its speaker name takes up, or `None` if it has no
+/// `NameStart` token.
+pub fn name_span(s: &Sc3String, dialect: Dialect) -> Result<Option<Range<usize>>, sc3::Error> {
+    let mut tokens = s.iter(dialect);
+    let mut start = None;
+    loop {
+        let pos = s.0.len() - tokens.remaining().len();
+        match (tokens.next().transpose()?, start) {
+            (Some(StringToken::NameStart), None) => {
+                start = Some(s.0.len() - tokens.remaining().len())
+            }
+            (Some(StringToken::LineStart), Some(start)) | (None, Some(start)) => {
+                return Ok(Some(start..pos))
+            }
+            (Some(_), _) => {}
+            (None, None) => return Ok(None),
+        }
+    }
+}
+
+/// Counts the names of `lines`, the most frequent first.
+pub fn count<'a>(lines: impl Iterator<Item = &'a str>) -> Vec<NameCount> {
+    let mut counts = HashMap::new();
+    for name in lines.filter_map(speaker) {
+        *counts.entry(name).or_insert(0) += 1;
+    }
+    sorted(counts)
+}
+
+/// Names counted some other way, the most frequent first.
+pub fn sorted(counts: HashMap<String, usize>) -> Vec<NameCount> {
+    let mut names = counts
+        .into_iter()
+        .map(|(name, count)| NameCount { name, count })
+        .collect::<Vec<_>>();
+    names.sort_by(|a, b| (Reverse(a.count), &a.name).cmp(&(Reverse(b.count), &b.name)));
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        gamedef::{self, test_defs::DEFS},
+        sc3::Sc3StringBuilder,
+    };
+
+    #[test]
+    fn speakers() {
+        assert_eq!(speaker("[name]Okabe[line]Hm.").as_deref(), Some("Okabe"));
+        assert_eq!(
+            speaker("[name]Okabe Rintarou").as_deref(),
+            Some("Okabe Rintarou")
+        );
+        assert_eq!(
+            speaker("[name][color index=\"830000\"]Mayuri[line]Tutturu~").as_deref(),
+            Some("[color index=\"830000\"]Mayuri")
+        );
+        assert_eq!(speaker("No one[linebreak]speaks."), None);
+        assert_eq!(speaker("[name][line]Hm."), None);
+
+        let lines = [
+            "[name]Okabe[line]Hm.",
+            "[name]Kurisu[line]What?",
+            "Narration.",
+            "[name]Okabe[line]Nothing.",
+        ];
+        assert_eq!(
+            count(lines.iter().copied()),
+            vec![
+                NameCount {
+                    name: "Okabe".to_owned(),
+                    count: 2
+                },
+                NameCount {
+                    name: "Kurisu".to_owned(),
+                    count: 1
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn name_spans() {
+        let gamedef = gamedef::get_by_alias(&DEFS, "sg0").unwrap();
+        let unit = gamedef.code_unit;
+        let text = |n| StringToken::Text(vec![0x8000; n].into());
+        let s = Sc3StringBuilder::new()
+            .push_name_start()
+            .push(text(5))
+            .push_line_start()
+            .push(text(3))
+            .build(gamedef);
+        assert_eq!(name_span(&s, unit.into()).unwrap(), Some(1..11));
+        let s = Sc3StringBuilder::new()
+            .push_name_start()
+            .push(text(5))
+            .push(StringToken::Terminator)
+            .build(gamedef);
+        assert_eq!(name_span(&s, unit.into()).unwrap(), Some(1..11));
+        let s = Sc3StringBuilder::new().push(text(5)).build(gamedef);
+        assert_eq!(name_span(&s, unit.into()).unwrap(), None);
+    }
+}
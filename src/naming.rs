@@ -0,0 +1,324 @@
+// How text files are named after the scripts they belong to.
+//
+// By default, the text file of `foo.scx` is `foo.scx.txt`, and `foo.txt` is accepted as
+// well. With a custom suffix such as `ja.txt`, it's `foo.ja.txt` and nothing else.
+//
+// The text of a large script can be split into parts numbered from 1, which are read one after
+// the other: `foo.scx.part1.txt`, `foo.scx.part2.txt` and so on, or `foo.part1.ja.txt` with a
+// custom suffix.
+
+use std::{
+    error,
+    ffi::OsString,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+/// Scripts paired with their text files.
+pub struct Pairing<'a> {
+    /// Each script along with its text files in the order they're read: none, a single one,
+    /// or its parts.
+    pub scripts: Vec<(&'a Path, Vec<&'a Path>)>,
+    /// The scripts whose parts can't be put together.
+    pub broken_parts: Vec<(&'a Path, PartsError)>,
+    /// The text files that don't belong to any of the scripts.
+    pub lone_text_files: Vec<&'a Path>,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum PartsError {
+    /// The parts with these numbers are missing, given the highest one.
+    Missing(Vec<u32>),
+    /// More than one file has this part number.
+    Duplicate(u32),
+    /// There's a text file for the whole script as well as parts of one.
+    WholeAndParts,
+}
+
+impl error::Error for PartsError {}
+
+pub fn text_file_name(script: &Path, suffix: Option<&str>) -> Option<OsString> {
+    let mut name = match suffix {
+        Some(_) => script.file_stem()?.to_owned(),
+        None => script.file_name()?.to_owned(),
+    };
+    name.push(".");
+    name.push(suffix.map_or("txt", |x| x.trim_start_matches('.')));
+    Some(name)
+}
+
+pub fn is_text_file_for(text_file: &Path, script: &Path, suffix: Option<&str>) -> bool {
+    match suffix {
+        Some(_) => text_file.file_name() == text_file_name(script, suffix).as_deref(),
+        None => {
+            let stem = text_file.file_stem();
+            stem == script.file_stem() || stem == script.file_name()
+        }
+    }
+}
+
+/// The number of the part of the script's text that `text_file` holds, if it's one of them.
+pub fn part_number(text_file: &Path, script: &Path, suffix: Option<&str>) -> Option<u32> {
+    let name = text_file.file_name()?.to_str()?;
+    let extension = format!(".{}", suffix.map_or("txt", |x| x.trim_start_matches('.')));
+    let (base, number) = name.strip_suffix(&extension)?.rsplit_once(".part")?;
+    if number.is_empty() || !number.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let stem = script.file_stem().and_then(|x| x.to_str());
+    let belongs = match suffix {
+        Some(_) => Some(base) == stem,
+        None => Some(base) == stem || Some(base) == script.file_name().and_then(|x| x.to_str()),
+    };
+    if belongs {
+        number.parse().ok()
+    } else {
+        None
+    }
+}
+
+/// Sorts the parts of a script's text by their number, which have to go from 1 up to the
+/// highest one without gaps.
+pub fn order_parts(mut parts: Vec<(u32, &Path)>) -> Result<Vec<&Path>, PartsError> {
+    parts.sort_by_key(|(number, _)| *number);
+    if let Some(pair) = parts.windows(2).find(|x| x[0].0 == x[1].0) {
+        return Err(PartsError::Duplicate(pair[0].0));
+    }
+    let last = parts.last().map_or(0, |(number, _)| *number);
+    let missing = (1..=last)
+        .filter(|x| {
+            parts
+                .binary_search_by_key(x, |(number, _)| *number)
+                .is_err()
+        })
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        return Err(PartsError::Missing(missing));
+    }
+    Ok(parts.into_iter().map(|(_, path)| path).collect())
+}
+
+pub fn has_text_suffix(path: &Path, suffix: Option<&str>) -> bool {
+    let suffix = suffix.map_or("txt", |x| x.trim_start_matches('.'));
+    path.file_name()
+        .map(|x| x.to_string_lossy())
+        .is_some_and(|x| x.ends_with(&format!(".{}", suffix)))
+}
+
+pub fn is_script(path: &Path) -> bool {
+    path.extension()
+        .and_then(|x| x.to_str())
+        .is_some_and(|x| x.eq_ignore_ascii_case("scx") || x.eq_ignore_ascii_case("msb"))
+}
+
+pub fn pair<'a>(
+    scripts: &'a [PathBuf],
+    text_files: &'a [PathBuf],
+    suffix: Option<&str>,
+) -> Pairing<'a> {
+    let mut paired = Vec::new();
+    let mut broken_parts = Vec::new();
+    let mut claimed = Vec::new();
+    for script in scripts {
+        let whole = text_files
+            .iter()
+            .find(|x| is_text_file_for(x, script, suffix))
+            .map(PathBuf::as_path);
+        let parts = text_files
+            .iter()
+            .filter_map(|x| Some((part_number(x, script, suffix)?, x.as_path())))
+            .collect::<Vec<_>>();
+        claimed.extend(whole);
+        claimed.extend(parts.iter().map(|(_, path)| *path));
+        let files = match (whole, parts.is_empty()) {
+            (whole, true) => Ok(whole.into_iter().collect()),
+            (Some(_), false) => Err(PartsError::WholeAndParts),
+            (None, false) => order_parts(parts),
+        };
+        match files {
+            Ok(files) => paired.push((script.as_path(), files)),
+            Err(err) => broken_parts.push((script.as_path(), err)),
+        }
+    }
+    let lone_text_files = text_files
+        .iter()
+        .map(PathBuf::as_path)
+        .filter(|x| !claimed.contains(x))
+        .collect();
+    Pairing {
+        scripts: paired,
+        broken_parts,
+        lone_text_files,
+    }
+}
+
+impl fmt::Display for PartsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PartsError::Missing(numbers) => {
+                let numbers = numbers.iter().map(u32::to_string).collect::<Vec<_>>();
+                write!(
+                    f,
+                    "part(s) {} of the text file are missing",
+                    numbers.join(", ")
+                )
+            }
+            PartsError::Duplicate(number) => {
+                write!(f, "more than one text file is part {}", number)
+            }
+            PartsError::WholeAndParts => {
+                write!(f, "there's both a whole text file and parts of one")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(script: &str, suffix: Option<&str>) -> String {
+        let name = text_file_name(Path::new(script), suffix).unwrap();
+        name.into_string().unwrap()
+    }
+
+    fn pairs(text_file: &str, script: &str, suffix: Option<&str>) -> bool {
+        is_text_file_for(Path::new(text_file), Path::new(script), suffix)
+    }
+
+    #[test]
+    fn default_scheme() {
+        assert_eq!(name("dir/foo.scx", None), "foo.scx.txt");
+        assert!(pairs("txt/foo.scx.txt", "dir/foo.scx", None));
+        assert!(pairs("txt/foo.txt", "dir/foo.scx", None));
+        assert!(!pairs("txt/foo.msb.txt", "dir/foo.scx", None));
+        assert!(!pairs("txt/foobar.txt", "dir/foo.scx", None));
+    }
+
+    #[test]
+    fn custom_suffix() {
+        assert_eq!(name("dir/foo.scx", Some("txt")), "foo.txt");
+        assert_eq!(name("dir/foo.scx", Some(".ja.txt")), "foo.ja.txt");
+        assert!(pairs("txt/foo.ja.txt", "dir/foo.scx", Some("ja.txt")));
+        assert!(!pairs("txt/foo.scx.txt", "dir/foo.scx", Some("ja.txt")));
+        assert!(!pairs("txt/foo.en.txt", "dir/foo.scx", Some("ja.txt")));
+        assert!(pairs("txt/foo.txt", "dir/foo.scx", Some("txt")));
+    }
+
+    #[test]
+    fn pairing() {
+        let paths = |names: &[&str]| names.iter().map(PathBuf::from).collect::<Vec<_>>();
+        let scripts = paths(&["scr/a.scx", "scr/b.scx", "scr/c.scx"]);
+        let text_files = paths(&[
+            "txt/a.scx.txt",
+            "txt/c.txt",
+            "txt/d.scx.txt",
+            "txt/notes.md",
+        ]);
+        let pairing = pair(&scripts, &text_files, None);
+        assert_eq!(
+            pairing.scripts,
+            vec![
+                (Path::new("scr/a.scx"), vec![Path::new("txt/a.scx.txt")]),
+                (Path::new("scr/b.scx"), vec![]),
+                (Path::new("scr/c.scx"), vec![Path::new("txt/c.txt")]),
+            ]
+        );
+        assert_eq!(
+            pairing.lone_text_files,
+            vec![Path::new("txt/d.scx.txt"), Path::new("txt/notes.md")]
+        );
+
+        let pairing = pair(&scripts, &text_files, Some("txt"));
+        assert!(pairing.scripts[0].1.is_empty());
+        assert_eq!(pairing.scripts[2].1, vec![Path::new("txt/c.txt")]);
+        assert_eq!(pairing.lone_text_files.len(), 3);
+    }
+
+    #[test]
+    fn parts() {
+        let number =
+            |text_file, suffix| part_number(Path::new(text_file), Path::new("scr/a.scx"), suffix);
+        assert_eq!(number("txt/a.scx.part1.txt", None), Some(1));
+        assert_eq!(number("txt/a.part12.txt", None), Some(12));
+        assert_eq!(number("txt/a.part2.ja.txt", Some("ja.txt")), Some(2));
+        assert_eq!(number("txt/a.scx.part.txt", None), None);
+        assert_eq!(number("txt/a.scx.partx.txt", None), None);
+        assert_eq!(number("txt/b.scx.part1.txt", None), None);
+        assert_eq!(number("txt/a.scx.part1.txt", Some("ja.txt")), None);
+
+        let parts = |numbers: &[u32]| {
+            let parts = numbers
+                .iter()
+                .map(|x| {
+                    (
+                        *x,
+                        Path::new(["one", "two", "three", "ten"][*x.min(&4) as usize - 1]),
+                    )
+                })
+                .collect();
+            order_parts(parts)
+        };
+        assert_eq!(
+            parts(&[2, 1, 3]),
+            Ok(vec![Path::new("one"), Path::new("two"), Path::new("three")])
+        );
+        assert_eq!(parts(&[1, 3]), Err(PartsError::Missing(vec![2])));
+        assert_eq!(parts(&[3]), Err(PartsError::Missing(vec![1, 2])));
+        assert_eq!(parts(&[1, 2, 2]), Err(PartsError::Duplicate(2)));
+    }
+
+    #[test]
+    fn part_pairing() {
+        let paths = |names: &[&str]| names.iter().map(PathBuf::from).collect::<Vec<_>>();
+        let scripts = paths(&["scr/a.scx", "scr/b.scx", "scr/c.scx"]);
+        let text_files = paths(&[
+            "txt/a.scx.part10.txt",
+            "txt/a.scx.part2.txt",
+            "txt/a.scx.part1.txt",
+            "txt/a.scx.part3.txt",
+            "txt/a.scx.part4.txt",
+            "txt/a.scx.part5.txt",
+            "txt/a.scx.part6.txt",
+            "txt/a.scx.part7.txt",
+            "txt/a.scx.part8.txt",
+            "txt/a.scx.part9.txt",
+            "txt/b.scx.txt",
+            "txt/b.scx.part1.txt",
+            "txt/c.part2.txt",
+        ]);
+        let pairing = pair(&scripts, &text_files, None);
+        assert_eq!(pairing.scripts.len(), 1);
+        let (script, parts) = &pairing.scripts[0];
+        assert_eq!(*script, Path::new("scr/a.scx"));
+        assert_eq!(parts.len(), 10);
+        assert_eq!(parts[1], Path::new("txt/a.scx.part2.txt"));
+        assert_eq!(parts[9], Path::new("txt/a.scx.part10.txt"));
+        assert_eq!(
+            pairing.broken_parts,
+            vec![
+                (Path::new("scr/b.scx"), PartsError::WholeAndParts),
+                (Path::new("scr/c.scx"), PartsError::Missing(vec![1])),
+            ]
+        );
+        assert!(pairing.lone_text_files.is_empty());
+    }
+
+    #[test]
+    fn file_kinds() {
+        assert!(is_script(Path::new("dir/foo.scx")));
+        assert!(is_script(Path::new("dir/FOO.MSB")));
+        assert!(!is_script(Path::new("dir/foo.scx.txt")));
+        assert!(!is_script(Path::new("dir/scx")));
+        assert!(has_text_suffix(Path::new("txt/foo.scx.txt"), None));
+        assert!(!has_text_suffix(Path::new("txt/foo.scx.txt.map"), None));
+        assert!(has_text_suffix(
+            Path::new("txt/foo.ja.txt"),
+            Some(".ja.txt")
+        ));
+        assert!(!has_text_suffix(
+            Path::new("txt/foo.en.txt"),
+            Some("ja.txt")
+        ));
+    }
+}
@@ -0,0 +1,87 @@
+// Translator notes that can follow the text of a line in a text file, after a tab and a `#`:
+//
+// [name]Okabe[line]Hm.	# deadpan, max 40 columns
+//
+// replace-text ignores them, and extract-text keeps them when it overwrites a text file. A tab
+// followed by `#` in the text itself is written with a backslash in between (`\t\#`), and a tab
+// followed by backslashes and `#` gets one more backslash.
+
+use std::borrow::Cow;
+
+const SEPARATOR: &str = "\t#";
+
+/// Splits a line of a text file into its text and its note, if it has one.
+pub fn split(line: &str) -> (Cow<'_, str>, Option<&str>) {
+    match line.split_once(SEPARATOR) {
+        Some((text, note)) => (unescape(text), Some(note)),
+        None => (unescape(line), None),
+    }
+}
+
+pub fn join<'a>(text: &'a str, note: Option<&str>) -> Cow<'a, str> {
+    match note {
+        Some(note) => Cow::Owned(format!("{}{}{}", text, SEPARATOR, note)),
+        None => Cow::Borrowed(text),
+    }
+}
+
+/// Escapes the text of a line so that it isn't mistaken for a note.
+pub fn escape(text: &str) -> Cow<'_, str> {
+    map_tabs(text, |rest| {
+        if rest.trim_start_matches('\\').starts_with('#') {
+            ("\\", rest)
+        } else {
+            ("", rest)
+        }
+    })
+}
+
+fn unescape(text: &str) -> Cow<'_, str> {
+    map_tabs(text, |rest| match rest.strip_prefix('\\') {
+        Some(unescaped) if unescaped.trim_start_matches('\\').starts_with('#') => ("", unescaped),
+        _ => ("", rest),
+    })
+}
+
+// Rewrites what follows each tab: `f` gets the rest of the text and returns what to insert
+// after the tab and where to continue from.
+fn map_tabs<'a>(text: &'a str, f: impl Fn(&str) -> (&'static str, &str)) -> Cow<'a, str> {
+    if !text.contains('\t') {
+        return Cow::Borrowed(text);
+    }
+    let mut mapped = String::with_capacity(text.len() + 1);
+    let mut rest = text;
+    while let Some(pos) = rest.find('\t') {
+        mapped.push_str(&rest[..=pos]);
+        let (inserted, next) = f(&rest[pos + 1..]);
+        mapped.push_str(inserted);
+        rest = next;
+    }
+    mapped.push_str(rest);
+    Cow::Owned(mapped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_notes() {
+        assert_eq!(split("Hm."), ("Hm.".into(), None));
+        assert_eq!(split("Hm.\t# deadpan"), ("Hm.".into(), Some(" deadpan")));
+        assert_eq!(split("a\tb\t#\t# c"), ("a\tb".into(), Some("\t# c")));
+    }
+
+    #[test]
+    fn escaping_roundtrip() {
+        for text in &["a\t#b", "a\t\\#b", "a\t\\\\#", "a\tb", "a\t", "\t#\t#"] {
+            let escaped = escape(text);
+            assert!(!escaped.contains(SEPARATOR), "{:?}", escaped);
+            let line = join(&escaped, Some(" note"));
+            assert_eq!(split(&line), ((*text).into(), Some(" note")));
+            assert_eq!(split(&escaped), ((*text).into(), None));
+        }
+        assert_eq!(escape("a\t#b"), "a\t\\#b");
+        assert_eq!(escape("a\tb"), "a\tb");
+    }
+}
@@ -0,0 +1,87 @@
+// A script's text can also be written one string per file, in a directory named after the
+// script, for workflows that edit and track the history of each line on its own:
+//
+// txt/_startup/0000.txt
+// txt/_startup/0001.txt
+//
+// The files are named after the index of their string, zero-padded to at least 4 digits like
+// the ones of dump-raw, so that they sort in order and keep their names when a script gains or
+// loses a few strings.
+
+use crate::encoding;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+pub fn file_name(index: usize, count: usize) -> String {
+    let width = count.to_string().len().max(4);
+    format!("{:0width$}.txt", index, width = width)
+}
+
+/// The index of the string the file holds, if it's named like one of them.
+pub fn index_of(path: &Path) -> Option<usize> {
+    if path.extension()? != "txt" {
+        return None;
+    }
+    let stem = path.file_stem()?.to_str()?;
+    if stem.is_empty() || !stem.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    stem.parse().ok()
+}
+
+/// Creates the directory, or clears the files of an earlier extraction out of it so that a
+/// filtered one doesn't leave stale strings behind. Other files are left alone.
+pub fn prepare_dir(dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    for path in files(dir)? {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// The strings of the directory along with their indices, in order.
+pub fn read(dir: &Path) -> io::Result<Vec<(usize, String)>> {
+    let mut lines = Vec::new();
+    for path in files(dir)? {
+        let text = encoding::read_to_string(&path)?;
+        let text = text.strip_suffix('\n').unwrap_or(&text);
+        let text = text.strip_suffix('\r').unwrap_or(text);
+        if text.contains('\n') {
+            let message = format!("{}: expected a single line", path.display());
+            return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+        }
+        lines.push((index_of(&path).unwrap(), text.to_owned()));
+    }
+    lines.sort_by_key(|(index, _)| *index);
+    Ok(lines)
+}
+
+fn files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && index_of(&path).is_some() {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names() {
+        assert_eq!(file_name(0, 6), "0000.txt");
+        assert_eq!(file_name(42, 9999), "0042.txt");
+        assert_eq!(file_name(42, 10000), "00042.txt");
+        assert_eq!(index_of(Path::new("txt/sample/0042.txt")), Some(42));
+        assert_eq!(index_of(Path::new("42.txt")), Some(42));
+        assert_eq!(index_of(Path::new("0042.bin")), None);
+        assert_eq!(index_of(Path::new("notes.txt")), None);
+        assert_eq!(index_of(Path::new(".txt")), None);
+    }
+}
@@ -0,0 +1,141 @@
+// Lines with their tags swapped for numbered placeholders, so that they survive machine
+// translation:
+//
+// [name]Okabe[line]Hm.  ->  {0}Okabe{1}Hm.
+//
+// The tags of each line go to a sidecar file next to the text file, one JSON array per line
+// in the sparse format (`12: ["[name]","[line]"]`). Braces in the text itself are replaced
+// as well, so that whatever is between braces in a translation is a placeholder.
+
+use crate::{coz::CozString, coz::StringSegment, sparse};
+use std::{
+    collections::HashMap,
+    error, fmt, fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum Error {
+    Malformed,
+    Unknown(usize),
+    Missing(usize),
+}
+
+impl error::Error for Error {}
+
+/// Replaces the tags of a line with placeholders and returns them in order.
+pub fn mask(line: &str) -> (String, Vec<String>) {
+    let mut masked = String::with_capacity(line.len());
+    let mut tags = Vec::new();
+    let mut replace = |masked: &mut String, tag: String| {
+        masked.push_str(&format!("{{{}}}", tags.len()));
+        tags.push(tag);
+    };
+    for segment in CozString(line.into()).iter() {
+        match segment {
+            StringSegment::Text(text) => {
+                for c in text.as_str().chars() {
+                    if c == '{' || c == '}' {
+                        replace(&mut masked, c.to_string());
+                    } else {
+                        masked.push(c);
+                    }
+                }
+            }
+            tag => replace(&mut masked, tag.to_string()),
+        }
+    }
+    (masked, tags)
+}
+
+/// Puts the tags back in place of the placeholders. Every placeholder has to be there, in
+/// any order.
+pub fn unmask(line: &str, tags: &[String]) -> Result<String, Error> {
+    let mut unmasked = String::with_capacity(line.len());
+    let mut seen = vec![false; tags.len()];
+    let mut rest = line;
+    while let Some(start) = rest.find(['{', '}']) {
+        unmasked.push_str(&rest[..start]);
+        let (number, after) = rest[start..]
+            .strip_prefix('{')
+            .and_then(|x| x.split_once('}'))
+            .ok_or(Error::Malformed)?;
+        let n = number
+            .trim()
+            .parse::<usize>()
+            .map_err(|_| Error::Malformed)?;
+        unmasked.push_str(tags.get(n).ok_or(Error::Unknown(n))?);
+        seen[n] = true;
+        rest = after;
+    }
+    unmasked.push_str(rest);
+    match seen.iter().position(|x| !x) {
+        Some(n) => Err(Error::Missing(n)),
+        None => Ok(unmasked),
+    }
+}
+
+pub fn sidecar_path(text_file: &Path) -> PathBuf {
+    text_file.with_extension("tags")
+}
+
+/// Writes the tags of each line that has any, by the index of its line.
+pub fn write_sidecar(path: &Path, tags: &[(usize, Vec<String>)]) -> io::Result<()> {
+    let mut writer = io::BufWriter::new(fs::File::create(path)?);
+    for (i, line_tags) in tags.iter().filter(|(_, x)| !x.is_empty()) {
+        let json = serde_json::to_string(line_tags).map_err(io::Error::from)?;
+        sparse::write_line(&mut writer, *i, &json)?;
+    }
+    writer.flush()
+}
+
+pub fn read_sidecar(path: &Path) -> io::Result<HashMap<usize, Vec<String>>> {
+    let mut tags = HashMap::new();
+    for line in fs::read_to_string(path)?.lines() {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "malformed tag file");
+        let (i, json) = sparse::parse_line(line).ok_or_else(invalid)?;
+        tags.insert(i, serde_json::from_str(json).map_err(|_| invalid())?);
+    }
+    Ok(tags)
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Malformed => write!(f, "a brace doesn't enclose a placeholder number"),
+            Error::Unknown(n) => write!(f, "placeholder {{{}}} isn't in the original line", n),
+            Error::Missing(n) => write!(f, "placeholder {{{}}} is missing", n),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let line = "[name]Okabe[line]A {brace}[ruby-base]x[ruby-text-start]y[ruby-text-end]";
+        let (masked, tags) = mask(line);
+        assert_eq!(masked, "{0}Okabe{1}A {2}brace{3}{4}x{5}y{6}");
+        assert_eq!(tags[0], "[name]");
+        assert_eq!(tags[2], "{");
+        assert_eq!(unmask(&masked, &tags).unwrap(), line);
+        assert_eq!(
+            unmask("{1}B {2}brace{3}{0}Okabe{4}x{5}y{6}", &tags).unwrap(),
+            "[line]B {brace}[name]Okabe[ruby-base]x[ruby-text-start]y[ruby-text-end]"
+        );
+        assert_eq!(mask("Hm."), ("Hm.".to_owned(), Vec::new()));
+    }
+
+    #[test]
+    fn invalid_placeholders() {
+        let tags = vec!["[name]".to_owned(), "[line]".to_owned()];
+        assert_eq!(unmask("{0}Okabe", &tags), Err(Error::Missing(1)));
+        assert_eq!(unmask("{0}Okabe{1}{2}", &tags), Err(Error::Unknown(2)));
+        assert_eq!(unmask("{0}Okabe{1", &tags), Err(Error::Malformed));
+        assert_eq!(unmask("{0}Okabe{one}", &tags), Err(Error::Malformed));
+        assert_eq!(unmask("{ 0 }Okabe{1}", &tags).unwrap(), "[name]Okabe[line]");
+    }
+}
@@ -0,0 +1,105 @@
+// The time a run spends in each phase of processing a script, added up across files and
+// printed by --profile. Until it's enabled, timing a phase costs an atomic load.
+
+use std::{
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static NANOS: [AtomicU64; 4] = [
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+];
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Phase {
+    /// Reading the header and the string index of a script.
+    Open,
+    /// Turning strings into lines of text.
+    Decode,
+    /// Turning lines of text into strings.
+    Encode,
+    /// Writing scripts and text files.
+    Write,
+}
+
+const PHASES: [Phase; 4] = [Phase::Open, Phase::Decode, Phase::Encode, Phase::Write];
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Counts the time until the returned timer is dropped towards `phase`.
+pub fn start(phase: Phase) -> Timer {
+    let start = if ENABLED.load(Ordering::Relaxed) {
+        Some(Instant::now())
+    } else {
+        None
+    };
+    Timer { phase, start }
+}
+
+pub struct Timer {
+    phase: Phase,
+    start: Option<Instant>,
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        if let Some(start) = self.start {
+            let nanos = start.elapsed().as_nanos() as u64;
+            NANOS[self.phase as usize].fetch_add(nanos, Ordering::Relaxed);
+        }
+    }
+}
+
+/// The time spent in each phase so far, one per line.
+pub fn summary() -> String {
+    let totals = NANOS
+        .iter()
+        .map(|x| Duration::from_nanos(x.load(Ordering::Relaxed)))
+        .collect::<Vec<_>>();
+    format_summary(&totals)
+}
+
+fn format_summary(totals: &[Duration]) -> String {
+    let all = totals.iter().sum::<Duration>().as_secs_f64();
+    let mut s = "Time spent per phase:\n".to_owned();
+    for (phase, total) in PHASES.iter().zip(totals) {
+        let name = match phase {
+            Phase::Open => "open/parse",
+            Phase::Decode => "decode",
+            Phase::Encode => "encode",
+            Phase::Write => "write",
+        };
+        let share = total.as_secs_f64() / all.max(f64::EPSILON) * 100.0;
+        s += &format!(
+            "    {:<10} {:>9.1} ms {:>4.0}%\n",
+            name,
+            total.as_secs_f64() * 1000.0,
+            share
+        );
+    }
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summary_lines() {
+        let ms = Duration::from_millis;
+        assert_eq!(
+            format_summary(&[ms(5), ms(15), ms(0), ms(1230)]),
+            "Time spent per phase:\n    \
+             open/parse       5.0 ms    0%\n    \
+             decode          15.0 ms    1%\n    \
+             encode           0.0 ms    0%\n    \
+             write         1230.0 ms   98%\n"
+        );
+    }
+}
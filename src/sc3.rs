@@ -1,12 +1,14 @@
-use byteorder::{BigEndian, WriteBytesExt};
+use crate::{coz, gamedef::GameDef, text, Sc3ToolsError};
+use byteorder::{BigEndian, LittleEndian, WriteBytesExt};
 use nom::{
     bytes::complete::{tag, take},
-    combinator::{cond, map, peek, recognize, verify},
+    combinator::{cond, map, peek, recognize, rest, verify},
     multi::many_till,
-    number::complete::{be_u16, be_u8},
+    number::complete::{be_u16, be_u8, le_u16},
     sequence::terminated,
     IResult,
 };
+use serde::{ser::SerializeMap, ser::SerializeSeq, Serialize, Serializer};
 use std::{borrow::Cow, fmt, io};
 
 #[derive(Debug)]
@@ -18,47 +20,342 @@ pub enum Error {
 
 impl std::error::Error for Error {}
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Eq, Debug)]
 pub struct Sc3String<'a>(pub Cow<'a, [u8]>);
 
-impl<'a> Sc3String<'_> {
-    pub fn iter(&self) -> Sc3StringIter {
-        Sc3StringIter { remaining: &self.0 }
+/// How the characters of text runs are stored. Each character is the index of its glyph with
+/// the high bit set, so that it can't be mistaken for an instruction.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub enum CodeUnit {
+    /// One byte per character.
+    U8,
+    /// Two bytes per character, high byte first.
+    #[default]
+    U16Be,
+    /// Two bytes per character, low byte first. A character is recognized by its second byte.
+    U16Le,
+}
+
+/// What sets the strings of a game apart from those of others when decoding them.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct Dialect {
+    pub unit: CodeUnit,
+    /// Whether `[present]` is followed by the expression of a voice to play.
+    pub present_voice: bool,
+}
+
+impl From<CodeUnit> for Dialect {
+    fn from(unit: CodeUnit) -> Self {
+        Dialect {
+            unit,
+            present_voice: false,
+        }
+    }
+}
+
+/// Turns the bytes of a string into tokens and back. A dialect is the codec of the games
+/// sc3tools supports; another one can be supplied to try out the opcodes of a game it doesn't
+/// know yet, by mapping them to the existing tokens and handing the rest to a dialect.
+pub trait TokenCodec {
+    /// The token at the start of `i`, and what follows it.
+    fn decode<'a>(&self, i: &'a [u8]) -> Result<(&'a [u8], StringToken<'a>), Error>;
+
+    fn encode(&self, token: &StringToken, sink: &mut dyn io::Write) -> Result<(), io::Error>;
+}
+
+impl TokenCodec for Dialect {
+    fn decode<'a>(&self, i: &'a [u8]) -> Result<(&'a [u8], StringToken<'a>), Error> {
+        StringToken::decode(i, *self)
+    }
+
+    fn encode(&self, token: &StringToken, mut sink: &mut dyn io::Write) -> Result<(), io::Error> {
+        token.encode(&mut sink, self.unit)
+    }
+}
+
+impl<C: TokenCodec + ?Sized> TokenCodec for &C {
+    fn decode<'a>(&self, i: &'a [u8]) -> Result<(&'a [u8], StringToken<'a>), Error> {
+        (**self).decode(i)
+    }
+
+    fn encode(&self, token: &StringToken, sink: &mut dyn io::Write) -> Result<(), io::Error> {
+        (**self).encode(token, sink)
     }
 }
 
-pub struct Sc3StringIter<'a> {
+impl Sc3String<'_> {
+    pub fn iter(&self, dialect: impl Into<Dialect>) -> Sc3StringIter<'_> {
+        self.iter_with(dialect.into())
+    }
+
+    /// Like `iter`, but yields an `Unknown` token for each byte that can't be decoded and
+    /// carries on with the next one instead of failing.
+    pub fn iter_lenient(&self, dialect: impl Into<Dialect>) -> Sc3StringIter<'_> {
+        self.iter_lenient_with(dialect.into())
+    }
+
+    /// Like `iter`, with the tokens decoded by `codec`.
+    pub fn iter_with<C: TokenCodec>(&self, codec: C) -> Sc3StringIter<'_, C> {
+        Sc3StringIter {
+            remaining: &self.0,
+            lenient: false,
+            cut_short: false,
+            terminated: false,
+            codec,
+        }
+    }
+
+    /// Like `iter_lenient`, with the tokens decoded by `codec`.
+    pub fn iter_lenient_with<C: TokenCodec>(&self, codec: C) -> Sc3StringIter<'_, C> {
+        Sc3StringIter {
+            remaining: &self.0,
+            lenient: true,
+            cut_short: false,
+            terminated: false,
+            codec,
+        }
+    }
+
+    /// Whether the string has any text, as opposed to only control tokens.
+    pub fn has_text(&self, dialect: impl Into<Dialect>) -> bool {
+        self.iter_lenient(dialect)
+            .any(|x| matches!(x, Ok(StringToken::Text(text)) if !text.is_empty()))
+    }
+}
+
+/// The tokens of a string, for inspecting and changing it as data rather than as a line of a
+/// text file. It serializes to an array with an object per token:
+///
+/// ```json
+/// [
+///   { "tag": "name" },
+///   { "text": "Okabe" },
+///   { "tag": "line" },
+///   { "tag": "color", "index": "830000" },
+///   { "text": "It's red" }
+/// ]
+/// ```
+///
+/// Text is decoded with the charset of the game, and the other tokens are named after their
+/// tags in text files, along with their attribute if they have one. Expressions are in hex.
+#[derive(Clone)]
+pub struct DecodedLine<'a> {
+    pub tokens: Vec<StringToken<'a>>,
+    gamedef: &'a GameDef,
+    keep_fullwidth_chars: bool,
+}
+
+pub struct Sc3StringIter<'a, C = Dialect> {
     remaining: &'a [u8],
+    lenient: bool,
+    cut_short: bool,
+    terminated: bool,
+    codec: C,
+}
+
+#[derive(Default)]
+pub struct Sc3StringBuilder<'a> {
+    tokens: Vec<StringToken<'a>>,
+}
+
+impl<'a> Sc3StringBuilder<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, token: StringToken<'a>) -> &mut Self {
+        self.tokens.push(token);
+        self
+    }
+
+    pub fn push_text(
+        &mut self,
+        s: &str,
+        gamedef: &GameDef,
+    ) -> Result<&mut Self, text::EncodingError> {
+        let encoded = text::encode_str(&text::Text(s.into()), gamedef, false)?;
+        Ok(self.push(StringToken::Text(encoded.into())))
+    }
+
+    pub fn push_line_break(&mut self) -> &mut Self {
+        self.push(StringToken::LineBreak)
+    }
+
+    pub fn push_name_start(&mut self) -> &mut Self {
+        self.push(StringToken::NameStart)
+    }
+
+    pub fn push_line_start(&mut self) -> &mut Self {
+        self.push(StringToken::LineStart)
+    }
+
+    pub fn push_present(&mut self, action: PresentAction) -> &mut Self {
+        self.push(StringToken::Present(action))
+    }
+
+    pub fn push_color(&mut self, color: Expr<'a>) -> &mut Self {
+        self.push(StringToken::Color(color))
+    }
+
+    /// The string of the tokens, terminated if the game's strings are.
+    pub fn build(&self, gamedef: &GameDef) -> Sc3String<'static> {
+        self.build_with(&gamedef.dialect(), gamedef.terminated)
+            .unwrap()
+    }
+
+    /// Like `build`, with the tokens encoded by `codec`, which fails if it has no encoding for
+    /// one of them.
+    pub fn build_with(
+        &self,
+        codec: &impl TokenCodec,
+        terminated: bool,
+    ) -> Result<Sc3String<'static>, io::Error> {
+        let mut buf = Vec::new();
+        let terminator = Some(&StringToken::Terminator).filter(|_| terminated);
+        for tk in self.tokens.iter().chain(terminator) {
+            codec.encode(tk, &mut buf)?;
+        }
+        Ok(Sc3String(buf.into()))
+    }
+}
+
+impl<'a> DecodedLine<'a> {
+    /// Fails if the string has bytes that aren't a token, or text that isn't in the charset.
+    pub fn decode(
+        s: &'a Sc3String,
+        gamedef: &'a GameDef,
+        keep_fullwidth_chars: bool,
+    ) -> Result<Self, Sc3ToolsError> {
+        let tokens = s.iter(gamedef.dialect()).collect::<Result<Vec<_>, _>>()?;
+        for tk in &tokens {
+            if let StringToken::Text(text) = tk {
+                text::decode_str(text, gamedef, keep_fullwidth_chars)?;
+            }
+        }
+        Ok(DecodedLine {
+            tokens,
+            gamedef,
+            keep_fullwidth_chars,
+        })
+    }
+
+    /// The string with the tokens as they are now, terminated if the game's strings are.
+    pub fn encode(&self) -> Sc3String<'static> {
+        let mut buf = Vec::new();
+        let terminator = Some(&StringToken::Terminator).filter(|_| self.gamedef.terminated);
+        for tk in self.tokens.iter().chain(terminator) {
+            tk.encode(&mut buf, self.gamedef.code_unit).unwrap();
+        }
+        Sc3String(buf.into())
+    }
+}
+
+impl Serialize for DecodedLine<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.tokens.len()))?;
+        for tk in &self.tokens {
+            let segment = tk
+                .clone()
+                .serialize(self.gamedef, self.keep_fullwidth_chars)
+                .map_err(serde::ser::Error::custom)?;
+            seq.serialize_element(&SerializedToken(segment))?;
+        }
+        seq.end()
+    }
 }
 
-impl<'a> Iterator for Sc3StringIter<'a> {
+// A token of a DecodedLine, as serialized.
+struct SerializedToken<'a>(coz::StringSegment<'a>);
+
+impl Serialize for SerializedToken<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        match &self.0 {
+            coz::StringSegment::Text(text) => map.serialize_entry("text", &text.unescaped())?,
+            coz::StringSegment::Tag(tag) => {
+                map.serialize_entry("tag", tag.name())?;
+                if let Some((key, value)) = tag.attribute() {
+                    map.serialize_entry(key, value)?;
+                }
+            }
+        }
+        map.end()
+    }
+}
+
+impl<'a, C> Sc3StringIter<'a, C> {
+    /// The bytes that haven't been decoded yet.
+    pub fn remaining(&self) -> &'a [u8] {
+        self.remaining
+    }
+
+    /// Lets the string end partway through its last token, which is then left out instead of
+    /// failing. Only the last string of a dump that was cut short by the end of the file can
+    /// end like that; anywhere else, the missing bytes mean the string is broken.
+    pub fn cut_short(mut self, allowed: bool) -> Self {
+        self.cut_short = allowed;
+        self
+    }
+
+    /// Whether the iterator stopped at the terminator of the string, as opposed to the end of
+    /// its bytes.
+    pub fn terminated(&self) -> bool {
+        self.terminated
+    }
+}
+
+impl<'a, C: TokenCodec> Iterator for Sc3StringIter<'a, C> {
     type Item = Result<StringToken<'a>, Error>;
 
     fn next(&mut self) -> Option<Self::Item> {
         if self.remaining.is_empty() {
             return None;
         }
-        match StringToken::decode(&self.remaining) {
+        match self.codec.decode(self.remaining) {
             Ok((rem, tk)) => {
                 self.remaining = rem;
                 if let StringToken::Terminator = tk {
+                    self.terminated = true;
                     None
                 } else {
                     Some(Ok(tk))
                 }
             }
-            Err(err) => Some(Err(err)),
+            // The end of the file cuts the last token short. What came before it is still good.
+            Err(Error::ExpectedMoreInput) if self.cut_short => {
+                self.remaining = &[];
+                None
+            }
+            Err(_) if self.lenient => {
+                let b = self.remaining[0];
+                self.remaining = &self.remaining[1..];
+                Some(Ok(StringToken::Unknown(b)))
+            }
+            Err(err) => {
+                self.remaining = &[];
+                Some(Err(err))
+            }
         }
     }
 }
 
+/// The first byte of each instruction that `StringToken::decode` knows.
+pub(crate) const OPCODES: [u8; 23] = [
+    0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x08, 0x09, 0x0A, 0x0B, 0x0C, 0x0E, 0x0F, 0x11, 0x12, 0x13,
+    0x15, 0x18, 0x19, 0x1A, 0x1E, 0x1F, 0xFF,
+];
+
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum StringToken<'a> {
     Text(Cow<'a, [u16]>),
+    /// `[linebreak]`, the break the games use for forced line breaks.
     LineBreak,
     NameStart,
     LineStart,
     Present(PresentAction),
+    /// A `[present]` that plays the voice the expression evaluates to, in games whose
+    /// dialect has `present_voice`.
+    PresentVoice(Expr<'a>),
     Color(Expr<'a>),
     RubyBaseStart,
     RubyTextStart,
@@ -74,7 +371,11 @@ pub enum StringToken<'a> {
     #[allow(non_camel_case_types)]
     AutoForward_1A,
     RubyCenterPerChar,
+    /// `[alt-linebreak]`, a second kind of line break that some games use alongside
+    /// `LineBreak`. Both are kept as they are so that translators decide which one to use.
     AltLineBreak,
+    /// A byte that couldn't be decoded, only produced by `Sc3String::iter_lenient`.
+    Unknown(u8),
     Terminator,
 }
 
@@ -115,11 +416,65 @@ impl<'a> Expr<'a> {
     fn const_len(b: u8) -> usize {
         (((b & 0xE0) - 0x80) / 0x20 + 1) as usize
     }
+
+    // A constant is stored big-endian: bits 5-6 of the lead byte hold the length minus one
+    // and its low 5 bits are the most significant (signed) part of the value. Returns `None`
+    // if the value takes more than the 29 bits of the longest form.
+    pub fn from_const(value: i32) -> Option<Expr<'static>> {
+        let fits = |len: usize| {
+            let bits = 5 + 8 * (len - 1);
+            (-(1 << (bits - 1))..(1 << (bits - 1))).contains(&value)
+        };
+        let len = (1..=4).find(|&n| fits(n))?;
+        let mut buf = Vec::with_capacity(len + 2);
+        let lead = (value >> (8 * (len - 1))) as u8 & 0x1F;
+        buf.push(0x80 | ((len as u8 - 1) << 5) | lead);
+        for i in (0..len - 1).rev() {
+            buf.push((value >> (8 * i)) as u8);
+        }
+        buf.extend_from_slice(&[0x00, 0x00]);
+        Some(Expr(buf.into()))
+    }
+
+    /// Returns the value of the expression if it consists of a single constant in its
+    /// shortest form, so that `from_const` gives back the same bytes.
+    pub fn as_const(&self) -> Option<i32> {
+        let (value, rest) = Self::read_const(&self.0)?;
+        if rest.len() != 2 {
+            return None;
+        }
+        Self::from_const(value)
+            .filter(|x| *x.0 == *self.0)
+            .map(|_| value)
+    }
+
+    fn read_const(i: &[u8]) -> Option<(i32, &[u8])> {
+        let (&lead, rest) = i.split_first()?;
+        if lead < 0x80 || rest.len() < Self::const_len(lead) - 1 {
+            return None;
+        }
+        let (bytes, rest) = rest.split_at(Self::const_len(lead) - 1);
+        let mut value = (lead & 0x1F) as i32;
+        if value & 0x10 != 0 {
+            value -= 0x20;
+        }
+        for b in bytes {
+            value = (value << 8) | *b as i32;
+        }
+        Some((value, rest))
+    }
 }
 
-impl<'a> StringToken<'_> {
-    pub fn decode(i: &[u8]) -> Result<(&[u8], StringToken), Error> {
-        fn parse<'a, O, P, F>(i: &'a [u8], parser: P, f: F) -> Result<(&[u8], StringToken), Error>
+impl StringToken<'_> {
+    pub fn decode(
+        i: &[u8],
+        dialect: impl Into<Dialect>,
+    ) -> Result<(&[u8], StringToken<'_>), Error> {
+        fn parse<'a, O, P, F>(
+            i: &'a [u8],
+            parser: P,
+            f: F,
+        ) -> Result<(&'a [u8], StringToken<'a>), Error>
         where
             P: Fn(&'a [u8]) -> IResult<&'a [u8], O>,
             F: Fn(O) -> StringToken<'a>,
@@ -134,17 +489,58 @@ impl<'a> StringToken<'_> {
             Ok((i, b))
         }
 
-        fn text(i: &[u8]) -> IResult<&[u8], Vec<u16>> {
-            let (i, (chars, _)) =
-                many_till(be_u16, verify(peek(be_u8), |b| *b < 0x80u8 || *b == 0xFFu8))(i)?;
+        // The first byte of a little-endian character is its low byte, which can be anything.
+        // It only starts a character when it isn't the opcode of an instruction.
+        fn is_text(i: &[u8], unit: CodeUnit) -> bool {
+            match (unit, i) {
+                (CodeUnit::U16Le, [low, high, ..]) => *high >= 0x80u8 && !OPCODES.contains(low),
+                (CodeUnit::U16Le, _) => false,
+                (_, [b, ..]) => *b >= 0x80u8 && *b != 0xFFu8,
+                _ => false,
+            }
+        }
+
+        fn unit_len(unit: CodeUnit) -> usize {
+            match unit {
+                CodeUnit::U8 => 1,
+                CodeUnit::U16Be | CodeUnit::U16Le => 2,
+            }
+        }
+
+        fn text(i: &[u8], unit: CodeUnit) -> IResult<&[u8], Vec<u16>> {
+            let char_code = |i| match unit {
+                CodeUnit::U8 => map(be_u8, |b| 0x8000 | (b & 0x7F) as u16)(i),
+                CodeUnit::U16Be => be_u16(i),
+                CodeUnit::U16Le => le_u16(i),
+            };
+            // A text run can end the string in games whose strings have no terminator.
+            let (i, (chars, _)) = many_till(
+                char_code,
+                verify(peek(rest), |rest: &[u8]| {
+                    !is_text(rest, unit) || rest.len() < unit_len(unit)
+                }),
+            )(i)?;
             Ok((i, chars))
         }
 
+        let dialect = dialect.into();
+        let unit = dialect.unit;
+        if is_text(i, unit) {
+            if i.len() < unit_len(unit) {
+                return Err(Error::ExpectedMoreInput);
+            }
+            return parse(
+                i,
+                |i| text(i, unit),
+                |chars| StringToken::Text(chars.into()),
+            );
+        }
         let (i, op) = peek_op(i).map_err(|_| Error::ExpectedMoreInput)?;
         match op {
             0x00 => Ok((i, StringToken::LineBreak)),
             0x01 => Ok((i, StringToken::NameStart)),
             0x02 => Ok((i, StringToken::LineStart)),
+            0x03 if dialect.present_voice => parse(i, Expr::parse, StringToken::PresentVoice),
             0x03 => Ok((i, StringToken::Present(PresentAction::None))),
             0x04 => parse(i, Expr::parse, StringToken::Color),
             0x05 => Ok((i, StringToken::Present(PresentAction::Unknown_0x05))),
@@ -165,16 +561,30 @@ impl<'a> StringToken<'_> {
             0x1E => Ok((i, StringToken::RubyCenterPerChar)),
             0x1F => Ok((i, StringToken::AltLineBreak)),
             0xFF => Ok((i, StringToken::Terminator)),
-            #[allow(overlapping_patterns)]
-            0x00..=0x7F => Err(Error::UnrecognizedInstr(op)),
-            _ => parse(i, text, |chars| StringToken::Text(chars.into())),
+            op => Err(Error::UnrecognizedInstr(op)),
         }
     }
 
-    pub fn encode(&self, sink: &mut impl io::Write) -> Result<(), io::Error> {
+    pub fn encode(&self, sink: &mut impl io::Write, unit: CodeUnit) -> Result<(), io::Error> {
         if let StringToken::Text(chars) = self {
             for code in chars.iter() {
-                sink.write_u16::<BigEndian>(*code)?;
+                match unit {
+                    CodeUnit::U8 if *code & 0x7FFF < 0x7F => sink.write_u8(0x80 | *code as u8)?,
+                    CodeUnit::U8 => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("character {:04X} doesn't fit in a byte", code),
+                        ))
+                    }
+                    CodeUnit::U16Be => sink.write_u16::<BigEndian>(*code)?,
+                    CodeUnit::U16Le if OPCODES.contains(&(*code as u8)) => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("character {:04X} would be read as an instruction", code),
+                        ))
+                    }
+                    CodeUnit::U16Le => sink.write_u16::<LittleEndian>(*code)?,
+                }
             }
             return Ok(());
         }
@@ -184,6 +594,7 @@ impl<'a> StringToken<'_> {
             StringToken::NameStart => 0x01,
             StringToken::LineStart => 0x02,
             StringToken::Present(PresentAction::None) => 0x03,
+            StringToken::PresentVoice(_) => 0x03,
             StringToken::Color(_) => 0x04,
             StringToken::Present(PresentAction::Unknown_0x05) => 0x05,
             StringToken::Present(PresentAction::ResetAlignment) => 0x08,
@@ -202,22 +613,35 @@ impl<'a> StringToken<'_> {
             StringToken::AutoForward_1A => 0x1A,
             StringToken::RubyCenterPerChar => 0x1E,
             StringToken::AltLineBreak => 0x1F,
+            StringToken::Unknown(b) => *b,
             StringToken::Terminator => 0xFF,
             StringToken::Text(_) => unreachable!(),
         };
 
-        sink.write(&code.to_be_bytes()).map(|_| ())?;
+        sink.write_all(&[code])?;
 
         match self {
-            StringToken::Color(expr) => sink.write(&expr.0),
-            StringToken::FontSize(val) => sink.write(&val.to_be_bytes()),
-            StringToken::MarginTop(val) => sink.write(&val.to_be_bytes()),
-            StringToken::MarginLeft(val) => sink.write(&val.to_be_bytes()),
-            StringToken::Eval(expr) => sink.write(&expr.0),
-            StringToken::HardcodedValue(val) => sink.write(&val.to_be_bytes()),
-            _ => Ok(0usize),
+            StringToken::Color(expr) => sink.write_all(&expr.0),
+            StringToken::PresentVoice(expr) => sink.write_all(&expr.0),
+            StringToken::FontSize(val) => sink.write_all(&val.to_be_bytes()),
+            StringToken::MarginTop(val) => sink.write_all(&val.to_be_bytes()),
+            StringToken::MarginLeft(val) => sink.write_all(&val.to_be_bytes()),
+            StringToken::Eval(expr) => sink.write_all(&expr.0),
+            StringToken::HardcodedValue(val) => sink.write_all(&val.to_be_bytes()),
+            _ => Ok(()),
         }
-        .map(|_| ())
+    }
+
+    /// Whether the token tells the game what to do once the text is shown rather than adding
+    /// to it. Strings often end with a few of these.
+    pub fn is_control(&self) -> bool {
+        matches!(
+            self,
+            StringToken::Present(_)
+                | StringToken::PresentVoice(_)
+                | StringToken::AutoForward
+                | StringToken::AutoForward_1A
+        )
     }
 }
 
@@ -240,13 +664,331 @@ impl fmt::Display for Error {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::gamedef::{self, test_defs::DEFS};
+
+    #[test]
+    fn build_dialogue_line() -> Result<(), text::EncodingError> {
+        let gamedef = gamedef::get_by_alias(&DEFS, "sg0").unwrap();
+        let line = Sc3StringBuilder::new()
+            .push_name_start()
+            .push_text("LuLu", gamedef)?
+            .push_line_start()
+            .push_text("Meow", gamedef)?
+            .push_present(PresentAction::None)
+            .build(gamedef);
+
+        let tokens = line
+            .iter(CodeUnit::U16Be)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(tokens.len(), 5);
+        assert_eq!(tokens[0], StringToken::NameStart);
+        assert_eq!(tokens[2], StringToken::LineStart);
+        assert_eq!(tokens[4], StringToken::Present(PresentAction::None));
+        if let StringToken::Text(name) = &tokens[1] {
+            assert_eq!(text::decode_str(name, gamedef, false)?.as_str(), "LuLu");
+        } else {
+            panic!("expected a text token, got {:?}", tokens[1]);
+        }
+        assert_eq!(line.0.last(), Some(&0xFF));
+        Ok(())
+    }
+
+    #[test]
+    fn build_unterminated() {
+        let mut defs = gamedef::builtin_gamedefs();
+        let gamedef = defs
+            .iter_mut()
+            .find(|x| x.aliases.contains(&"sg0".into()))
+            .unwrap();
+        gamedef.terminated = false;
+        let line = Sc3StringBuilder::new()
+            .push_line_start()
+            .push(StringToken::Text(vec![0x8001].into()))
+            .build(gamedef);
+        assert_eq!(line.0, &[0x02, 0x80, 0x01][..]);
+    }
+
+    #[test]
+    fn decoded_line() -> Result<(), Box<dyn std::error::Error>> {
+        let gamedef = gamedef::get_by_alias(&DEFS, "sg0").unwrap();
+        let s = Sc3StringBuilder::new()
+            .push_name_start()
+            .push_text("LuLu", gamedef)?
+            .push_line_start()
+            .push_color(Expr::from_const(5).unwrap())
+            .push_text("Meow", gamedef)?
+            .build(gamedef);
+        let mut line = DecodedLine::decode(&s, gamedef, false)?;
+        assert_eq!(
+            serde_json::to_string(&line)?,
+            r#"[{"tag":"name"},{"text":"LuLu"},{"tag":"line"},{"tag":"color","index":"850000"},{"text":"Meow"}]"#
+        );
+        assert_eq!(line.encode(), s);
+
+        line.tokens.truncate(3);
+        line.tokens.push(StringToken::LineBreak);
+        assert_eq!(
+            serde_json::to_string(&line)?,
+            r#"[{"tag":"name"},{"text":"LuLu"},{"tag":"line"},{"tag":"linebreak"}]"#
+        );
+        assert_eq!(line.encode().0.last(), Some(&0xFF));
+        Ok(())
+    }
 
     #[test]
     fn unrecognized_instr() {
-        let i = vec![0x05u8];
-        let res = StringToken::decode(&i);
+        let i = vec![0x06u8];
+        let res = StringToken::decode(&i, CodeUnit::U16Be);
         println!("{:?}", res);
-        assert_eq!(res.is_err(), true);
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn cut_short() {
+        let text = StringToken::Text(vec![0x8010, 0x8011].into());
+        // Nothing, half a character, and half an expression after the text.
+        for tail in [&[][..], &[0x80], &[0x04, 0xA0]] {
+            let mut bytes = vec![0x80, 0x10, 0x80, 0x11];
+            bytes.extend_from_slice(tail);
+            let s = Sc3String(bytes.into());
+            let tokens = s.iter(CodeUnit::U16Be).cut_short(true);
+            assert_eq!(
+                tokens.collect::<Result<Vec<_>, _>>().unwrap(),
+                vec![text.clone()]
+            );
+            // Any other string that ends partway through a token is broken.
+            let tokens = s.iter(CodeUnit::U16Be).collect::<Result<Vec<_>, _>>();
+            assert_eq!(tokens.is_err(), !tail.is_empty());
+        }
+
+        let s = Sc3String(vec![0x80, 0x10, 0xFF].into());
+        let mut tokens = s.iter(CodeUnit::U16Be).cut_short(true);
+        assert!(tokens.by_ref().all(|x| x.is_ok()));
+        assert!(tokens.terminated());
+    }
+
+    #[test]
+    fn lenient_iteration() {
+        let s = Sc3String(vec![0x80, 0x10, 0x06, 0x80, 0x11, 0xFF].into());
+        let mut strict = s.iter(CodeUnit::U16Be);
+        assert!(matches!(strict.next(), Some(Ok(StringToken::Text(_)))));
+        assert!(matches!(
+            strict.next(),
+            Some(Err(Error::UnrecognizedInstr(0x06)))
+        ));
+        assert!(strict.next().is_none());
+
+        let tokens = s
+            .iter_lenient(CodeUnit::U16Be)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                StringToken::Text(vec![0x8010].into()),
+                StringToken::Unknown(0x06),
+                StringToken::Text(vec![0x8011].into()),
+            ]
+        );
+
+        let mut buf = Vec::new();
+        for tk in tokens.iter().chain(Some(&StringToken::Terminator)) {
+            tk.encode(&mut buf, CodeUnit::U16Be).unwrap();
+        }
+        assert_eq!(buf, *s.0);
+    }
+
+    // A game like the others but for an opcode 0x20 that auto-forwards, the way a codec for a
+    // game that isn't supported yet could start out.
+    struct Experimental(Dialect);
+
+    impl TokenCodec for Experimental {
+        fn decode<'a>(&self, i: &'a [u8]) -> Result<(&'a [u8], StringToken<'a>), Error> {
+            match i {
+                [0x20, rest @ ..] => Ok((rest, StringToken::AutoForward)),
+                _ => self.0.decode(i),
+            }
+        }
+
+        fn encode(&self, token: &StringToken, sink: &mut dyn io::Write) -> Result<(), io::Error> {
+            match token {
+                StringToken::AutoForward => sink.write_all(&[0x20]),
+                _ => self.0.encode(token, sink),
+            }
+        }
+    }
+
+    #[test]
+    fn custom_codec() {
+        let codec = Experimental(CodeUnit::U16Be.into());
+        let s = Sc3String(vec![0x01, 0x80, 0x10, 0x20, 0xFF].into());
+        assert!(matches!(
+            s.iter(CodeUnit::U16Be).nth(2),
+            Some(Err(Error::UnrecognizedInstr(0x20)))
+        ));
+
+        let tokens = s.iter_with(&codec).collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                StringToken::NameStart,
+                StringToken::Text(vec![0x8010].into()),
+                StringToken::AutoForward,
+            ]
+        );
+        let mut builder = Sc3StringBuilder::new();
+        for tk in tokens {
+            builder.push(tk);
+        }
+        assert_eq!(builder.build_with(&codec, true).unwrap(), s);
+        let gamedef = gamedef::get_by_alias(&DEFS, "sg0").unwrap();
+        assert_ne!(builder.build(gamedef), s);
+    }
+
+    // Accepts one byte per call, like a pipe with a full buffer.
+    struct TrickleWriter(Vec<u8>);
+
+    impl io::Write for TrickleWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            match buf.first() {
+                Some(&b) => {
+                    self.0.push(b);
+                    Ok(1)
+                }
+                None => Ok(0),
+            }
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn encode_with_short_writes() {
+        let tokens = [
+            StringToken::Color(Expr::from_const(3).unwrap()),
+            StringToken::FontSize(24),
+            StringToken::MarginTop(38),
+            StringToken::Eval(Expr::from_const(0x123).unwrap()),
+            StringToken::Text(vec![0x8010, 0x8011].into()),
+            StringToken::Terminator,
+        ];
+        let mut expected = Vec::new();
+        let mut trickle = TrickleWriter(Vec::new());
+        for tk in &tokens {
+            tk.encode(&mut expected, CodeUnit::U16Be).unwrap();
+            tk.encode(&mut trickle, CodeUnit::U16Be).unwrap();
+        }
+        assert_eq!(trickle.0, expected);
+        assert_eq!(expected.len(), 4 + 3 + 3 + 5 + 4 + 1);
+    }
+
+    fn assert_roundtrip(bytes: &[u8], unit: CodeUnit, expected: Vec<StringToken>) {
+        let s = Sc3String(bytes.into());
+        let tokens = s.iter(unit).collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(tokens, expected);
+        let mut buf = Vec::new();
+        for tk in tokens.iter().chain(Some(&StringToken::Terminator)) {
+            tk.encode(&mut buf, unit).unwrap();
+        }
+        assert_eq!(buf, bytes);
+    }
+
+    #[test]
+    fn byte_text_units() {
+        assert_roundtrip(
+            &[0x81, 0x82, 0x00, 0xFE, 0xFF],
+            CodeUnit::U8,
+            vec![
+                StringToken::Text(vec![0x8001, 0x8002].into()),
+                StringToken::LineBreak,
+                StringToken::Text(vec![0x807E].into()),
+            ],
+        );
+        let mut buf = Vec::new();
+        let wide = StringToken::Text(vec![0x807F].into());
+        assert!(wide.encode(&mut buf, CodeUnit::U8).is_err());
+    }
+
+    #[test]
+    fn little_endian_text_units() {
+        assert_roundtrip(
+            &[0x10, 0x80, 0xFE, 0x81, 0x00, 0x34, 0x92, 0xFF],
+            CodeUnit::U16Le,
+            vec![
+                StringToken::Text(vec![0x8010, 0x81FE].into()),
+                StringToken::LineBreak,
+                StringToken::Text(vec![0x9234].into()),
+            ],
+        );
+        // Operands and characters can start with a byte past 0x80 as well.
+        assert_roundtrip(
+            &[0x00, 0x85, 0x80, 0x04, 0x81, 0x00, 0x00, 0x86, 0x80, 0xFF],
+            CodeUnit::U16Le,
+            vec![
+                StringToken::LineBreak,
+                StringToken::Text(vec![0x8085].into()),
+                StringToken::Color(Expr(vec![0x81, 0x00, 0x00].into())),
+                StringToken::Text(vec![0x8086].into()),
+            ],
+        );
+        // A character whose low byte is an opcode can't be told apart from the instruction,
+        // so glyph 0 reads as a line break.
+        let mut buf = Vec::new();
+        for code in [0x8000, 0x8101] {
+            let text = StringToken::Text(vec![code].into());
+            assert!(text.encode(&mut buf, CodeUnit::U16Le).is_err());
+        }
+        let (_, token) = StringToken::decode(&[0x00, 0x80, 0xFF], CodeUnit::U16Le).unwrap();
+        assert_eq!(token, StringToken::LineBreak);
+    }
+
+    #[test]
+    fn opcodes() {
+        for op in 0..=0xFF {
+            let bytes = [op, 0x80, 0x00, 0x00];
+            let decoded = StringToken::decode(&bytes, CodeUnit::U16Be);
+            let unknown = matches!(decoded, Err(Error::UnrecognizedInstr(_)));
+            assert_eq!(unknown, op < 0x80 && !OPCODES.contains(&op), "{:02X}", op);
+        }
+    }
+
+    #[test]
+    fn const_expr_roundtrip() {
+        for value in [0, 15, -16, 16, 4095, -4096, 4096, 1 << 20, -(1 << 28)] {
+            let expr = Expr::from_const(value).unwrap();
+            assert_eq!(Expr::parse(&expr.0).unwrap().1, expr);
+            assert_eq!(expr.as_const(), Some(value));
+        }
+        assert_eq!(Expr::from_const(3).unwrap().0.as_ref(), &[0x83, 0x00, 0x00]);
+        assert_eq!(
+            Expr::from_const(0x123).unwrap().0.as_ref(),
+            &[0xA1, 0x23, 0x00, 0x00]
+        );
+        assert_eq!(Expr::from_const(1 << 28), None);
+        assert_eq!(Expr::from_const(-(1 << 28) - 1), None);
+    }
+
+    #[test]
+    fn multi_byte_consts() {
+        let decode = |bytes: &[u8]| Expr(Cow::from(bytes.to_vec())).as_const();
+        assert_eq!(decode(&[0xC1, 0x23, 0x45, 0x00, 0x00]), Some(0x12345));
+        assert_eq!(
+            decode(&[0xFF, 0x00, 0x00, 0x00, 0x00, 0x00]),
+            Some(-(1 << 24))
+        );
+        assert_eq!(decode(&[0xAF, 0xFF, 0x00, 0x00]), Some(0xFFF));
+        // -1 fits in one byte, so the four-byte form doesn't round-trip.
+        assert_eq!(decode(&[0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x00]), None);
+        assert_eq!(decode(&[0xC1, 0x23]), None);
+    }
+
+    #[test]
+    fn non_const_expr() {
+        let expr = vec![0x29, 0x0A, 0xA0, 0x5A, 0x14, 0x14, 0x00, 0x80, 0x00, 0x00];
+        assert_eq!(Expr(Cow::from(&expr)).as_const(), None);
     }
 
     #[test]
@@ -0,0 +1,118 @@
+// A sparse text file holds only some of a script's strings, each one prefixed with its
+// index in the script:
+//
+// 12: [name]Okabe[line]Hm.
+// 40: ...
+
+use std::{error, fmt, io};
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum Error {
+    MalformedLine,
+    UnorderedIndex(usize),
+    IndexOutOfRange(usize, usize),
+}
+
+impl error::Error for Error {}
+
+pub fn write_line(out: &mut impl io::Write, index: usize, text: &str) -> io::Result<()> {
+    writeln!(out, "{}", format_line(index, text))
+}
+
+pub fn format_line(index: usize, text: &str) -> String {
+    format!("{}: {}", index, text)
+}
+
+pub fn parse_line(line: &str) -> Option<(usize, &str)> {
+    let (number, text) = line.split_once(':')?;
+    let number = number.trim().parse().ok()?;
+    // Some tools drop the space after an empty line's number.
+    Some((number, text.strip_prefix(' ').unwrap_or(text)))
+}
+
+/// Places the lines of a sparse text file at their indices in a script of `count` strings.
+/// Returns the strings that aren't in the file as `None`, or the position of the offending
+/// line along with the error.
+pub fn expand(
+    lines: impl Iterator<Item = String>,
+    count: usize,
+) -> Result<Vec<Option<String>>, (usize, Error)> {
+    let mut expanded = vec![None; count];
+    let mut next = 0;
+    for (i, line) in lines.enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (index, text) = parse_line(&line).ok_or((i, Error::MalformedLine))?;
+        if index >= count {
+            return Err((i, Error::IndexOutOfRange(index, count)));
+        }
+        if index < next {
+            return Err((i, Error::UnorderedIndex(index)));
+        }
+        expanded[index] = Some(text.to_owned());
+        next = index + 1;
+    }
+    Ok(expanded)
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::MalformedLine => write!(f, "expected '<index>: <text>'"),
+            Error::UnorderedIndex(index) => write!(
+                f,
+                "string {} is out of order or appears more than once",
+                index
+            ),
+            Error::IndexOutOfRange(index, count) => write!(
+                f,
+                "string {} doesn't exist, the script only has {}",
+                index, count
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> impl Iterator<Item = String> + '_ {
+        text.lines().map(str::to_owned)
+    }
+
+    #[test]
+    fn roundtrip() {
+        let mut buf = Vec::new();
+        write_line(&mut buf, 1, "[name]Okabe[line]Hm.").unwrap();
+        write_line(&mut buf, 3, "").unwrap();
+        let text = String::from_utf8(buf).unwrap();
+        assert_eq!(
+            expand(lines(&text), 4).unwrap(),
+            vec![
+                None,
+                Some("[name]Okabe[line]Hm.".to_owned()),
+                None,
+                Some("".to_owned())
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_files() {
+        assert_eq!(expand(lines("text"), 4), Err((0, Error::MalformedLine)));
+        assert_eq!(
+            expand(lines("2: a\n1: b"), 4),
+            Err((1, Error::UnorderedIndex(1)))
+        );
+        assert_eq!(
+            expand(lines("2: a\n2: b"), 4),
+            Err((1, Error::UnorderedIndex(2)))
+        );
+        assert_eq!(
+            expand(lines("\n4: a"), 4),
+            Err((1, Error::IndexOutOfRange(4, 4)))
+        );
+    }
+}
@@ -0,0 +1,103 @@
+// An optional first line of an extracted text file that identifies the script it was
+// extracted from, so that replace-text can refuse to apply it to a different revision.
+
+use crate::format::MagesScript;
+use std::{fmt, io};
+
+const PREFIX: &str = "// sc3tools stamp: ";
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Stamp {
+    pub line_count: usize,
+    pub hash: u64,
+}
+
+impl Stamp {
+    pub fn of(script: &dyn MagesScript) -> io::Result<Self> {
+        let index = script.string_index();
+        let mut hash = Fnv1a::new();
+        for handle in index.iter() {
+            hash.write(&script.read_string(handle)?.0);
+        }
+        Ok(Self {
+            line_count: index.count(),
+            hash: hash.finish(),
+        })
+    }
+
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut line_count = None;
+        let mut hash = None;
+        for field in line.strip_prefix(PREFIX)?.split_whitespace() {
+            match field.split_once('=')? {
+                ("lines", value) => line_count = value.parse().ok(),
+                ("hash", value) => hash = u64::from_str_radix(value, 16).ok(),
+                _ => return None,
+            }
+        }
+        Some(Self {
+            line_count: line_count?,
+            hash: hash?,
+        })
+    }
+}
+
+impl fmt::Display for Stamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}lines={} hash={:016x}",
+            PREFIX, self.line_count, self.hash
+        )
+    }
+}
+
+// FNV-1a: simple and, unlike `DefaultHasher`, stable across Rust versions.
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    fn new() -> Self {
+        Fnv1a(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= *b as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_roundtrip() {
+        let stamp = Stamp {
+            line_count: 42,
+            hash: 0x0123_4567_89ab_cdef,
+        };
+        let line = stamp.to_string();
+        assert_eq!(line, "// sc3tools stamp: lines=42 hash=0123456789abcdef");
+        assert_eq!(Stamp::parse(&line), Some(stamp));
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert_eq!(Stamp::parse("[name]Okabe[line]Hm."), None);
+        assert_eq!(Stamp::parse("// sc3tools stamp: lines=42"), None);
+        assert_eq!(Stamp::parse("// sc3tools stamp: lines=x hash=0"), None);
+    }
+
+    #[test]
+    fn fnv1a() {
+        let mut hash = Fnv1a::new();
+        hash.write(b"a");
+        assert_eq!(hash.finish(), 0xaf63_dc4c_8601_ec8c);
+    }
+}
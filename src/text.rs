@@ -1,13 +1,31 @@
 use nom::{
-    branch::alt, bytes::complete::is_not, character::complete::anychar, character::complete::char,
-    combinator::map, combinator::map_res, combinator::recognize, sequence::delimited, IResult,
+    branch::alt,
+    bytes::complete::is_not,
+    character::complete::anychar,
+    character::complete::char,
+    character::complete::one_of,
+    combinator::map,
+    combinator::map_res,
+    combinator::recognize,
+    error::{Error, ErrorKind},
+    sequence::{delimited, preceded},
+    IResult,
 };
 
-use crate::gamedef::GameDef;
-use std::{borrow::Cow, collections::HashMap, error, fmt};
+use crate::{
+    coz::{CozString, StringSegment},
+    gamedef::GameDef,
+};
+use itertools::Itertools;
+use std::{borrow::Cow, collections::HashMap, error, fmt, ops::RangeInclusive};
 
 pub const FULLWIDTH_SPACE: char = '\u{3000}';
 
+// A literal `[` is written as `\[` so that it isn't taken for the start of a tag or of a
+// compound character, and a literal backslash as `\\`. Any other backslash is read as is.
+const ESCAPE: char = '\\';
+const ESCAPED_CHARS: &str = "[\\";
+
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub struct Text<'a>(pub Cow<'a, str>);
 
@@ -18,7 +36,7 @@ pub enum Char<'a> {
 }
 
 impl<'a> Text<'a> {
-    pub fn iter(&self, encoding_maps: &'a EncodingMaps) -> CharIterator {
+    pub fn iter(&self, encoding_maps: &'a EncodingMaps) -> CharIterator<'_> {
         CharIterator {
             remaining: &self.0,
             encoding_maps,
@@ -29,6 +47,28 @@ impl<'a> Text<'a> {
         &self.0
     }
 
+    /// The text with its escapes taken out, as it appears in the game.
+    pub fn unescaped(&self) -> Cow<'_, str> {
+        if !self.0.contains(ESCAPE) {
+            return Cow::from(self.as_str());
+        }
+        let mut buf = String::with_capacity(self.0.len());
+        let mut rest = self.as_str();
+        while let Some(c) = rest.chars().next() {
+            match escaped_char(rest) {
+                Ok((after, c)) => {
+                    buf.push(c);
+                    rest = after;
+                }
+                Err(_) => {
+                    buf.push(c);
+                    rest = &rest[c.len_utf8()..];
+                }
+            }
+        }
+        Cow::from(buf)
+    }
+
     pub fn from_chars(
         chars: impl Iterator<Item = Char<'a>>,
         keep_fullwidth_chars: bool,
@@ -41,11 +81,14 @@ impl<'a> Text<'a> {
                         c = replace_fullwidth(c);
                     }
 
+                    if ESCAPED_CHARS.contains(c) {
+                        buf.push(ESCAPE);
+                    }
                     buf.push(c);
                 }
                 Char::Compound(s) => {
                     buf.push('[');
-                    buf.push_str(&s);
+                    buf.push_str(s);
                     buf.push(']');
                 }
             }
@@ -55,6 +98,11 @@ impl<'a> Text<'a> {
     }
 }
 
+/// A character written with a backslash before it, such as `\[`.
+pub fn escaped_char(input: &str) -> IResult<&str, char> {
+    preceded(char(ESCAPE), one_of(ESCAPED_CHARS))(input)
+}
+
 pub struct CharIterator<'a> {
     remaining: &'a str,
     encoding_maps: &'a EncodingMaps,
@@ -65,17 +113,26 @@ impl<'a> Iterator for CharIterator<'a> {
 
     fn next(&mut self) -> Option<Self::Item> {
         fn next_char<'a>(s: &'a str, encoding_maps: &EncodingMaps) -> IResult<&'a str, Char<'a>> {
-            let encode_compound = move |ch| encode_compound_char(ch, &encoding_maps);
+            let encode_compound = move |ch| encode_compound_char(ch, encoding_maps);
             let compound = delimited(
                 char('['),
                 recognize(map_res(is_not("]"), encode_compound)),
                 char(']'),
             );
-
-            alt((map(compound, Char::Compound), map(anychar, Char::Regular)))(s)
+            let bare_compound = |s: &'a str| match encoding_maps.bare_compound_len(s) {
+                Some(len) => Ok((&s[len..], &s[..len])),
+                None => Err(nom::Err::Error(Error::new(s, ErrorKind::Verify))),
+            };
+
+            alt((
+                map(escaped_char, Char::Regular),
+                map(compound, Char::Compound),
+                map(bare_compound, Char::Compound),
+                map(anychar, Char::Regular),
+            ))(s)
         }
 
-        let res = next_char(&self.remaining, &self.encoding_maps).ok();
+        let res = next_char(self.remaining, self.encoding_maps).ok();
         if let Some((rem, ch)) = res {
             self.remaining = rem;
             Some(ch)
@@ -85,6 +142,13 @@ impl<'a> Iterator for CharIterator<'a> {
     }
 }
 
+// A character that doesn't end up in the script as it was written in the text file.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum Substitution {
+    Fullwidth(char, char),
+    Compound(String, char),
+}
+
 #[derive(Debug, Eq, PartialEq)]
 pub enum EncodingError {
     IllegalCharCode(u16),
@@ -97,17 +161,28 @@ impl error::Error for EncodingError {}
 #[derive(Debug)]
 pub struct EncodingMapConstructionError {
     pub missing_pua_chars: Vec<char>,
+    /// Compound characters mapped past the end of the reserved range, or by a run of mappings
+    /// that crosses one of its ends.
+    pub unreserved_pua_chars: Vec<char>,
 }
 
 pub struct EncodingMaps {
     main: HashMap<char, u16>,
     compound: HashMap<String, u16>,
+    // How many characters the longest compound character that can be written without
+    // brackets has, or 0 if they all need brackets.
+    max_bare_compound_len: usize,
 }
 
 impl EncodingMaps {
+    /// `reserved` is the block of placeholder glyphs that new compound characters go into.
+    /// The glyphs before it belong to the game's own font, so it rules out the mappings that
+    /// come after it and the runs of mappings to the same text that cross one of its ends,
+    /// which belong to neither. Blank filler is left alone.
     pub fn new(
         charset: &[char],
         pua_mappings: &HashMap<char, String>,
+        reserved: Option<&RangeInclusive<char>>,
     ) -> Result<Self, EncodingMapConstructionError> {
         let main: HashMap<_, _> = (0..charset.len())
             .map(|i| {
@@ -118,21 +193,79 @@ impl EncodingMaps {
             })
             .collect();
 
-        let lookup_compound = |ch| main.get(ch).ok_or_else(|| *ch);
+        let lookup_compound = |ch| main.get(ch).ok_or(*ch);
 
         let (compound, missing): (Vec<_>, Vec<_>) = pua_mappings
             .iter()
             .map(|(k, v)| lookup_compound(k).map(|code| (v.clone(), *code)))
             .partition(Result::is_ok);
 
-        if !missing.is_empty() {
+        let unreserved = match reserved {
+            Some(range) => unreserved_pua_chars(pua_mappings, range),
+            None => Vec::new(),
+        };
+
+        if !missing.is_empty() || !unreserved.is_empty() {
             return Err(EncodingMapConstructionError {
                 missing_pua_chars: missing.into_iter().map(Result::unwrap_err).collect(),
+                unreserved_pua_chars: unreserved,
             });
         }
 
-        let compound: HashMap<_, _> = compound.into_iter().map(Result::unwrap).collect();
-        Ok(EncodingMaps { main, compound })
+        // When several glyphs stand for the same text, the one that comes first in the
+        // charset is used.
+        let mut compound_map = HashMap::new();
+        for (s, code) in compound
+            .into_iter()
+            .map(Result::unwrap)
+            .sorted_by_key(|(_, code)| *code)
+        {
+            compound_map.entry(s).or_insert(code);
+        }
+        Ok(EncodingMaps {
+            main,
+            compound: compound_map,
+            max_bare_compound_len: 0,
+        })
+    }
+
+    /// Lets compound characters be written without brackets. Where compound characters that
+    /// start the same way overlap (`¹` and `¹⁸`), the longest one is used. Blank compound
+    /// characters still need brackets, and so do single characters that are in the charset
+    /// as regular characters as well, since those are encoded as the regular ones.
+    pub fn match_bare_compounds(&mut self) {
+        self.max_bare_compound_len = self
+            .compound
+            .keys()
+            .filter(|x| !x.trim().is_empty())
+            .map(|x| x.chars().count())
+            .max()
+            .unwrap_or_default();
+    }
+
+    // The length in bytes of the longest compound character that `s` starts with, if bare
+    // compound characters are matched.
+    fn bare_compound_len(&self, s: &str) -> Option<usize> {
+        let ends = s
+            .char_indices()
+            .map(|(i, c)| i + c.len_utf8())
+            .take(self.max_bare_compound_len)
+            .collect::<Vec<_>>();
+        let first_is_regular = s.chars().next().is_some_and(|c| self.main.contains_key(&c));
+        ends.into_iter()
+            .enumerate()
+            .rev()
+            .filter(|(i, _)| *i > 0 || !first_is_regular)
+            .map(|(_, end)| end)
+            .find(|end| !s[..*end].trim().is_empty() && self.compound.contains_key(&s[..*end]))
+    }
+
+    pub fn code_of(&self, c: char) -> Option<u16> {
+        self.main.get(&c).cloned()
+    }
+
+    pub fn compound_code_of(&self, s: &str) -> Option<u16> {
+        self.compound.get(s).cloned()
     }
 }
 
@@ -141,19 +274,120 @@ pub fn encode_str(
     gamedef: &GameDef,
     convert_to_fullwidth: bool,
 ) -> Result<Vec<u16>, EncodingError> {
-    let mut buf = Vec::new();
-    for mut ch in s.iter(&gamedef.encoding_maps) {
-        if let Char::Regular(c) = &ch {
-            if convert_to_fullwidth && !gamedef.fullwidth_blocklist.contains(&*c) {
-                ch = Char::Regular(replace_halfwidth(*c));
-            } else if *c == '\u{20}' {
-                ch = Char::Regular(FULLWIDTH_SPACE);
+    encode_str_with_policy(s, gamedef, convert_to_fullwidth, MissingCharPolicy::Fail)
+}
+
+/// What to do with a character that isn't in the charset when encoding text.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub enum MissingCharPolicy {
+    #[default]
+    Fail,
+    /// Leave the character out.
+    Skip,
+    /// Write this character in its place. It's converted to fullwidth like the rest of the
+    /// text, and has to be in the charset.
+    Replace(char),
+}
+
+/// Like `encode_str`, but a character that isn't in the charset is dealt with by `policy`.
+/// `missing_chars` lists the ones that are skipped or replaced.
+pub fn encode_str_with_policy(
+    s: &Text,
+    gamedef: &GameDef,
+    convert_to_fullwidth: bool,
+    policy: MissingCharPolicy,
+) -> Result<Vec<u16>, EncodingError> {
+    let mut codes = Vec::new();
+    for ch in s.iter(&gamedef.encoding_maps) {
+        let ch = substitute(ch, gamedef, convert_to_fullwidth);
+        match (encode_char(&ch, gamedef), policy) {
+            (Ok(code), _) => codes.push(code),
+            (Err(EncodingError::CharNotInCharset(_)), MissingCharPolicy::Skip) => {}
+            (Err(EncodingError::CharNotInCharset(_)), MissingCharPolicy::Replace(fallback)) => {
+                let fallback = substitute(Char::Regular(fallback), gamedef, convert_to_fullwidth);
+                codes.push(encode_char(&fallback, gamedef)?);
             }
+            (Err(err), _) => return Err(err),
         }
-        buf.push(encode_char(&ch, &gamedef)?);
     }
+    Ok(codes)
+}
+
+/// Checks that a line, written the way it is in text files, can be encoded for the game,
+/// without a script to encode it into. Only its text is checked, as its tags aren't made of
+/// glyphs. Otherwise, returns the characters the charset doesn't have, each one once, in the
+/// order they come up. Those of a compound character the game doesn't have are all listed.
+pub fn can_encode(line: &str, gamedef: &GameDef) -> Result<(), Vec<char>> {
+    let missing = missing_chars(&CozString(line.into()), gamedef, false);
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(missing)
+    }
+}
 
-    Ok(buf)
+/// The characters `can_encode` lists, once the text is converted to fullwidth if
+/// `convert_to_fullwidth` is set.
+pub fn missing_chars(line: &CozString, gamedef: &GameDef, convert_to_fullwidth: bool) -> Vec<char> {
+    let mut missing = Vec::new();
+    for segment in line.iter() {
+        let text = match segment {
+            StringSegment::Text(text) => text,
+            StringSegment::Tag(_) => continue,
+        };
+        for ch in text.iter(&gamedef.encoding_maps) {
+            let substituted = substitute(ch.clone(), gamedef, convert_to_fullwidth);
+            if encode_char(&substituted, gamedef).is_ok() {
+                continue;
+            }
+            let chars = match ch {
+                Char::Regular(c) => vec![c],
+                Char::Compound(s) => s.chars().collect(),
+            };
+            for c in chars {
+                if !missing.contains(&c) {
+                    missing.push(c);
+                }
+            }
+        }
+    }
+    missing
+}
+
+// Lists the substitutions `encode_str` makes, each one only once.
+pub fn substitutions(
+    s: &Text,
+    gamedef: &GameDef,
+    convert_to_fullwidth: bool,
+) -> Result<Vec<Substitution>, EncodingError> {
+    let mut substitutions = Vec::new();
+    for ch in s.iter(&gamedef.encoding_maps) {
+        let substitution = match substitute(ch.clone(), gamedef, convert_to_fullwidth) {
+            Char::Regular(c) => match ch {
+                Char::Regular(orig) if orig != c => Substitution::Fullwidth(orig, c),
+                _ => continue,
+            },
+            compound @ Char::Compound(_) => {
+                let code = encode_char(&compound, gamedef)?;
+                let pua_ch = gamedef.charset()[(code & 0x7FFF) as usize];
+                Substitution::Compound(compound.to_string(), pua_ch)
+            }
+        };
+        if !substitutions.contains(&substitution) {
+            substitutions.push(substitution);
+        }
+    }
+    Ok(substitutions)
+}
+
+fn substitute<'a>(ch: Char<'a>, gamedef: &GameDef, convert_to_fullwidth: bool) -> Char<'a> {
+    match ch {
+        Char::Regular(c) if convert_to_fullwidth && !gamedef.fullwidth_blocklist.contains(&c) => {
+            Char::Regular(replace_halfwidth(c))
+        }
+        Char::Regular('\u{20}') => Char::Regular(FULLWIDTH_SPACE),
+        _ => ch,
+    }
 }
 
 fn encode_char(ch: &Char, gamedef: &GameDef) -> Result<u16, EncodingError> {
@@ -200,22 +434,35 @@ pub fn decode_char<'a>(
     let ch = charset
         .get(i)
         .cloned()
-        .ok_or_else(|| EncodingError::IllegalCharCode(code))?;
+        .ok_or(EncodingError::IllegalCharCode(code))?;
     if let '\u{e000}'..='\u{f8ff}' = ch {
         // Private Use Area
         compound_map
             .get(&ch)
             .map(|s| Char::Compound(s))
-            .ok_or_else(|| EncodingError::PuaCharNotMapped(code, ch))
+            .ok_or(EncodingError::PuaCharNotMapped(code, ch))
     } else {
         Ok(Char::Regular(ch))
     }
 }
 
+pub fn normalize_width(
+    s: &[u16],
+    gamedef: &GameDef,
+    to_fullwidth: bool,
+) -> Result<Vec<u16>, EncodingError> {
+    let decoded = decode_str(s, gamedef, false)?;
+    encode_str(&decoded, gamedef, to_fullwidth)
+}
+
 pub fn to_halfwidth<'a>(s: &'a Text, encoding_maps: &'a EncodingMaps) -> Text<'a> {
     Text::from_chars(s.iter(encoding_maps), false)
 }
 
+pub fn to_halfwidth_spaces(s: &str) -> String {
+    s.replace(FULLWIDTH_SPACE, " ")
+}
+
 pub fn is_fullwidth_ch(ch: char) -> bool {
     ('\u{ff00}'..='\u{ff7f}').contains(&ch)
 }
@@ -236,6 +483,29 @@ pub fn replace_fullwidth(ch: char) -> char {
     }
 }
 
+impl fmt::Display for Char<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Char::Regular(c) => write!(f, "{}", c),
+            Char::Compound(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+impl fmt::Display for Substitution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Substitution::Fullwidth(from, FULLWIDTH_SPACE) => {
+                write!(f, "'{}' → U+3000 (fullwidth)", from)
+            }
+            Substitution::Fullwidth(from, to) => write!(f, "{} → {} (fullwidth)", from, to),
+            Substitution::Compound(from, to) => {
+                write!(f, "{} → U+{:04X} (compound)", from, *to as u32)
+            }
+        }
+    }
+}
+
 impl fmt::Display for EncodingError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -255,24 +525,88 @@ impl fmt::Display for EncodingError {
     }
 }
 
+// The compound characters that `reserved` rules out, as described in `EncodingMaps::new`. A
+// run is a range of consecutive codepoints mapped to the same text, such as a `[E021-E067]=①`
+// line of a map.
+fn unreserved_pua_chars(
+    pua_mappings: &HashMap<char, String>,
+    reserved: &RangeInclusive<char>,
+) -> Vec<char> {
+    let mut runs: Vec<(Vec<char>, &str)> = Vec::new();
+    let mappings = pua_mappings.iter().filter(|(_, v)| !v.trim().is_empty());
+    for (ch, text) in mappings.sorted() {
+        match runs.last_mut() {
+            Some((run, last_text))
+                if *last_text == text && (*run.last().unwrap() as u32) + 1 == *ch as u32 =>
+            {
+                run.push(*ch)
+            }
+            _ => runs.push((vec![*ch], text)),
+        }
+    }
+
+    let mut unreserved = Vec::new();
+    for (run, _) in runs {
+        let inside = run.iter().filter(|x| reserved.contains(x)).count();
+        if inside > 0 && inside < run.len() {
+            unreserved.extend(run.into_iter().filter(|x| !reserved.contains(x)));
+        } else if run[0] > *reserved.end() {
+            unreserved.extend(run);
+        }
+    }
+    unreserved
+}
+
+impl fmt::Display for EncodingMapConstructionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let list = |chars: &[char]| {
+            chars
+                .iter()
+                .map(|ch| format!("'{}'", ch.escape_unicode()))
+                .join(", ")
+        };
+        if !self.missing_pua_chars.is_empty() {
+            write!(
+                f,
+                "The following Private Use Area characters were not found in the charset: [{}]",
+                list(&self.missing_pua_chars)
+            )?;
+        }
+        if !self.unreserved_pua_chars.is_empty() {
+            if !self.missing_pua_chars.is_empty() {
+                write!(f, " ")?;
+            }
+            write!(
+                f,
+                "The following compound characters are mapped past the end of the reserved range, or by a range that crosses one of its ends: [{}]",
+                list(&self.unreserved_pua_chars)
+            )?;
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::gamedef;
-
-    static SG0_DEF_JSON: &str = r#"
-    [{
-        "name": "Steins;Gate 0",
-        "resource_dir": "sg0",
-        "aliases": ["sg0", "steinsgate0"],
-        "reserved_codepoints": {
-        "start": "\\u{E12F}",
-        "end": "\\u{E2AF}"
-        },
-        "fullwidth_blocklist": ["'", "-", "[", "]", "(", ")"]
-    }]"#;
-
-    static DEFS: std::sync::LazyLock<Vec<gamedef::GameDef>> = std::sync::LazyLock::new(|| gamedef::build_gamedefs_from_json(SG0_DEF_JSON));
+    use crate::gamedef::{self, test_defs::DEFS};
+
+    #[test]
+    fn encodable_text() {
+        let gamedef: &GameDef = gamedef::get_by_alias(&DEFS, "sg0").unwrap();
+        assert_eq!(can_encode("Hello, world! [ü] \\[sic]", gamedef), Ok(()));
+        assert_eq!(can_encode("", gamedef), Ok(()));
+        assert_eq!(
+            can_encode("Snow☃man, ☃ and 😀", gamedef),
+            Err(vec!['☃', '😀'])
+        );
+        // Tags aren't text, whatever their attributes.
+        assert_eq!(
+            can_encode("[name]☃[line][color index=\"0\"]Hi[linebreak]", gamedef),
+            Err(vec!['☃'])
+        );
+        assert_eq!(can_encode("[hardcoded-value 0x1F]", gamedef), Ok(()));
+    }
 
     #[test]
     fn char_iter_regular() {
@@ -290,11 +624,32 @@ mod tests {
         assert_eq!(ch, Char::Compound("ü"));
     }
 
+    #[test]
+    fn char_iter_escaped() {
+        let gamedef: &GameDef = gamedef::get_by_alias(&DEFS, "sg0").unwrap();
+        let text = Text(Cow::from("\\[ü]\\\\\\a"));
+        let chars = text.iter(&gamedef.encoding_maps).collect::<Vec<_>>();
+        assert_eq!(
+            chars,
+            vec![
+                Char::Regular('['),
+                Char::Regular('ü'),
+                Char::Regular(']'),
+                Char::Regular('\\'),
+                Char::Regular('\\'),
+                Char::Regular('a')
+            ]
+        );
+        let text = Text::from_chars(chars.into_iter(), false);
+        assert_eq!(text.as_str(), "\\[ü]\\\\\\\\a");
+        assert_eq!(text.unescaped(), "[ü]\\\\a");
+    }
+
     #[test]
     fn encode_roundtrip_regular() {
         let gamedef: &GameDef = gamedef::get_by_alias(&DEFS, "sg0").unwrap();
         let ch = Char::Regular('A');
-        let code = encode_char(&ch, &gamedef).unwrap();
+        let code = encode_char(&ch, gamedef).unwrap();
         let decoded = decode_char(code, gamedef.charset(), &gamedef.compound_chars);
         assert_eq!(decoded, Ok(ch));
     }
@@ -303,15 +658,142 @@ mod tests {
     fn encode_roundtrip_compound() {
         let gamedef: &GameDef = gamedef::get_by_alias(&DEFS, "sg0").unwrap();
         let ch = Char::Compound("ü");
-        let code = encode_char(&ch, &gamedef).unwrap();
+        let code = encode_char(&ch, gamedef).unwrap();
         let decoded = decode_char(code, gamedef.charset(), &gamedef.compound_chars);
         assert_eq!(decoded, Ok(ch));
     }
 
+    #[test]
+    fn normalize_width_both_ways() {
+        let gamedef: &GameDef = gamedef::get_by_alias(&DEFS, "sg0").unwrap();
+        let text = Text(Cow::from("It's OK"));
+        let halfwidth = encode_str(&text, gamedef, false).unwrap();
+        let fullwidth = encode_str(&text, gamedef, true).unwrap();
+        assert_ne!(halfwidth, fullwidth);
+
+        assert_eq!(
+            normalize_width(&halfwidth, gamedef, true),
+            Ok(fullwidth.clone())
+        );
+        assert_eq!(normalize_width(&fullwidth, gamedef, false), Ok(halfwidth));
+
+        let apostrophe = encode_char(&Char::Regular('\''), gamedef).unwrap();
+        assert_eq!(fullwidth[2], apostrophe);
+    }
+
+    #[test]
+    fn missing_char_policies() {
+        let gamedef: &GameDef = gamedef::get_by_alias(&DEFS, "sg0").unwrap();
+        let text = Text(Cow::from("A☃B"));
+        let encode = |policy| encode_str_with_policy(&text, gamedef, false, policy);
+        let code = |c| encode_char(&Char::Regular(c), gamedef).unwrap();
+
+        assert_eq!(
+            encode(MissingCharPolicy::Fail),
+            Err(EncodingError::CharNotInCharset("☃".to_owned()))
+        );
+        assert_eq!(
+            encode(MissingCharPolicy::Skip),
+            Ok(vec![code('A'), code('B')])
+        );
+        assert_eq!(
+            encode(MissingCharPolicy::Replace('?')),
+            Ok(vec![code('A'), code('?'), code('B')])
+        );
+        assert_eq!(
+            encode(MissingCharPolicy::Replace('♫')),
+            Err(EncodingError::CharNotInCharset("♫".to_owned()))
+        );
+    }
+
+    #[test]
+    fn list_substitutions() {
+        let gamedef: &GameDef = gamedef::get_by_alias(&DEFS, "sg0").unwrap();
+        let text = Text(Cow::from("A [ü]A"));
+        let subs = substitutions(&text, gamedef, false).unwrap();
+        assert_eq!(subs.len(), 2);
+        assert_eq!(subs[0], Substitution::Fullwidth(' ', FULLWIDTH_SPACE));
+        assert!(matches!(
+            &subs[1],
+            Substitution::Compound(s, pua_ch) if s == "ü" && gamedef.compound_chars[pua_ch] == "ü"
+        ));
+
+        let subs = substitutions(&text, gamedef, true).unwrap();
+        assert_eq!(subs[0], Substitution::Fullwidth('A', 'Ａ'));
+        assert_eq!(subs[0].to_string(), "A → Ａ (fullwidth)");
+        assert_eq!(subs[1].to_string(), "' ' → U+3000 (fullwidth)");
+    }
+
     #[test]
     fn decode_invalid() {
         let gamedef: &GameDef = gamedef::get_by_alias(&DEFS, "sg0").unwrap();
-        let code = 40270u16;
+        let code = 0xFFFFu16;
         assert!(decode_char(code, gamedef.charset(), &gamedef.compound_chars).is_err());
     }
+
+    #[test]
+    fn bare_compound_chars() {
+        let charset: Vec<char> = "A\u{E000}\u{E001}\u{E002}\u{E003}\u{E004}"
+            .chars()
+            .collect();
+        let mappings: HashMap<char, String> = [
+            ('\u{E000}', "¹"),
+            ('\u{E001}', "¹⁸"),
+            ('\u{E002}', "ー"),
+            ('\u{E003}', "ー"),
+            ('\u{E004}', "A"),
+        ]
+        .iter()
+        .map(|(k, v)| (*k, v.to_string()))
+        .collect();
+        let mut maps = EncodingMaps::new(&charset, &mappings, None).unwrap();
+        assert_eq!(maps.compound_code_of("ー"), Some(0x8003));
+
+        let text = Text(Cow::from("A¹⁸¹[¹]ー"));
+        assert_eq!(text.iter(&maps).nth(1), Some(Char::Regular('¹')));
+        maps.match_bare_compounds();
+        assert_eq!(
+            text.iter(&maps).collect::<Vec<_>>(),
+            vec![
+                Char::Regular('A'),
+                Char::Compound("¹⁸"),
+                Char::Compound("¹"),
+                Char::Compound("¹"),
+                Char::Compound("ー"),
+            ]
+        );
+    }
+
+    #[test]
+    fn reserved_codepoints() {
+        let charset = ['A', '\u{E000}', '\u{E001}', '\u{E002}', '\u{E003}'];
+        let reserved = '\u{E001}'..='\u{E002}';
+        let mappings = |pairs: &[(char, &str)]| -> HashMap<char, String> {
+            pairs.iter().map(|(k, v)| (*k, v.to_string())).collect()
+        };
+
+        let ok = mappings(&[('\u{E000}', "¹⁸"), ('\u{E002}', "ü"), ('\u{E003}', " ")]);
+        let maps = EncodingMaps::new(&charset, &ok, Some(&reserved)).unwrap();
+        assert_eq!(maps.compound_code_of("ü"), Some(0x8003));
+
+        let outside = mappings(&[('\u{E002}', "ü"), ('\u{E003}', "ö")]);
+        let err = EncodingMaps::new(&charset, &outside, Some(&reserved))
+            .err()
+            .unwrap();
+        assert!(err.missing_pua_chars.is_empty());
+        assert_eq!(err.unreserved_pua_chars, vec!['\u{E003}']);
+        assert!(EncodingMaps::new(&charset, &outside, None).is_ok());
+
+        let crossing = mappings(&[('\u{E002}', "ü"), ('\u{E003}', "ü")]);
+        let err = EncodingMaps::new(&charset, &crossing, Some(&reserved))
+            .err()
+            .unwrap();
+        assert_eq!(err.unreserved_pua_chars, vec!['\u{E003}']);
+
+        let crossing_start = mappings(&[('\u{E000}', "ü"), ('\u{E001}', "ü")]);
+        let err = EncodingMaps::new(&charset, &crossing_start, Some(&reserved))
+            .err()
+            .unwrap();
+        assert_eq!(err.unreserved_pua_chars, vec!['\u{E000}']);
+    }
 }
@@ -0,0 +1,338 @@
+// Text files converted to JSON and back, for tools that would rather deal with the tokens of
+// each line than parse tags. The JSON is an array with an element per line, in the shape of a
+// `DecodedLine`:
+//
+// [
+//   [{ "tag": "name" }, { "text": "Okabe" }, { "tag": "line" }, { "text": "Hm." }],
+//   [{ "text": "Second line!" }]
+// ]
+//
+// Either way, each line is encoded with the charset of the game and decoded back, so a line
+// the game can't have is an error rather than something that gets carried over. Text files
+// can have things JSON has no place for and the other way around, such as notes or line
+// breaks in the middle of a text token. Those are dropped with a warning.
+
+use crate::{
+    coz::{CozString, StringSegment, Tag},
+    gamedef::GameDef,
+    note,
+    sc3::{DecodedLine, Sc3String, StringToken},
+    stamp::Stamp,
+    text,
+};
+use serde_json::{Map, Value};
+use std::{borrow::Cow, error, fmt};
+
+/// The formats convert-text goes between.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum TextFormat {
+    Txt,
+    Json,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Json(serde_json::Error),
+    /// The token at this position of this line is neither a text nor a tag.
+    MalformedToken(usize, usize),
+    /// The line can't be encoded for the game.
+    Line(usize, Box<dyn error::Error>),
+}
+
+impl error::Error for Error {}
+
+/// Converts `s` from `from` to `to`, adding what got dropped on the way to `warnings`.
+pub fn convert(
+    s: &str,
+    from: TextFormat,
+    to: TextFormat,
+    gamedef: &GameDef,
+    keep_fullwidth_chars: bool,
+    warnings: &mut Vec<String>,
+) -> Result<String, Error> {
+    let lines = match from {
+        TextFormat::Txt => from_txt(s, gamedef, warnings)?,
+        TextFormat::Json => from_json(s, gamedef, warnings)?,
+    };
+    match to {
+        TextFormat::Txt => to_txt(&lines, gamedef, keep_fullwidth_chars),
+        TextFormat::Json => to_json(&lines, gamedef, keep_fullwidth_chars),
+    }
+}
+
+fn from_txt(
+    s: &str,
+    gamedef: &GameDef,
+    warnings: &mut Vec<String>,
+) -> Result<Vec<Sc3String<'static>>, Error> {
+    let mut lines = s.lines().peekable();
+    if lines.peek().and_then(|x| Stamp::parse(x)).is_some() {
+        lines.next();
+        warnings.push("dropped the stamp, JSON has no place for it".to_owned());
+    }
+    let mut encoded = Vec::new();
+    for (i, line) in lines.enumerate() {
+        let (text, note) = note::split(line);
+        if note.is_some() {
+            warnings.push(format!(
+                "line {}: dropped the note, JSON has no place for it",
+                i + 1
+            ));
+        }
+        let s = Sc3String::deserialize(&CozString(text), gamedef, false)
+            .map_err(|err| Error::Line(i, Box::new(err)))?;
+        encoded.push(s);
+    }
+    Ok(encoded)
+}
+
+fn from_json(
+    s: &str,
+    gamedef: &GameDef,
+    warnings: &mut Vec<String>,
+) -> Result<Vec<Sc3String<'static>>, Error> {
+    let lines: Vec<Vec<Map<String, Value>>> = serde_json::from_str(s)?;
+    let mut encoded = Vec::new();
+    for (i, tokens) in lines.iter().enumerate() {
+        let mut buf = Vec::new();
+        for (j, token) in tokens.iter().enumerate() {
+            let seg = segment(token, gamedef).ok_or(Error::MalformedToken(i, j))?;
+            let seg = match seg {
+                StringSegment::Tag(tag) if !tag.is_known() => {
+                    warnings.push(format!(
+                        "line {}: dropped [{}], text files have no such tag",
+                        i + 1,
+                        tag.name()
+                    ));
+                    continue;
+                }
+                StringSegment::Text(s) if s.0.contains(['\n', '\r']) => {
+                    warnings.push(format!(
+                        "line {}: dropped the line breaks of a text, use [linebreak] instead",
+                        i + 1
+                    ));
+                    StringSegment::Text(text::Text(s.0.replace(['\n', '\r'], "").into()))
+                }
+                seg => seg,
+            };
+            StringToken::deserialize(&seg, gamedef, false)
+                .map_err(|err| Error::Line(i, Box::new(err)))?
+                .encode(&mut buf, gamedef.code_unit)
+                .unwrap();
+        }
+        if gamedef.terminated {
+            StringToken::Terminator
+                .encode(&mut buf, gamedef.code_unit)
+                .unwrap();
+        }
+        encoded.push(Sc3String(buf.into()));
+    }
+    Ok(encoded)
+}
+
+fn to_txt(
+    lines: &[Sc3String],
+    gamedef: &GameDef,
+    keep_fullwidth_chars: bool,
+) -> Result<String, Error> {
+    let mut out = String::new();
+    for (i, s) in lines.iter().enumerate() {
+        let line = s
+            .serialize(gamedef, keep_fullwidth_chars)
+            .map_err(|err| Error::Line(i, Box::new(err)))?;
+        out.push_str(&note::escape(&line.to_string()));
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+fn to_json(
+    lines: &[Sc3String],
+    gamedef: &GameDef,
+    keep_fullwidth_chars: bool,
+) -> Result<String, Error> {
+    let mut decoded = Vec::new();
+    for (i, s) in lines.iter().enumerate() {
+        let line = DecodedLine::decode(s, gamedef, keep_fullwidth_chars)
+            .map_err(|err| Error::Line(i, Box::new(err)))?;
+        decoded.push(serde_json::to_value(&line)?);
+    }
+    let mut out = serde_json::to_string_pretty(&decoded)?;
+    out.push('\n');
+    Ok(out)
+}
+
+// A token object as a segment of a line, or `None` if it's neither `{"text": ...}` nor a
+// `{"tag": ...}` with at most one attribute.
+fn segment<'a>(token: &'a Map<String, Value>, gamedef: &GameDef) -> Option<StringSegment<'a>> {
+    if let Some(s) = token.get("text") {
+        return match s {
+            Value::String(s) if token.len() == 1 => {
+                Some(StringSegment::Text(text::Text(escape(s, gamedef).into())))
+            }
+            _ => None,
+        };
+    }
+    let name = token.get("tag")?.as_str()?;
+    let mut attrs = token.iter().filter(|(key, _)| *key != "tag");
+    let attr = match attrs.next() {
+        Some((key, Value::String(value))) => Some((key.as_str(), Cow::from(value.as_str()))),
+        Some((key, Value::Number(value))) => Some((key.as_str(), value.to_string().into())),
+        Some(_) => return None,
+        None => None,
+    };
+    if attrs.next().is_some() {
+        return None;
+    }
+    Some(StringSegment::Tag(Tag::new(name, attr)))
+}
+
+// The text of a token, which has no escapes, the way it's written in a text file. A `[` only
+// stays as it is if it starts a compound character of the game.
+fn escape(s: &str, gamedef: &GameDef) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for (i, c) in s.char_indices() {
+        let compound = || {
+            let rest = &s[i + 1..];
+            rest.find(']')
+                .and_then(|end| gamedef.encoding_maps.compound_code_of(&rest[..end]))
+                .is_some()
+        };
+        if c == '\\' || (c == '[' && !compound()) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::Json(err)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Json(err) => write!(f, "expected an array of lines of tokens: {}", err),
+            Error::MalformedToken(i, j) => write!(
+                f,
+                "line {}, token {}: expected {{\"text\": ...}} or {{\"tag\": ...}} with at most one attribute",
+                i + 1,
+                j + 1
+            ),
+            Error::Line(i, err) => write!(f, "line {}: {}", i + 1, err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gamedef;
+
+    static DEFS: std::sync::LazyLock<Vec<GameDef>> =
+        std::sync::LazyLock::new(gamedef::builtin_gamedefs);
+
+    fn sg0() -> &'static GameDef {
+        gamedef::get_by_alias(&DEFS, "sg0").unwrap()
+    }
+
+    #[test]
+    fn txt_to_json_and_back() {
+        let txt = "[name]LuLu[line]Meow \\[sic]\t# note\n[color index=\"830000\"]Red\n";
+        let mut warnings = Vec::new();
+        let json = convert(
+            txt,
+            TextFormat::Txt,
+            TextFormat::Json,
+            sg0(),
+            false,
+            &mut warnings,
+        )
+        .unwrap();
+        let value: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!([
+                [
+                    { "tag": "name" },
+                    { "text": "LuLu" },
+                    { "tag": "line" },
+                    { "text": "Meow [sic]" }
+                ],
+                [{ "tag": "color", "index": "830000" }, { "text": "Red" }]
+            ])
+        );
+        assert_eq!(
+            warnings,
+            vec!["line 1: dropped the note, JSON has no place for it"]
+        );
+
+        let mut warnings = Vec::new();
+        let back = convert(
+            &json,
+            TextFormat::Json,
+            TextFormat::Txt,
+            sg0(),
+            false,
+            &mut warnings,
+        )
+        .unwrap();
+        assert_eq!(
+            back,
+            "[name]LuLu[line]Meow \\[sic]\n[color index=\"830000\"]Red\n"
+        );
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn json_to_txt() {
+        let json = r#"[
+            [{ "tag": "font", "size": 24 }, { "text": "a\nb" }, { "tag": "sparkle" }],
+            []
+        ]"#;
+        let mut warnings = Vec::new();
+        let txt = convert(
+            json,
+            TextFormat::Json,
+            TextFormat::Txt,
+            sg0(),
+            false,
+            &mut warnings,
+        )
+        .unwrap();
+        assert_eq!(txt, "[font size=\"24\"]ab\n\n");
+        assert_eq!(
+            warnings,
+            vec![
+                "line 1: dropped the line breaks of a text, use [linebreak] instead",
+                "line 1: dropped [sparkle], text files have no such tag",
+            ]
+        );
+
+        let err = |json| {
+            convert(
+                json,
+                TextFormat::Json,
+                TextFormat::Txt,
+                sg0(),
+                false,
+                &mut Vec::new(),
+            )
+            .err()
+            .unwrap()
+            .to_string()
+        };
+        assert_eq!(
+            err(r#"[[{ "text": "a" }], [{ "text": "a", "tag": "line" }]]"#),
+            "line 2, token 1: expected {\"text\": ...} or {\"tag\": ...} with at most one attribute"
+        );
+        assert_eq!(
+            err(r#"[[{ "tag": "margin", "up": "3" }]]"#),
+            "line 1: unexpected attribute: 'up'"
+        );
+        assert!(err(r#"{ "text": "a" }"#).starts_with("expected an array of lines of tokens"));
+    }
+}
@@ -0,0 +1,74 @@
+// A translation memory kept as a TSV of the lines of a script and their translations, one pair
+// per line, for replace-text --tsv:
+//
+// [name]Okabe[line]Hm.	[name]Okabe[line]Hmm.
+// Second line!	Deuxième ligne !
+//
+// Both columns are written the way they are in text files. Strings are found by their text
+// rather than their position, so a line that comes up in several strings or scripts only needs
+// translating once. Blank lines are skipped.
+
+use std::{collections::HashMap, error, fmt};
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum Error {
+    /// The line has no tab between its two columns.
+    MalformedLine(usize),
+    /// The line gives a translation to a line that an earlier one gave another one to.
+    Conflict(usize, String),
+}
+
+impl error::Error for Error {}
+
+/// The translation of each line, by the line.
+pub fn parse(s: &str) -> Result<HashMap<String, String>, Error> {
+    let mut pairs = HashMap::new();
+    for (i, line) in s.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (old, new) = line.split_once('\t').ok_or(Error::MalformedLine(i))?;
+        match pairs.get(old) {
+            Some(x) if x != new => return Err(Error::Conflict(i, old.to_owned())),
+            Some(_) => {}
+            None => {
+                pairs.insert(old.to_owned(), new.to_owned());
+            }
+        }
+    }
+    Ok(pairs)
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::MalformedLine(i) => write!(f, "line {}: expected <line>\\t<translation>", i + 1),
+            Error::Conflict(i, line) => write!(
+                f,
+                "line {}: '{}' was already given a different translation",
+                i + 1,
+                line
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pairs() {
+        let pairs = parse("Hm.\tHmm.\n\nFirst\tPremière\t# note\r\nHm.\tHmm.\n").unwrap();
+        assert_eq!(pairs.len(), 2);
+        assert_eq!(pairs["Hm."], "Hmm.");
+        // The translation is a line of a text file, so it can have a note.
+        assert_eq!(pairs["First"], "Première\t# note");
+
+        assert_eq!(parse("a\tb\nc"), Err(Error::MalformedLine(1)));
+        assert_eq!(
+            parse("a\tb\na\tc").err().unwrap().to_string(),
+            "line 2: 'a' was already given a different translation"
+        );
+    }
+}
@@ -0,0 +1,88 @@
+// Estimating how wide a line of text gets on screen, so that lines that would overflow the
+// text box can be caught without running the game.
+//
+// Fullwidth and CJK characters take up `fullwidth` columns and the others `halfwidth`. Line
+// breaks as well as the name and the dialogue of a line are measured separately, and ruby text
+// doesn't count since it's drawn above its base.
+
+use crate::{
+    coz::{CozString, StringSegment},
+    gamedef::GlyphWidths,
+    sc3::StringToken,
+};
+
+/// The width of the widest row of a line of a text file.
+pub fn max_width(line: &str, widths: &GlyphWidths) -> u32 {
+    let mut max = 0;
+    let mut row = 0;
+    let mut in_ruby_text = false;
+    for segment in CozString(line.into()).iter() {
+        match segment {
+            StringSegment::Text(text) if !in_ruby_text => {
+                row += text
+                    .unescaped()
+                    .chars()
+                    .map(|c| {
+                        if is_wide(c) {
+                            widths.fullwidth
+                        } else {
+                            widths.halfwidth
+                        }
+                    })
+                    .sum::<u32>();
+            }
+            StringSegment::Text(_) => {}
+            StringSegment::Tag(tag) => match StringToken::from_tag(&tag) {
+                Ok(Some(StringToken::LineBreak))
+                | Ok(Some(StringToken::AltLineBreak))
+                | Ok(Some(StringToken::NameStart))
+                | Ok(Some(StringToken::LineStart)) => {
+                    max = max.max(row);
+                    row = 0;
+                }
+                Ok(Some(StringToken::RubyTextStart)) => in_ruby_text = true,
+                Ok(Some(StringToken::RubyTextEnd)) => in_ruby_text = false,
+                _ => {}
+            },
+        }
+    }
+    max.max(row)
+}
+
+fn is_wide(c: char) -> bool {
+    matches!(
+        c,
+        '\u{1100}'..='\u{115f}'
+            | '\u{2e80}'..='\u{a4cf}'
+            | '\u{ac00}'..='\u{d7a3}'
+            | '\u{f900}'..='\u{faff}'
+            | '\u{fe30}'..='\u{fe4f}'
+            | '\u{ff00}'..='\u{ff60}'
+            | '\u{ffe0}'..='\u{ffe6}'
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn widths() {
+        let widths = GlyphWidths::default();
+        assert_eq!(max_width("", &widths), 0);
+        assert_eq!(max_width("Hello, world.", &widths), 13);
+        assert_eq!(max_width("「あ」", &widths), 6);
+        assert_eq!(max_width("Ｈｉ　there", &widths), 11);
+        assert_eq!(max_width("Short[linebreak]A longer row", &widths), 12);
+        assert_eq!(max_width("[name]Okabe Rintarou[line]Hm.", &widths), 14);
+        assert_eq!(max_width("[name]Okabe Rintarou", &widths), 14);
+        assert_eq!(
+            max_width(
+                "[ruby-base]AB[ruby-text-start]long ruby text[ruby-text-end]C",
+                &widths
+            ),
+            3
+        );
+        assert_eq!(max_width("[color index=\"830000\"]red", &widths), 3);
+    }
+}
@@ -0,0 +1,1762 @@
+use sc3tools::{
+    align_text, check_roundtrip, convert_text, dump_raw,
+    encoding::{self, TextEncoding},
+    extract_text, format,
+    gamedef::{self, GameDef},
+    import_raw, inspect, list_empty, rename_speakers, replace_text, replace_text_parts,
+    AlignOptions, EncodingError, ExtractOptions, LineFilter, MissingCharPolicy, ProcessingError,
+    ReplaceOptions, Sc3ToolsError, TextFormat,
+};
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    fs, iter,
+    ops::Deref,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::LazyLock,
+    time::{Duration, UNIX_EPOCH},
+};
+
+fn fixture(game: &str, name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(game)
+        .join(name)
+}
+
+// A directory of its own for a test, removed once dropped, even if the test fails.
+struct ScratchDir(PathBuf);
+
+impl Deref for ScratchDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for ScratchDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn scratch_dir(game: &str) -> ScratchDir {
+    let dir = std::env::temp_dir().join(format!(
+        "sc3tools-roundtrip-{}-{}",
+        std::process::id(),
+        game
+    ));
+    fs::create_dir_all(&dir).unwrap();
+    ScratchDir(dir)
+}
+
+static DEFS: LazyLock<Vec<GameDef>> = LazyLock::new(gamedef::builtin_gamedefs);
+
+// The sg0 sample script copied into a scratch directory, with the path of its text file next
+// to it, which isn't written.
+fn sg0_sample(name: &str) -> (&'static GameDef, PathBuf, PathBuf, ScratchDir) {
+    let gamedef = gamedef::get_by_alias(&DEFS, "sg0").unwrap();
+    let dir = scratch_dir(name);
+    let script = dir.join("sample.scx");
+    let txt = dir.join("sample.scx.txt");
+    fs::copy(fixture("sg0", "sample.scx"), &script).unwrap();
+    (gamedef, script, txt, dir)
+}
+
+fn roundtrip(game: &str, script_name: &str) {
+    let defs = gamedef::builtin_gamedefs();
+    let gamedef = gamedef::get_by_alias(&defs, game).unwrap();
+    let original = fs::read(fixture(game, script_name)).unwrap();
+    let golden = fixture(game, &format!("{}.txt", script_name));
+
+    let dir = scratch_dir(game);
+    let script = dir.join(script_name);
+    let txt = dir.join(format!("{}.txt", script_name));
+    fs::write(&script, &original).unwrap();
+
+    extract_text(&script, &txt, gamedef, &ExtractOptions::default()).unwrap();
+    assert_eq!(
+        fs::read_to_string(&txt).unwrap(),
+        fs::read_to_string(&golden).unwrap(),
+        "extracted text of {} doesn't match the golden file",
+        script_name
+    );
+
+    replace_text(&script, &golden, gamedef, &ReplaceOptions::default()).unwrap();
+    assert_eq!(
+        fs::read(&script).unwrap(),
+        original,
+        "a no-op replacement changed {}",
+        script_name
+    );
+}
+
+#[test]
+fn shared_strings() {
+    let gamedef = gamedef::get_by_alias(&DEFS, "sg0").unwrap();
+    let dir = scratch_dir("shared-strings");
+    let script = dir.join("shared.scx");
+    let txt = dir.join("shared.scx.txt");
+    // The first two lines point at the same string.
+    let mut original = b"SC3\0".to_vec();
+    for x in [12u32, 24, 24, 24, 29] {
+        original.extend_from_slice(&x.to_le_bytes());
+    }
+    original.extend_from_slice(&[0x80, 0x01, 0x80, 0x02, 0xFF, 0x80, 0x03, 0xFF]);
+    fs::write(&script, &original).unwrap();
+    extract_text(&script, &txt, gamedef, &ExtractOptions::default()).unwrap();
+    assert_eq!(fs::read_to_string(&txt).unwrap(), "01\n01\n2\n");
+
+    // The second line keeps the text the first one drops.
+    fs::write(&txt, "2\n01\n2\n").unwrap();
+    let err = replace_text(&script, &txt, gamedef, &ReplaceOptions::default()).unwrap_err();
+    assert!(matches!(
+        err,
+        Sc3ToolsError::Processing(ProcessingError::ConflictingSharedString(_, 0, 1))
+    ));
+    assert_eq!(fs::read(&script).unwrap(), original);
+
+    // A line the text file leaves out is changed along with the other one.
+    fs::write(&txt, "0: 2\n").unwrap();
+    let options = ReplaceOptions {
+        sparse: true,
+        ..Default::default()
+    };
+    replace_text(&script, &txt, gamedef, &options).unwrap();
+    extract_text(&script, &txt, gamedef, &ExtractOptions::default()).unwrap();
+    assert_eq!(fs::read_to_string(&txt).unwrap(), "2\n2\n2\n");
+
+    // Each line can have text of its own with a copy of the string.
+    fs::write(&txt, "01\n2\n2\n").unwrap();
+    let options = ReplaceOptions {
+        split_shared: true,
+        ..Default::default()
+    };
+    replace_text(&script, &txt, gamedef, &options).unwrap();
+    extract_text(&script, &txt, gamedef, &ExtractOptions::default()).unwrap();
+    assert_eq!(fs::read_to_string(&txt).unwrap(), "01\n2\n2\n");
+}
+
+#[test]
+fn partial_replacement() {
+    let (gamedef, script, txt, dir) = sg0_sample("partial");
+
+    let golden = fs::read_to_string(fixture("sg0", "sample.scx.txt")).unwrap();
+    let edited = golden
+        .replace("Hello, world.", "Hello ☃")
+        .replace("Second line!", "Second line?");
+    fs::write(&txt, &edited).unwrap();
+
+    let options = ReplaceOptions::default();
+    assert!(replace_text(&script, &txt, gamedef, &options).is_err());
+    let options = ReplaceOptions {
+        partial: true,
+        ..Default::default()
+    };
+    let report = replace_text(&script, &txt, gamedef, &options).unwrap();
+    assert_eq!(report.total, 6);
+    assert_eq!(report.changed, report.changed_indices.len());
+    assert!(report.changed_indices.contains(&1));
+    assert!(!report.changed_indices.contains(&0));
+    assert_eq!(report.errors.len(), 1);
+
+    let extracted = dir.join("extracted.txt");
+    extract_text(&script, &extracted, gamedef, &ExtractOptions::default()).unwrap();
+    assert_eq!(
+        fs::read_to_string(&extracted).unwrap(),
+        golden.replace("Second line!", "Second line?")
+    );
+}
+
+#[test]
+fn preserved_tail() {
+    let (gamedef, script, txt, dir) = sg0_sample("tail");
+    let extracted = dir.join("extracted.txt");
+
+    let golden = fs::read_to_string(fixture("sg0", "sample.scx.txt")).unwrap();
+    let translated = golden.replace("Text[auto-forward]", "Texte");
+    fs::write(&txt, &translated).unwrap();
+
+    replace_text(&script, &txt, gamedef, &ReplaceOptions::default()).unwrap();
+    extract_text(&script, &extracted, gamedef, &ExtractOptions::default()).unwrap();
+    assert_eq!(fs::read_to_string(&extracted).unwrap(), translated);
+
+    fs::copy(fixture("sg0", "sample.scx"), &script).unwrap();
+    let options = ReplaceOptions {
+        preserve_tail: true,
+        ..Default::default()
+    };
+    replace_text(&script, &txt, gamedef, &options).unwrap();
+    extract_text(&script, &extracted, gamedef, &ExtractOptions::default()).unwrap();
+    assert_eq!(
+        fs::read_to_string(&extracted).unwrap(),
+        golden.replace("Text[auto-forward]", "Texte[auto-forward]")
+    );
+}
+
+#[test]
+fn auto_forward_kinds() {
+    let (gamedef, script, txt, dir) = sg0_sample("auto-forward");
+    let extracted = dir.join("extracted.txt");
+    let golden = fs::read_to_string(fixture("sg0", "sample.scx.txt")).unwrap();
+
+    // The short tag stands for the same token, so the line isn't changed.
+    fs::write(&txt, golden.replace("[auto-forward]", "[auto]")).unwrap();
+    let report = replace_text(&script, &txt, gamedef, &ReplaceOptions::default()).unwrap();
+    assert!(!report.changed_indices.contains(&4));
+
+    let variant = golden.replace("[auto-forward]", "[auto-forward-1a]");
+    fs::write(&txt, &variant).unwrap();
+    let report = replace_text(&script, &txt, gamedef, &ReplaceOptions::default()).unwrap();
+    assert!(report.changed_indices.contains(&4));
+    extract_text(&script, &extracted, gamedef, &ExtractOptions::default()).unwrap();
+    assert_eq!(fs::read_to_string(&extracted).unwrap(), variant);
+}
+
+#[test]
+fn translation_memory() {
+    let (gamedef, script, txt, dir) = sg0_sample("tsv");
+    let tsv = dir.join("memory.tsv");
+    let extracted = dir.join("extracted.txt");
+
+    // Give two strings the same line.
+    let golden = fs::read_to_string(fixture("sg0", "sample.scx.txt")).unwrap();
+    let repeated = golden.replace("「あ」", "First line[linebreak]Second line!");
+    fs::write(&txt, &repeated).unwrap();
+    replace_text(&script, &txt, gamedef, &ReplaceOptions::default()).unwrap();
+
+    fs::write(
+        &tsv,
+        "First line[linebreak]Second line!\tPremière ligne[linebreak]Deuxième ligne !\n\
+         Not in the script\tPas dans le script\n",
+    )
+    .unwrap();
+    let options = ReplaceOptions {
+        tsv: true,
+        ..Default::default()
+    };
+    let report = replace_text(&script, &tsv, gamedef, &options).unwrap();
+    assert_eq!(report.changed_indices, vec![1, 5]);
+    extract_text(&script, &extracted, gamedef, &ExtractOptions::default()).unwrap();
+    assert_eq!(
+        fs::read_to_string(&extracted).unwrap(),
+        repeated.replace(
+            "First line[linebreak]Second line!",
+            "Première ligne[linebreak]Deuxième ligne !"
+        )
+    );
+
+    fs::write(&tsv, "Hello\tBonjour\nHello\tSalut\n").unwrap();
+    let err = replace_text(&script, &tsv, gamedef, &options)
+        .err()
+        .unwrap();
+    assert!(err
+        .to_string()
+        .ends_with("line 2: 'Hello' was already given a different translation"));
+}
+
+#[test]
+fn missing_chars() {
+    let (gamedef, script, txt, dir) = sg0_sample("missing-chars");
+    let extracted = dir.join("extracted.txt");
+
+    let golden = fs::read_to_string(fixture("sg0", "sample.scx.txt")).unwrap();
+    fs::write(&txt, golden.replace("Hello, world.", "Hello ☃ world.")).unwrap();
+
+    let replaced = |on_missing, strict| {
+        fs::copy(fixture("sg0", "sample.scx"), &script).unwrap();
+        let options = ReplaceOptions {
+            on_missing,
+            strict,
+            ..Default::default()
+        };
+        replace_text(&script, &txt, gamedef, &options)?;
+        extract_text(&script, &extracted, gamedef, &ExtractOptions::default())?;
+        Ok::<_, Box<dyn std::error::Error>>(fs::read_to_string(&extracted).unwrap())
+    };
+    assert!(replaced(MissingCharPolicy::Fail, false).is_err());
+    assert_eq!(
+        replaced(MissingCharPolicy::Skip, false).unwrap(),
+        golden.replace("Hello, world.", "Hello  world.")
+    );
+    assert_eq!(
+        replaced(MissingCharPolicy::Replace('?'), false).unwrap(),
+        golden.replace("Hello, world.", "Hello ? world.")
+    );
+    // --strict turns the warnings back into errors.
+    assert!(replaced(MissingCharPolicy::Skip, true).is_err());
+}
+
+#[test]
+fn inspected_string() {
+    let defs = gamedef::builtin_gamedefs();
+    let gamedef = gamedef::get_by_alias(&defs, "sg0").unwrap();
+    let script = fixture("sg0", "sample.scx");
+
+    let string = inspect(&script, gamedef, 4).unwrap();
+    assert_eq!(string.count, 6);
+    assert_eq!(string.header, fs::read(&script).unwrap()[..12]);
+    assert_eq!(string.offset, 0x90);
+    assert_eq!(string.bytes.len(), 22);
+    assert_eq!(
+        string.text.unwrap(),
+        "[font size=\"24\"]Big[margin top=\"38\"]Text[auto-forward]"
+    );
+    let tokens = string
+        .tokens
+        .iter()
+        .map(|x| (x.offset, x.bytes.len(), x.text.as_str()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        tokens,
+        vec![
+            (0, 3, "[font size=\"24\"]"),
+            (3, 6, "Big"),
+            (9, 3, "[margin top=\"38\"]"),
+            (12, 8, "Text"),
+            (20, 1, "[auto-forward]"),
+        ]
+    );
+    assert_eq!(string.tokens[0].bytes, [0x0C, 0x00, 0x18]);
+
+    let err = inspect(&script, gamedef, 6).err().unwrap();
+    assert_eq!(
+        err.to_string(),
+        "there's no string 6, the script only has 6 strings"
+    );
+}
+
+#[test]
+fn skipped_decode_errors() {
+    let defs = gamedef::builtin_gamedefs();
+    let gamedef = gamedef::get_by_alias(&defs, "sg0").unwrap();
+    let dir = scratch_dir("skip-errors");
+    let script = dir.join("sample.scx");
+    let txt = dir.join("sample.scx.txt");
+
+    // Turn the first byte of the second string into an instruction that doesn't exist.
+    let mut corrupted = fs::read(fixture("sg0", "sample.scx")).unwrap();
+    let start = {
+        let original = format::open(fs::File::open(fixture("sg0", "sample.scx")).unwrap()).unwrap();
+        original.string_index().get(1).unwrap().0.start as usize
+    };
+    corrupted[start] = 0x06;
+    fs::write(&script, &corrupted).unwrap();
+
+    assert!(extract_text(&script, &txt, gamedef, &ExtractOptions::default()).is_err());
+    let options = ExtractOptions {
+        skip_errors: true,
+        ..Default::default()
+    };
+    extract_text(&script, &txt, gamedef, &options).unwrap();
+    let golden = fs::read_to_string(fixture("sg0", "sample.scx.txt")).unwrap();
+    let expected = golden.replace("First line[linebreak]Second line!", "[decode-error]");
+    assert_eq!(fs::read_to_string(&txt).unwrap(), expected);
+
+    // The string that couldn't be decoded is left as it is.
+    replace_text(&script, &txt, gamedef, &ReplaceOptions::default()).unwrap();
+    assert_eq!(fs::read(&script).unwrap(), corrupted);
+}
+
+#[test]
+fn decoded_strings() {
+    let defs = gamedef::builtin_gamedefs();
+    let gamedef = gamedef::get_by_alias(&defs, "sg0").unwrap();
+    let dir = scratch_dir("strings");
+    let path = dir.join("sample.scx");
+    fs::copy(fixture("sg0", "sample.scx"), &path).unwrap();
+
+    let golden = fs::read_to_string(fixture("sg0", "sample.scx.txt")).unwrap();
+    let script = format::open(fs::File::open(&path).unwrap()).unwrap();
+    let options = format::DecodeOptions::default();
+    let strings = script
+        .strings(gamedef, options)
+        .map(|x| x.unwrap().1)
+        .collect::<Vec<_>>();
+    assert_eq!(strings, golden.lines().collect::<Vec<_>>());
+    let (handle, _) = script.strings(gamedef, options).nth(1).unwrap().unwrap();
+    assert_eq!(handle.0, script.string_index().get(1).unwrap().0);
+
+    // An error doesn't end the iteration.
+    let mut corrupted = fs::read(&path).unwrap();
+    corrupted[handle.0.start as usize] = 0x06;
+    fs::write(&path, &corrupted).unwrap();
+    let script = format::open(fs::File::open(&path).unwrap()).unwrap();
+    let decoded = script
+        .strings(gamedef, options)
+        .map(|x| x.is_ok())
+        .collect::<Vec<_>>();
+    assert_eq!(decoded, vec![true, false, true, true, true, true]);
+    assert!(script
+        .strings(gamedef, options)
+        .collect::<Result<Vec<_>, _>>()
+        .is_err());
+}
+
+#[test]
+fn raw_strings() {
+    let dir = scratch_dir("dump-raw");
+    let script = fixture("sg0", "sample.scx");
+    dump_raw(&script, &dir, None, true).unwrap();
+
+    let bytes = fs::read(&script).unwrap();
+    let manifest = fs::read_to_string(dir.join("manifest.json")).unwrap();
+    let manifest: serde_json::Value = serde_json::from_str(&manifest).unwrap();
+    let strings = manifest.as_array().unwrap();
+    assert_eq!(strings.len(), 6);
+    assert_eq!(strings[0]["file"], "0000.bin");
+    assert_eq!(strings[0]["offset"], 0x24);
+    for string in strings {
+        let offset = string["offset"].as_u64().unwrap() as usize;
+        let size = string["size"].as_u64().unwrap() as usize;
+        let file = dir.join(string["file"].as_str().unwrap());
+        assert_eq!(fs::read(file).unwrap(), &bytes[offset..offset + size]);
+    }
+}
+
+#[test]
+fn raw_import() {
+    let dir = scratch_dir("import-raw");
+    let script = dir.join("sample.scx");
+    let bins = dir.join("bin");
+    fs::copy(fixture("sg0", "sample.scx"), &script).unwrap();
+    let original = fs::read(&script).unwrap();
+
+    dump_raw(&script, &bins, None, false).unwrap();
+    import_raw(&script, &bins, None, Default::default(), true).unwrap();
+    assert_eq!(fs::read(&script).unwrap(), original);
+
+    // The terminator is added back, and the strings without a file are left alone.
+    let strings = (0..6)
+        .map(|i| fs::read(bins.join(format!("{:04}.bin", i))).unwrap())
+        .collect::<Vec<_>>();
+    fs::write(bins.join("0001.bin"), &strings[0][..strings[0].len() - 1]).unwrap();
+    fs::remove_file(bins.join("0002.bin")).unwrap();
+    import_raw(&script, &bins, None, Default::default(), true).unwrap();
+    let script_file = format::open(fs::File::open(&script).unwrap()).unwrap();
+    let index = script_file.string_index();
+    let read = |i| script_file.read_string(index.get(i).unwrap()).unwrap().0;
+    assert_eq!(read(1), strings[0]);
+    assert_eq!(read(2), strings[2]);
+    assert_eq!(read(5), strings[5]);
+    drop(script_file);
+
+    // A character that ends in the same byte as the terminator isn't taken for one.
+    fs::write(bins.join("0001.bin"), [0x80, 0xFF]).unwrap();
+    import_raw(&script, &bins, None, Default::default(), true).unwrap();
+    let script_file = format::open(fs::File::open(&script).unwrap()).unwrap();
+    let string = script_file.read_string(script_file.string_index().get(1).unwrap());
+    assert_eq!(string.unwrap().0, &[0x80, 0xFF, 0xFF][..]);
+    drop(script_file);
+
+    fs::write(bins.join("0006.bin"), &strings[0]).unwrap();
+    match import_raw(&script, &bins, None, Default::default(), true).unwrap_err() {
+        Sc3ToolsError::Processing(ProcessingError::File(path, err)) => {
+            assert_eq!(path, bins.join("0006.bin"));
+            assert_eq!(err.to_string(), "the script only has 6 strings");
+        }
+        err => panic!("unexpected error: {:?}", err),
+    }
+}
+
+#[test]
+fn fixed_size_unterminated() {
+    let (_, script, txt, _dir) = sg0_sample("fixed-size-unterminated");
+    let mut defs = gamedef::builtin_gamedefs();
+    let gamedef = defs
+        .iter_mut()
+        .find(|x| x.aliases.contains(&"sg0".into()))
+        .unwrap();
+    gamedef.terminated = false;
+    fs::copy(fixture("sg0", "sample.scx.txt"), &txt).unwrap();
+    let original = fs::read(&script).unwrap();
+
+    let options = ReplaceOptions {
+        fixed_size: true,
+        ..Default::default()
+    };
+    let err = replace_text(&script, &txt, gamedef, &options).unwrap_err();
+    assert!(matches!(err, Sc3ToolsError::InvalidArgument(_)));
+    assert_eq!(
+        err.to_string(),
+        "--fixed-size needs strings that end with a terminator"
+    );
+    assert_eq!(fs::read(&script).unwrap(), original);
+}
+
+#[test]
+fn in_memory_replacement() {
+    let defs = gamedef::builtin_gamedefs();
+    let dir = scratch_dir("in-memory");
+    for (game, name) in [
+        ("sg0", "sample.scx"),
+        ("rn", "sample.msb"),
+        ("cc", "sample.scx"),
+    ] {
+        let gamedef = gamedef::get_by_alias(&defs, game).unwrap();
+        let script = dir.join(format!("{}-{}", game, name));
+        fs::copy(fixture(game, name), &script).unwrap();
+        let original = fs::read(&script).unwrap();
+
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&script)
+            .unwrap();
+        let mut script_file = format::open_with(file, gamedef).unwrap();
+        let index = script_file.string_index();
+        let last = index.count() - 1;
+        let first = script_file.read_string(index.get(0).unwrap()).unwrap();
+        let second = script_file.read_string(index.get(1).unwrap()).unwrap();
+        let mut changes = HashMap::new();
+        changes.insert(0, second);
+        changes.insert(last, first);
+
+        let replaced =
+            format::replace_in_memory(&original, &changes, gamedef, Default::default()).unwrap();
+        script_file
+            .replace_strings(&changes, Default::default())
+            .unwrap();
+        drop(script_file);
+        assert_eq!(replaced, fs::read(&script).unwrap(), "{}", game);
+        assert_ne!(replaced, original, "{}", game);
+    }
+
+    let empty = HashMap::new();
+    let err = format::replace_in_memory(b"not a script", &empty, &defs[0], Default::default());
+    assert!(matches!(err, Err(format::Error::UnrecognizedFormat)));
+    let err = format::replace_in_memory(b"nope", &empty, &defs[0], Default::default());
+    assert!(matches!(err, Err(format::Error::TooSmall(4))));
+}
+
+#[test]
+fn far_too_long_text_file() {
+    let (gamedef, script, txt, _dir) = sg0_sample("too-long");
+    let original = fs::read(&script).unwrap();
+    fs::write(&txt, "garbage\n".repeat(1000)).unwrap();
+
+    let options = ReplaceOptions {
+        max_line_factor: Some(10),
+        ..Default::default()
+    };
+    let err = replace_text(&script, &txt, gamedef, &options).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "sample.scx.txt has 1000 lines for a script of 6 strings, this doesn't look like the \
+         right text file (see --max-line-factor)"
+    );
+    // Without the guard, the line counts still don't match.
+    let err = replace_text(&script, &txt, gamedef, &ReplaceOptions::default()).unwrap_err();
+    assert!(err.to_string().starts_with("The number of lines"));
+    assert_eq!(fs::read(&script).unwrap(), original);
+}
+
+#[test]
+fn error_kinds() {
+    let (gamedef, script, txt, dir) = sg0_sample("error-kinds");
+    let options = ExtractOptions::default();
+
+    let err = extract_text(&dir.join("missing.scx"), &txt, gamedef, &options).unwrap_err();
+    assert!(matches!(err, Sc3ToolsError::Io(_)));
+    fs::write(&script, b"not a script").unwrap();
+    let err = extract_text(&script, &txt, gamedef, &options).unwrap_err();
+    assert!(matches!(
+        err,
+        Sc3ToolsError::Format(format::Error::UnrecognizedFormat)
+    ));
+
+    fs::copy(fixture("sg0", "sample.scx"), &script).unwrap();
+    extract_text(&script, &txt, gamedef, &options).unwrap();
+    let text = fs::read_to_string(&txt).unwrap();
+    fs::write(&txt, text.replace("Second line!", "Second line\u{1F980}")).unwrap();
+    let err = replace_text(&script, &txt, gamedef, &ReplaceOptions::default()).unwrap_err();
+    match err {
+        Sc3ToolsError::Processing(ProcessingError::Text(_, 1, err)) => {
+            // The encoding error is somewhere down the chain of sources.
+            let encoding = iter::successors(Some(&*err), |&x| x.source())
+                .find_map(|x| x.downcast_ref::<EncodingError>());
+            assert!(matches!(encoding, Some(EncodingError::CharNotInCharset(_))));
+        }
+        err => panic!("unexpected error: {:?}", err),
+    }
+    fs::write(&txt, "garbage\n".repeat(1000)).unwrap();
+    let options = ReplaceOptions {
+        max_line_factor: Some(10),
+        ..Default::default()
+    };
+    let err = replace_text(&script, &txt, gamedef, &options).unwrap_err();
+    assert!(matches!(
+        err,
+        Sc3ToolsError::Processing(ProcessingError::TooManyLines(_, 1000, 6))
+    ));
+
+    let no_parts: &[PathBuf] = &[];
+    let err = replace_text_parts(&script, no_parts, gamedef, &ReplaceOptions::default());
+    assert!(matches!(err, Err(Sc3ToolsError::InvalidArgument(_))));
+    fs::write(&txt, "no tab on this line\n").unwrap();
+    let options = ReplaceOptions {
+        tsv: true,
+        ..Default::default()
+    };
+    match replace_text(&script, &txt, gamedef, &options).unwrap_err() {
+        Sc3ToolsError::Processing(ProcessingError::File(path, _)) => assert_eq!(path, txt),
+        err => panic!("unexpected error: {:?}", err),
+    }
+    extract_text(&script, &txt, gamedef, &ExtractOptions::default()).unwrap();
+    let tags = dir.join("sample.scx.tags");
+    fs::write(&tags, "not a tag file\n").unwrap();
+    let options = ReplaceOptions {
+        placeholders: true,
+        ..Default::default()
+    };
+    match replace_text(&script, &txt, gamedef, &options).unwrap_err() {
+        Sc3ToolsError::Processing(ProcessingError::File(path, _)) => assert_eq!(path, tags),
+        err => panic!("unexpected error: {:?}", err),
+    }
+
+    // A string that can't be decoded, whether it's read on its own or extracted.
+    let mut corrupted = fs::read(&script).unwrap();
+    let handle = {
+        let original = format::open(fs::File::open(&script).unwrap()).unwrap();
+        original.string_index().get(1).unwrap()
+    };
+    corrupted[handle.0.start as usize] = 0x06;
+    fs::write(&script, &corrupted).unwrap();
+    let opened = format::open(fs::File::open(&script).unwrap()).unwrap();
+    let decoded = opened
+        .strings(gamedef, format::DecodeOptions::default())
+        .nth(1);
+    assert!(matches!(decoded, Some(Err(Sc3ToolsError::Decode(_)))));
+    let options = ExtractOptions {
+        skip_errors: true,
+        max_errors: Some(1),
+        ..Default::default()
+    };
+    let err = extract_text(&script, &txt, gamedef, &options).unwrap_err();
+    assert!(matches!(
+        err,
+        Sc3ToolsError::Processing(ProcessingError::TooManyErrors(_, 1, _))
+    ));
+}
+
+#[test]
+fn dry_run() {
+    let (gamedef, script, txt, _dir) = sg0_sample("dry-run");
+    let original = fs::read(&script).unwrap();
+    let golden = fs::read_to_string(fixture("sg0", "sample.scx.txt")).unwrap();
+    let options = ReplaceOptions {
+        dry_run: true,
+        map_report: true,
+        ..Default::default()
+    };
+
+    fs::write(&txt, golden.replace("Second line!", "Second line?")).unwrap();
+    let report = replace_text(&script, &txt, gamedef, &options).unwrap();
+    assert!(report.changed_indices.contains(&1));
+    assert_eq!(fs::read(&script).unwrap(), original);
+    assert!(!txt.with_extension("map").exists());
+
+    // Failures are the same as those of a real replacement.
+    fs::write(&txt, golden.replace("Second line!", "☃")).unwrap();
+    assert!(replace_text(&script, &txt, gamedef, &options).is_err());
+    fs::write(&txt, "One line\n").unwrap();
+    assert!(replace_text(&script, &txt, gamedef, &options).is_err());
+    assert_eq!(fs::read(&script).unwrap(), original);
+}
+
+#[test]
+fn truncated_last_string() {
+    let defs = gamedef::builtin_gamedefs();
+    let gamedef = gamedef::get_by_alias(&defs, "sg0").unwrap();
+    let dir = scratch_dir("truncated-last");
+    let script = dir.join("sample.scx");
+    let txt = dir.join("sample.scx.txt");
+    // The terminator and half of the last character are missing.
+    let original = fs::read(fixture("sg0", "sample.scx")).unwrap();
+    fs::write(&script, &original[..original.len() - 2]).unwrap();
+
+    extract_text(&script, &txt, gamedef, &ExtractOptions::default()).unwrap();
+    let golden = fs::read_to_string(fixture("sg0", "sample.scx.txt")).unwrap();
+    assert_eq!(
+        fs::read_to_string(&txt).unwrap(),
+        golden.replace("「あ」", "「あ")
+    );
+}
+
+#[test]
+fn empty_strings() {
+    let defs = gamedef::builtin_gamedefs();
+    let dir = scratch_dir("empty-strings");
+    for (game, name) in [
+        ("sg0", "sample.scx"),
+        ("rn", "sample.msb"),
+        ("cc", "sample.scx"),
+        ("rnd", "sample.scx"),
+    ] {
+        let gamedef = gamedef::get_by_alias(&defs, game).unwrap();
+        let original = fs::read(fixture(game, name)).unwrap();
+        let golden = fs::read_to_string(fixture(game, &format!("{}.txt", name))).unwrap();
+        let script = dir.join(name);
+        let txt = dir.join(format!("{}.txt", name));
+        fs::write(&script, &original).unwrap();
+
+        // Emptying a string in the middle of the heap leaves the ones after it alone.
+        let mut lines = golden.lines().collect::<Vec<_>>();
+        lines[1] = "";
+        let emptied = lines.iter().map(|x| format!("{}\n", x)).collect::<String>();
+        fs::write(&txt, &emptied).unwrap();
+        replace_text(&script, &txt, gamedef, &ReplaceOptions::default()).unwrap();
+        extract_text(&script, &txt, gamedef, &ExtractOptions::default()).unwrap();
+        assert_eq!(fs::read_to_string(&txt).unwrap(), emptied, "{}", game);
+
+        // Putting the text back gives back the original script, offsets and all.
+        fs::write(&txt, &golden).unwrap();
+        replace_text(&script, &txt, gamedef, &ReplaceOptions::default()).unwrap();
+        assert_eq!(fs::read(&script).unwrap(), original, "{}", game);
+    }
+}
+
+// System strings can have a name with no `[line]` after it, which is kept as is: the text after
+// `[name]` is the name, and there's no dialogue.
+#[test]
+fn lone_name_start() {
+    let (gamedef, script, txt, _dir) = sg0_sample("lone-name");
+
+    let golden = fs::read_to_string(fixture("sg0", "sample.scx.txt")).unwrap();
+    let translated = golden
+        .replace("[name]Okabe[line]Hello, world.", "[name]Okabe Rintarou")
+        .replace("「あ」", "[name]");
+    fs::write(&txt, &translated).unwrap();
+    replace_text(&script, &txt, gamedef, &ReplaceOptions::default()).unwrap();
+    extract_text(&script, &txt, gamedef, &ExtractOptions::default()).unwrap();
+    assert_eq!(fs::read_to_string(&txt).unwrap(), translated);
+    check_roundtrip(&script, gamedef, &ExtractOptions::default()).unwrap();
+
+    let options = ExtractOptions {
+        only: Some(LineFilter::Names),
+        ..Default::default()
+    };
+    extract_text(&script, &txt, gamedef, &options).unwrap();
+    assert_eq!(
+        fs::read_to_string(&txt).unwrap(),
+        "0: [name]Okabe Rintarou\n5: [name]\n"
+    );
+    let options = ExtractOptions {
+        only: Some(LineFilter::Dialogue),
+        ..Default::default()
+    };
+    extract_text(&script, &txt, gamedef, &options).unwrap();
+    assert_eq!(fs::read_to_string(&txt).unwrap(), "");
+}
+
+#[test]
+fn truncated_middle_string() {
+    let defs = gamedef::builtin_gamedefs();
+    let gamedef = gamedef::get_by_alias(&defs, "sg0").unwrap();
+    let dir = scratch_dir("truncated-middle");
+    let script = dir.join("sample.scx");
+    let txt = dir.join("sample.scx.txt");
+    // Only the last string can end partway through a character. Here the terminator of the
+    // second one is the first half of a character instead.
+    let mut bytes = fs::read(fixture("sg0", "sample.scx")).unwrap();
+    let end = {
+        let original = format::open(fs::File::open(fixture("sg0", "sample.scx")).unwrap()).unwrap();
+        original.string_index().get(1).unwrap().0.end as usize
+    };
+    assert_eq!(bytes[end - 1], 0xFF);
+    bytes[end - 1] = 0x80;
+    fs::write(&script, &bytes).unwrap();
+
+    let err = extract_text(&script, &txt, gamedef, &ExtractOptions::default()).unwrap_err();
+    assert!(matches!(
+        err,
+        Sc3ToolsError::Processing(ProcessingError::Script(_, 1, _))
+    ));
+    assert!(!txt.exists());
+}
+
+#[test]
+fn trimmed_lines() {
+    let (gamedef, script, txt, dir) = sg0_sample("trim");
+    let extracted = dir.join("extracted.txt");
+
+    let golden = fs::read_to_string(fixture("sg0", "sample.scx.txt")).unwrap();
+    let translated = golden.replace("Hello, world.", "Hello,  world. ");
+    fs::write(&txt, &translated).unwrap();
+
+    replace_text(&script, &txt, gamedef, &ReplaceOptions::default()).unwrap();
+    extract_text(&script, &extracted, gamedef, &ExtractOptions::default()).unwrap();
+    assert_eq!(fs::read_to_string(&extracted).unwrap(), translated);
+
+    fs::copy(fixture("sg0", "sample.scx"), &script).unwrap();
+    let options = ReplaceOptions {
+        trim: true,
+        ..Default::default()
+    };
+    replace_text(&script, &txt, gamedef, &options).unwrap();
+    extract_text(&script, &extracted, gamedef, &ExtractOptions::default()).unwrap();
+    assert_eq!(
+        fs::read_to_string(&extracted).unwrap(),
+        golden.replace("Hello, world.", "Hello,  world.")
+    );
+}
+
+#[test]
+fn control_characters() {
+    let (gamedef, script, txt, _dir) = sg0_sample("check-controls");
+    let original = fs::read(&script).unwrap();
+
+    let golden = fs::read_to_string(fixture("sg0", "sample.scx.txt")).unwrap();
+    fs::write(&txt, golden.replace("Hello, world.", "Hello,\tworld.")).unwrap();
+    let options = ReplaceOptions {
+        check_controls: true,
+        strict: true,
+        ..Default::default()
+    };
+    let err = replace_text(&script, &txt, gamedef, &options).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "sample.scx.txt, line 1: control character U+0009 at column 24"
+    );
+    assert_eq!(fs::read(&script).unwrap(), original);
+}
+
+#[test]
+fn roundtrip_check() {
+    let defs = gamedef::builtin_gamedefs();
+    let gamedef = gamedef::get_by_alias(&defs, "sg0").unwrap();
+    let options = ExtractOptions::default();
+    let dir = scratch_dir("check");
+    let script = dir.join("sample.scx");
+    fs::copy(fixture("sg0", "sample.scx"), &script).unwrap();
+    check_roundtrip(&script, gamedef, &options).unwrap();
+
+    // The 'O' of the first line becomes the first of the two '`' glyphs, which is encoded
+    // back as the second one.
+    let mut bytes = fs::read(&script).unwrap();
+    assert_eq!(bytes[0x25..0x27], [0x80, 0x19]);
+    bytes[0x25..0x27].copy_from_slice(&[0x80, 0x4E]);
+    fs::write(&script, &bytes).unwrap();
+    match check_roundtrip(&script, gamedef, &options).unwrap_err() {
+        Sc3ToolsError::Processing(ProcessingError::Script(_, 0, err)) => assert!(err
+            .to_string()
+            .starts_with("byte 1 was 804E802F80258026 but is encoded back as 8171")),
+        err => panic!("unexpected error: {:?}", err),
+    }
+}
+
+#[test]
+fn stamped_text_file() {
+    let (gamedef, script, txt, _dir) = sg0_sample("stamp");
+
+    let options = ExtractOptions {
+        stamp: true,
+        ..Default::default()
+    };
+    extract_text(&script, &txt, gamedef, &options).unwrap();
+    let stamped = fs::read_to_string(&txt).unwrap();
+    assert!(stamped.starts_with("// sc3tools stamp: lines=6 hash="));
+
+    let edited = stamped.replace("Second line!", "Second line?");
+    fs::write(&txt, &edited).unwrap();
+    replace_text(&script, &txt, gamedef, &ReplaceOptions::default()).unwrap();
+
+    // The script has changed since the text file was stamped.
+    let err = replace_text(&script, &txt, gamedef, &ReplaceOptions::default()).unwrap_err();
+    assert!(err.to_string().contains("different version"));
+}
+
+#[test]
+fn extraction_manifest() {
+    let dir = scratch_dir("manifest");
+    for name in ["a.scx", "b.scx"] {
+        fs::copy(fixture("sg0", "sample.scx"), dir.join(name)).unwrap();
+    }
+    let manifest = dir.join("manifest.json");
+    let status = Command::new(env!("CARGO_BIN_EXE_sc3tools"))
+        .arg("extract-text")
+        .arg(dir.join("*.scx"))
+        .arg("sg0")
+        .arg("--manifest")
+        .arg(&manifest)
+        .stdout(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let entries: Vec<serde_json::Value> =
+        serde_json::from_str(&fs::read_to_string(&manifest).unwrap()).unwrap();
+    let listed = entries
+        .iter()
+        .map(|x| {
+            let script = PathBuf::from(x["input_script"].as_str().unwrap());
+            let txt = PathBuf::from(x["output_txt"].as_str().unwrap());
+            let line_count = x["line_count"].as_u64().unwrap() as usize;
+            assert_eq!(
+                fs::read_to_string(&txt).unwrap().lines().count(),
+                line_count
+            );
+            (script, txt)
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        listed,
+        vec![
+            (dir.join("a.scx"), dir.join("txt/a.scx.txt")),
+            (dir.join("b.scx"), dir.join("txt/b.scx.txt")),
+        ]
+    );
+    let mut created = fs::read_dir(dir.join("txt"))
+        .unwrap()
+        .map(|x| x.unwrap().path())
+        .collect::<Vec<_>>();
+    created.sort();
+    assert_eq!(
+        created,
+        listed.into_iter().map(|(_, txt)| txt).collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn modified_since() {
+    let dir = scratch_dir("since");
+    let at = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+    let set_mtime = |path: &Path, secs| {
+        let file = fs::File::options().write(true).open(path).unwrap();
+        file.set_modified(at(secs)).unwrap();
+    };
+    for (name, mtime) in [("old.scx", 1_000), ("new.scx", 3_000)] {
+        fs::copy(fixture("sg0", "sample.scx"), dir.join(name)).unwrap();
+        set_mtime(&dir.join(name), mtime);
+    }
+    let reference = dir.join("last-run");
+    fs::write(&reference, "").unwrap();
+    set_mtime(&reference, 2_000);
+    let run = |args: &[&OsStr]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_sc3tools"))
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    let pattern = dir.join("*.scx");
+    for since in [OsStr::new("2000"), reference.as_os_str()] {
+        let stdout = run(&[
+            "extract-text".as_ref(),
+            pattern.as_ref(),
+            "sg0".as_ref(),
+            "--since".as_ref(),
+            since,
+        ]);
+        assert!(stdout.contains("Skipped 1 scripts"), "{}", stdout);
+        assert!(dir.join("txt/new.scx.txt").exists());
+        assert!(!dir.join("txt/old.scx.txt").exists());
+        fs::remove_dir_all(dir.join("txt")).unwrap();
+    }
+
+    // replace-text goes by the text files alone, as writing the script makes it newer.
+    let golden = fs::read_to_string(fixture("sg0", "sample.scx.txt")).unwrap();
+    let translated = golden.replace("Hello, world.", "Hi.");
+    for (name, mtime) in [("old.scx.txt", 3_000), ("new.scx.txt", 1_000)] {
+        fs::write(dir.join(name), &translated).unwrap();
+        set_mtime(&dir.join(name), mtime);
+    }
+    let stdout = run(&[
+        "replace-text".as_ref(),
+        pattern.as_ref(),
+        dir.join("*.txt").as_ref(),
+        "sg0".as_ref(),
+        "--since".as_ref(),
+        reference.as_ref(),
+    ]);
+    assert!(stdout.contains("Skipped 1 scripts"), "{}", stdout);
+    let original = fs::read(fixture("sg0", "sample.scx")).unwrap();
+    assert_ne!(fs::read(dir.join("old.scx")).unwrap(), original);
+    assert_eq!(fs::read(dir.join("new.scx")).unwrap(), original);
+}
+
+#[test]
+fn char_frequencies() {
+    let run = |format: &str| {
+        let output = Command::new(env!("CARGO_BIN_EXE_sc3tools"))
+            .arg("char-freq")
+            .arg(fixture("sg0", "sample.scx"))
+            .arg("sg0")
+            .arg(format!("--{}", format))
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    let csv = run("csv");
+    let rows = csv.lines().collect::<Vec<_>>();
+    assert_eq!(rows[..3], ["char,count", "e,7", "l,5"]);
+    assert!(rows.contains(&"\",\",1"));
+    // Tags aren't text.
+    assert!(!rows.iter().any(|x| x.starts_with("[,")));
+
+    let json: Vec<serde_json::Value> = serde_json::from_str(&run("json")).unwrap();
+    assert_eq!(json.len(), rows.len() - 1);
+    assert_eq!(json[0], serde_json::json!({ "char": "e", "count": 7 }));
+    let counts = json
+        .iter()
+        .map(|x| x["count"].as_u64().unwrap())
+        .collect::<Vec<_>>();
+    assert!(counts.windows(2).all(|x| x[0] >= x[1]));
+}
+
+#[test]
+fn speaker_names() {
+    let output = Command::new(env!("CARGO_BIN_EXE_sc3tools"))
+        .arg("extract-names")
+        .arg(fixture("sg0", "sample.scx"))
+        .arg("sg0")
+        .arg("--json")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json, serde_json::json!([{ "name": "Okabe", "count": 1 }]));
+}
+
+#[test]
+fn max_errors() {
+    let dir = scratch_dir("max-errors");
+    for name in ["a.scx", "b.scx", "c.scx", "d.scx"] {
+        fs::write(dir.join(name), "not a script").unwrap();
+    }
+    let output = Command::new(env!("CARGO_BIN_EXE_sc3tools"))
+        .arg("extract-text")
+        .arg(dir.join("*.scx"))
+        .arg("sg0")
+        .args(["--max-errors", "2"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(stdout.matches("Processing").count(), 2);
+    assert!(stderr.contains("gave up after 2 errors, check that these are really scripts of"));
+
+    // A script gives up on its own after as many lines fail to encode.
+    let defs = gamedef::builtin_gamedefs();
+    let gamedef = gamedef::get_by_alias(&defs, "sg0").unwrap();
+    let script = dir.join("sample.scx");
+    let txt = dir.join("sample.scx.txt");
+    fs::copy(fixture("sg0", "sample.scx"), &script).unwrap();
+    let golden = fs::read_to_string(fixture("sg0", "sample.scx.txt")).unwrap();
+    let edited = golden
+        .replace("Hello, world.", "Hello ☃")
+        .replace("Second line!", "Second ☃");
+    fs::write(&txt, edited).unwrap();
+    let replaced = |max_errors| {
+        let options = ReplaceOptions {
+            partial: true,
+            max_errors,
+            ..Default::default()
+        };
+        replace_text(&script, &txt, gamedef, &options)
+    };
+    let err = replaced(Some(2)).err().unwrap();
+    assert!(err
+        .to_string()
+        .ends_with("gave up after 2 errors, check that these are really scripts of Steins;Gate 0"));
+    assert_eq!(replaced(Some(3)).unwrap().errors.len(), 2);
+}
+
+#[test]
+fn grouped_by_dir() {
+    let defs = gamedef::builtin_gamedefs();
+    let gamedef = gamedef::get_by_alias(&defs, "sg0").unwrap();
+    let dir = scratch_dir("group");
+    for name in ["a.scx", "b.scx"] {
+        fs::copy(fixture("sg0", "sample.scx"), dir.join(name)).unwrap();
+    }
+    let run = |args: &[&OsStr]| {
+        let status = Command::new(env!("CARGO_BIN_EXE_sc3tools"))
+            .args(args)
+            .stdout(Stdio::null())
+            .status()
+            .unwrap();
+        assert!(status.success());
+    };
+
+    let pattern = dir.join("*.scx");
+    run(&[
+        "extract-text".as_ref(),
+        pattern.as_ref(),
+        "sg0".as_ref(),
+        "--group-by-dir".as_ref(),
+    ]);
+    let txt_dir = dir.join("txt");
+    let bundle = txt_dir.join(format!(
+        "{}.bundle.txt",
+        dir.file_name().unwrap().to_str().unwrap()
+    ));
+    assert_eq!(fs::read_dir(&txt_dir).unwrap().count(), 1);
+    let golden = fs::read_to_string(fixture("sg0", "sample.scx.txt")).unwrap();
+    let section = |name| {
+        let lines = golden
+            .lines()
+            .enumerate()
+            .map(|(i, x)| format!("{}: {}\n", i, x));
+        format!("## {}\n{}", name, lines.collect::<String>())
+    };
+    let grouped = fs::read_to_string(&bundle).unwrap();
+    assert_eq!(
+        grouped,
+        format!("{}\n{}", section("a.scx"), section("b.scx"))
+    );
+
+    // Each script gets its own translation back once the bundle is split.
+    let translated = grouped.replacen("Hello, world.", "Hi.", 1);
+    fs::write(&bundle, translated).unwrap();
+    run(&["unbundle".as_ref(), bundle.as_ref(), txt_dir.as_ref()]);
+    fs::remove_file(&bundle).unwrap();
+    let txts = txt_dir.join("*.txt");
+    run(&[
+        "replace-text".as_ref(),
+        pattern.as_ref(),
+        txts.as_ref(),
+        "sg0".as_ref(),
+    ]);
+    for (name, expected) in [
+        ("a.scx", golden.replace("Hello, world.", "Hi.")),
+        ("b.scx", golden.clone()),
+    ] {
+        let extracted = dir.join("extracted.txt");
+        extract_text(
+            &dir.join(name),
+            &extracted,
+            gamedef,
+            &ExtractOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(&extracted).unwrap(),
+            expected,
+            "{}",
+            name
+        );
+    }
+}
+
+#[test]
+fn text_in_parts() {
+    let (gamedef, script, _, dir) = sg0_sample("parts");
+    let parts = [
+        dir.join("sample.scx.part1.txt"),
+        dir.join("sample.scx.part2.txt"),
+    ];
+    let extracted = dir.join("extracted.txt");
+
+    let golden = fs::read_to_string(fixture("sg0", "sample.scx.txt")).unwrap();
+    let translated = golden.replace("Big", "Huge");
+    let lines = translated.lines().collect::<Vec<_>>();
+    // The last line of the first part is empty.
+    fs::write(&parts[0], lines[..3].join("\n") + "\n").unwrap();
+    fs::write(&parts[1], lines[3..].join("\n")).unwrap();
+    replace_text_parts(&script, &parts, gamedef, &ReplaceOptions::default()).unwrap();
+    extract_text(&script, &extracted, gamedef, &ExtractOptions::default()).unwrap();
+    assert_eq!(fs::read_to_string(&extracted).unwrap(), translated);
+
+    // Errors point at the line of the part, not of the combined text.
+    fs::write(&parts[1], lines[3..].join("\n").replace("Huge", "Huge☃")).unwrap();
+    let err = replace_text_parts(&script, &parts, gamedef, &ReplaceOptions::default());
+    assert!(err
+        .unwrap_err()
+        .to_string()
+        .starts_with("sample.scx.part2.txt, line 2:"));
+
+    fs::write(&parts[1], lines[3..5].join("\n")).unwrap();
+    let err = replace_text_parts(&script, &parts, gamedef, &ReplaceOptions::default());
+    assert!(err.unwrap_err().to_string().contains("number of lines"));
+}
+
+#[test]
+fn line_endings() {
+    let defs = gamedef::builtin_gamedefs();
+    let gamedef = gamedef::get_by_alias(&defs, "sg0").unwrap();
+    let dir = scratch_dir("line-endings");
+    let original = fs::read(fixture("sg0", "sample.scx")).unwrap();
+    let golden = fs::read_to_string(fixture("sg0", "sample.scx.txt")).unwrap();
+    let script = dir.join("sample.scx");
+    let txt = dir.join("sample.scx.txt");
+    fs::write(&script, &original).unwrap();
+
+    for crlf in [false, true] {
+        let options = ExtractOptions {
+            crlf,
+            stamp: true,
+            ..Default::default()
+        };
+        extract_text(&script, &txt, gamedef, &options).unwrap();
+        let extracted = fs::read_to_string(&txt).unwrap();
+        let lines = extracted.lines().skip(1).collect::<Vec<_>>();
+        assert_eq!(lines, golden.lines().collect::<Vec<_>>());
+        assert_eq!(extracted.matches("\r\n").count(), if crlf { 7 } else { 0 });
+
+        replace_text(&script, &txt, gamedef, &ReplaceOptions::default()).unwrap();
+        assert_eq!(fs::read(&script).unwrap(), original);
+    }
+}
+
+#[test]
+fn text_encodings() {
+    let defs = gamedef::builtin_gamedefs();
+    let gamedef = gamedef::get_by_alias(&defs, "sg0").unwrap();
+    let dir = scratch_dir("encodings");
+    let original = fs::read(fixture("sg0", "sample.scx")).unwrap();
+    let golden = fs::read_to_string(fixture("sg0", "sample.scx.txt")).unwrap();
+    let script = dir.join("sample.scx");
+    let txt = dir.join("sample.scx.txt");
+
+    for (encoding, bom) in [
+        (TextEncoding::Utf8, &[][..]),
+        (TextEncoding::Utf16Le, &[0xFF, 0xFE][..]),
+        (TextEncoding::Utf16Be, &[0xFE, 0xFF][..]),
+    ] {
+        fs::write(&script, &original).unwrap();
+        let options = ExtractOptions {
+            encoding,
+            ..Default::default()
+        };
+        extract_text(&script, &txt, gamedef, &options).unwrap();
+        let bytes = fs::read(&txt).unwrap();
+        assert!(bytes.starts_with(bom));
+        assert_eq!(encoding::decode(&bytes).unwrap(), golden);
+
+        let edited = golden.replace("Second line!", "Second line?");
+        let mut bytes = bom.to_vec();
+        bytes.extend_from_slice(&encoding.encode(&edited));
+        fs::write(&txt, &bytes).unwrap();
+        replace_text(&script, &txt, gamedef, &ReplaceOptions::default()).unwrap();
+        extract_text(&script, &txt, gamedef, &ExtractOptions::default()).unwrap();
+        assert_eq!(fs::read_to_string(&txt).unwrap(), edited);
+    }
+}
+
+#[test]
+fn placeholders() {
+    let (gamedef, script, txt, dir) = sg0_sample("placeholders");
+
+    let options = ExtractOptions {
+        placeholders: true,
+        ..Default::default()
+    };
+    extract_text(&script, &txt, gamedef, &options).unwrap();
+    let masked = fs::read_to_string(&txt).unwrap();
+    assert!(masked.starts_with("{0}Okabe{1}Hello, world.\n"));
+    assert!(!masked.contains('['));
+    assert!(dir.join("sample.scx.tags").exists());
+
+    let options = ReplaceOptions {
+        placeholders: true,
+        ..Default::default()
+    };
+    let translated = masked.replace("{0}Okabe{1}Hello, world.", "{0}Okabe{1}Bonjour !");
+    fs::write(&txt, translated.replace("Big{1}", "Grand")).unwrap();
+    let err = replace_text(&script, &txt, gamedef, &options).unwrap_err();
+    assert!(err.to_string().contains("placeholder {1} is missing"));
+
+    fs::write(&txt, &translated).unwrap();
+    replace_text(&script, &txt, gamedef, &options).unwrap();
+    let extracted = dir.join("extracted.txt");
+    extract_text(&script, &extracted, gamedef, &ExtractOptions::default()).unwrap();
+    let golden = fs::read_to_string(fixture("sg0", "sample.scx.txt")).unwrap();
+    assert_eq!(
+        fs::read_to_string(&extracted).unwrap(),
+        golden.replace("Hello, world.", "Bonjour !")
+    );
+}
+
+#[test]
+fn aligned_translation() {
+    let defs = gamedef::builtin_gamedefs();
+    let gamedef = gamedef::get_by_alias(&defs, "sg0").unwrap();
+    let dir = scratch_dir("align");
+    let new_script = dir.join("new.scx");
+    let edited = dir.join("edited.txt");
+    fs::copy(fixture("sg0", "sample.scx"), &new_script).unwrap();
+
+    let golden = fs::read_to_string(fixture("sg0", "sample.scx.txt")).unwrap();
+    let changed_line = "Something else entirely[present]";
+    let edited_text = golden
+        .replace("Second line!", "Second line?")
+        .replace("[color index=\"830000\"]It's red[present]", changed_line);
+    fs::write(&edited, &edited_text).unwrap();
+    replace_text(&new_script, &edited, gamedef, &ReplaceOptions::default()).unwrap();
+
+    let old_translation = dir.join("old.txt");
+    let new_translation = dir.join("new.txt");
+    fs::write(&old_translation, "T0\nT1\nT2\nT3\nT4\nT5\n").unwrap();
+    align_text(
+        fixture("sg0", "sample.scx"),
+        &new_script,
+        &old_translation,
+        &new_translation,
+        gamedef,
+        &AlignOptions::default(),
+    )
+    .unwrap();
+    assert_eq!(
+        fs::read_to_string(&new_translation).unwrap(),
+        format!("T0\nT1\nT2\n{}\nT4\nT5\n", changed_line)
+    );
+}
+
+#[test]
+fn line_notes() {
+    let (gamedef, script, txt, _dir) = sg0_sample("notes");
+
+    let golden = fs::read_to_string(fixture("sg0", "sample.scx.txt")).unwrap();
+    let annotated = golden
+        .replace("Hello, world.", "Hello, world.\t# cheerful")
+        .replace("Second line!", "Second line?\t# max 40 columns");
+    fs::write(&txt, &annotated).unwrap();
+    replace_text(&script, &txt, gamedef, &ReplaceOptions::default()).unwrap();
+
+    extract_text(&script, &txt, gamedef, &ExtractOptions::default()).unwrap();
+    assert_eq!(fs::read_to_string(&txt).unwrap(), annotated);
+}
+
+#[test]
+fn filtered_extraction() {
+    let (gamedef, script, txt, dir) = sg0_sample("filtered");
+
+    let extract = |only| {
+        let options = ExtractOptions {
+            only: Some(only),
+            ..Default::default()
+        };
+        extract_text(&script, &txt, gamedef, &options).unwrap();
+        fs::read_to_string(&txt).unwrap()
+    };
+    assert_eq!(
+        extract(LineFilter::Dialogue),
+        "0: [name]Okabe[line]Hello, world.\n"
+    );
+    // The [line] tag of the first line isn't text.
+    assert_eq!(
+        extract(LineFilter::Matching("line".to_owned())),
+        "1: First line[linebreak]Second line!\n"
+    );
+
+    fs::write(&txt, "1: First line[linebreak]Second line?\n").unwrap();
+    let options = ReplaceOptions {
+        sparse: true,
+        ..Default::default()
+    };
+    replace_text(&script, &txt, gamedef, &options).unwrap();
+
+    let golden = fs::read_to_string(fixture("sg0", "sample.scx.txt")).unwrap();
+    let extracted = dir.join("extracted.txt");
+    extract_text(&script, &extracted, gamedef, &ExtractOptions::default()).unwrap();
+    assert_eq!(
+        fs::read_to_string(&extracted).unwrap(),
+        golden.replace("Second line!", "Second line?")
+    );
+}
+
+#[test]
+fn strings_without_text() {
+    let (gamedef, script, txt, dir) = sg0_sample("without-text");
+    let golden = fs::read_to_string(fixture("sg0", "sample.scx.txt")).unwrap();
+    let control_only = golden.replace("Big[margin top=\"38\"]Text", "[margin top=\"38\"]");
+    fs::write(&txt, &control_only).unwrap();
+    replace_text(&script, &txt, gamedef, &ReplaceOptions::default()).unwrap();
+
+    assert_eq!(list_empty(&script, gamedef).unwrap(), vec![2, 4]);
+    let options = ExtractOptions {
+        skip_empty_text: true,
+        ..Default::default()
+    };
+    extract_text(&script, &txt, gamedef, &options).unwrap();
+    assert_eq!(
+        fs::read_to_string(&txt).unwrap(),
+        "0: [name]Okabe[line]Hello, world.\n\
+         1: First line[linebreak]Second line!\n\
+         3: [color index=\"830000\"]It's red[present]\n\
+         5: 「あ」\n"
+    );
+
+    // The strings without text are left alone by a sparse replacement.
+    fs::write(&txt, "5: 「い」\n").unwrap();
+    let options = ReplaceOptions {
+        sparse: true,
+        ..Default::default()
+    };
+    replace_text(&script, &txt, gamedef, &options).unwrap();
+    let extracted = dir.join("extracted.txt");
+    extract_text(&script, &extracted, gamedef, &ExtractOptions::default()).unwrap();
+    assert_eq!(
+        fs::read_to_string(&extracted).unwrap(),
+        control_only.replace("「あ」", "「い」")
+    );
+}
+
+#[test]
+fn per_line_files() {
+    let (gamedef, script, _, dir) = sg0_sample("per-line");
+    let lines_dir = dir.join("sample");
+    let golden = fs::read_to_string(fixture("sg0", "sample.scx.txt")).unwrap();
+
+    let options = ExtractOptions {
+        per_line: true,
+        ..Default::default()
+    };
+    fs::create_dir_all(&lines_dir).unwrap();
+    fs::write(lines_dir.join("0042.txt"), "stale\n").unwrap();
+    fs::write(lines_dir.join("README.md"), "kept\n").unwrap();
+    extract_text(&script, &lines_dir, gamedef, &options).unwrap();
+    let mut names = fs::read_dir(&lines_dir)
+        .unwrap()
+        .map(|x| x.unwrap().file_name().into_string().unwrap())
+        .collect::<Vec<_>>();
+    names.sort();
+    assert_eq!(
+        names,
+        [
+            "0000.txt",
+            "0001.txt",
+            "0002.txt",
+            "0003.txt",
+            "0004.txt",
+            "0005.txt",
+            "README.md"
+        ]
+    );
+    let line = |i: usize| fs::read_to_string(lines_dir.join(format!("{:04}.txt", i))).unwrap();
+    assert_eq!(line(5), "「あ」\n");
+    let joined = (0..6).map(line).collect::<String>();
+    assert_eq!(joined, golden);
+
+    // The strings whose file is gone are left untouched.
+    fs::write(
+        lines_dir.join("0001.txt"),
+        "First line[linebreak]Second line?\r\n",
+    )
+    .unwrap();
+    fs::remove_file(lines_dir.join("0005.txt")).unwrap();
+    let options = ReplaceOptions {
+        per_line: true,
+        ..Default::default()
+    };
+    let report = replace_text(&script, &lines_dir, gamedef, &options).unwrap();
+    assert!(report.changed_indices.contains(&1));
+    let txt = dir.join("sample.scx.txt");
+    extract_text(&script, &txt, gamedef, &ExtractOptions::default()).unwrap();
+    assert_eq!(
+        fs::read_to_string(&txt).unwrap(),
+        golden.replace("Second line!", "Second line?")
+    );
+
+    fs::write(lines_dir.join("0002.txt"), "two\nlines\n").unwrap();
+    let err = replace_text(&script, &lines_dir, gamedef, &options).unwrap_err();
+    assert!(err
+        .to_string()
+        .ends_with("0002.txt: expected a single line"));
+}
+
+#[test]
+fn fullwidth_spaces() {
+    let defs = gamedef::builtin_gamedefs();
+    let gamedef = gamedef::get_by_alias(&defs, "sg0").unwrap();
+    let dir = scratch_dir("spaces");
+    let script = dir.join("sample.scx");
+    let txt = dir.join("sample.scx.txt");
+    let original = fs::read(fixture("sg0", "sample.scx")).unwrap();
+    let golden = fs::read_to_string(fixture("sg0", "sample.scx.txt")).unwrap();
+
+    // The sample script uses fullwidth spaces (0x803F). Mix in a regular one (0x8000) in
+    // the line "First line[linebreak]Second line!".
+    let mut mixed = original.clone();
+    let (pos, _) = mixed
+        .windows(2)
+        .enumerate()
+        .filter(|(_, x)| *x == [0x80, 0x3F])
+        .nth(1)
+        .unwrap();
+    mixed[pos + 1] = 0x00;
+    fs::write(&script, &mixed).unwrap();
+
+    let extract = |options: &ExtractOptions| {
+        extract_text(&script, &txt, gamedef, options).unwrap();
+        fs::read_to_string(&txt).unwrap()
+    };
+    let preserved = extract(&ExtractOptions {
+        keep_fullwidth_chars: true,
+        ..Default::default()
+    });
+    assert!(preserved.contains("First line[linebreak]Second\u{3000}line!"));
+    let halfwidth = extract(&ExtractOptions {
+        keep_fullwidth_chars: true,
+        halfwidth_spaces: true,
+        ..Default::default()
+    });
+    assert_eq!(halfwidth, golden);
+
+    let options = ReplaceOptions {
+        halfwidth_spaces: true,
+        ..Default::default()
+    };
+    replace_text(&script, &txt, gamedef, &options).unwrap();
+    assert_eq!(fs::read(&script).unwrap(), mixed);
+
+    // Without the option, the line counts as changed and gets fullwidth spaces only.
+    replace_text(&script, &txt, gamedef, &ReplaceOptions::default()).unwrap();
+    assert_eq!(fs::read(&script).unwrap(), original);
+}
+
+#[test]
+fn sghd() {
+    roundtrip("sghd", "sample.scx");
+}
+
+#[test]
+fn chlcc() {
+    roundtrip("chlcc", "sample.scx");
+}
+
+#[test]
+fn rn() {
+    roundtrip("rn", "sample.msb");
+}
+
+#[test]
+fn sglbp() {
+    roundtrip("sglbp", "sample.scx");
+}
+
+#[test]
+fn cc() {
+    roundtrip("cc", "sample.scx");
+}
+
+#[test]
+fn sg0() {
+    roundtrip("sg0", "sample.scx");
+}
+
+#[test]
+fn cclcc() {
+    roundtrip("cclcc", "sample.scx");
+}
+
+#[test]
+fn rnd() {
+    roundtrip("rnd", "sample.scx");
+}
+
+#[test]
+fn sge() {
+    roundtrip("sge", "sample.scx");
+}
+
+#[test]
+fn text_to_json_and_back() {
+    let defs = gamedef::builtin_gamedefs();
+    let gamedef = gamedef::get_by_alias(&defs, "sg0").unwrap();
+    let dir = scratch_dir("convert-text");
+    let golden = fixture("sg0", "sample.scx.txt");
+    let json = dir.join("sample.json");
+    let txt = dir.join("sample.txt");
+
+    let warnings = convert_text(
+        &golden,
+        &json,
+        gamedef,
+        TextFormat::Txt,
+        TextFormat::Json,
+        false,
+    )
+    .unwrap();
+    assert!(warnings.is_empty());
+    let lines: Vec<serde_json::Value> =
+        serde_json::from_str(&fs::read_to_string(&json).unwrap()).unwrap();
+    assert_eq!(
+        lines.len(),
+        fs::read_to_string(&golden).unwrap().lines().count()
+    );
+
+    convert_text(
+        &json,
+        &txt,
+        gamedef,
+        TextFormat::Json,
+        TextFormat::Txt,
+        false,
+    )
+    .unwrap();
+    assert_eq!(
+        fs::read_to_string(&txt).unwrap(),
+        fs::read_to_string(&golden).unwrap()
+    );
+}
+
+#[test]
+fn substitution_log() {
+    let dir = scratch_dir("subst-log");
+    let golden = fs::read_to_string(fixture("sg0", "sample.scx.txt")).unwrap();
+    let edited = golden.replace("Hello, world.", "Hello there, world.");
+    for (name, text) in [
+        ("a.scx", edited.clone()),
+        ("b.scx", edited.replace("Second line!", "Second [ü] line!")),
+    ] {
+        fs::copy(fixture("sg0", "sample.scx"), dir.join(name)).unwrap();
+        fs::write(dir.join(format!("{}.txt", name)), text).unwrap();
+    }
+    let log = dir.join("subst.log");
+    let status = Command::new(env!("CARGO_BIN_EXE_sc3tools"))
+        .arg("replace-text")
+        .arg(dir.join("*.scx"))
+        .arg(dir.join("*.txt"))
+        .arg("sg0")
+        .arg("--halfwidth-spaces")
+        .arg("--subst-log")
+        .arg(&log)
+        .stdout(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    // Across both scripts, three changed lines have spaces and one has a compound character.
+    let log = fs::read_to_string(&log).unwrap();
+    let entries = log.lines().collect::<Vec<_>>();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0], "3\t' ' → U+3000 (fullwidth)");
+    assert!(entries[1].starts_with("1\tü → U+"));
+    assert!(entries[1].ends_with("(compound)"));
+}
+
+#[test]
+fn renamed_speakers() {
+    let (gamedef, script, txt, _dir) = sg0_sample("apply-names");
+
+    let glossary = HashMap::from([("Okabe".to_owned(), "Hououin Kyouma".to_owned())]);
+    let renamed = rename_speakers(&script, gamedef, &glossary, false).unwrap();
+    assert_eq!(renamed.changed, 1);
+    assert!(renamed.unknown.is_empty());
+    extract_text(&script, &txt, gamedef, &ExtractOptions::default()).unwrap();
+    let golden = fs::read_to_string(fixture("sg0", "sample.scx.txt")).unwrap();
+    assert_eq!(
+        fs::read_to_string(&txt).unwrap(),
+        golden.replace("[name]Okabe[line]", "[name]Hououin Kyouma[line]")
+    );
+
+    let renamed = rename_speakers(&script, gamedef, &HashMap::new(), false).unwrap();
+    assert_eq!(renamed.changed, 0);
+    assert_eq!(
+        renamed.unknown,
+        HashMap::from([("Hououin Kyouma".to_owned(), 1)])
+    );
+}
+
+#[test]
+fn bundled_names() {
+    let dir = scratch_dir("bundle-names");
+    for sub in ["a", "b"] {
+        fs::create_dir_all(dir.join(sub)).unwrap();
+        fs::copy(fixture("sg0", "sample.scx"), dir.join(sub).join("op01.scx")).unwrap();
+    }
+    let bundle = dir.join("all.bundle.txt");
+    let output = Command::new(env!("CARGO_BIN_EXE_sc3tools"))
+        .arg("bundle")
+        .arg(dir.join("*").join("*.scx"))
+        .arg("sg0")
+        .arg(&bundle)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("would both be bundled as op01.scx"));
+    assert!(!bundle.exists());
+}
+
+#[test]
+fn confirmation_without_terminal() {
+    let dir = scratch_dir("confirm");
+    let script = dir.join("sample.scx");
+    fs::copy(fixture("sg0", "sample.scx"), &script).unwrap();
+    let golden = fs::read_to_string(fixture("sg0", "sample.scx.txt")).unwrap();
+    fs::write(
+        dir.join("sample.scx.txt"),
+        golden.replace("Hello, world.", "Hi."),
+    )
+    .unwrap();
+
+    // There's no one to ask, so the scripts are replaced without asking.
+    let status = Command::new(env!("CARGO_BIN_EXE_sc3tools"))
+        .arg("replace-text")
+        .arg(&script)
+        .arg(dir.join("*.txt"))
+        .arg("sg0")
+        .args(["--confirm-threshold", "0"])
+        .stdin(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert_ne!(
+        fs::read(&script).unwrap(),
+        fs::read(fixture("sg0", "sample.scx")).unwrap()
+    );
+}
+
+#[test]
+fn game_map_groups() {
+    let dir = scratch_dir("game-map-groups");
+    fs::copy(fixture("sg0", "sample.scx"), dir.join("a.scx")).unwrap();
+    fs::copy(fixture("cc", "sample.scx"), dir.join("b.scx")).unwrap();
+    let golden = fs::read_to_string(fixture("sg0", "sample.scx.txt")).unwrap();
+    fs::write(
+        dir.join("a.scx.txt"),
+        golden.replace("Hello, world.", "Hi."),
+    )
+    .unwrap();
+    fs::copy(fixture("cc", "sample.scx.txt"), dir.join("b.scx.txt")).unwrap();
+    // A part next to the whole text file of the second game.
+    fs::write(dir.join("b.scx.part1.txt"), "").unwrap();
+    let map = dir.join("games.map");
+    fs::write(&map, "a.scx = sg0\nb.scx = cc\n").unwrap();
+    let report = dir.join("report.json");
+    let run = || {
+        Command::new(env!("CARGO_BIN_EXE_sc3tools"))
+            .arg("replace-text")
+            .arg(dir.join("*.scx"))
+            .arg(dir.join("*.txt"))
+            .arg("--game-map")
+            .arg(&map)
+            .arg("--report-json")
+            .arg(&report)
+            .output()
+            .unwrap()
+    };
+
+    // Nothing is written when the scripts of one game can't be replaced.
+    assert!(!run().status.success());
+    let original = fs::read(fixture("sg0", "sample.scx")).unwrap();
+    assert_eq!(fs::read(dir.join("a.scx")).unwrap(), original);
+    assert!(!report.exists());
+
+    // The report covers the scripts of both games.
+    fs::remove_file(dir.join("b.scx.part1.txt")).unwrap();
+    assert!(run().status.success());
+    assert_ne!(fs::read(dir.join("a.scx")).unwrap(), original);
+    let report: serde_json::Value = serde_json::from_slice(&fs::read(&report).unwrap()).unwrap();
+    let files = report
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|x| PathBuf::from(x["file"].as_str().unwrap()))
+        .collect::<Vec<_>>();
+    assert_eq!(files, [dir.join("a.scx"), dir.join("b.scx")]);
+}